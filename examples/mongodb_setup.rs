@@ -32,12 +32,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .options(IndexOptions::builder().unique(true).build())
         .build();
     
-    // Unique index for username
+    // Unique index for the canonical (lowercased) username
     let username_index = IndexModel::builder()
-        .keys(doc! { "username": 1 })
+        .keys(doc! { "username_canonical": 1 })
         .options(IndexOptions::builder().unique(true).build())
         .build();
     
+    // Unique index for the linked OAuth account, only over the users that have one
+    let oauth_index = IndexModel::builder()
+        .keys(doc! { "oauth_provider": 1, "oauth_subject": 1 })
+        .options(IndexOptions::builder()
+            .unique(true)
+            .partial_filter_expression(doc! { "oauth_subject": { "$exists": true } })
+            .build())
+        .build();
+    
     // Index for created_at (useful for sorting)
     let created_at_index = IndexModel::builder()
         .keys(doc! { "created_at": -1 })
@@ -47,6 +56,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     collection.create_indexes(vec![
         email_index,
         username_index,
+        oauth_index,
         created_at_index,
     ]).await?;
     
@@ -54,7 +64,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("✅ MongoDB is ready to use.");
     println!("\nCreated indexes:");
     println!("  - email (unique)");
-    println!("  - username (unique)");
+    println!("  - username_canonical (unique)");
+    println!("  - oauth_provider + oauth_subject (unique, linked users only)");
     println!("  - created_at (descending)");
     
     Ok(())
@@ -1,16 +0,0 @@
--- Migration to create the users table in SQLite
--- Execute with: sqlite3 auth.db < migrations/003_create_users_sqlite.sql
-
-CREATE TABLE IF NOT EXISTS users (
-    id TEXT PRIMARY KEY,
-    username TEXT UNIQUE NOT NULL,
-    email TEXT UNIQUE NOT NULL,
-    password_hash TEXT NOT NULL,
-    created_at TEXT NOT NULL,
-    updated_at TEXT NOT NULL,
-    is_active INTEGER DEFAULT 1
-);
-
--- Indexes to improve performance
-CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
-CREATE INDEX IF NOT EXISTS idx_users_username ON users(username);
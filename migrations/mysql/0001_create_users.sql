@@ -0,0 +1,34 @@
+-- Migration to create the users table in MySQL
+-- Applied by mysql_connection::run_migrations, or by hand with:
+-- mysql -u user -p auth_db < migrations/mysql/0001_create_users.sql
+
+CREATE TABLE IF NOT EXISTS users (
+    id CHAR(36) PRIMARY KEY,
+    username VARCHAR(50) UNIQUE NOT NULL,
+    -- Trimmed, NFC, lowercased username (canonical_username), used for lookups and uniqueness
+    username_canonical VARCHAR(50) UNIQUE NOT NULL,
+    email VARCHAR(255) UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    roles VARCHAR(255) NOT NULL DEFAULT '',
+    email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    last_login_at TIMESTAMP NULL DEFAULT NULL,
+    pending_email VARCHAR(255) NULL DEFAULT NULL,
+    tenant_id VARCHAR(255) NULL DEFAULT NULL,
+    -- Indexes to improve performance, declared here as MySQL has no CREATE INDEX IF NOT EXISTS
+    INDEX idx_users_email (email),
+    INDEX idx_users_username (username),
+    INDEX idx_users_tenant (tenant_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+-- Previous password hashes, to refuse reusing them (see PasswordPolicy::history)
+CREATE TABLE IF NOT EXISTS password_history (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    user_id CHAR(36) NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    INDEX idx_password_history_user (user_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,6 @@
+-- Version of the tokens of each user, embedded in them and bumped on password change
+-- (see User::token_version): tokens issued before the bump are rejected
+-- Applied by mysql_connection::run_migrations, or by hand with:
+-- mysql -u user -p auth_db < migrations/mysql/0002_add_token_version.sql
+
+ALTER TABLE users ADD COLUMN token_version BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- External account (OAuth provider + its user id) the user logs in with, see User::oauth_provider
+-- Applied by mysql_connection::run_migrations, or by hand with:
+-- mysql -u user -p auth_db < migrations/mysql/0003_add_oauth_identity.sql
+
+-- One local user per external account (NULLs, users without one, don't collide)
+ALTER TABLE users
+    ADD COLUMN oauth_provider VARCHAR(50) NULL DEFAULT NULL,
+    ADD COLUMN oauth_subject VARCHAR(255) NULL DEFAULT NULL,
+    ADD UNIQUE INDEX idx_users_oauth (oauth_provider, oauth_subject);
@@ -0,0 +1,50 @@
+-- Migration to create the users table in PostgreSQL
+-- Applied by postgres_connection::run_migrations, or by hand with:
+-- psql -U user -d auth_db -f migrations/postgres/0001_create_users.sql
+
+CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
+
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    username VARCHAR(50) UNIQUE NOT NULL,
+    username_canonical VARCHAR(50) UNIQUE NOT NULL,
+    email VARCHAR(255) UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    is_active BOOLEAN DEFAULT TRUE,
+    roles TEXT[] NOT NULL DEFAULT '{}',
+    email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    last_login_at TIMESTAMP WITH TIME ZONE,
+    pending_email VARCHAR(255),
+    tenant_id VARCHAR(255)
+);
+
+-- Indexes to improve search performance
+CREATE INDEX IF NOT EXISTS idx_users_email ON users(email);
+CREATE INDEX IF NOT EXISTS idx_users_username ON users(username);
+CREATE INDEX IF NOT EXISTS idx_users_tenant ON users(tenant_id);
+
+-- Previous password hashes, to refuse reusing them (see PasswordPolicy::history)
+CREATE TABLE IF NOT EXISTS password_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_history_user ON password_history(user_id);
+
+-- Comments
+COMMENT ON TABLE users IS 'Authentication system users table';
+COMMENT ON COLUMN users.id IS 'Unique user ID (UUID)';
+COMMENT ON COLUMN users.username IS 'Username as typed at registration (display)';
+COMMENT ON COLUMN users.username_canonical IS 'Trimmed, NFC, lowercased username (unique), compared by the lookups';
+COMMENT ON COLUMN users.email IS 'User email (unique)';
+COMMENT ON COLUMN users.password_hash IS 'Password hash (Argon2)';
+COMMENT ON COLUMN users.roles IS 'Roles used for authorization (e.g. admin)';
+COMMENT ON COLUMN users.email_verified IS 'Whether the user confirmed the email address';
+COMMENT ON COLUMN users.last_login_at IS 'Last successful login (NULL before the first one)';
+COMMENT ON COLUMN users.pending_email IS 'New email waiting for confirmation (NULL when no change is pending)';
+COMMENT ON COLUMN users.tenant_id IS 'Tenant of the user (NULL without multi-tenancy)';
+COMMENT ON TABLE password_history IS 'Previous password hashes of the users, newest has the highest id';
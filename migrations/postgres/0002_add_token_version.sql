@@ -0,0 +1,6 @@
+-- Version of the tokens of each user, embedded in them and bumped on password change
+-- (see User::token_version): tokens issued before the bump are rejected
+-- Applied by postgres_connection::run_migrations, or by hand with:
+-- psql -U user -d auth_db -f migrations/postgres/0002_add_token_version.sql
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS token_version BIGINT NOT NULL DEFAULT 0;
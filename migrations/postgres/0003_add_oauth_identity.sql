@@ -0,0 +1,9 @@
+-- External account (OAuth provider + its user id) the user logs in with, see User::oauth_provider
+-- Applied by postgres_connection::run_migrations, or by hand with:
+-- psql -U user -d auth_db -f migrations/postgres/0003_add_oauth_identity.sql
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS oauth_provider VARCHAR(50);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS oauth_subject VARCHAR(255);
+
+-- One local user per external account (NULLs, users without one, don't collide)
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oauth ON users(oauth_provider, oauth_subject);
@@ -0,0 +1,6 @@
+-- Version of the tokens of each user, embedded in them and bumped on password change
+-- (see User::token_version): tokens issued before the bump are rejected
+-- Applied by sqlite_connection::run_migrations, or by hand with:
+-- sqlite3 auth.db < migrations/sqlite/0002_add_token_version.sql
+
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- External account (OAuth provider + its user id) the user logs in with, see User::oauth_provider
+-- Applied by sqlite_connection::run_migrations, or by hand with:
+-- sqlite3 auth.db < migrations/sqlite/0003_add_oauth_identity.sql
+
+ALTER TABLE users ADD COLUMN oauth_provider TEXT;
+ALTER TABLE users ADD COLUMN oauth_subject TEXT;
+
+-- One local user per external account (NULLs, users without one, don't collide)
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oauth ON users(oauth_provider, oauth_subject);
@@ -0,0 +1,158 @@
+// This file is responsible for wiring the routes of the server,
+// so tests and other projects can mount the app without binding a socket
+
+use axum::{Json, Router, extract::DefaultBodyLimit, middleware, routing::{delete, get, patch, post, put}};
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::Level;
+use utoipa::OpenApi;
+use crate::{
+    auth::extractor::{AdminRole, ApiKeyUser, RequireRole},
+    csrf::csrf_protect,
+    envelope::envelope,
+    handlers::{admin_handler, api_key_handler, auth_handler, session_handler},
+    models::auth::MAX_INTROSPECT_BATCH,
+    rate_limit::{rate_limit, RateLimiter},
+    ApiDoc, AppState,
+};
+#[cfg(feature = "oauth")]
+use crate::handlers::oauth_handler;
+#[cfg(feature = "webauthn")]
+use crate::handlers::webauthn_handler;
+#[cfg(feature = "utoipa-swagger-ui")]
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+/// Maximum size of the request bodies of the anonymous routes (`/register`, `/login`, ...)
+///
+/// Their payloads are a few short fields, a bigger body is rejected with
+/// `413 payload_too_large` before being buffered in full.
+pub const AUTH_BODY_LIMIT_BYTES: usize = 16 * 1024;
+
+/// Maximum size of the request bodies of `/introspect` and `/introspect/batch`
+///
+/// Room for `MAX_INTROSPECT_BATCH` tokens of 2 KiB, a bigger body is also `413 payload_too_large`.
+pub const INTROSPECT_BODY_LIMIT_BYTES: usize = MAX_INTROSPECT_BATCH * 2 * 1024;
+
+/// Builds the router with every route of the auth system
+///
+/// Usage:
+/// ```ignore
+/// let app = build_router(config.app_state(user_repo));
+/// axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+/// ```
+///
+/// In tests, requests can be sent with `tower::ServiceExt::oneshot`.
+pub fn build_router(state: AppState) -> Router {
+    // Routes open to anonymous clients, throttled per IP
+    let mut auth_routes = Router::new()
+        .route("/register", post(auth_handler::register_handler))
+        .route("/login", post(auth_handler::login_handler))
+        .route("/refresh", post(auth_handler::refresh_handler))
+        .route("/token/refresh-rotate", post(auth_handler::refresh_rotate_handler))
+        .route("/forgot-password", post(auth_handler::forgot_password_handler))
+        .route("/reset-password", post(auth_handler::reset_password_handler))
+        .route("/verify-email", post(auth_handler::verify_email_handler));
+    #[cfg(feature = "oauth")]
+    if state.google_oauth.is_some() {
+        auth_routes = auth_routes
+            .route("/auth/google", get(oauth_handler::google_login_handler))
+            .route("/auth/google/callback", get(oauth_handler::google_callback_handler));
+    }
+    #[cfg(feature = "webauthn")]
+    if state.webauthn.is_some() {
+        auth_routes = auth_routes
+            .route("/webauthn/login/start", post(webauthn_handler::login_start_handler))
+            .route("/webauthn/login/finish", post(webauthn_handler::login_finish_handler));
+    }
+    auth_routes = auth_routes.layer(DefaultBodyLimit::max(AUTH_BODY_LIMIT_BYTES));
+    if let Some(rate_limit_config) = state.rate_limit.clone() {
+        auth_routes = auth_routes.layer(middleware::from_fn_with_state(RateLimiter::new(rate_limit_config).with_trusted_proxies(state.trusted_proxies), rate_limit));
+    }
+    let cors = state.cors.clone();
+    let token_response_header = state.token_response_header.clone();
+    let response_envelope = state.response_envelope;
+
+    // Routes of authenticated clients
+    let mut user_routes = Router::new()
+        .route("/logout", post(auth_handler::logout_handler))
+        .route("/logout-all", post(session_handler::logout_all_handler))
+        .route("/sessions", get(session_handler::list_sessions_handler))
+        .route("/sessions/{jti}", delete(session_handler::revoke_session_handler))
+        .route("/change-password", post(auth_handler::change_password_handler))
+        .route("/change-email", post(auth_handler::change_email_handler))
+        .route("/me", get(auth_handler::me_handler).merge(patch(auth_handler::update_me_handler)).merge(delete(auth_handler::delete_me_handler)))
+        .route("/private", get(auth_handler::private_handler))
+        .route("/service", get(service_handler))
+        .route("/api-keys", post(api_key_handler::create_api_key_handler))
+        .route("/api-keys/{id}", delete(api_key_handler::revoke_api_key_handler))
+        .route("/admin", get(admin_handler))
+        .route("/users", get(admin_handler::list_users_handler))
+        .route("/users/search", get(admin_handler::search_users_handler))
+        .route("/stats", get(admin_handler::stats_handler))
+        .route("/users/{id}/active", put(admin_handler::set_user_active_handler));
+    #[cfg(feature = "webauthn")]
+    if state.webauthn.is_some() {
+        user_routes = user_routes
+            .route("/webauthn/register/start", post(webauthn_handler::register_start_handler))
+            .route("/webauthn/register/finish", post(webauthn_handler::register_finish_handler));
+    }
+    // Only these read the auth cookie, a stale one mustn't block the login
+    if let Some(cookie) = state.auth_cookie.clone().filter(|cookie| cookie.csrf) {
+        user_routes = user_routes.layer(middleware::from_fn_with_state(cookie, csrf_protect));
+    }
+
+    // Called by gateways for each of their requests, so not throttled per IP like the anonymous routes
+    let introspect_routes = Router::new()
+        .route("/introspect", post(auth_handler::introspect_handler))
+        .route("/introspect/batch", post(auth_handler::introspect_batch_handler))
+        .layer(DefaultBodyLimit::max(INTROSPECT_BODY_LIMIT_BYTES));
+
+    let router = Router::new()
+        .merge(auth_routes)
+        .merge(user_routes)
+        .merge(introspect_routes)
+        .route("/openapi.json", get(openapi_handler));
+    // The page reads the document above, it isn't served a second time
+    #[cfg(feature = "utoipa-swagger-ui")]
+    let router = router.merge(SwaggerUi::new("/swagger-ui").config(Config::from("/openapi.json")));
+
+    let mut router = router
+        .with_state(state)
+        // One span per request (method + path), closed with the status and latency
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(DefaultOnResponse::new().level(Level::INFO))
+        );
+    // Around the rate limiter too, so its 429 is wrapped like the other errors
+    if response_envelope {
+        router = router.layer(middleware::from_fn(envelope));
+    }
+
+    // Outermost, so preflight requests are answered before any other layer
+    match cors {
+        // Browser scripts can only read the response headers that are exposed
+        Some(cors) => match token_response_header {
+            Some(name) => router.layer(cors.layer().expose_headers([name])),
+            None => router.layer(cors.layer()),
+        },
+        None => router,
+    }
+}
+
+
+/// Handler serving the OpenAPI document (`ApiDoc`)
+///
+/// Endpoint: GET /openapi.json
+pub async fn openapi_handler() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Authenticated with an API key (X-API-Key header) instead of a JWT
+async fn service_handler(user: ApiKeyUser) -> String {
+    format!("Access granted for user: {} (API key {})", user.user_id, user.key_id)
+}
+
+// Only users with the "admin" role get here, others receive 403
+async fn admin_handler(RequireRole { user, .. }: RequireRole<AdminRole>) -> String {
+    format!("Admin access granted for user: {}", user.user_id)
+}
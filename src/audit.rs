@@ -0,0 +1,152 @@
+// This file is responsible for the audit trail of the security-relevant actions
+// (logins, registrations, password changes, token revocations), for security teams
+
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::{auth::extractor::ClientInfo, errors::AuthError};
+
+/// Action recorded by an `AuditEvent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    Register,
+    PasswordChange,
+    PasswordReset,
+    /// Logout, revocation of a session or of every session
+    TokenRevocation,
+    /// `DELETE /me`
+    AccountDeletion,
+    /// A revoked token was presented (e.g. a stolen token after a logout)
+    RevokedTokenUse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// One entry of the audit trail
+///
+/// Only holds ids and error codes, never passwords or tokens.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEvent {
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    /// The user, when known (not for a login with an unknown username)
+    pub user_id: Option<Uuid>,
+    /// Client IP, see `ClientInfo`
+    pub ip: Option<String>,
+    /// Code of the error of a failure (e.g. `invalid_credentials`)
+    pub reason: Option<&'static str>,
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    pub at: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    pub fn success(action: AuditAction, user_id: Option<Uuid>, client: &ClientInfo) -> Self {
+        Self {
+            action,
+            outcome: AuditOutcome::Success,
+            user_id,
+            ip: client.ip.clone(),
+            reason: None,
+            at: Utc::now(),
+        }
+    }
+
+    pub fn failure(action: AuditAction, user_id: Option<Uuid>, client: &ClientInfo, error: &AuthError) -> Self {
+        Self {
+            outcome: AuditOutcome::Failure,
+            reason: Some(error.code()),
+            ..Self::success(action, user_id, client)
+        }
+    }
+}
+
+
+/// Trait that defines where audit events go
+///
+/// Receives one append-only event per audited action, e.g. to forward it to a SIEM
+/// or write it to a table; events are never updated or removed by the auth system.
+/// Recording never fails the request, implementations handle their own errors.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn record(&self, event: AuditEvent);
+}
+
+
+/// Audit sink writing every event to the logs (`auth_system::audit` target)
+///
+/// Failures are logged as warnings. The anonymous routes are rate limited,
+/// so a brute force can't flood the logs with failed logins.
+#[derive(Clone, Default)]
+pub struct LogAuditSink;
+
+#[async_trait]
+impl AuditSink for LogAuditSink {
+    async fn record(&self, event: AuditEvent) {
+        let user_id = event.user_id.map(|id| id.to_string());
+        let user_id = user_id.as_deref().unwrap_or("-");
+        let ip = event.ip.as_deref().unwrap_or("-");
+
+        match event.outcome {
+            AuditOutcome::Success => info!(action = ?event.action, user_id, ip, "audit: success"),
+            AuditOutcome::Failure => {
+                warn!(action = ?event.action, user_id, ip, reason = event.reason.unwrap_or("-"), "audit: failure")
+            }
+        }
+    }
+}
+
+
+/// In-memory audit sink, keeping every event (for tests and development)
+///
+/// WARNING: Events are lost when the process ends, and memory grows with them!
+#[derive(Clone, Default)]
+pub struct InMemoryAuditSink {
+    events: Arc<Mutex<Vec<AuditEvent>>>,
+}
+
+impl InMemoryAuditSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recorded events, oldest first
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl AuditSink for InMemoryAuditSink {
+    async fn record(&self, event: AuditEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failure_records_error_code_and_ip() {
+        let sink = InMemoryAuditSink::new();
+        let client = ClientInfo { user_agent: None, ip: Some("203.0.113.7".to_string()) };
+        sink.record(AuditEvent::failure(AuditAction::Login, None, &client, &AuthError::InvalidCredentials)).await;
+
+        let events = sink.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].outcome, AuditOutcome::Failure);
+        assert_eq!(events[0].reason, Some("invalid_credentials"));
+        assert_eq!(events[0].ip.as_deref(), Some("203.0.113.7"));
+    }
+}
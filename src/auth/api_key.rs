@@ -0,0 +1,46 @@
+// This file is responsible for generating API keys and hashing them for storage
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Prefix of every API key, so leaked keys are easy to recognize
+pub const API_KEY_PREFIX: &str = "ak_";
+
+// Generates a new random API key ("ak_" + 32 random bytes in hex)
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", API_KEY_PREFIX, hex::encode(bytes))
+}
+
+// Hashes an API key for storage and lookup
+// A fast hash is enough here: unlike passwords, keys are long random values
+// that can't be brute forced, and the hash must be found without a salt
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_are_unique_and_prefixed() {
+        let first = generate_api_key();
+        let second = generate_api_key();
+
+        assert!(first.starts_with(API_KEY_PREFIX));
+        assert_eq!(first.len(), API_KEY_PREFIX.len() + 64);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_hash_is_stable_and_hides_key() {
+        let key = generate_api_key();
+
+        assert_eq!(hash_api_key(&key), hash_api_key(&key));
+        assert_ne!(hash_api_key(&key), hash_api_key(&generate_api_key()));
+        assert!(!hash_api_key(&key).contains(&key[API_KEY_PREFIX.len()..]));
+    }
+}
@@ -0,0 +1,206 @@
+// This file is responsible for delivering the access token in a cookie,
+// for browser apps that shouldn't handle the token in JS, with the CSRF cookie going along
+
+use std::str::FromStr;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::{HeaderMap, HeaderValue, header};
+use chrono::Duration;
+
+/// Name of the cookie holding the CSRF token, readable by the page's scripts
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+/// Header in which the scripts send the CSRF token back, see `csrf::csrf_protect`
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// `SameSite` attribute of the cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => Err(format!("expected `strict`, `lax` or `none`, got `{value}`")),
+        }
+    }
+}
+
+/// Settings of the auth cookie
+///
+/// When set in `AppState::auth_cookie`, register and login also return the
+/// access token in an `HttpOnly` cookie, and `AuthUser` reads it from there
+/// when the request has no `Authorization` header.
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    /// Name of the cookie
+    pub name: String,
+    /// Only send the cookie over HTTPS (disable for local HTTP development only)
+    pub secure: bool,
+    /// `None` lets other sites send the cookie, it requires `secure`
+    pub same_site: SameSite,
+    /// Also set the `CSRF_COOKIE`, and require it back in the `CSRF_HEADER` of the
+    /// state-changing requests authenticated by the cookie (double-submit)
+    pub csrf: bool,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            name: "auth_token".to_string(),
+            secure: true,
+            same_site: SameSite::Strict,
+            csrf: true,
+        }
+    }
+}
+
+impl CookieConfig {
+    /// `Set-Cookie` value storing `token`, expiring after `max_age`
+    pub fn set_cookie(&self, token: &str, max_age: Duration) -> String {
+        self.cookie(&self.name, token, max_age, true)
+    }
+
+    /// `Set-Cookie` value storing the CSRF token, with the attributes of the auth cookie
+    /// but without `HttpOnly`: the page's scripts read it to send it back in `CSRF_HEADER`
+    pub fn set_csrf_cookie(&self, csrf_token: &str, max_age: Duration) -> String {
+        self.cookie(CSRF_COOKIE, csrf_token, max_age, false)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: Duration, http_only: bool) -> String {
+        let mut cookie = format!("{}={}; Path=/; Max-Age={}", name, value, max_age.num_seconds().max(0));
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        cookie.push_str("; SameSite=");
+        cookie.push_str(self.same_site.as_str());
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+
+    /// `Set-Cookie` value removing the cookie (used on logout)
+    pub fn clear_cookie(&self) -> String {
+        self.set_cookie("", Duration::zero())
+    }
+
+    /// Headers setting the cookie to `token`, and the CSRF cookie to a new random token
+    pub fn headers(&self, token: &str, max_age: Duration) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        // A JWT only contains base64url characters and dots, so this can't fail
+        if let Ok(value) = HeaderValue::from_str(&self.set_cookie(token, max_age)) {
+            headers.insert(header::SET_COOKIE, value);
+        }
+        if self.csrf
+            && let Ok(value) = HeaderValue::from_str(&self.set_csrf_cookie(&new_csrf_token(), max_age))
+        {
+            headers.append(header::SET_COOKIE, value);
+        }
+        headers
+    }
+
+    /// Headers removing the cookie, and the CSRF cookie
+    pub fn clear_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&self.clear_cookie()) {
+            headers.insert(header::SET_COOKIE, value);
+        }
+        if self.csrf
+            && let Ok(value) = HeaderValue::from_str(&self.set_csrf_cookie("", Duration::zero()))
+        {
+            headers.append(header::SET_COOKIE, value);
+        }
+        headers
+    }
+
+    /// Reads the value of this cookie from the `Cookie` request headers
+    pub fn token_from_headers<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        cookie_value(headers, &self.name)
+    }
+
+    /// Reads the CSRF token from the `Cookie` request headers
+    pub fn csrf_from_headers<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        cookie_value(headers, CSRF_COOKIE)
+    }
+}
+
+// Value of the cookie `name` among the `Cookie` request headers (an empty value counts as none)
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+// 32 random bytes in hex, a new one with each auth cookie
+fn new_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_cookie_attributes() {
+        let cookie = CookieConfig::default().set_cookie("abc.def.ghi", Duration::minutes(5));
+        assert_eq!(cookie, "auth_token=abc.def.ghi; Path=/; Max-Age=300; HttpOnly; SameSite=Strict; Secure");
+    }
+
+    #[test]
+    fn test_csrf_cookie_is_readable_by_scripts() {
+        let config = CookieConfig { same_site: SameSite::Lax, secure: false, ..CookieConfig::default() };
+        assert_eq!(config.set_csrf_cookie("abc", Duration::minutes(5)), "csrf_token=abc; Path=/; Max-Age=300; SameSite=Lax");
+
+        let headers = CookieConfig::default().headers("abc.def.ghi", Duration::minutes(5));
+        let cookies: Vec<_> = headers.get_all(header::SET_COOKIE).iter().map(|v| v.to_str().unwrap()).collect();
+        assert_eq!(cookies.len(), 2);
+        assert!(cookies[1].starts_with("csrf_token=") && !cookies[1].contains("HttpOnly"));
+
+        let without_csrf = CookieConfig { csrf: false, ..CookieConfig::default() };
+        assert_eq!(without_csrf.headers("abc.def.ghi", Duration::minutes(5)).get_all(header::SET_COOKIE).iter().count(), 1);
+    }
+
+    #[test]
+    fn test_same_site_is_parsed_ignoring_case() {
+        assert_eq!("Lax".parse::<SameSite>(), Ok(SameSite::Lax));
+        assert_eq!("none".parse::<SameSite>(), Ok(SameSite::None));
+        assert!("relaxed".parse::<SameSite>().is_err());
+    }
+
+    #[test]
+    fn test_token_is_read_among_other_cookies() {
+        let config = CookieConfig { name: "session".to_string(), ..CookieConfig::default() };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark; session=abc.def.ghi; lang=en"));
+
+        assert_eq!(config.token_from_headers(&headers), Some("abc.def.ghi"));
+        assert_eq!(CookieConfig::default().token_from_headers(&headers), None);
+    }
+}
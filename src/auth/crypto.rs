@@ -1,33 +1,528 @@
-// This file is responsible for the password protection using Argon2id, 
+// This file is responsible for the password protection using Argon2 (Argon2id by default),
     // for password hashing
+// With the "scrypt" feature, new hashes can be made with scrypt instead (`Argon2Config::scheme`)
+// With the "bcrypt" feature, legacy bcrypt hashes are verified too (and upgraded on login)
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock, PoisonError};
 
 use argon2::{
-    Argon2, password_hash::{
+    Algorithm, Argon2, Params, Version, password_hash::{
         PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng
     }
 };
+use hmac::{Hmac, Mac};
+#[cfg(feature = "scrypt")]
+use scrypt::Scrypt;
+use sha2::Sha256;
+
+/// Application-wide secret mixed into every password before hashing
+///
+/// The password is replaced by HMAC-SHA256(pepper, password) before Argon2,
+/// so a leaked database can't be brute-forced offline without the pepper
+/// (keep it out of the database, e.g. in the environment).
+///
+/// Rotation is not supported: changing the pepper makes every stored hash
+/// fail verification, users would have to reset their password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pepper(Vec<u8>);
+
+impl Pepper {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    // HMAC-SHA256 of the password, keyed with the pepper
+    fn apply(&self, password: &str) -> Vec<u8> {
+        // HMAC accepts keys of any length, this can't fail
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        mac.update(password.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+// The secret never ends up in logs
+impl fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Pepper(..)")
+    }
+}
+
+/// Algorithm of the new hashes
+///
+/// Only used for hashing: stored hashes are verified with the algorithm named by their
+/// PHC identifier (`$argon2id$`, `$scrypt$`), whatever the scheme.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashScheme {
+    #[default]
+    Argon2,
+    /// scrypt, with the costs of `Argon2Config::scrypt` ("scrypt" feature)
+    #[cfg(feature = "scrypt")]
+    Scrypt,
+}
+
+impl FromStr for HashScheme {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "argon2" => Ok(Self::Argon2),
+            #[cfg(feature = "scrypt")]
+            "scrypt" => Ok(Self::Scrypt),
+            #[cfg(not(feature = "scrypt"))]
+            "scrypt" => Err("scrypt requires the \"scrypt\" feature".to_string()),
+            _ => Err(format!("expected `argon2` or `scrypt`, got `{value}`")),
+        }
+    }
+}
+
+/// scrypt cost parameters, used when `Argon2Config::scheme` is `HashScheme::Scrypt`
+#[cfg(feature = "scrypt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptConfig {
+    /// log2 of the CPU/memory cost N (memory used is 128 * r * 2^log_n bytes)
+    pub log_n: u8,
+    /// Block size
+    pub r: u32,
+    /// Degree of parallelism
+    pub p: u32,
+}
+
+#[cfg(feature = "scrypt")]
+impl Default for ScryptConfig {
+    // Same values as `scrypt::Params::recommended()`
+    fn default() -> Self {
+        Self {
+            log_n: scrypt::Params::RECOMMENDED_LOG_N,
+            r: scrypt::Params::RECOMMENDED_R,
+            p: scrypt::Params::RECOMMENDED_P,
+        }
+    }
+}
+
+#[cfg(feature = "scrypt")]
+impl ScryptConfig {
+    fn params(&self) -> Result<scrypt::Params, argon2::password_hash::Error> {
+        scrypt::Params::new(self.log_n, self.r, self.p, scrypt::Params::RECOMMENDED_LEN)
+            .map_err(|_| argon2::password_hash::Error::ParamValueInvalid(argon2::password_hash::errors::InvalidValue::Malformed))
+    }
+
+    // True for the hashes of another algorithm, or with weaker parameters
+    fn needs_rehash(&self, parsed_hash: &PasswordHash<'_>) -> bool {
+        if parsed_hash.algorithm != scrypt::ALG_ID {
+            return true;
+        }
+
+        let Ok(params) = scrypt::Params::try_from(parsed_hash) else {
+            return true;
+        };
+
+        params.log_n() < self.log_n || params.r() < self.r || params.p() < self.p
+    }
+}
+
+/// Password hashing algorithm, Argon2 variant and cost parameters
+///
+/// Raise the costs in production to make brute force more expensive,
+/// lower them in tests to keep hashing fast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Argon2Config {
+    /// Algorithm of the new hashes, Argon2 unless scrypt is the standard of the environment
+    pub scheme: HashScheme,
+    /// Variant used for new hashes, Argon2id unless a compliance regime requires another one
+    pub algorithm: Algorithm,
+    /// Version used for new hashes (0x13, the current one, by default)
+    pub version: Version,
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of iterations (time cost)
+    pub iterations: u32,
+    /// Degree of parallelism (lanes)
+    pub parallelism: u32,
+    /// Costs of the scrypt hashes
+    #[cfg(feature = "scrypt")]
+    pub scrypt: ScryptConfig,
+    /// Optional secret applied to passwords before hashing (see `Pepper`)
+    pub pepper: Option<Pepper>,
+}
+
+impl Default for Argon2Config {
+    // Same values as `Argon2::default()` (OWASP recommended minimum)
+    fn default() -> Self {
+        Self {
+            scheme: HashScheme::default(),
+            algorithm: Algorithm::default(),
+            version: Version::default(),
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            #[cfg(feature = "scrypt")]
+            scrypt: ScryptConfig::default(),
+            pepper: None,
+        }
+    }
+}
+
+impl Argon2Config {
+    // Builds the hasher of the configured variant for these parameters
+    fn hasher(&self) -> Result<Argon2<'static>, argon2::password_hash::Error> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)?;
+        Ok(Argon2::new(self.algorithm, self.version, params))
+    }
+
+    // Bytes actually given to Argon2: the password, or its HMAC with the pepper
+    fn password_input<'a>(&self, password: &'a str) -> Cow<'a, [u8]> {
+        match &self.pepper {
+            Some(pepper) => Cow::Owned(pepper.apply(password)),
+            None => Cow::Borrowed(password.as_bytes()),
+        }
+    }
+}
 
 // Generates a hash for a password using Argon2
 // Args: 'password' - string
 // Returns: String with password's hash, including salt and parameters
 pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    hash_password_with(&Argon2Config::default(), password)
+}
+
+// Generates a hash for a password using the algorithm (Argon2 or scrypt) and cost parameters of `config`
+// The algorithm and parameters are recorded in the returned PHC string, the pepper (if any) is not
+pub fn hash_password_with(config: &Argon2Config, password: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let input = config.password_input(password);
 
     // Generate the hash
-    let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
+    let password_hash = match config.scheme {
+        HashScheme::Argon2 => config.hasher()?.hash_password(&input, &salt)?,
+        #[cfg(feature = "scrypt")]
+        HashScheme::Scrypt => Scrypt.hash_password_customized(&input, None, None, config.scrypt.params()?, &salt)?,
+    };
 
     // Returns hash as a string
     Ok(password_hash.to_string())
 }
 
+// Algorithm, variant and parameters are read from the stored hash, so no config is needed here
+// (only for hashes made without a pepper, see verify_password_with)
 pub fn verify_password(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    verify_password_with(&Argon2Config::default(), hash, password)
+}
+
+// Verifies a password hashed with hash_password_with, only the pepper of `config` is used
+// The scheme is detected from the hash prefix: `$argon2...`, `$scrypt$` with the "scrypt" feature,
+// or `$2a$` / `$2b$` / `$2y$` for bcrypt
+pub fn verify_password_with(config: &Argon2Config, hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    #[cfg(feature = "bcrypt")]
+    if is_bcrypt_hash(hash) {
+        return verify_bcrypt(hash, password);
+    }
+
     // Store parsed hash
     let parsed_hash = PasswordHash::new(hash)?;
 
-    // Create Argo2 instance
-    let argon2 = Argon2::default();
+    // Each verifier only accepts the hashes of its own algorithm (PHC identifier)
+    // The variant, version and parameters of the hash are used, not the ones of these instances
+    let verifiers: &[&dyn PasswordVerifier] = &[
+        &Argon2::default(),
+        #[cfg(feature = "scrypt")]
+        &Scrypt,
+    ];
 
     // Verify if the password correpond to the hash
-    Ok(argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok())
-}
\ No newline at end of file
+    Ok(parsed_hash.verify_password(verifiers, config.password_input(password)).is_ok())
+}
+
+// Hashes in the Modular Crypt Format of bcrypt
+#[cfg(feature = "bcrypt")]
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+// Legacy hashes were made by another system, so the pepper is never applied here
+// A malformed bcrypt hash is an error, like a malformed PHC string
+#[cfg(feature = "bcrypt")]
+fn verify_bcrypt(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    bcrypt::verify(password, hash).map_err(|_| argon2::password_hash::Error::PhcStringField)
+}
+
+// Dummy hashes already made, by algorithm and cost parameters
+static DUMMY_HASHES: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+/// Hash of a throwaway password, with the algorithm and cost parameters of `config`
+///
+/// Verified against when the user doesn't exist, so a login for an unknown
+/// username costs the same work as one for an existing user, whatever the tuned costs.
+/// Made on first use for each distinct set of parameters, then reused.
+pub fn dummy_hash(config: &Argon2Config) -> Result<String, argon2::password_hash::Error> {
+    let key = dummy_key(config);
+    let hashes = DUMMY_HASHES.get_or_init(Default::default);
+    if let Some(hash) = hashes.lock().unwrap_or_else(PoisonError::into_inner).get(&key) {
+        return Ok(hash.clone());
+    }
+
+    // Hashed outside of the lock; the pepper changes the input, not the cost
+    let hash = hash_password_with(&Argon2Config { pepper: None, ..config.clone() }, "dummy-password-for-timing")?;
+    Ok(hashes.lock().unwrap_or_else(PoisonError::into_inner).entry(key).or_insert(hash).clone())
+}
+
+// Everything of `config` that changes the work of a verification
+fn dummy_key(config: &Argon2Config) -> String {
+    let key = format!(
+        "{:?}/{:?}/{:?}/m={},t={},p={}",
+        config.scheme, config.algorithm, config.version, config.memory_kib, config.iterations, config.parallelism,
+    );
+    #[cfg(feature = "scrypt")]
+    let key = format!("{}/{:?}", key, config.scrypt);
+    key
+}
+
+// Verifies the password against the user's hash, or against `dummy_hash` when there is no user
+// Without a user the result is always false, even if the dummy hash matches
+pub fn verify_or_dummy(hash: Option<&str>, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    verify_or_dummy_with(&Argon2Config::default(), hash, password)
+}
+
+// Same as verify_or_dummy, applying the pepper of `config`
+pub fn verify_or_dummy_with(config: &Argon2Config, hash: Option<&str>, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    match hash {
+        Some(hash) => verify_password_with(config, hash, password),
+        None => {
+            verify_password_with(config, &dummy_hash(config)?, password)?;
+            Ok(false)
+        }
+    }
+}
+
+// Checks if a stored hash should be re-created with the current config
+// True when the hash uses another algorithm, variant or version than `config` (e.g. bcrypt)
+// or weaker parameters
+// (a hash that can't be parsed also needs a rehash)
+pub fn needs_rehash(hash: &str, config: &Argon2Config) -> bool {
+    let Ok(parsed_hash) = PasswordHash::new(hash) else {
+        return true;
+    };
+
+    #[cfg(feature = "scrypt")]
+    if config.scheme == HashScheme::Scrypt {
+        return config.scrypt.needs_rehash(&parsed_hash);
+    }
+
+    if parsed_hash.algorithm != config.algorithm.ident() || parsed_hash.version != Some(config.version.into()) {
+        return true;
+    }
+
+    let Ok(params) = Params::try_from(&parsed_hash) else {
+        return true;
+    };
+
+    params.m_cost() < config.memory_kib
+        || params.t_cost() < config.iterations
+        || params.p_cost() < config.parallelism
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_config() -> Argon2Config {
+        Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() }
+    }
+
+    #[test]
+    fn test_hash_and_verify_with_low_cost() {
+        let hash = hash_password_with(&fast_config(), "Password123!").unwrap();
+        assert!(verify_password(&hash, "Password123!").unwrap());
+        assert!(!verify_password(&hash, "WrongPassword1!").unwrap());
+    }
+
+    #[test]
+    fn test_hash_records_chosen_parameters() {
+        let config = Argon2Config { memory_kib: 128, iterations: 3, parallelism: 2, ..Argon2Config::default() };
+        let hash = hash_password_with(&config, "Password123!").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=128,t=3,p=2$"));
+    }
+
+    #[test]
+    fn test_hash_with_argon2i_and_verify() {
+        let config = Argon2Config { algorithm: Algorithm::Argon2i, ..fast_config() };
+        let hash = hash_password_with(&config, "Password123!").unwrap();
+
+        assert!(hash.starts_with("$argon2i$v=19$m=64,t=1,p=1$"));
+        // The variant is read from the hash, whatever the config
+        assert!(verify_password(&hash, "Password123!").unwrap());
+        assert!(!verify_password_with(&config, &hash, "WrongPassword1!").unwrap());
+    }
+
+    #[test]
+    fn test_hash_records_chosen_variant_and_version() {
+        let config = Argon2Config { algorithm: Algorithm::Argon2d, version: Version::V0x10, ..fast_config() };
+        let hash = hash_password_with(&config, "Password123!").unwrap();
+
+        assert!(hash.starts_with("$argon2d$v=16$m=64,t=1,p=1$"));
+        assert!(verify_password(&hash, "Password123!").unwrap());
+    }
+
+    #[test]
+    fn test_needs_rehash_when_variant_changes() {
+        let argon2i = Argon2Config { algorithm: Algorithm::Argon2i, ..fast_config() };
+        let hash = hash_password_with(&argon2i, "Password123!").unwrap();
+
+        assert!(!needs_rehash(&hash, &argon2i));
+        assert!(needs_rehash(&hash, &fast_config()));
+        assert!(needs_rehash(&hash, &Argon2Config { version: Version::V0x10, ..argon2i }));
+    }
+
+    #[test]
+    fn test_needs_rehash_when_parameters_are_weaker() {
+        let hash = hash_password_with(&fast_config(), "Password123!").unwrap();
+        assert!(needs_rehash(&hash, &Argon2Config::default()));
+    }
+
+    #[test]
+    fn test_no_rehash_when_parameters_match() {
+        let hash = hash_password_with(&fast_config(), "Password123!").unwrap();
+        assert!(!needs_rehash(&hash, &fast_config()));
+    }
+
+    #[test]
+    fn test_dummy_hash_is_a_valid_argon2_hash() {
+        // An unparseable dummy would return early and skip the Argon2 work
+        let hash = dummy_hash(&Argon2Config::default()).unwrap();
+        assert!(PasswordHash::new(&hash).is_ok());
+        assert!(!needs_rehash(&hash, &Argon2Config::default()));
+        assert_eq!(dummy_hash(&Argon2Config::default()).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_dummy_hash_follows_the_tuned_costs() {
+        let tuned = Argon2Config { memory_kib: 128, iterations: 3, parallelism: 1, ..Argon2Config::default() };
+        let hash = dummy_hash(&tuned).unwrap();
+        assert!(hash.contains("m=128,t=3,p=1"));
+        assert!(!needs_rehash(&hash, &tuned));
+
+        // The pepper doesn't change the cost, the same dummy is used
+        let peppered = Argon2Config { pepper: Some(Pepper::new("pepper-secret")), ..tuned };
+        assert_eq!(dummy_hash(&peppered).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_verify_or_dummy_without_user_is_false() {
+        assert!(!verify_or_dummy(None, "Password123!").unwrap());
+        assert!(!verify_or_dummy(None, "dummy-password-for-timing").unwrap());
+    }
+
+    #[test]
+    fn test_verify_or_dummy_with_user_checks_hash() {
+        let hash = hash_password_with(&fast_config(), "Password123!").unwrap();
+        assert!(verify_or_dummy(Some(&hash), "Password123!").unwrap());
+        assert!(!verify_or_dummy(Some(&hash), "WrongPassword1!").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_parameters_are_rejected() {
+        let config = Argon2Config { memory_kib: 1, iterations: 0, parallelism: 1, ..Argon2Config::default() };
+        assert!(hash_password_with(&config, "Password123!").is_err());
+    }
+
+    #[test]
+    fn test_peppered_hash_needs_the_pepper() {
+        let peppered = Argon2Config { pepper: Some(Pepper::new("pepper-secret")), ..fast_config() };
+        let hash = hash_password_with(&peppered, "Password123!").unwrap();
+
+        assert!(verify_password_with(&peppered, &hash, "Password123!").unwrap());
+        assert!(!verify_password_with(&peppered, &hash, "WrongPassword1!").unwrap());
+        assert!(!verify_password(&hash, "Password123!").unwrap());
+
+        let other = Argon2Config { pepper: Some(Pepper::new("other-secret")), ..fast_config() };
+        assert!(!verify_password_with(&other, &hash, "Password123!").unwrap());
+    }
+
+    // bcrypt hash (cost 4) of "Password123!"
+    #[cfg(feature = "bcrypt")]
+    const BCRYPT_HASH: &str = "$2b$04$LPzSKi9aRSn9jM7ZkZy8FOW0vI2nEeDs35fwvwkiN2qRLEUM3jonm";
+
+    #[cfg(feature = "bcrypt")]
+    #[test]
+    fn test_verify_known_bcrypt_hash() {
+        assert!(verify_password(BCRYPT_HASH, "Password123!").unwrap());
+        assert!(!verify_password(BCRYPT_HASH, "WrongPassword1!").unwrap());
+
+        // The pepper is not applied to legacy hashes
+        let peppered = Argon2Config { pepper: Some(Pepper::new("pepper-secret")), ..fast_config() };
+        assert!(verify_password_with(&peppered, BCRYPT_HASH, "Password123!").unwrap());
+    }
+
+    #[cfg(feature = "bcrypt")]
+    #[test]
+    fn test_bcrypt_hash_needs_rehash() {
+        assert!(needs_rehash(BCRYPT_HASH, &fast_config()));
+        assert!(verify_password("$2b$04$not-a-valid-hash", "Password123!").is_err());
+    }
+
+    #[cfg(feature = "scrypt")]
+    fn fast_scrypt_config() -> Argon2Config {
+        Argon2Config { scheme: HashScheme::Scrypt, scrypt: ScryptConfig { log_n: 4, r: 8, p: 1 }, ..fast_config() }
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn test_hash_with_scrypt_and_verify() {
+        let hash = hash_password_with(&fast_scrypt_config(), "Password123!").unwrap();
+
+        assert!(hash.starts_with("$scrypt$ln=4,r=8,p=1$"));
+        // The algorithm is read from the hash, whatever the config
+        assert!(verify_password(&hash, "Password123!").unwrap());
+        assert!(!verify_password_with(&fast_scrypt_config(), &hash, "WrongPassword1!").unwrap());
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn test_scrypt_config_still_verifies_argon2_hashes() {
+        let argon2_hash = hash_password_with(&fast_config(), "Password123!").unwrap();
+
+        assert!(verify_password_with(&fast_scrypt_config(), &argon2_hash, "Password123!").unwrap());
+        assert!(!verify_password_with(&fast_scrypt_config(), &argon2_hash, "WrongPassword1!").unwrap());
+        // Upgraded to scrypt on the next login
+        assert!(needs_rehash(&argon2_hash, &fast_scrypt_config()));
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn test_scrypt_needs_rehash() {
+        let hash = hash_password_with(&fast_scrypt_config(), "Password123!").unwrap();
+
+        assert!(!needs_rehash(&hash, &fast_scrypt_config()));
+        assert!(needs_rehash(&hash, &Argon2Config { scheme: HashScheme::Scrypt, ..fast_config() }));
+        assert!(needs_rehash(&hash, &fast_config()));
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn test_scrypt_dummy_hash_is_a_valid_scrypt_hash() {
+        let config = Argon2Config { scheme: HashScheme::Scrypt, ..Argon2Config::default() };
+        let hash = dummy_hash(&config).unwrap();
+        assert!(PasswordHash::new(&hash).is_ok());
+        assert!(!needs_rehash(&hash, &config));
+    }
+
+    #[test]
+    fn test_hash_scheme_is_parsed() {
+        assert_eq!("Argon2".parse::<HashScheme>(), Ok(HashScheme::Argon2));
+        #[cfg(feature = "scrypt")]
+        assert_eq!("scrypt".parse::<HashScheme>(), Ok(HashScheme::Scrypt));
+        #[cfg(not(feature = "scrypt"))]
+        assert!("scrypt".parse::<HashScheme>().is_err());
+        assert!("bcrypt".parse::<HashScheme>().is_err());
+    }
+
+    #[test]
+    fn test_pepper_is_not_debug_printed() {
+        let config = Argon2Config { pepper: Some(Pepper::new("pepper-secret")), ..fast_config() };
+        assert!(!format!("{config:?}").contains("pepper-secret"));
+    }
+}
@@ -1,46 +1,976 @@
-use crate::auth::jwt::Claims; // Importe o segredo aqui 
+use crate::auth::api_key::hash_api_key;
+use crate::auth::jwt::{validate_token_type, TokenType, SESSION_CLAIM};
+use crate::errors::AuthError;
+use crate::audit::{AuditAction, AuditEvent};
 use crate::AppState;
+use crate::models::user::{User, UserId};
+use crate::rate_limit::known_client_ip;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use serde_json::{Map, Value};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 use axum::{ 
-    extract::{FromRequestParts, FromRef}, 
+    extract::{ConnectInfo, FromRequestParts, FromRef}, 
     http::request::Parts,
-    http::StatusCode, 
+    http::header,
 };
 
 // Struct that represents a autheticated user
 pub struct AuthUser {
-    pub user_id: String,
+    pub user_id: UserId,
+    pub jti: String,    // Id of the token used, so it can be revoked
+    pub roles: Vec<String>,
+    /// When the token was issued (`iat` claim)
+    pub issued_at: DateTime<Utc>,
+    /// Tenant of the user (`tenant_id` claim), `None` without multi-tenancy
+    pub tenant_id: Option<String>,
+    /// Custom claims of the token (see `create_token_with_claims`)
+    pub extra: Map<String, Value>,
+}
+
+impl AuthUser {
+    /// Checks if the token grants the given role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// Id of the session (login) of the token, see `Claims::session_id`
+    pub fn session_id(&self) -> &str {
+        self.extra.get(SESSION_CLAIM).and_then(Value::as_str).unwrap_or(&self.jti)
+    }
 }
 
 // Allow use AuthUser as a parameter in Axum handlers
 impl<S> FromRequestParts<S> for AuthUser where AppState: FromRef<S>, S: Send + Sync {
-    type Rejection = (StatusCode, String);  // Defining Fallback
+    type Rejection = AuthError;  // Sent as the usual JSON error body
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let app_state = AppState::from_ref(state);
 
-        // Search for the header
-        let auth_header = parts
-            .headers
-            .get("Authorization") 
-            .and_then(|h| h.to_str().ok())              // Try to convert into string
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing Token".to_string()))?; // Activates fallbakc
-        
-        // Check if start with "Bearer "
-        if !auth_header.starts_with("Bearer ") {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid Token Format".into()));
+        // Search for the header, one that isn't valid text is rejected instead of falling back to the cookie
+        let auth_header = match parts.headers.get("Authorization") {
+            Some(h) => Some(h.to_str().map_err(|_| AuthError::InvalidToken)?),
+            None => None,
+        };
+
+        let token = match auth_header {
+            // The header takes precedence over the cookie
+            Some(auth_header) => {
+                // Check if start with "Bearer "
+                if !auth_header.starts_with("Bearer ") {
+                    return Err(AuthError::InvalidToken);
+                }
+
+                // Removes "Bearer " and stores the token
+                &auth_header[7..]
+            }
+            // Without header, fall back to the auth cookie (when enabled)
+            None => app_state.auth_cookie
+                .as_ref()
+                .and_then(|cookie| cookie.token_from_headers(&parts.headers))
+                .ok_or(AuthError::InvalidToken)?, // Activates fallbakc
+        };
+
+        //Validar o token using AppState keys
+        // Only access tokens are accepted, refresh tokens are rejected here
+        // An expired token is `TokenExpired` (the client can refresh), anything else `InvalidToken`
+        let claims = validate_token_type(token, &app_state.jwt_keys, &app_state.token_config, TokenType::Access)?;
+
+        // Reject tokens revoked by logout, and tokens of a revoked session
+        let mut revoked = app_state.token_blacklist.is_revoked(&claims.jti).await?;
+        if !revoked && claims.session_id() != claims.jti {
+            revoked = app_state.token_blacklist.is_revoked(claims.session_id()).await?;
         }
 
-        // Removes "Bearer " and stores the token
-        let token = &auth_header[7..];
+        if revoked {
+            let error = AuthError::InvalidToken;
+            let Ok(client) = ClientInfo::from_request_parts(parts, state).await;
+            app_state.audit.record(AuditEvent::failure(AuditAction::RevokedTokenUse, Some(claims.sub.as_uuid()), &client, &error)).await;
+            return Err(error);
+        }
 
-        //Validar o token using AppState secret
-        let token_data = jsonwebtoken::decode::<Claims>(
-            token,
-            &jsonwebtoken::DecodingKey::from_secret(app_state.jwt_secret.as_bytes()),
-            &jsonwebtoken::Validation::default(),
-        ).map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid Token or expired".into()))?;
+        let token_version = claims.token_version;
+        let issued_at = DateTime::from_timestamp(claims.iat as i64, 0).ok_or(AuthError::InvalidToken)?;
+        let user = AuthUser {
+            user_id: claims.sub, jti: claims.jti, roles: claims.roles, issued_at, tenant_id: claims.tenant_id, extra: claims.extra,
+        };
+
+        // Kept for `CurrentUser`, so a handler taking both loads the user once
+        if let Some(stored) = check_stored_user(&app_state, user.user_id.as_uuid(), token_version).await? {
+            parts.extensions.insert(LoadedUser(stored));
+        }
 
         // Return the user authenticated
-        Ok(AuthUser { user_id: token_data.claims.sub })
+        Ok(user)
+    }
+}
+
+
+// User loaded by `AuthUser` for the request, see `check_stored_user`
+#[derive(Clone)]
+struct LoadedUser(User);
+
+/// Checks a token against its stored user, when there is something to check
+///
+/// Tokens issued at login carry the user's token version, compared with the stored one;
+/// the account is also re-checked to still be active when `check_active_on_request` is set.
+/// Returns the stored user when it was loaded, `None` when neither check applies.
+///
+/// A deleted user is `UserNotFound` (like `CurrentUser`), an outdated version
+/// (password change, logout everywhere) `InvalidToken`, a deactivated account `AccountDisabled`.
+pub(crate) async fn check_stored_user(state: &AppState, user_id: Uuid, token_version: Option<i64>) -> Result<Option<User>, AuthError> {
+    if token_version.is_none() && !state.check_active_on_request {
+        return Ok(None);
+    }
+
+    let stored = state.user_repo.find_by_id(user_id).await?.ok_or(AuthError::UserNotFound)?;
+    if token_version.is_some_and(|version| version != stored.token_version) {
+        return Err(AuthError::InvalidToken);
+    }
+    if state.check_active_on_request && !stored.is_active {
+        return Err(AuthError::AccountDisabled);
+    }
+
+    Ok(Some(stored))
+}
+
+
+/// Client that sent the request, recorded with the sessions
+///
+/// The IP is the one the rate limiter keys on: the socket address (when served with
+/// `into_make_service_with_connect_info`), or the `X-Forwarded-For` entry appended by the
+/// outermost of the `AppState::trusted_proxies`; `None` when unknown.
+/// Never rejects a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let trusted_proxies = AppState::from_ref(state).trusted_proxies;
+        let socket_ip = parts.extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        Ok(ClientInfo {
+            user_agent: parts.headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            ip: known_client_ip(&parts.headers, socket_ip, trusted_proxies).map(|ip| ip.to_string()),
+        })
+    }
+}
+
+
+/// Header naming the tenant of an anonymous request, see `RequestTenant`
+pub const TENANT_HEADER: &str = "X-Tenant-ID";
+
+/// Tenant an anonymous request (registration, login) is made for
+///
+/// Read from the `X-Tenant-ID` header, else from the subdomain of the `Host` under
+/// `AppState::tenant_domain` (`acme.example.com` is the tenant `acme` with `example.com`).
+/// `None` when neither names one. Never rejects a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestTenant(pub Option<String>);
+
+impl<S> FromRequestParts<S> for RequestTenant where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let header_tenant = parts.headers
+            .get(TENANT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|tenant| !tenant.is_empty());
+
+        let tenant = match header_tenant {
+            Some(tenant) => Some(tenant.to_string()),
+            None => AppState::from_ref(state).tenant_domain
+                .as_deref()
+                .and_then(|domain| subdomain_tenant(&parts.headers, domain)),
+        };
+
+        Ok(RequestTenant(tenant))
+    }
+}
+
+// First label of the Host when it is a direct subdomain of `domain` (the port is ignored)
+fn subdomain_tenant(headers: &axum::http::HeaderMap, domain: &str) -> Option<String> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let host = host.split(':').next()?.to_ascii_lowercase();
+    let tenant = host.strip_suffix(&domain.to_ascii_lowercase())?.strip_suffix('.')?;
+
+    (!tenant.is_empty() && !tenant.contains('.')).then(|| tenant.to_string())
+}
+
+
+/// Tenant of the authenticated user, from the `tenant_id` claim of the access token
+///
+/// Rejects like `AuthUser` when the token is invalid, and with `InvalidToken`
+/// when it has no tenant. The tenant is kept in the request extensions, so
+/// extracting it again (in a middleware then in the handler) checks the token once.
+///
+/// Usage: `async fn handler(Tenant(tenant_id): Tenant)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl<S> FromRequestParts<S> for Tenant where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(tenant) = parts.extensions.get::<Tenant>() {
+            return Ok(tenant.clone());
+        }
+
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        let tenant = Tenant(user.tenant_id.ok_or(AuthError::InvalidToken)?);
+        parts.extensions.insert(tenant.clone());
+
+        Ok(tenant)
+    }
+}
+
+
+/// Authenticated user, loaded from the repository
+///
+/// Rejects like `AuthUser` when the token is invalid (401), with 404 (`UserNotFound`) when
+/// the user was deleted after the token was issued, and with 403 (`AccountDisabled`) when the
+/// account is deactivated, so the stored user (email, verification, ...) is always current.
+///
+/// Usage: `async fn handler(CurrentUser { user, .. }: CurrentUser)`
+pub struct CurrentUser {
+    /// The token of the request
+    pub token: AuthUser,
+    pub user: User,
+}
+
+impl<S> FromRequestParts<S> for CurrentUser where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+        let token = AuthUser::from_request_parts(parts, state).await?;
+
+        // Not loaded again when `AuthUser` already checked it
+        let user = match parts.extensions.get::<LoadedUser>() {
+            Some(LoadedUser(user)) => user.clone(),
+            None => app_state.user_repo
+                .find_by_id(token.user_id.as_uuid())
+                .await?
+                .ok_or(AuthError::UserNotFound)?,
+        };
+        if !user.is_active {
+            return Err(AuthError::AccountDisabled);
+        }
+
+        Ok(CurrentUser { token, user })
+    }
+}
+
+
+/// Authenticated user whose email is verified
+///
+/// Loaded like `CurrentUser`, and rejected with 403 (`EmailNotVerified`)
+/// until the user confirmed the email at `POST /verify-email`.
+///
+/// Usage: `async fn handler(VerifiedUser { user, .. }: VerifiedUser)`
+pub struct VerifiedUser {
+    /// The token of the request
+    pub token: AuthUser,
+    pub user: User,
+}
+
+impl<S> FromRequestParts<S> for VerifiedUser where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let CurrentUser { token, user } = CurrentUser::from_request_parts(parts, state).await?;
+
+        if !user.email_verified {
+            return Err(AuthError::EmailNotVerified);
+        }
+
+        Ok(VerifiedUser { token, user })
+    }
+}
+
+
+/// Optionally authenticated user
+///
+/// `Some` when the request carries a valid access token, `None` when the token
+/// is missing, malformed, invalid, expired, revoked or belongs to a deleted or disabled account;
+/// anonymous requests are not rejected.
+/// Only a failing token blacklist is an error (500).
+///
+/// Usage: `async fn handler(MaybeAuthUser(user): MaybeAuthUser)`
+pub struct MaybeAuthUser(pub Option<AuthUser>);
+
+impl<S> FromRequestParts<S> for MaybeAuthUser where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match AuthUser::from_request_parts(parts, state).await {
+            Ok(user) => Ok(MaybeAuthUser(Some(user))),
+            Err(AuthError::InvalidToken | AuthError::TokenExpired | AuthError::UserNotFound | AuthError::AccountDisabled) => Ok(MaybeAuthUser(None)),
+            Err(rejection) => Err(rejection),
+        }
+    }
+}
+
+
+/// User authenticated by an API key (`X-API-Key` header)
+///
+/// For service-to-service callers that don't want to handle JWTs.
+/// Rejects with 401 (`InvalidToken`) when the key is missing, unknown or revoked
+/// or its owner was deleted, and with 403 (`AccountDisabled`) when the owner's account is disabled.
+///
+/// Usage: `async fn handler(user: ApiKeyUser)`
+pub struct ApiKeyUser {
+    pub user_id: UserId,
+    pub key_id: Uuid,
+}
+
+impl<S> FromRequestParts<S> for ApiKeyUser where AppState: FromRef<S>, S: Send + Sync {
+    type Rejection = AuthError;  // Sent as the usual JSON error body
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+
+        let key = parts
+            .headers
+            .get("X-API-Key")
+            .and_then(|h| h.to_str().ok())
+            .ok_or(AuthError::InvalidToken)?;
+
+        // Keys are looked up by hash, the plaintext is never stored
+        let api_key = app_state.api_keys
+            .find_by_hash(&hash_api_key(key))
+            .await?
+            .filter(|api_key| !api_key.revoked)
+            .ok_or(AuthError::InvalidToken)?;
+
+        // Keys are long-lived, so the owner is always checked (unlike short-lived tokens)
+        match app_state.user_repo.find_by_id(api_key.user_id).await? {
+            Some(user) if user.is_active => Ok(ApiKeyUser { user_id: UserId(user.id), key_id: api_key.id }),
+            Some(_) => Err(AuthError::AccountDisabled),
+            None => Err(AuthError::InvalidToken),
+        }
+    }
+}
+
+
+/// A role that can be required with `RequireRole`
+///
+/// Extractors are selected by type, so each role is a marker type:
+/// ```ignore
+/// pub struct EditorRole;
+/// impl Role for EditorRole { const NAME: &'static str = "editor"; }
+/// ```
+pub trait Role {
+    const NAME: &'static str;
+}
+
+/// The `"admin"` role
+pub struct AdminRole;
+
+impl Role for AdminRole {
+    const NAME: &'static str = "admin";
+}
+
+/// Authenticated user that has the role `R`
+///
+/// Rejects with 401 like `AuthUser` when the token is invalid,
+/// and with 403 when the role is missing.
+///
+/// Usage: `async fn handler(RequireRole { user, .. }: RequireRole<AdminRole>)`
+pub struct RequireRole<R: Role> {
+    pub user: AuthUser,
+    _role: PhantomData<R>,
+}
+
+impl<S, R> FromRequestParts<S> for RequireRole<R> where AppState: FromRef<S>, S: Send + Sync, R: Role {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if !user.has_role(R::NAME) {
+            return Err(AuthError::MissingRole(R::NAME.to_string()));
+        }
+
+        Ok(RequireRole { user, _role: PhantomData })
+    }
+}
+
+
+/// A set of roles that can be required with `RequireAnyRole` or `RequireAllRoles`
+///
+/// Like `Role`, each set is a marker type:
+/// ```ignore
+/// pub struct Staff;
+/// impl Roles for Staff { const NAMES: &'static [&'static str] = &["admin", "support"]; }
+/// ```
+pub trait Roles {
+    const NAMES: &'static [&'static str];
+}
+
+/// Authenticated user that has at least one of the roles of `R`
+///
+/// Rejects with 401 like `AuthUser` when the token is invalid,
+/// and with 403 when the token grants none of the roles.
+///
+/// Usage: `async fn handler(RequireAnyRole { user, .. }: RequireAnyRole<Staff>)`
+pub struct RequireAnyRole<R: Roles> {
+    pub user: AuthUser,
+    _roles: PhantomData<R>,
+}
+
+impl<S, R> FromRequestParts<S> for RequireAnyRole<R> where AppState: FromRef<S>, S: Send + Sync, R: Roles {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if !R::NAMES.iter().any(|role| user.has_role(role)) {
+            return Err(AuthError::MissingRole(R::NAMES.join(" or ")));
+        }
+
+        Ok(RequireAnyRole { user, _roles: PhantomData })
+    }
+}
+
+/// Authenticated user that has every role of `R`
+///
+/// Rejects with 401 like `AuthUser` when the token is invalid,
+/// and with 403 naming the first missing role.
+///
+/// Usage: `async fn handler(RequireAllRoles { user, .. }: RequireAllRoles<Staff>)`
+pub struct RequireAllRoles<R: Roles> {
+    pub user: AuthUser,
+    _roles: PhantomData<R>,
+}
+
+impl<S, R> FromRequestParts<S> for RequireAllRoles<R> where AppState: FromRef<S>, S: Send + Sync, R: Roles {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if let Some(missing) = R::NAMES.iter().find(|role| !user.has_role(role)) {
+            return Err(AuthError::MissingRole(missing.to_string()));
+        }
+
+        Ok(RequireAllRoles { user, _roles: PhantomData })
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::http::{Request, StatusCode};
+    use chrono::Duration;
+    use crate::auth::jwt::{
+        create_tenant_token, create_token, create_token_with_claims, create_token_with_config, create_refresh_token, create_session_token, JwtKeys, TokenConfig, TokenExpiries,
+    };
+    use crate::auth::cookie::CookieConfig;
+    use crate::db::memory_connection::InMemoryUserRepository;
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+    const USER_ID: UserId = UserId(Uuid::from_u128(1));
+
+    fn state() -> AppState {
+        AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()))
+    }
+
+    fn parts_with_token(token: &str) -> Parts {
+        let (parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts
+    }
+
+    #[tokio::test]
+    async fn test_access_token_is_accepted() {
+        let token = create_token(USER_ID, SECRET);
+        let mut parts = parts_with_token(&token);
+
+        let user = AuthUser::from_request_parts(&mut parts, &state()).await.unwrap();
+        assert_eq!(user.user_id, USER_ID);
+    }
+
+    #[tokio::test]
+    async fn test_current_user_is_loaded_from_the_repository() {
+        use crate::models::user::CreateUser;
+
+        let state = state();
+        let user = state.user_repo.create(CreateUser {
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }, "hash".to_string()).await.unwrap();
+
+        let token = create_token(UserId(user.id), SECRET);
+        let current = CurrentUser::from_request_parts(&mut parts_with_token(&token), &state).await.unwrap();
+        assert_eq!(current.user.id, user.id);
+        assert_eq!(current.user.username, "john_doe");
+        assert_eq!(current.token.user_id, UserId(user.id));
+    }
+
+    #[tokio::test]
+    async fn test_current_user_of_deleted_user_is_not_found() {
+        // A valid token whose user is no longer stored
+        let token = create_token(UserId(Uuid::new_v4()), SECRET);
+        let result = CurrentUser::from_request_parts(&mut parts_with_token(&token), &state()).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+
+        let result = CurrentUser::from_request_parts(&mut parts_with_token("not-a-jwt"), &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_token_with_non_uuid_sub_is_invalid() {
+        let user_id = Uuid::new_v4();
+        let mut parts = parts_with_token(&create_token(UserId(user_id), SECRET));
+        let user = AuthUser::from_request_parts(&mut parts, &state()).await.unwrap();
+        assert_eq!(user.user_id.as_uuid(), user_id);
+
+        // Signed with the right key, but not issued by this service
+        let now = Utc::now().timestamp();
+        let claims = serde_json::json!({
+            "sub": "not-a-uuid", "exp": now + 60, "iat": now, "token_type": "access",
+            "jti": "token-1", "iss": "auth-system", "aud": "auth-system",
+        });
+        let token = jsonwebtoken::encode(&Default::default(), &claims, &jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes())).unwrap();
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_reported_as_expired() {
+        let expiries = TokenExpiries { access: Duration::minutes(-5), ..TokenExpiries::default() };
+        let config = TokenConfig { expiries, ..TokenConfig::default() };
+        let token = create_token_with_config(USER_ID, &[], &JwtKeys::hmac(SECRET), &config).unwrap();
+
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state()).await;
+        assert!(matches!(result, Err(AuthError::TokenExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_or_forged_token_is_invalid() {
+        let result = AuthUser::from_request_parts(&mut parts_with_token("not-a-jwt"), &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+
+        let forged = create_token(USER_ID, "another_secret_that_is_long_enough_too");
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&forged), &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    fn parts_with_headers(headers: &[(&str, &str)]) -> Parts {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn test_request_tenant_from_header_or_subdomain() {
+        let mut state = state();
+        state.tenant_domain = Some("example.com".to_string());
+
+        let tenant = |headers: &[(&str, &str)]| {
+            let (mut parts, state) = (parts_with_headers(headers), state.clone());
+            async move { RequestTenant::from_request_parts(&mut parts, &state).await.unwrap().0 }
+        };
+
+        assert_eq!(tenant(&[("X-Tenant-ID", " acme ")]).await.as_deref(), Some("acme"));
+        assert_eq!(tenant(&[("Host", "acme.example.com:3000")]).await.as_deref(), Some("acme"));
+        // The header takes precedence over the subdomain
+        assert_eq!(tenant(&[("Host", "acme.example.com"), ("X-Tenant-ID", "globex")]).await.as_deref(), Some("globex"));
+        assert_eq!(tenant(&[("Host", "example.com")]).await, None);
+        assert_eq!(tenant(&[("Host", "a.b.example.com")]).await, None);
+        assert_eq!(tenant(&[("Host", "acme.other.com")]).await, None);
+        assert_eq!(tenant(&[]).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_is_read_from_the_token() {
+        let state = state();
+        let (user_id, _) = stored_user_token(&state, false).await;
+        let keys = JwtKeys::hmac(SECRET);
+        let token = create_tenant_token(UserId(user_id), &[], Some("acme"), 0, &keys, &TokenConfig::default()).unwrap();
+        let mut parts = parts_with_token(&token);
+
+        let Tenant(tenant) = Tenant::from_request_parts(&mut parts, &state).await.unwrap();
+        assert_eq!(tenant, "acme");
+        assert_eq!(parts.extensions.get::<Tenant>(), Some(&Tenant("acme".to_string())));
+
+        // A token without tenant doesn't give access to tenant routes
+        let result = Tenant::from_request_parts(&mut parts_with_token(&create_token(USER_ID, SECRET)), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_extra_claims_are_exposed() {
+        let mut extra = Map::new();
+        extra.insert("plan".to_string(), Value::from("pro"));
+        let token = create_token_with_claims(USER_ID, &[], extra, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+
+        let user = AuthUser::from_request_parts(&mut parts_with_token(&token), &state()).await.unwrap();
+        assert_eq!(user.extra["plan"], "pro");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_is_rejected() {
+        let token = create_refresh_token(USER_ID, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap().unwrap();
+        let mut parts = parts_with_token(&token);
+
+        let result = AuthUser::from_request_parts(&mut parts, &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_revoked_token_is_rejected() {
+        let state = state();
+        let token = create_token(USER_ID, SECRET);
+
+        let user = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.unwrap();
+        state.token_blacklist.revoke(&user.jti).await.unwrap();
+
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_use_of_revoked_token_is_audited() {
+        use crate::audit::{AuditAction, AuditOutcome, InMemoryAuditSink};
+
+        let mut state = state();
+        let audit = InMemoryAuditSink::new();
+        state.audit = Arc::new(audit.clone());
+        let user_id = Uuid::new_v4();
+        let token = create_token(UserId(user_id), SECRET);
+
+        let user = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.unwrap();
+        assert!(audit.events().is_empty());
+        state.token_blacklist.revoke(&user.jti).await.unwrap();
+
+        assert!(AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_err());
+        let events = audit.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action, AuditAction::RevokedTokenUse);
+        assert_eq!(events[0].outcome, AuditOutcome::Failure);
+        assert_eq!(events[0].user_id, Some(user_id));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_of_revoked_session_are_rejected() {
+        let state = state();
+        let (user_id, login) = stored_user_token(&state, false).await;
+        let session = AuthUser::from_request_parts(&mut parts_with_token(&login), &state).await.unwrap().jti;
+        let refreshed = create_session_token(UserId(user_id), &[], None, &session, 0, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+
+        let user = AuthUser::from_request_parts(&mut parts_with_token(&refreshed), &state).await.unwrap();
+        assert_eq!(user.session_id(), session);
+
+        state.token_blacklist.revoke(&session).await.unwrap();
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&refreshed), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_client_info_reads_user_agent_and_forwarded_ip() {
+        let (mut parts, _) = Request::builder()
+            .header("User-Agent", "Firefox/128.0")
+            .header("X-Forwarded-For", "203.0.113.7, 10.0.0.1")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let mut state = state();
+        state.trusted_proxies = 1;
+        let client = ClientInfo::from_request_parts(&mut parts, &state).await.unwrap();
+        assert_eq!(client.user_agent.as_deref(), Some("Firefox/128.0"));
+        assert_eq!(client.ip.as_deref(), Some("10.0.0.1"));
+
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+        assert_eq!(ClientInfo::from_request_parts(&mut parts, &state).await.unwrap(), ClientInfo::default());
+    }
+
+    fn token_with_roles(roles: &[&str]) -> String {
+        let roles: Vec<String> = roles.iter().map(|r| r.to_string()).collect();
+        create_token_with_config(USER_ID, &roles, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap()
+    }
+
+    fn state_with_cookie() -> AppState {
+        let mut state = state();
+        state.auth_cookie = Some(CookieConfig { name: "session".to_string(), ..CookieConfig::default() });
+        state
+    }
+
+    #[tokio::test]
+    async fn test_token_is_read_from_cookie() {
+        let token = create_token(USER_ID, SECRET);
+        let (mut parts, _) = Request::builder()
+            .header("Cookie", format!("theme=dark; session={}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let user = AuthUser::from_request_parts(&mut parts, &state_with_cookie()).await.unwrap();
+        assert_eq!(user.user_id, USER_ID);
+
+        // The cookie is ignored when cookies are disabled
+        let result = AuthUser::from_request_parts(&mut parts, &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_header_takes_precedence_over_cookie() {
+        let header_user = UserId(Uuid::new_v4());
+        let header_token = create_token(header_user, SECRET);
+        let cookie_token = create_token(UserId(Uuid::new_v4()), SECRET);
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", header_token))
+            .header("Cookie", format!("session={}", cookie_token))
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let user = AuthUser::from_request_parts(&mut parts, &state_with_cookie()).await.unwrap();
+        assert_eq!(user.user_id, header_user);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_header_does_not_fall_back_to_cookie() {
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", axum::http::HeaderValue::from_bytes(b"Bearer \xff").unwrap())
+            .header("Cookie", format!("session={}", create_token(USER_ID, SECRET)))
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let result = AuthUser::from_request_parts(&mut parts, &state_with_cookie()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_account_is_rejected_when_checked() {
+        use crate::models::user::CreateUser;
+
+        let mut state = state();
+        let user = state.user_repo.create(CreateUser {
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }, "hash".to_string()).await.unwrap();
+        let token = create_token(UserId(user.id), SECRET);
+        state.user_repo.set_active(user.id, false).await.unwrap();
+
+        // Without the check, the token stays valid until it expires
+        assert!(AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_ok());
+
+        state.check_active_on_request = true;
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+
+        state.user_repo.set_active(user.id, true).await.unwrap();
+        assert!(AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_ok());
+    }
+
+    // Token of a stored user, verified or not
+    async fn stored_user_token(state: &AppState, email_verified: bool) -> (Uuid, String) {
+        use crate::models::user::CreateUser;
+
+        let user = state.user_repo.create(CreateUser {
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }, "hash".to_string()).await.unwrap();
+        if email_verified {
+            state.user_repo.mark_email_verified(user.id).await.unwrap();
+        }
+        (user.id, create_token(UserId(user.id), SECRET))
+    }
+
+    #[tokio::test]
+    async fn test_token_of_an_older_version_is_rejected() {
+        let state = state();
+        let (user_id, _) = stored_user_token(&state, false).await;
+        let token = create_session_token(UserId(user_id), &[], None, "session-1", 0, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+        assert!(AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_ok());
+
+        state.user_repo.increment_token_version(user_id).await.unwrap();
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+
+        let token = create_session_token(UserId(user_id), &[], None, "session-1", 1, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+        assert!(AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_versioned_token_of_deleted_user_is_not_found() {
+        let state = state();
+        let (user_id, _) = stored_user_token(&state, false).await;
+        let token = create_session_token(UserId(user_id), &[], None, "session-1", 0, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+        state.user_repo.delete(user_id).await.unwrap();
+
+        // Same error from both extractors, and anonymous for the optional one
+        let result = AuthUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+        let result = CurrentUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+        let MaybeAuthUser(user) = MaybeAuthUser::from_request_parts(&mut parts_with_token(&token), &state).await.unwrap();
+        assert!(user.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_current_user_reuses_the_user_loaded_by_auth_user() {
+        let state = state();
+        let (user_id, _) = stored_user_token(&state, false).await;
+        let token = create_session_token(UserId(user_id), &[], None, "session-1", 0, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+        let mut parts = parts_with_token(&token);
+
+        AuthUser::from_request_parts(&mut parts, &state).await.unwrap();
+        let Some(LoadedUser(loaded)) = parts.extensions.get::<LoadedUser>() else { panic!("the user should be kept") };
+        assert_eq!(loaded.id, user_id);
+
+        let current = CurrentUser::from_request_parts(&mut parts, &state).await.unwrap();
+        assert_eq!(current.user.id, user_id);
+    }
+
+    #[tokio::test]
+    async fn test_current_user_of_deactivated_account_is_rejected() {
+        let state = state();
+        let (user_id, token) = stored_user_token(&state, false).await;
+        assert!(CurrentUser::from_request_parts(&mut parts_with_token(&token), &state).await.is_ok());
+
+        state.user_repo.set_active(user_id, false).await.unwrap();
+        let result = CurrentUser::from_request_parts(&mut parts_with_token(&token), &state).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_verified_user_passes() {
+        let state = state();
+        let (user_id, token) = stored_user_token(&state, true).await;
+
+        let verified = VerifiedUser::from_request_parts(&mut parts_with_token(&token), &state).await.unwrap();
+        assert_eq!(verified.user.id, user_id);
+        assert!(verified.user.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_unverified_user_is_rejected() {
+        use axum::response::IntoResponse;
+
+        let state = state();
+        let (_, token) = stored_user_token(&state, false).await;
+
+        let error = VerifiedUser::from_request_parts(&mut parts_with_token(&token), &state).await.err().unwrap();
+        assert!(matches!(error, AuthError::EmailNotVerified));
+        assert_eq!(error.into_response().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_require_role_allows_user_with_role() {
+        let mut parts = parts_with_token(&token_with_roles(&["admin"]));
+
+        let RequireRole { user, .. } = RequireRole::<AdminRole>::from_request_parts(&mut parts, &state()).await.unwrap();
+        assert!(user.has_role("admin"));
+    }
+
+    #[tokio::test]
+    async fn test_require_role_forbids_user_without_role() {
+        let mut parts = parts_with_token(&token_with_roles(&["editor"]));
+
+        let result = RequireRole::<AdminRole>::from_request_parts(&mut parts, &state()).await;
+        assert!(matches!(result, Err(AuthError::MissingRole(role)) if role == "admin"));
+    }
+
+    struct Staff;
+
+    impl Roles for Staff {
+        const NAMES: &'static [&'static str] = &["admin", "support"];
+    }
+
+    #[tokio::test]
+    async fn test_require_any_role_passes_with_one_matching_role() {
+        let mut parts = parts_with_token(&token_with_roles(&["support"]));
+        assert!(RequireAnyRole::<Staff>::from_request_parts(&mut parts, &state()).await.is_ok());
+
+        let mut parts = parts_with_token(&token_with_roles(&["editor"]));
+        let result = RequireAnyRole::<Staff>::from_request_parts(&mut parts, &state()).await;
+        assert!(matches!(result, Err(AuthError::MissingRole(roles)) if roles == "admin or support"));
+    }
+
+    #[tokio::test]
+    async fn test_require_all_roles_fails_when_one_is_missing() {
+        let mut parts = parts_with_token(&token_with_roles(&["admin"]));
+        let error = RequireAllRoles::<Staff>::from_request_parts(&mut parts, &state()).await.err().unwrap();
+        assert!(matches!(error, AuthError::MissingRole(_)));
+        assert!(error.to_string().contains("support"));
+
+        let mut parts = parts_with_token(&token_with_roles(&["support", "admin"]));
+        assert!(RequireAllRoles::<Staff>::from_request_parts(&mut parts, &state()).await.is_ok());
+    }
+
+    // Handler that greets logged-in users by id and everyone else anonymously
+    async fn greet(MaybeAuthUser(user): MaybeAuthUser) -> String {
+        match user {
+            Some(user) => format!("Hello {}", user.user_id),
+            None => "Hello anonymous".to_string(),
+        }
+    }
+
+    async fn greet_request(mut parts: Parts) -> String {
+        let user = MaybeAuthUser::from_request_parts(&mut parts, &state()).await.unwrap();
+        greet(user).await
+    }
+
+    #[tokio::test]
+    async fn test_maybe_auth_user_with_valid_token() {
+        let parts = parts_with_token(&create_token(USER_ID, SECRET));
+        assert_eq!(greet_request(parts).await, format!("Hello {USER_ID}"));
+    }
+
+    #[tokio::test]
+    async fn test_maybe_auth_user_without_header() {
+        let (parts, _) = Request::builder().body(()).unwrap().into_parts();
+        assert_eq!(greet_request(parts).await, "Hello anonymous");
+    }
+
+    #[tokio::test]
+    async fn test_maybe_auth_user_with_malformed_header() {
+        let (parts, _) = Request::builder()
+            .header("Authorization", "Basic dXNlcjpwYXNz")
+            .body(())
+            .unwrap()
+            .into_parts();
+        assert_eq!(greet_request(parts).await, "Hello anonymous");
+
+        assert_eq!(greet_request(parts_with_token("not-a-jwt")).await, "Hello anonymous");
+    }
+
+    #[tokio::test]
+    async fn test_require_role_rejects_missing_token() {
+        let (mut parts, _) = Request::builder().body(()).unwrap().into_parts();
+
+        let result = RequireRole::<AdminRole>::from_request_parts(&mut parts, &state()).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+}
@@ -1,54 +1,773 @@
+use std::sync::Arc;
 use serde::{Serialize, Deserialize};
-use chrono::{Utc, Duration};
+use serde_json::{Map, Value};
+use chrono::{DateTime, Utc, Duration};
+use uuid::Uuid;
+use thiserror::Error;
 use jsonwebtoken::{
     encode,
     decode,
+    errors::{Error, ErrorKind},
+    Algorithm,
     Header,
     Validation,
     EncodingKey,
     DecodingKey
 };
+use crate::clock::{Clock, SystemClock};
+use crate::models::user::UserId;
 
 // Data stored in JWT token
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    pub sub: String,    // User Id
+    pub sub: UserId,    // User Id (a token with another `sub` doesn't decode)
     pub exp: usize,       // Expiration time
     pub iat: usize,       // Issued at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<usize>,   // Not valid before (scheduled access, see `create_scheduled_token`)
+    pub token_type: TokenType,  // Access or refresh
+    pub jti: String,      // Unique token id (used for revocation)
+    pub iss: String,      // Issuer (service that created the token)
+    pub aud: String,      // Audience (service the token is meant for)
+    #[serde(default)]
+    pub roles: Vec<String>,   // User roles (used for authorization)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,  // Tenant of the user (multi-tenant setups, see `Tenant`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_version: Option<i64>,  // `User::token_version` when issued, the token dies once it changes
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,  // Custom claims of the consumer (plan, ...)
+}
+
+/// Claim of the tokens refreshed from a session, holding the id of the session
+/// (the `jti` of the access token issued at login, see `Claims::session_id`)
+pub const SESSION_CLAIM: &str = "sid";
+
+impl Claims {
+    /// Id of the session (login) the token belongs to
+    ///
+    /// Tokens issued at login are the session, their id is their own `jti`;
+    /// tokens from `POST /refresh` carry it in `SESSION_CLAIM`
+    pub fn session_id(&self) -> &str {
+        self.extra.get(SESSION_CLAIM).and_then(Value::as_str).unwrap_or(&self.jti)
+    }
+}
+
+/// Names of the claims set by this crate, they can't be overridden by extra claims
+pub const RESERVED_CLAIMS: &[&str] = &["sub", "exp", "iat", "nbf", "token_type", "jti", "iss", "aud", "roles", "tenant_id", "token_version"];
+
+/// Kind (purpose) of token, stored in the `token_type` claim
+///
+/// A token is only accepted where its purpose is expected: a refresh token
+/// can only be exchanged for a new access token, a reset token can only
+/// reset a password, a verify token can only confirm an email address,
+/// and none of them is accepted on protected routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
+    Reset,
+    Verify,
+}
+
+/// Algorithm used to sign the tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    /// HMAC-SHA256 with a shared secret (default)
+    HS256,
+    /// RSA-SHA256 with a private/public key pair
+    RS256,
+}
+
+impl From<JwtAlgorithm> for Algorithm {
+    fn from(alg: JwtAlgorithm) -> Self {
+        match alg {
+            JwtAlgorithm::HS256 => Algorithm::HS256,
+            JwtAlgorithm::RS256 => Algorithm::RS256,
+        }
+    }
+}
+
+/// Keys used to sign and verify tokens
+///
+/// - HS256: the same secret signs and verifies
+/// - RS256: the private key signs, the public key verifies.
+///   A service that only verifies tokens can be built with `JwtKeys::rsa_public_pem`
+#[derive(Clone)]
+pub struct JwtKeys {
+    algorithm: JwtAlgorithm,
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    /// Keys for HS256 from a shared secret
+    pub fn hmac(secret: &str) -> Self {
+        Self {
+            algorithm: JwtAlgorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(secret.as_bytes())),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+        }
+    }
+
+    /// Keys for RS256 from a PEM encoded private and public key
+    pub fn rsa_pem(private_pem: &[u8], public_pem: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            algorithm: JwtAlgorithm::RS256,
+            encoding: Some(EncodingKey::from_rsa_pem(private_pem)?),
+            decoding: DecodingKey::from_rsa_pem(public_pem)?,
+        })
+    }
+
+    /// Verify-only keys for RS256 from a PEM encoded public key
+    ///
+    /// Creating a token with these keys returns an error
+    pub fn rsa_public_pem(public_pem: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            algorithm: JwtAlgorithm::RS256,
+            encoding: None,
+            decoding: DecodingKey::from_rsa_pem(public_pem)?,
+        })
+    }
+
+    /// Algorithm these keys sign and verify with
+    pub fn algorithm(&self) -> JwtAlgorithm {
+        self.algorithm
+    }
+}
+
+/// Minimum size of an HS256 secret in bytes (256 bits, the size of the HMAC-SHA256 key)
+pub const MIN_SECRET_BYTES: usize = 32;
+
+/// Error returned for a secret that would make HS256 tokens forgeable
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SecretError {
+    #[error("JWT secret must be at least {MIN_SECRET_BYTES} bytes long (got {0}), generate a stronger one with: openssl rand -base64 32")]
+    TooShort(usize),
+}
+
+/// Rejects HS256 secrets shorter than `MIN_SECRET_BYTES`
+pub fn validate_secret(secret: &str) -> Result<(), SecretError> {
+    if secret.len() < MIN_SECRET_BYTES {
+        return Err(SecretError::TooShort(secret.len()));
+    }
+
+    Ok(())
+}
+
+/// Heuristic for secrets that are long enough but easy to guess
+/// ("aaaa...", "passwordpassword..."): fewer than 10 distinct bytes
+///
+/// Only meant for a startup warning, a random secret never trips it
+pub fn is_low_entropy_secret(secret: &str) -> bool {
+    let distinct: std::collections::HashSet<u8> = secret.bytes().collect();
+    distinct.len() < 10
+}
+
+/// Lifetime of each kind of token, read by the helper creating it
+///
+/// Kept together so the lifetimes are set in one place (`TOKEN_EXPIRY_SECONDS`,
+/// `REFRESH_TOKEN_EXPIRY_SECONDS`, ...) and no call site picks the wrong one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenExpiries {
+    /// Access tokens
+    pub access: Duration,
+    /// Refresh tokens, `None` disables them
+    pub refresh: Option<Duration>,
+    /// Password reset tokens, keep it short: the token is enough to take over the account
+    pub reset: Duration,
+    /// Email verification and email change tokens
+    pub verify: Duration,
+}
+
+impl Default for TokenExpiries {
+    fn default() -> Self {
+        Self {
+            access: Duration::hours(24),
+            refresh: Some(Duration::days(30)),
+            reset: Duration::minutes(15),
+            verify: Duration::hours(24),
+        }
+    }
+}
+
+/// Settings used when issuing and validating tokens
+///
+/// Stored in `AppState` so every handler issues tokens with the same lifetime.
+#[derive(Debug, Clone)]
+pub struct TokenConfig {
+    /// Written to the `iss` claim, and required when validating
+    pub issuer: String,
+
+    /// Written to the `aud` claim, and required when validating
+    /// Services sharing a secret must use different audiences,
+    /// so a token minted for one is rejected by the other
+    pub audience: String,
+
+    /// How long each kind of token stays valid after being issued
+    pub expiries: TokenExpiries,
+
+    /// Clock skew tolerated when checking `exp`, for servers whose clocks drift
+    /// Zero by default, so the configured expiry is honored to the second
+    pub leeway: Duration,
+
+    /// Time used for `iat` / `exp` when issuing, and for the expiry checks when validating
+    /// `SystemClock` by default, a `MockClock` in tests
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "auth-system".to_string(),
+            audience: "auth-system".to_string(),
+            expiries: TokenExpiries::default(),
+            leeway: Duration::zero(),
+            clock: Arc::new(SystemClock),
+        }
+    }
 }
 
 /// Creates a new JWT token for user
-pub fn create_token(user_id: &str, secret: &str) -> String {
-    let now = Utc::now();
-    // Validates token for 24 hours  
-    let expire = now + Duration::hours(24);
+///
+/// Convenience wrapper that uses HS256 and the default `TokenConfig` (24 hours)
+pub fn create_token(user_id: UserId, secret: &str) -> String {
+    create_token_with_config(user_id, &[], &JwtKeys::hmac(secret), &TokenConfig::default())
+        .expect("Error generating token")
+}
+
+/// Creates a new JWT access token for user, valid for `config.expiries.access`
+/// The user's roles are embedded so protected routes can authorize without a lookup
+pub fn create_token_with_config(user_id: UserId, roles: &[String], keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    create_token_with_claims(user_id, roles, Map::new(), keys, config)
+}
+
+/// Same as `create_token_with_config`, for a user of the tenant `tenant_id` (in the `tenant_id` claim)
+///
+/// `token_version` is the current `User::token_version`: once it is bumped (password change,
+/// logout everywhere) the token is rejected by `AuthUser`
+pub fn create_tenant_token(user_id: UserId, roles: &[String], tenant_id: Option<&str>, token_version: i64, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    let mut claims = new_claims(user_id, roles, Map::new(), config, TokenType::Access, config.expiries.access);
+    claims.tenant_id = tenant_id.map(str::to_string);
+    claims.token_version = Some(token_version);
+    sign(&claims, keys)
+}
+
+/// Same as `create_token_with_config`, only valid from `not_before` on (`nbf` claim)
+///
+/// Before that time it is rejected like an invalid token (the leeway of `config` applies).
+/// The expiry still counts from now, so a `not_before` past `config.expiries.access` never works
+pub fn create_scheduled_token(user_id: UserId, roles: &[String], not_before: DateTime<Utc>, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    let mut claims = new_claims(user_id, roles, Map::new(), config, TokenType::Access, config.expiries.access);
+    claims.nbf = Some(not_before.timestamp().max(0) as usize);
+    sign(&claims, keys)
+}
+
+/// Same as `create_token_with_config`, with custom claims merged into the token
+///
+/// The extra claims are exposed by `AuthUser::extra` on protected routes.
+/// Keys of `RESERVED_CLAIMS` are ignored, so a tenant can't forge `sub` or `roles`
+pub fn create_token_with_claims(user_id: UserId, roles: &[String], extra: Map<String, Value>, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    sign_token(user_id, roles, extra, keys, config, TokenType::Access, config.expiries.access)
+}
+
+/// Creates a long-lived refresh token, valid for `config.expiries.refresh`
+/// (`None` when refresh tokens are disabled)
+///
+/// It can only be used at `POST /refresh` to mint a new access token.
+/// Roles are not embedded, they are read again from the user when refreshing
+pub fn create_refresh_token(user_id: UserId, keys: &JwtKeys, config: &TokenConfig) -> Result<Option<String>, Error> {
+    config.expiries.refresh
+        .map(|expiry| sign_token(user_id, &[], Map::new(), keys, config, TokenType::Refresh, expiry))
+        .transpose()
+}
+
+/// Creates a refresh token of the session `session_id`, like `create_refresh_token`
+///
+/// The access tokens it is exchanged for belong to the same session,
+/// so revoking the session revokes them too.
+/// Like the access tokens, it can't be used anymore once `token_version` is bumped
+pub fn create_session_refresh_token(user_id: UserId, session_id: &str, token_version: i64, keys: &JwtKeys, config: &TokenConfig) -> Result<Option<String>, Error> {
+    config.expiries.refresh
+        .map(|expiry| {
+            let mut claims = new_claims(user_id, &[], session_claim(session_id), config, TokenType::Refresh, expiry);
+            claims.token_version = Some(token_version);
+            sign(&claims, keys)
+        })
+        .transpose()
+}
+
+/// Creates an access token of the session `session_id`, valid for `config.expiries.access` (used on refresh)
+pub fn create_session_token(user_id: UserId, roles: &[String], tenant_id: Option<&str>, session_id: &str, token_version: i64, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    let mut claims = new_claims(user_id, roles, session_claim(session_id), config, TokenType::Access, config.expiries.access);
+    claims.tenant_id = tenant_id.map(str::to_string);
+    claims.token_version = Some(token_version);
+    sign(&claims, keys)
+}
+
+fn session_claim(session_id: &str) -> Map<String, Value> {
+    let mut extra = Map::new();
+    extra.insert(SESSION_CLAIM.to_string(), Value::from(session_id));
+    extra
+}
+
+/// Creates a short-lived password reset token, valid for `config.expiries.reset`
+///
+/// It can only be used at `POST /reset-password`, and only once
+pub fn create_reset_token(user_id: UserId, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    sign_token(user_id, &[], Map::new(), keys, config, TokenType::Reset, config.expiries.reset)
+}
 
-    let claims = Claims {
-        sub: user_id.to_string(),
+/// Claim of an email change token holding the new address
+pub const NEW_EMAIL_CLAIM: &str = "new_email";
+
+/// Creates a token confirming the change of the user's email to `new_email`,
+/// valid for `config.expiries.verify`
+///
+/// It is a verify token (used at `POST /verify-email`) carrying the new address,
+/// so it only confirms the change it was issued for
+pub fn create_email_change_token(user_id: UserId, new_email: &str, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    let mut extra = Map::new();
+    extra.insert(NEW_EMAIL_CLAIM.to_string(), Value::from(new_email));
+    sign_token(user_id, &[], extra, keys, config, TokenType::Verify, config.expiries.verify)
+}
+
+/// Creates an email verification token, valid for `config.expiries.verify`
+///
+/// It can only be used at `POST /verify-email`
+pub fn create_verification_token(user_id: UserId, keys: &JwtKeys, config: &TokenConfig) -> Result<String, Error> {
+    sign_token(user_id, &[], Map::new(), keys, config, TokenType::Verify, config.expiries.verify)
+}
+
+fn sign_token(user_id: UserId, roles: &[String], extra: Map<String, Value>, keys: &JwtKeys, config: &TokenConfig, token_type: TokenType, expiry: Duration) -> Result<String, Error> {
+    sign(&new_claims(user_id, roles, extra, config, token_type, expiry), keys)
+}
+
+fn new_claims(user_id: UserId, roles: &[String], mut extra: Map<String, Value>, config: &TokenConfig, token_type: TokenType, expiry: Duration) -> Claims {
+    let now = config.clock.now();
+    let expire = now + expiry;
+
+    // Flattened next to the registered claims, a duplicate key would make the token ambiguous
+    extra.retain(|name, _| !RESERVED_CLAIMS.contains(&name.as_str()));
+
+    Claims {
+        sub: user_id,
         exp: expire.timestamp() as usize,
         iat: now.timestamp() as usize,
-    };
+        nbf: None,
+        token_type,
+        jti: Uuid::new_v4().to_string(),
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
+        roles: roles.to_vec(),
+        tenant_id: None,
+        token_version: None,
+        extra,
+    }
+}
+
+fn sign(claims: &Claims, keys: &JwtKeys) -> Result<String, Error> {
+    // Verify-only keys can't sign
+    let encoding_key = keys.encoding.as_ref().ok_or(ErrorKind::InvalidKeyFormat)?;
 
     // Encode and sign the token
     encode(
-        &Header::default(), 
-        &claims, 
-        &EncodingKey::from_secret(secret.as_ref()),
-    ).expect("Error generating token")
+        &Header::new(keys.algorithm.into()),
+        claims,
+        encoding_key,
+    )
 }
 
 /// Validate and decode the JWT token
 /// Args:
 ///     token - Token JWT beeing validated
 ///     secret - Secret used for verifying
-/// 
+///
 /// Returns: Claims if the Token is valid, Error otherwise
-pub fn validate_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+///
+/// Expects the issuer and audience of the default `TokenConfig`
+pub fn validate_token(token: &str, secret: &str) -> Result<Claims, Error> {
+    validate_token_with_keys(token, &JwtKeys::hmac(secret), &TokenConfig::default())
+}
+
+/// Validate and decode the JWT token using the algorithm of `keys`
+/// The `iss` and `aud` claims must match `config`, `exp` and `nbf` are checked against `config.clock`
+pub fn validate_token_with_keys(token: &str, keys: &JwtKeys, config: &TokenConfig) -> Result<Claims, Error> {
     let token_data = decode::<Claims>(
         token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default()
+        &keys.decoding,
+        &validation(keys.algorithm, config),
     )?;
 
+    check_time(&token_data.claims, config)?;
     Ok(token_data.claims)
-}
\ No newline at end of file
+}
+
+// Same rules as jsonwebtoken's own checks, with the time of `config.clock`
+// Valid up to `exp` included, from `nbf` included, both widened by the leeway
+fn check_time(claims: &Claims, config: &TokenConfig) -> Result<(), Error> {
+    let now = config.clock.now().timestamp();
+    let leeway = config.leeway.num_seconds().max(0);
+
+    if (claims.exp as i64) < now - leeway {
+        return Err(ErrorKind::ExpiredSignature.into());
+    }
+    if claims.nbf.is_some_and(|nbf| nbf as i64 > now + leeway) {
+        return Err(ErrorKind::ImmatureSignature.into());
+    }
+    Ok(())
+}
+
+/// Validate the JWT token and check that it is of the `expected` type
+///
+/// Returns: Claims if the Token is valid and has the right type, Error otherwise
+pub fn validate_token_type(token: &str, keys: &JwtKeys, config: &TokenConfig, expected: TokenType) -> Result<Claims, Error> {
+    let claims = validate_token_with_keys(token, keys, config)?;
+
+    if claims.token_type != expected {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+
+    Ok(claims)
+}
+
+// Validation rules shared by every token check (handlers and extractors)
+fn validation(algorithm: JwtAlgorithm, config: &TokenConfig) -> Validation {
+    let mut validation = Validation::new(algorithm.into());
+    // `exp` and `nbf` are checked by `check_time`, jsonwebtoken only reads the system clock
+    validation.validate_exp = false;
+    validation.validate_nbf = false;
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    validation
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::clock::MockClock;
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+    const USER_ID: UserId = UserId(Uuid::from_u128(1));
+    const RSA_PRIVATE: &[u8] = include_bytes!("../../tests/fixtures/rsa_private.pem");
+    const RSA_PUBLIC: &[u8] = include_bytes!("../../tests/fixtures/rsa_public.pem");
+
+    #[test]
+    fn test_token_roundtrip() {
+        let token = create_token(USER_ID, SECRET);
+        let claims = validate_token(&token, SECRET).unwrap();
+        assert_eq!(claims.sub, USER_ID);
+    }
+
+    #[test]
+    fn test_sub_is_the_uuid_string() {
+        let claims = validate_token(&create_token(USER_ID, SECRET), SECRET).unwrap();
+
+        let json = serde_json::to_value(&claims).unwrap();
+        assert_eq!(json["sub"], "00000000-0000-0000-0000-000000000001");
+        assert_eq!(serde_json::from_value::<Claims>(json).unwrap().sub, USER_ID);
+    }
+
+    #[test]
+    fn test_token_with_non_uuid_sub_is_rejected() {
+        let now = Utc::now().timestamp();
+        let claims = serde_json::json!({
+            "sub": "user-1", "exp": now + 60, "iat": now, "token_type": "access",
+            "jti": "token-1", "iss": "auth-system", "aud": "auth-system",
+        });
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET.as_bytes())).unwrap();
+
+        assert!(validate_token(&token, SECRET).is_err());
+        assert!("user-1".parse::<UserId>().is_err());
+        assert_eq!(USER_ID.to_string().parse::<UserId>().unwrap(), USER_ID);
+    }
+
+    // Token settings with an access token lifetime of `access`
+    fn access_expiry(access: Duration) -> TokenConfig {
+        TokenConfig { expiries: TokenExpiries { access, ..TokenExpiries::default() }, ..TokenConfig::default() }
+    }
+
+    // Lifetime stamped on the token (`exp - iat`), in seconds
+    fn lifetime(token: &str, keys: &JwtKeys, config: &TokenConfig, token_type: TokenType) -> usize {
+        let claims = validate_token_type(token, keys, config, token_type).unwrap();
+        claims.exp - claims.iat
+    }
+
+    #[test]
+    fn test_configured_expiry_is_used() {
+        let config = access_expiry(Duration::minutes(5));
+        let token = create_token_with_config(USER_ID, &[], &JwtKeys::hmac(SECRET), &config).unwrap();
+        let claims = validate_token(&token, SECRET).unwrap();
+        assert_eq!(claims.exp - claims.iat, 300);
+    }
+
+    #[test]
+    fn test_each_helper_stamps_its_configured_expiry() {
+        let keys = JwtKeys::hmac(SECRET);
+        let config = TokenConfig {
+            expiries: TokenExpiries {
+                access: Duration::minutes(10),
+                refresh: Some(Duration::days(7)),
+                reset: Duration::minutes(5),
+                verify: Duration::hours(2),
+            },
+            ..TokenConfig::default()
+        };
+
+        let access = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+        assert_eq!(lifetime(&access, &keys, &config, TokenType::Access), 600);
+        let session = create_session_token(USER_ID, &[], None, "session-1", 0, &keys, &config).unwrap();
+        assert_eq!(lifetime(&session, &keys, &config, TokenType::Access), 600);
+
+        let refresh = create_refresh_token(USER_ID, &keys, &config).unwrap().unwrap();
+        assert_eq!(lifetime(&refresh, &keys, &config, TokenType::Refresh), 7 * 86400);
+        let session_refresh = create_session_refresh_token(USER_ID, "session-1", 0, &keys, &config).unwrap().unwrap();
+        assert_eq!(lifetime(&session_refresh, &keys, &config, TokenType::Refresh), 7 * 86400);
+
+        let reset = create_reset_token(USER_ID, &keys, &config).unwrap();
+        assert_eq!(lifetime(&reset, &keys, &config, TokenType::Reset), 300);
+
+        let verify = create_verification_token(USER_ID, &keys, &config).unwrap();
+        assert_eq!(lifetime(&verify, &keys, &config, TokenType::Verify), 7200);
+        let email_change = create_email_change_token(USER_ID, "new@example.com", &keys, &config).unwrap();
+        assert_eq!(lifetime(&email_change, &keys, &config, TokenType::Verify), 7200);
+    }
+
+    #[test]
+    fn test_no_refresh_token_when_disabled() {
+        let config = TokenConfig { expiries: TokenExpiries { refresh: None, ..TokenExpiries::default() }, ..TokenConfig::default() };
+        assert_eq!(create_refresh_token(USER_ID, &JwtKeys::hmac(SECRET), &config).unwrap(), None);
+    }
+
+    // Token settings reading the time of a mock clock, starting on 2030-01-01
+    fn mock_clock_config() -> (MockClock, TokenConfig) {
+        let clock = MockClock::new(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+        let config = TokenConfig { clock: Arc::new(clock.clone()), ..access_expiry(Duration::seconds(60)) };
+        (clock, config)
+    }
+
+    #[test]
+    fn test_mock_clock_sets_iat_and_exp() {
+        let keys = JwtKeys::hmac(SECRET);
+        let (clock, config) = mock_clock_config();
+        let token = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+
+        let claims = validate_token_with_keys(&token, &keys, &config).unwrap();
+        let issued_at = clock.now().timestamp() as usize;
+        assert_eq!(claims.iat, issued_at);
+        assert_eq!(claims.exp, issued_at + 60);
+    }
+
+    #[test]
+    fn test_token_expires_after_configured_expiry() {
+        let keys = JwtKeys::hmac(SECRET);
+        let (clock, config) = mock_clock_config();
+        let token = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+
+        // Still valid at the second of `exp`, expired the second after
+        clock.advance(Duration::seconds(60));
+        assert!(validate_token_with_keys(&token, &keys, &config).is_ok());
+        clock.advance(Duration::seconds(1));
+        let result = validate_token_with_keys(&token, &keys, &config);
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::ExpiredSignature);
+
+        // A token issued by the system clock, long before 2030
+        let token = create_token(USER_ID, SECRET);
+        assert!(validate_token_with_keys(&token, &keys, &config).is_err());
+    }
+
+    #[test]
+    fn test_leeway_uses_the_clock() {
+        let keys = JwtKeys::hmac(SECRET);
+        let (clock, config) = mock_clock_config();
+        let tolerant = TokenConfig { leeway: Duration::seconds(5), ..config.clone() };
+        let token = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+
+        clock.advance(Duration::seconds(65));
+        assert!(validate_token_with_keys(&token, &keys, &tolerant).is_ok());
+        assert!(validate_token_with_keys(&token, &keys, &config).is_err());
+        clock.advance(Duration::seconds(1));
+        assert!(validate_token_with_keys(&token, &keys, &tolerant).is_err());
+    }
+
+    #[test]
+    fn test_leeway_accepts_recently_expired_token() {
+        let keys = JwtKeys::hmac(SECRET);
+        let expired = access_expiry(Duration::seconds(-2));
+        let token = create_token_with_config(USER_ID, &[], &keys, &expired).unwrap();
+
+        let tolerant = TokenConfig { leeway: Duration::seconds(5), ..TokenConfig::default() };
+        assert!(validate_token_with_keys(&token, &keys, &tolerant).is_ok());
+        assert!(validate_token_with_keys(&token, &keys, &TokenConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_token_is_rejected_before_nbf() {
+        let keys = JwtKeys::hmac(SECRET);
+        let (clock, config) = mock_clock_config();
+        let token = create_scheduled_token(USER_ID, &[], clock.now() + Duration::seconds(10), &keys, &config).unwrap();
+
+        let result = validate_token_with_keys(&token, &keys, &config);
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::ImmatureSignature);
+        // The leeway tolerates clocks running behind
+        let tolerant = TokenConfig { leeway: Duration::seconds(10), ..config.clone() };
+        assert!(validate_token_with_keys(&token, &keys, &tolerant).is_ok());
+
+        clock.advance(Duration::seconds(10));
+        let claims = validate_token_with_keys(&token, &keys, &config).unwrap();
+        assert!(claims.nbf.is_some());
+    }
+
+    #[test]
+    fn test_extra_claims_roundtrip() {
+        let keys = JwtKeys::hmac(SECRET);
+        let mut extra = Map::new();
+        extra.insert("plan".to_string(), Value::from("pro"));
+        extra.insert("sub".to_string(), Value::from("someone-else"));
+        extra.insert("tenant_id".to_string(), Value::from("acme"));
+        extra.insert("token_version".to_string(), Value::from(7));
+
+        let token = create_token_with_claims(USER_ID, &[], extra, &keys, &TokenConfig::default()).unwrap();
+        let claims = validate_token_with_keys(&token, &keys, &TokenConfig::default()).unwrap();
+        assert_eq!(claims.extra.get("plan"), Some(&Value::from("pro")));
+        // Reserved claims can't be overridden
+        assert_eq!(claims.sub, USER_ID);
+        assert!(!claims.extra.contains_key("sub"));
+        assert_eq!(claims.tenant_id, None);
+        assert_eq!(claims.token_version, None);
+    }
+
+    #[test]
+    fn test_tenant_token_carries_tenant() {
+        let keys = JwtKeys::hmac(SECRET);
+        let config = TokenConfig::default();
+
+        let token = create_tenant_token(USER_ID, &[], Some("acme"), 0, &keys, &config).unwrap();
+        let claims = validate_token_with_keys(&token, &keys, &config).unwrap();
+        assert_eq!(claims.tenant_id.as_deref(), Some("acme"));
+        assert!(!claims.extra.contains_key("tenant_id"));
+
+        // Tokens without a tenant don't have the claim at all
+        let token = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+        let claims = validate_token_with_keys(&token, &keys, &config).unwrap();
+        assert_eq!(claims.tenant_id, None);
+    }
+
+    #[test]
+    fn test_token_type_is_checked() {
+        let keys = JwtKeys::hmac(SECRET);
+        let access = create_token(USER_ID, SECRET);
+        let config = TokenConfig::default();
+        let refresh = create_refresh_token(USER_ID, &keys, &config).unwrap().unwrap();
+
+        assert!(validate_token_type(&access, &keys, &config, TokenType::Access).is_ok());
+        assert!(validate_token_type(&access, &keys, &config, TokenType::Refresh).is_err());
+        assert!(validate_token_type(&refresh, &keys, &config, TokenType::Refresh).is_ok());
+        assert!(validate_token_type(&refresh, &keys, &config, TokenType::Access).is_err());
+    }
+
+    #[test]
+    fn test_roles_are_embedded_in_access_token() {
+        let roles = vec!["admin".to_string()];
+        let token = create_token_with_config(USER_ID, &roles, &JwtKeys::hmac(SECRET), &TokenConfig::default()).unwrap();
+        let claims = validate_token(&token, SECRET).unwrap();
+        assert_eq!(claims.roles, roles);
+    }
+
+    #[test]
+    fn test_each_token_has_unique_jti() {
+        let first = validate_token(&create_token(USER_ID, SECRET), SECRET).unwrap();
+        let second = validate_token(&create_token(USER_ID, SECRET), SECRET).unwrap();
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[test]
+    fn test_refreshed_token_keeps_session_id() {
+        let keys = JwtKeys::hmac(SECRET);
+        let config = TokenConfig::default();
+        let login = validate_token(&create_token_with_config(USER_ID, &[], &keys, &config).unwrap(), SECRET).unwrap();
+        assert_eq!(login.session_id(), login.jti);
+
+        let refreshed = create_session_token(USER_ID, &[], None, &login.jti, 0, &keys, &config).unwrap();
+        let refreshed = validate_token(&refreshed, SECRET).unwrap();
+        assert_ne!(refreshed.jti, login.jti);
+        assert_eq!(refreshed.session_id(), login.jti);
+    }
+
+    #[test]
+    fn test_rs256_verifies_with_public_key_only() {
+        let signing_keys = JwtKeys::rsa_pem(RSA_PRIVATE, RSA_PUBLIC).unwrap();
+        let token = create_token_with_config(USER_ID, &[], &signing_keys, &TokenConfig::default()).unwrap();
+
+        let verify_keys = JwtKeys::rsa_public_pem(RSA_PUBLIC).unwrap();
+        let claims = validate_token_with_keys(&token, &verify_keys, &TokenConfig::default()).unwrap();
+        assert_eq!(claims.sub, USER_ID);
+
+        // Verify-only keys can't sign
+        assert!(create_token_with_config(USER_ID, &[], &verify_keys, &TokenConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_short_secret_is_rejected() {
+        assert_eq!(validate_secret("abc"), Err(SecretError::TooShort(3)));
+        assert!(validate_secret(&"k".repeat(MIN_SECRET_BYTES - 1)).is_err());
+    }
+
+    #[test]
+    fn test_32_byte_secret_is_accepted() {
+        assert!(validate_secret("0123456789abcdef0123456789abcdef").is_ok());
+        assert!(validate_secret(SECRET).is_ok());
+    }
+
+    #[test]
+    fn test_low_entropy_secret_is_detected() {
+        assert!(is_low_entropy_secret(&"a".repeat(64)));
+        assert!(is_low_entropy_secret(&"secret".repeat(8)));
+        assert!(!is_low_entropy_secret("q5Yx0n2d8VtL+W3rJm9kZ1fP7bC4sH6gA0eNuRoyTiM="));
+    }
+
+    #[test]
+    fn test_rs256_rejects_hs256_token() {
+        let hs_token = create_token(USER_ID, SECRET);
+        let verify_keys = JwtKeys::rsa_public_pem(RSA_PUBLIC).unwrap();
+        assert!(validate_token_with_keys(&hs_token, &verify_keys, &TokenConfig::default()).is_err());
+    }
+
+    fn config_for(audience: &str) -> TokenConfig {
+        TokenConfig { audience: audience.to_string(), ..TokenConfig::default() }
+    }
+
+    #[test]
+    fn test_matching_audience_is_accepted() {
+        let keys = JwtKeys::hmac(SECRET);
+        let config = config_for("billing");
+        let token = create_token_with_config(USER_ID, &[], &keys, &config).unwrap();
+
+        let claims = validate_token_with_keys(&token, &keys, &config).unwrap();
+        assert_eq!(claims.aud, "billing");
+        assert_eq!(claims.iss, "auth-system");
+    }
+
+    #[test]
+    fn test_wrong_audience_is_rejected() {
+        let keys = JwtKeys::hmac(SECRET);
+        let token = create_token_with_config(USER_ID, &[], &keys, &config_for("billing")).unwrap();
+
+        let result = validate_token_with_keys(&token, &keys, &config_for("reports"));
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::InvalidAudience);
+    }
+
+    #[test]
+    fn test_wrong_issuer_is_rejected() {
+        let keys = JwtKeys::hmac(SECRET);
+        let other_issuer = TokenConfig { issuer: "other-service".to_string(), ..TokenConfig::default() };
+        let token = create_token_with_config(USER_ID, &[], &keys, &other_issuer).unwrap();
+
+        let result = validate_token_with_keys(&token, &keys, &TokenConfig::default());
+        assert_eq!(result.unwrap_err().kind(), &ErrorKind::InvalidIssuer);
+    }
+}
@@ -1,3 +1,9 @@
 pub mod extractor;
 pub mod crypto;
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+pub mod cookie;
+pub mod api_key;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+#[cfg(feature = "webauthn")]
+pub mod webauthn;
@@ -0,0 +1,224 @@
+// This file is responsible for the login with an external OAuth2 / OpenID Connect provider (Google):
+// the authorization URL users are sent to, then the exchange of the code they come back with
+// for their identity at the provider (only compiled with the "oauth" feature)
+
+use std::fmt;
+use chrono::Duration;
+use oauth2::{
+    basic::BasicClient, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+};
+use serde::Deserialize;
+use tracing::warn;
+use crate::auth::cookie::{CookieConfig, SameSite};
+use crate::errors::AuthError;
+
+/// Name of the cookie holding the state and PKCE verifier between the redirect and the callback
+pub const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+/// How long users have to log in at the provider before the state cookie expires
+pub const OAUTH_STATE_EXPIRY_MINUTES: i64 = 10;
+
+/// Endpoints and credentials of an OAuth2 provider, see `OAuthProvider::google`
+///
+/// The endpoints can be pointed elsewhere (another provider, or a local mock in tests).
+/// The userinfo endpoint must answer with the OpenID Connect claims `sub`, `email` and `email_verified`.
+#[derive(Clone)]
+pub struct OAuthProvider {
+    /// Stored on the linked users (`User::oauth_provider`)
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    /// Our callback, as registered at the provider (`https://auth.example.com/auth/google/callback`)
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+}
+
+// The client secret never ends up in logs
+impl fmt::Debug for OAuthProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthProvider")
+            .field("name", &self.name)
+            .field("client_id", &self.client_id)
+            .field("auth_url", &self.auth_url)
+            .field("token_url", &self.token_url)
+            .field("userinfo_url", &self.userinfo_url)
+            .field("redirect_url", &self.redirect_url)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where to send the user, and what the callback must get back (kept in the state cookie)
+#[derive(Debug)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    /// CSRF token, returned by the provider as the `state` query parameter
+    pub state: String,
+    /// Secret half of the PKCE challenge sent in `url`
+    pub pkce_verifier: String,
+}
+
+/// The user as known by the provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    /// `OAuthProvider::name`
+    pub provider: String,
+    /// Id of the user at the provider, stable across email changes
+    pub subject: String,
+    pub email: String,
+    /// Whether the provider checked that the user owns `email`
+    pub email_verified: bool,
+}
+
+// Claims of the userinfo response that are used (the others, like `name` or `picture`, are ignored)
+#[derive(Deserialize)]
+struct UserInfo {
+    sub: String,
+    email: String,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+impl OAuthProvider {
+    /// Google, with the `openid email` scopes
+    pub fn google(client_id: impl Into<String>, client_secret: impl Into<String>, redirect_url: impl Into<String>) -> Self {
+        Self {
+            name: "google".to_string(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            auth_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo".to_string(),
+            redirect_url: redirect_url.into(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        }
+    }
+
+    /// Starts the authorization-code flow: a URL at the provider with a new CSRF state
+    /// and PKCE challenge
+    pub fn authorize(&self) -> Result<AuthorizationRequest, AuthError> {
+        let client = BasicClient::new(ClientId::new(self.client_id.clone()))
+            .set_auth_uri(AuthUrl::new(self.auth_url.clone()).map_err(|_| AuthError::InternalError)?)
+            .set_redirect_uri(RedirectUrl::new(self.redirect_url.clone()).map_err(|_| AuthError::InternalError)?);
+
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes.iter().cloned().map(Scope::new))
+            .set_pkce_challenge(challenge)
+            .url();
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state: state.secret().clone(),
+            pkce_verifier: verifier.secret().clone(),
+        })
+    }
+
+    /// Exchanges the code of the callback for an access token, then reads the user's identity
+    /// from the userinfo endpoint with it
+    ///
+    /// Returns OAuthProviderError when the provider refuses the code or can't be reached.
+    pub async fn exchange(&self, code: &str, pkce_verifier: &str) -> Result<ExternalIdentity, AuthError> {
+        let client = BasicClient::new(ClientId::new(self.client_id.clone()))
+            .set_client_secret(ClientSecret::new(self.client_secret.clone()))
+            .set_token_uri(TokenUrl::new(self.token_url.clone()).map_err(|_| AuthError::InternalError)?)
+            .set_redirect_uri(RedirectUrl::new(self.redirect_url.clone()).map_err(|_| AuthError::InternalError)?);
+
+        // Following redirects would expose the code to another host (see the oauth2 docs)
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|_| AuthError::InternalError)?;
+
+        let token = client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier.to_string()))
+            .request_async(&http)
+            .await
+            .map_err(|e| {
+                warn!(provider = %self.name, error = %e, "oauth code exchange failed");
+                AuthError::OAuthProviderError
+            })?;
+
+        let info: UserInfo = http
+            .get(&self.userinfo_url)
+            .bearer_auth(token.access_token().secret())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| {
+                warn!(provider = %self.name, error = %e, "oauth userinfo request failed");
+                AuthError::OAuthProviderError
+            })?
+            .json()
+            .await
+            .map_err(|_| AuthError::OAuthProviderError)?;
+
+        Ok(ExternalIdentity {
+            provider: self.name.clone(),
+            subject: info.sub,
+            email: info.email,
+            email_verified: info.email_verified,
+        })
+    }
+
+    /// Settings of the state cookie
+    ///
+    /// `Lax`, as the callback is a top-level navigation coming from the provider's site
+    /// (a `Strict` cookie wouldn't be sent). Only `Secure` when our callback is over HTTPS.
+    pub fn state_cookie(&self) -> CookieConfig {
+        CookieConfig {
+            name: OAUTH_STATE_COOKIE.to_string(),
+            secure: self.redirect_url.starts_with("https://"),
+            same_site: SameSite::Lax,
+            csrf: false,
+        }
+    }
+
+    /// Lifetime of the state cookie
+    pub fn state_expiry(&self) -> Duration {
+        Duration::minutes(OAUTH_STATE_EXPIRY_MINUTES)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> OAuthProvider {
+        OAuthProvider::google("client-id", "client-secret", "https://auth.example.com/auth/google/callback")
+    }
+
+    #[test]
+    fn test_authorize_url_carries_state_and_pkce_challenge() {
+        let request = provider().authorize().unwrap();
+        let url = reqwest::Url::parse(&request.url).unwrap();
+        let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+        assert_eq!(url.host_str(), Some("accounts.google.com"));
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["client_id"], "client-id");
+        assert_eq!(query["redirect_uri"], "https://auth.example.com/auth/google/callback");
+        assert_eq!(query["scope"], "openid email");
+        assert_eq!(query["state"], request.state);
+        assert_eq!(query["code_challenge_method"], "S256");
+        // Only the challenge is sent, the verifier stays in the cookie
+        assert!(!request.url.contains(&request.pkce_verifier));
+    }
+
+    #[test]
+    fn test_each_authorization_has_a_new_state() {
+        assert_ne!(provider().authorize().unwrap().state, provider().authorize().unwrap().state);
+    }
+
+    #[test]
+    fn test_debug_hides_client_secret() {
+        assert!(!format!("{:?}", provider()).contains("client-secret"));
+    }
+}
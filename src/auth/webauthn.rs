@@ -0,0 +1,122 @@
+// This file is responsible for the passkey (WebAuthn) ceremonies: the challenges sent to the
+// browser, then the verification of what the authenticator answered, both done by webauthn-rs
+// (only compiled with the "webauthn" feature)
+
+use std::sync::Arc;
+use chrono::Duration;
+use tracing::warn;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    AuthenticationResult, CreationChallengeResponse, CredentialID, Passkey, PasskeyAuthentication,
+    PasskeyRegistration, PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse, Url,
+    Webauthn, WebauthnBuilder,
+};
+use crate::errors::AuthError;
+
+/// How long users have to answer a challenge before it expires
+pub const PASSKEY_CHALLENGE_EXPIRY_MINUTES: i64 = 5;
+
+/// The relying party the passkeys are registered for (our site), see `PasskeyProvider::new`
+///
+/// A passkey only works on the `rp_id` domain it was registered on, from pages of `origin`.
+#[derive(Debug, Clone)]
+pub struct PasskeyProvider {
+    /// Domain of the site, e.g. `auth.example.com` (or a parent domain of `origin`)
+    pub rp_id: String,
+    /// Origin of the pages calling `navigator.credentials`, e.g. `https://auth.example.com`
+    pub origin: Url,
+    webauthn: Arc<Webauthn>,
+}
+
+impl PasskeyProvider {
+    /// Relying party `rp_id`, accepting the ceremonies of the pages of `origin`
+    ///
+    /// Returns the reason when `origin` isn't a URL or isn't on the `rp_id` domain.
+    pub fn new(rp_id: &str, origin: &str) -> Result<Self, String> {
+        let origin = Url::parse(origin).map_err(|error| format!("not a URL: {error}"))?;
+        let webauthn = WebauthnBuilder::new(rp_id, &origin)
+            .and_then(|builder| {
+                builder
+                    .rp_name(rp_id)
+                    .timeout(Self::challenge_expiry().to_std().unwrap_or_default())
+                    .build()
+            })
+            .map_err(|error| format!("not usable with `{rp_id}`: {error}"))?;
+
+        Ok(Self { rp_id: rp_id.to_string(), origin, webauthn: Arc::new(webauthn) })
+    }
+
+    /// How long a started ceremony can be finished (`PASSKEY_CHALLENGE_EXPIRY_MINUTES`)
+    pub fn challenge_expiry() -> Duration {
+        Duration::minutes(PASSKEY_CHALLENGE_EXPIRY_MINUTES)
+    }
+
+    /// Challenge to create a new passkey for the user, excluding the passkeys they already have
+    pub fn start_registration(
+        &self,
+        user_id: Uuid,
+        username: &str,
+        existing: &[Passkey],
+    ) -> Result<(CreationChallengeResponse, PasskeyRegistration), AuthError> {
+        let exclude: Vec<CredentialID> = existing.iter().map(|passkey| passkey.cred_id().clone()).collect();
+        self.webauthn
+            .start_passkey_registration(user_id, username, username, Some(exclude))
+            .map_err(|error| {
+                warn!(error = %error, "passkey registration couldn't be started");
+                AuthError::InternalError
+            })
+    }
+
+    /// Verifies the new credential against the challenge of `start_registration`
+    ///
+    /// Returns PasskeyRejected when the attestation doesn't verify (wrong challenge or origin, ...).
+    pub fn finish_registration(
+        &self,
+        credential: &RegisterPublicKeyCredential,
+        registration: &PasskeyRegistration,
+    ) -> Result<Passkey, AuthError> {
+        self.webauthn.finish_passkey_registration(credential, registration).map_err(|error| {
+            warn!(error = %error, "passkey registration rejected");
+            AuthError::PasskeyRejected
+        })
+    }
+
+    /// Challenge to sign with one of the user's passkeys
+    pub fn start_authentication(
+        &self,
+        passkeys: &[Passkey],
+    ) -> Result<(RequestChallengeResponse, PasskeyAuthentication), AuthError> {
+        self.webauthn.start_passkey_authentication(passkeys).map_err(|error| {
+            warn!(error = %error, "passkey login couldn't be started");
+            AuthError::InternalError
+        })
+    }
+
+    /// Verifies the assertion against the challenge of `start_authentication`
+    ///
+    /// Returns InvalidCredentials when the signature doesn't verify, like a wrong password.
+    pub fn finish_authentication(
+        &self,
+        credential: &PublicKeyCredential,
+        authentication: &PasskeyAuthentication,
+    ) -> Result<AuthenticationResult, AuthError> {
+        self.webauthn.finish_passkey_authentication(credential, authentication).map_err(|error| {
+            warn!(error = %error, "passkey login rejected");
+            AuthError::InvalidCredentials
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_must_be_on_the_rp_domain() {
+        assert!(PasskeyProvider::new("example.com", "https://auth.example.com").is_ok());
+        assert!(PasskeyProvider::new("example.com", "https://auth.example.org").is_err());
+        assert!(PasskeyProvider::new("example.com", "not a url").is_err());
+        assert!(PasskeyProvider::new("localhost", "http://localhost:3000").is_ok());
+    }
+}
@@ -0,0 +1,123 @@
+// This file is responsible for the delays between the attempts of the retrying operations
+// (connecting to the database, ...): exponential backoff, optionally with full jitter
+
+use std::time::Duration;
+use argon2::password_hash::rand_core::RngCore;
+
+/// Delays of an exponential backoff
+///
+/// Retry `n` (0 = first retry) waits `base * 2^n`, capped at `max`. With `jitter`,
+/// the wait is instead random between zero and that delay ("full jitter"), so clients
+/// failing at the same time don't all retry at the same time.
+///
+/// Usage, in an async loop:
+/// ```ignore
+/// let mut delays = Backoff::default().delays(OsRng).take(5);
+/// while let Err(error) = attempt().await {
+///     let Some(delay) = delays.next() else { return Err(error) };
+///     tokio::time::sleep(delay).await;
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay of the retry `attempt` without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt)
+            .and_then(|factor| self.base.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    /// Endless iterator of the delays of the retries, the jitter drawn from `rng`
+    ///
+    /// `rng` is only used with `jitter`; tests give a seeded one to get the same delays every run
+    pub fn delays<R: RngCore>(&self, rng: R) -> Delays<R> {
+        Delays { backoff: *self, attempt: 0, rng }
+    }
+}
+
+/// Iterator returned by `Backoff::delays`
+#[derive(Debug, Clone)]
+pub struct Delays<R> {
+    backoff: Backoff,
+    attempt: u32,
+    rng: R,
+}
+
+impl<R: RngCore> Iterator for Delays<R> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let delay = self.backoff.delay(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+
+        if !self.backoff.jitter {
+            return Some(delay);
+        }
+
+        // Uniform between 0 and `delay` (included), the modulo bias is negligible here
+        let nanos = u64::try_from(delay.as_nanos()).unwrap_or(u64::MAX);
+        Some(Duration::from_nanos(self.rng.next_u64() % nanos.saturating_add(1)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn backoff(jitter: bool) -> Backoff {
+        Backoff { base: Duration::from_millis(100), max: Duration::from_secs(1), jitter }
+    }
+
+    #[test]
+    fn test_delays_double_up_to_max() {
+        let delays: Vec<u64> = backoff(false)
+            .delays(StdRng::seed_from_u64(1))
+            .take(7)
+            .map(|delay| delay.as_millis() as u64)
+            .collect();
+
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000, 1000]);
+    }
+
+    #[test]
+    fn test_delay_doesnt_overflow() {
+        assert_eq!(backoff(false).delay(31), Duration::from_secs(1));
+        assert_eq!(backoff(false).delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let backoff = backoff(true);
+        let delays: Vec<Duration> = backoff.delays(StdRng::seed_from_u64(42)).take(50).collect();
+
+        for (attempt, delay) in delays.iter().enumerate() {
+            assert!(*delay <= backoff.delay(attempt as u32));
+        }
+        // Random, not stuck at the bound
+        assert!(delays[10..].iter().any(|delay| *delay < backoff.max / 2));
+    }
+
+    #[test]
+    fn test_same_seed_same_delays() {
+        let run = || backoff(true).delays(StdRng::seed_from_u64(7)).take(10).collect::<Vec<_>>();
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,69 @@
+// This file is responsible for the current time used by the tokens (`iat`, `exp`, `nbf`),
+// behind a trait so tests can fix it and move it forward instead of sleeping
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of the current time
+///
+/// Set in `TokenConfig::clock`, read when issuing and when validating tokens.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock (`Utc::now()`), used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock standing still at a given time until moved with `advance` or `set`
+///
+/// Clones share the same time, so a test can keep one and give the other to `TokenConfig`:
+/// ```
+/// use std::sync::Arc;
+/// use chrono::{Duration, TimeZone, Utc};
+/// use auth_system::auth::jwt::TokenConfig;
+/// use auth_system::clock::{Clock, MockClock};
+///
+/// let clock = MockClock::new(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+/// let config = TokenConfig { clock: Arc::new(clock.clone()), ..TokenConfig::default() };
+///
+/// clock.advance(Duration::hours(1));
+/// assert_eq!(config.clock.now(), Utc.with_ymd_and_hms(2030, 1, 1, 1, 0, 0).unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now: Arc::new(Mutex::new(now)) }
+    }
+
+    // Moves the time forward (or back, with a negative duration)
+    pub fn advance(&self, by: Duration) {
+        *self.time() += by;
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.time() = now;
+    }
+
+    // A test panicking while holding the lock doesn't break the other clones
+    fn time(&self) -> std::sync::MutexGuard<'_, DateTime<Utc>> {
+        self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.time()
+    }
+}
@@ -0,0 +1,43 @@
+// This file is responsible for the CORS headers,
+// so a browser frontend on another origin can call the API
+
+use std::time::Duration;
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use crate::auth::cookie::CSRF_HEADER;
+
+/// Origins allowed to call the API from a browser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// Any origin (`*`), not allowed together with credentials
+    Any,
+    /// Only these origins, e.g. `https://app.example.com`
+    List(Vec<HeaderValue>),
+}
+
+/// Settings of the CORS layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    /// Let the browser send cookies (needed with `AppState::auth_cookie`),
+    /// requires an explicit list of origins
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// Builds the tower-http layer for these settings
+    pub fn layer(&self) -> CorsLayer {
+        let allow_origin = match &self.allowed_origins {
+            AllowedOrigins::Any => AllowOrigin::any(),
+            AllowedOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+        };
+
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static("x-api-key"), HeaderName::from_static(CSRF_HEADER)])
+            .allow_credentials(self.allow_credentials)
+            // Browsers cache the preflight response for this long
+            .max_age(Duration::from_secs(3600))
+    }
+}
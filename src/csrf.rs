@@ -0,0 +1,77 @@
+// This file is responsible for protecting the routes authenticated by the auth cookie
+// against cross-site requests, with the double-submit CSRF token
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use crate::auth::cookie::{CookieConfig, CSRF_HEADER};
+use crate::errors::AuthError;
+
+/// Middleware requiring the `X-CSRF-Token` header to match the `csrf_token` cookie
+///
+/// Browsers attach the auth cookie to requests made by other sites, but only our pages
+/// can read the CSRF cookie to copy it into the header. Only checked on state-changing
+/// requests (not GET, HEAD or OPTIONS) authenticated by the cookie: requests with a bearer
+/// `Authorization` header, or without the auth cookie, go through. Any other `Authorization`
+/// header (unreadable, another scheme) doesn't exempt the request.
+///
+/// Returns `403 csrf_token_mismatch` when the header is missing or differs.
+pub async fn csrf_protect(State(cookie): State<CookieConfig>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let exempt = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || has_bearer_token(headers)
+        || cookie.token_from_headers(headers).is_none();
+    if exempt {
+        return next.run(request).await;
+    }
+
+    let submitted = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    match (cookie.csrf_from_headers(headers), submitted) {
+        (Some(expected), Some(submitted)) if same_token(expected, submitted) => next.run(request).await,
+        _ => {
+            tracing::warn!(method = %request.method(), path = %request.uri().path(), "csrf token mismatch");
+            AuthError::CsrfTokenMismatch.into_response()
+        }
+    }
+}
+
+// Requests with a bearer token are authenticated by it (see `AuthUser`), browsers never add one on their own
+fn has_bearer_token(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("Bearer "))
+}
+
+// Compares in constant time, so the response time doesn't tell how much of the token matched
+fn same_token(expected: &str, submitted: &str) -> bool {
+    expected.len() == submitted.len()
+        && expected.bytes().zip(submitted.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("abc123", "abc123"));
+        assert!(!same_token("abc123", "abc124"));
+        assert!(!same_token("abc123", "abc12"));
+    }
+
+    #[test]
+    fn test_only_readable_bearer_headers_exempt() {
+        let headers = |value: HeaderValue| HeaderMap::from_iter([(header::AUTHORIZATION, value)]);
+
+        assert!(has_bearer_token(&headers(HeaderValue::from_static("Bearer abc"))));
+        assert!(!has_bearer_token(&headers(HeaderValue::from_static("Basic dXNlcjpwYXNz"))));
+        assert!(!has_bearer_token(&headers(HeaderValue::from_bytes(b"Bearer \xff").unwrap())));
+        assert!(!has_bearer_token(&HeaderMap::new()));
+    }
+}
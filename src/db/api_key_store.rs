@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use chrono::Utc;
+use uuid::Uuid;
+use crate::{errors::AuthError, models::api_key::ApiKey};
+
+/// Trait that defines API key operations
+///
+/// Keys are stored by the SHA-256 hash of their plaintext (see `auth::api_key`),
+/// the plaintext itself is never stored.
+///
+/// Keys don't expire: a key stays valid until `revoke`, which keeps it flagged as revoked
+/// (still found by `find_by_hash`, `ApiKeyUser` rejects it) so its id can't be reused.
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    // Store a new key of the user
+    async fn create(&self, user_id: Uuid, name: String, key_hash: String) -> Result<ApiKey, AuthError>;
+
+    // Find a key by the hash of its plaintext (revoked keys included)
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError>;
+
+    // Revoke the key `id` of the user, returns false if the user has no such key
+    async fn revoke(&self, id: Uuid, user_id: Uuid) -> Result<bool, AuthError>;
+}
+
+
+/// In-memory implementation of ApiKeyStore
+///
+/// WARNING: Keys are lost when the process ends!
+#[derive(Clone, Default)]
+pub struct InMemoryApiKeyStore {
+    /// Thread-safe map: key_hash -> ApiKey
+    keys: Arc<Mutex<HashMap<String, ApiKey>>>,
+}
+
+impl InMemoryApiKeyStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for InMemoryApiKeyStore {
+    async fn create(&self, user_id: Uuid, name: String, key_hash: String) -> Result<ApiKey, AuthError> {
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            key_hash: key_hash.clone(),
+            created_at: Utc::now(),
+            revoked: false,
+        };
+
+        self.keys.lock().unwrap().insert(key_hash, api_key.clone());
+        Ok(api_key)
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, AuthError> {
+        Ok(self.keys.lock().unwrap().get(key_hash).cloned())
+    }
+
+    async fn revoke(&self, id: Uuid, user_id: Uuid) -> Result<bool, AuthError> {
+        let mut keys = self.keys.lock().unwrap();
+
+        match keys.values_mut().find(|k| k.id == id && k.user_id == user_id) {
+            Some(api_key) => {
+                api_key.revoked = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revoke_only_affects_own_keys() {
+        let store = InMemoryApiKeyStore::new();
+        let owner = Uuid::new_v4();
+        let api_key = store.create(owner, "ci".into(), "hash".into()).await.unwrap();
+
+        assert!(!store.revoke(api_key.id, Uuid::new_v4()).await.unwrap());
+        assert!(!store.find_by_hash("hash").await.unwrap().unwrap().revoked);
+
+        assert!(store.revoke(api_key.id, owner).await.unwrap());
+        assert!(store.find_by_hash("hash").await.unwrap().unwrap().revoked);
+    }
+}
@@ -0,0 +1,510 @@
+// This file is responsible for the caching decorator of the user repositories, sparing the
+// database the repeated lookups of the same users (e.g. every authenticated request)
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use uuid::Uuid;
+use crate::{
+    db::user_repository::UserRepository,
+    models::{user::{User, CreateUser, UpdateUser, UserFilter, UserStats}, validation::canonical_username},
+    errors::AuthError,
+};
+
+// Lookup a cached user was found with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Id(Uuid),
+    /// Canonical form, like the `username_canonical` column
+    Username(String),
+    Email(String),
+}
+
+/// UserRepository caching the results of `find_by_id`, `find_by_username` and `find_by_email`
+/// of another repository for `ttl`
+///
+/// Wraps any repository, including another decorator:
+/// ```
+/// use std::{sync::Arc, time::Duration};
+/// use auth_system::db::{caching_repository::CachingUserRepository, memory_connection::InMemoryUserRepository};
+///
+/// let user_repo = Arc::new(CachingUserRepository::new(Arc::new(InMemoryUserRepository::new()), Duration::from_secs(30)));
+/// ```
+///
+/// Only found users are cached, an unknown username is looked up again every time
+/// (so a registration is seen right away). Every change made through the decorator
+/// (`update`, `set_active`, ...) drops the cached entries of the user, and a lookup that
+/// was under way meanwhile doesn't cache what it read (it may be from before the change).
+///
+/// WARNING: Changes made to the database by anything else (another instance of the server,
+/// a migration, ...) are only seen once the entries expire. Keep `ttl` short!
+pub struct CachingUserRepository {
+    inner: Arc<dyn UserRepository>,
+    ttl: Duration,
+    cache: Mutex<Cache>,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<CacheKey, (User, Instant)>,
+    /// Number of invalidations so far. One counter for every user: a lookup by username
+    /// or email doesn't know the id of its user before it is done
+    generation: u64,
+}
+
+impl CachingUserRepository {
+    pub fn new(inner: Arc<dyn UserRepository>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    // The entries are only a copy of the database: one left half-changed by a panic
+    // is at worst stale until its ttl, so a poisoned lock is used anyway
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Cached user of `key`, dropping the entry once expired
+    fn cached(&self, key: &CacheKey) -> Option<User> {
+        let entries = &mut self.lock().entries;
+
+        match entries.get(key) {
+            Some((user, cached_at)) if cached_at.elapsed() < self.ttl => Some(user.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // Caches `user` under `key`, removing the expired entries so memory stays bounded
+    // by the users looked up during the last `ttl`
+    // Nothing is cached when an invalidation happened since `generation` (read before the lookup)
+    fn store(&self, key: CacheKey, user: &Option<User>, generation: u64) {
+        if let Some(user) = user {
+            let mut cache = self.lock();
+            if cache.generation != generation {
+                return;
+            }
+            cache.entries.retain(|_, (_, cached_at)| cached_at.elapsed() < self.ttl);
+            cache.entries.insert(key, (user.clone(), Instant::now()));
+        }
+    }
+
+    // Drops the entries of the user (by id, username and email)
+    fn invalidate(&self, id: Uuid) {
+        let mut cache = self.lock();
+        cache.generation += 1;
+        cache.entries.retain(|_, (user, _)| user.id != id);
+    }
+
+    async fn find(&self, key: CacheKey) -> Result<Option<User>, AuthError> {
+        if let Some(user) = self.cached(&key) {
+            return Ok(Some(user));
+        }
+
+        let generation = self.lock().generation;
+        let user = match &key {
+            CacheKey::Id(id) => self.inner.find_by_id(*id).await?,
+            CacheKey::Username(username) => self.inner.find_by_username(username).await?,
+            CacheKey::Email(email) => self.inner.find_by_email(email).await?,
+        };
+        self.store(key, &user, generation);
+
+        Ok(user)
+    }
+}
+
+#[async_trait]
+impl UserRepository for CachingUserRepository {
+    async fn create(&self, user: CreateUser, password_hash: String) -> Result<User, AuthError> {
+        self.inner.create(user, password_hash).await
+    }
+
+    async fn create_many(&self, users: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+        self.inner.create_many(users).await
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        self.find(CacheKey::Email(email.to_string())).await
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        self.find(CacheKey::Username(canonical_username(username))).await
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        self.find(CacheKey::Id(id)).await
+    }
+
+    // The cached user, so these keep sparing the database (the login looks up users in their tenant)
+    async fn find_active_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_email(email).await?.filter(|user| user.is_active))
+    }
+
+    async fn find_active_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_username(username).await?.filter(|user| user.is_active))
+    }
+
+    async fn find_active_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_id(id).await?.filter(|user| user.is_active))
+    }
+
+    async fn find_by_email_in_tenant(&self, tenant_id: Option<&str>, email: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_email(email).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    async fn find_by_username_in_tenant(&self, tenant_id: Option<&str>, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_username(username).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    async fn find_by_id_in_tenant(&self, tenant_id: Option<&str>, id: Uuid) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_id(id).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    // Only used by /register, for users that usually don't exist yet: not worth caching
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        self.inner.find_by_email_or_username(email, username).await
+    }
+
+    // Only used by the OAuth callback, not worth caching
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+        self.inner.find_by_oauth(provider, subject).await
+    }
+
+    // The entries are dropped even when the change fails, it may have been partly applied
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+        let result = self.inner.update(id, changes, password_hash).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.inner.mark_email_verified(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let result = self.inner.set_pending_email(id, email).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let result = self.inner.confirm_pending_email(id, email).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.inner.touch_last_login(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.inner.increment_token_version(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+        let result = self.inner.link_oauth(id, provider, subject).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+        let result = self.inner.set_active(id, is_active).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.inner.delete(id).await;
+        self.invalidate(id);
+        result
+    }
+
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        self.inner.list(limit, offset).await
+    }
+
+    async fn count(&self) -> Result<u64, AuthError> {
+        self.inner.count().await
+    }
+
+    async fn stats(&self) -> Result<UserStats, AuthError> {
+        self.inner.stats().await
+    }
+
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        self.inner.search(filter).await
+    }
+
+    async fn list_in_tenant(&self, tenant_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        self.inner.list_in_tenant(tenant_id, limit, offset).await
+    }
+
+    async fn count_in_tenant(&self, tenant_id: Option<&str>) -> Result<u64, AuthError> {
+        self.inner.count_in_tenant(tenant_id).await
+    }
+
+    async fn stats_in_tenant(&self, tenant_id: Option<&str>) -> Result<UserStats, AuthError> {
+        self.inner.stats_in_tenant(tenant_id).await
+    }
+
+    async fn search_in_tenant(&self, tenant_id: Option<&str>, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        self.inner.search_in_tenant(tenant_id, filter).await
+    }
+
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+        self.inner.password_history(id).await
+    }
+
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+        self.inner.add_password_history(id, password_hash, keep).await
+    }
+
+    async fn ping(&self) -> Result<(), AuthError> {
+        self.inner.ping().await
+    }
+
+    async fn verify_schema(&self) -> Result<(), AuthError> {
+        self.inner.verify_schema().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::sync::Notify;
+    use crate::db::memory_connection::InMemoryUserRepository;
+
+    // In-memory repository counting the finder calls that reach it
+    #[derive(Default)]
+    struct CountingRepository {
+        inner: InMemoryUserRepository,
+        finds: AtomicU32,
+        /// When set, the next `find_by_id` signals the first once it read the user,
+        /// then waits for the second before returning it
+        pause: Mutex<Option<Arc<(Notify, Notify)>>>,
+    }
+
+    impl CountingRepository {
+        fn finds(&self) -> u32 {
+            self.finds.load(Ordering::SeqCst)
+        }
+
+        fn pause_next_find(&self) -> Arc<(Notify, Notify)> {
+            let pause = Arc::new((Notify::new(), Notify::new()));
+            *self.pause.lock().unwrap() = Some(pause.clone());
+            pause
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for CountingRepository {
+        async fn create(&self, user: CreateUser, password_hash: String) -> Result<User, AuthError> {
+            self.inner.create(user, password_hash).await
+        }
+
+        async fn create_many(&self, users: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+            self.inner.create_many(users).await
+        }
+
+        async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+            self.finds.fetch_add(1, Ordering::SeqCst);
+            self.inner.find_by_email(email).await
+        }
+
+        async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+            self.finds.fetch_add(1, Ordering::SeqCst);
+            self.inner.find_by_username(username).await
+        }
+
+        async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+            self.finds.fetch_add(1, Ordering::SeqCst);
+            let user = self.inner.find_by_id(id).await;
+            let pause = self.pause.lock().unwrap().take();
+            if let Some(pause) = pause {
+                pause.0.notify_one();
+                pause.1.notified().await;
+            }
+            user
+        }
+
+        async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+            self.inner.find_by_oauth(provider, subject).await
+        }
+
+        async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+            self.inner.update(id, changes, password_hash).await
+        }
+
+        async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+            self.inner.mark_email_verified(id).await
+        }
+
+        async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+            self.inner.set_pending_email(id, email).await
+        }
+
+        async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+            self.inner.confirm_pending_email(id, email).await
+        }
+
+        async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+            self.inner.touch_last_login(id).await
+        }
+
+        async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+            self.inner.increment_token_version(id).await
+        }
+
+        async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+            self.inner.link_oauth(id, provider, subject).await
+        }
+
+        async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+            self.inner.set_active(id, is_active).await
+        }
+
+        async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+            self.inner.delete(id).await
+        }
+
+        async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+            self.inner.list(limit, offset).await
+        }
+
+        async fn count(&self) -> Result<u64, AuthError> {
+            self.inner.count().await
+        }
+
+        async fn stats(&self) -> Result<UserStats, AuthError> {
+            self.inner.stats().await
+        }
+
+        async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+            self.inner.search(filter).await
+        }
+
+        async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+            self.inner.password_history(id).await
+        }
+
+        async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+            self.inner.add_password_history(id, password_hash, keep).await
+        }
+
+        async fn ping(&self) -> Result<(), AuthError> {
+            self.inner.ping().await
+        }
+    }
+
+    fn caching(ttl: Duration) -> (Arc<CountingRepository>, CachingUserRepository, User) {
+        let counting = Arc::new(CountingRepository::default());
+        let user = counting.inner.insert_with_password("john_doe", "john@example.com", "Password123!").unwrap();
+        let repo = CachingUserRepository::new(counting.clone(), ttl);
+        (counting, repo, user)
+    }
+
+    #[tokio::test]
+    async fn test_second_lookup_hits_the_cache() {
+        let (counting, repo, user) = caching(Duration::from_secs(60));
+
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "john_doe");
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "john_doe");
+        assert_eq!(counting.finds(), 1);
+
+        // Usernames are cached by their canonical form
+        repo.find_by_username("john_doe").await.unwrap().unwrap();
+        repo.find_by_username("John_Doe").await.unwrap().unwrap();
+        repo.find_by_email("john@example.com").await.unwrap().unwrap();
+        repo.find_by_email("john@example.com").await.unwrap().unwrap();
+        assert_eq!(counting.finds(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_users_are_not_cached() {
+        let (counting, repo, _) = caching(Duration::from_secs(60));
+
+        assert!(repo.find_by_username("jane_doe").await.unwrap().is_none());
+        counting.inner.insert_with_password("jane_doe", "jane@example.com", "Password123!").unwrap();
+        assert!(repo.find_by_username("jane_doe").await.unwrap().is_some());
+        assert_eq!(counting.finds(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_invalidates_the_entries_of_the_user() {
+        let (counting, repo, user) = caching(Duration::from_secs(60));
+        repo.find_by_id(user.id).await.unwrap().unwrap();
+        repo.find_by_username("john_doe").await.unwrap().unwrap();
+
+        let changes = UpdateUser { username: Some("johnny".to_string()), ..UpdateUser::default() };
+        repo.update(user.id, changes, None).await.unwrap();
+
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "johnny");
+        assert!(repo.find_by_username("john_doe").await.unwrap().is_none());
+        assert_eq!(counting.finds(), 4);
+
+        repo.set_active(user.id, false).await.unwrap();
+        assert!(repo.find_active_by_id(user.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_racing_a_change_is_not_cached() {
+        let (counting, repo, user) = caching(Duration::from_secs(60));
+        let repo = Arc::new(repo);
+
+        // Reads the user, then the account is deactivated before the read is cached
+        let pause = counting.pause_next_find();
+        let lookup = tokio::spawn({
+            let repo = repo.clone();
+            async move { repo.find_by_id(user.id).await }
+        });
+        pause.0.notified().await;
+        repo.set_active(user.id, false).await.unwrap();
+        pause.1.notify_one();
+        assert!(lookup.await.unwrap().unwrap().unwrap().is_active);
+
+        assert!(repo.find_active_by_id(user.id).await.unwrap().is_none());
+        assert_eq!(counting.finds(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_poisoned_cache_keeps_working() {
+        let (counting, repo, user) = caching(Duration::from_secs(60));
+        repo.find_by_id(user.id).await.unwrap().unwrap();
+
+        let poison = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _cache = repo.cache.lock().unwrap();
+            panic!("poisoning the cache");
+        }));
+        assert!(poison.is_err() && repo.cache.is_poisoned());
+
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "john_doe");
+        repo.update(user.id, UpdateUser { username: Some("johnny".to_string()), ..UpdateUser::default() }, None).await.unwrap();
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "johnny");
+        assert_eq!(counting.finds(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire_after_the_ttl() {
+        let (counting, repo, user) = caching(Duration::from_millis(20));
+        repo.find_by_id(user.id).await.unwrap().unwrap();
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(counting.finds(), 2);
+    }
+}
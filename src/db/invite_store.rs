@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use crate::errors::AuthError;
+
+/// Trait that defines invite code operations
+///
+/// Used when registration is invite-only (`AppState::invite_only`),
+/// each code can be used to register a single account.
+///
+/// It only holds the unused codes: `redeem` must remove the code atomically, so two
+/// concurrent registrations can't both use it. Codes don't expire.
+#[async_trait]
+pub trait InviteStore: Send + Sync {
+    // Add a code that can be redeemed once
+    async fn add(&self, code: String) -> Result<(), AuthError>;
+
+    // Consume the code, returns false if it doesn't exist (or was already used)
+    async fn redeem(&self, code: &str) -> Result<bool, AuthError>;
+}
+
+
+/// In-memory implementation of InviteStore
+///
+/// WARNING: Codes are lost when the process ends!
+#[derive(Clone, Default)]
+pub struct InMemoryInviteStore {
+    /// Thread-safe set of the unused codes
+    codes: Arc<Mutex<HashSet<String>>>,
+}
+
+impl InMemoryInviteStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Create a store holding the given codes (e.g. read from the environment)
+    pub fn with_codes(codes: impl IntoIterator<Item = String>) -> Self {
+        Self { codes: Arc::new(Mutex::new(codes.into_iter().collect())) }
+    }
+}
+
+#[async_trait]
+impl InviteStore for InMemoryInviteStore {
+    async fn add(&self, code: String) -> Result<(), AuthError> {
+        self.codes.lock().unwrap().insert(code);
+        Ok(())
+    }
+
+    async fn redeem(&self, code: &str) -> Result<bool, AuthError> {
+        Ok(self.codes.lock().unwrap().remove(code))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_code_can_only_be_redeemed_once() {
+        let store = InMemoryInviteStore::with_codes(["welcome-42".to_string()]);
+
+        assert!(!store.redeem("unknown").await.unwrap());
+        assert!(store.redeem("welcome-42").await.unwrap());
+        assert!(!store.redeem("welcome-42").await.unwrap());
+    }
+}
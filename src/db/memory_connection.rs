@@ -1,17 +1,21 @@
 use async_trait::async_trait;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::hash::Hash;
 use chrono::Utc;
 use uuid::Uuid;
 use crate::{
+    auth::crypto,
     db::user_repository::UserRepository,
-    models::user::{User, CreateUser},
+    models::{user::{User, CreateUser, UpdateUser, UserFilter, UserStats}, validation::canonical_username},
     errors::AuthError,
 };
 
 /// In-memory implementation of UserRepository
 /// 
-/// Stores users in a HashMap in memory.
+/// Stores users in a HashMap in memory, with an index per unique field (email, username,
+/// OAuth account) so every finder is a hash lookup, even with thousands of seeded users.
 /// Useful for:
 /// - Local development
 /// - Unit tests
@@ -20,17 +24,218 @@ use crate::{
 /// WARNING: Data is lost when the process ends!
 #[derive(Clone)]
 pub struct InMemoryUserRepository {
-    /// Thread-safe HashMap that stores users (Key, Value)
-    users: Arc<Mutex<HashMap<String, User>>>,
+    /// Thread-safe table that stores users, with its indexes
+    users: Arc<Mutex<UserTable>>,
+    /// Previous password hashes by user, newest first
+    password_history: Arc<Mutex<HashMap<Uuid, Vec<String>>>>,
 }
 
 impl InMemoryUserRepository {
     // Create a new instance of the in-memory repository
     pub fn new() -> Self {
         Self{
-            users: Arc::new(Mutex::new(HashMap::new())),
+            users: Arc::new(Mutex::new(UserTable::default())),
+            password_history: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    /// Repository preloaded with `users`, stored as given (ids, roles, flags...)
+    ///
+    /// Meant for tests of code embedding this crate, e.g. logging in as a known user:
+    /// ```
+    /// use std::sync::Arc;
+    /// use auth_system::{AppState, db::memory_connection::InMemoryUserRepository};
+    /// use auth_system::{auth::extractor::{ClientInfo, RequestTenant}, extract::Json, handlers::auth_handler::login_handler, models::auth::LoginRequest};
+    /// use axum::extract::State;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let repo = InMemoryUserRepository::with_users(Vec::new());
+    /// repo.insert_with_password("john_doe", "john@example.com", "Password123!").unwrap();
+    ///
+    /// let state = AppState::new("a_secret_that_is_long_enough_for_hs256".to_string(), Arc::new(repo));
+    /// let login = LoginRequest { username: "john_doe".to_string(), password: "Password123!".to_string().into() };
+    /// let (_, Json(response)) = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(login)).await.unwrap();
+    /// assert!(!response.token.is_empty());
+    /// # }
+    /// ```
+    pub fn with_users(users: Vec<User>) -> Self {
+        Self {
+            users: Arc::new(Mutex::new(users.into_iter().fold(UserTable::default(), |mut table, user| {
+                table.insert(user);
+                table
+            }))),
+            password_history: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Adds an active user whose password is `password` (hashed with the default Argon2 settings)
+    ///
+    /// Returns UserAlreadyExists when the username or email is taken
+    pub fn insert_with_password(&self, username: &str, email: &str, password: &str) -> Result<User, AuthError> {
+        let password_hash = crypto::hash_password(password).map_err(|_| AuthError::InternalError)?;
+        let mut users = self.users();
+
+        if users.find_by_email_or_username(email, username).is_some() {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        let now = Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            email: email.to_string(),
+            password_hash,
+            created_at: now,
+            updated_at: now,
+            is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: None,
+            pending_email: None,
+            tenant_id: None,
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
+        };
+        users.insert(user.clone());
+
+        Ok(user)
+    }
+
+    // The maps are only changed once everything is checked, so a thread panicking while
+    // holding a lock leaves them consistent: the poisoned lock is recovered instead of
+    // making every later request panic
+    fn users(&self) -> MutexGuard<'_, UserTable> {
+        self.users.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn history(&self) -> MutexGuard<'_, HashMap<Uuid, Vec<String>>> {
+        self.password_history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+/// Users by id, and the id of the user holding each email, username and OAuth account
+///
+/// The indexes are only changed through the methods below, together with the user,
+/// so they never point to a value the user no longer has.
+#[derive(Default)]
+struct UserTable {
+    by_id: HashMap<Uuid, User>,
+    by_email: HashMap<String, Uuid>,
+    /// Keyed by the canonical username, so lookups ignore the case like the databases' `username_canonical`
+    by_username: HashMap<String, Uuid>,
+    /// Keyed by (provider, subject)
+    by_oauth: HashMap<(String, String), Uuid>,
+}
+
+impl UserTable {
+    fn get(&self, id: Uuid) -> Option<&User> {
+        self.by_id.get(&id)
+    }
+
+    // Only for the fields that aren't indexed, see set_email / set_username / set_oauth
+    fn get_mut(&mut self, id: Uuid) -> Option<&mut User> {
+        self.by_id.get_mut(&id)
+    }
+
+    fn find_by_email(&self, email: &str) -> Option<&User> {
+        self.by_email.get(email).and_then(|id| self.by_id.get(id))
+    }
+
+    fn find_by_username(&self, username: &str) -> Option<&User> {
+        self.by_username.get(&canonical_username(username)).and_then(|id| self.by_id.get(id))
+    }
+
+    fn find_by_email_or_username(&self, email: &str, username: &str) -> Option<&User> {
+        self.find_by_email(email).or_else(|| self.find_by_username(username))
+    }
+
+    fn find_by_oauth(&self, provider: &str, subject: &str) -> Option<&User> {
+        self.by_oauth
+            .get(&(provider.to_string(), subject.to_string()))
+            .and_then(|id| self.by_id.get(id))
+    }
+
+    // True when another user than `id` holds the value of this index
+    fn held_by_other<K, Q>(index: &HashMap<K, Uuid>, key: &Q, id: Uuid) -> bool
+    where
+        K: Borrow<Q> + Hash + Eq,
+        Q: Hash + Eq + ?Sized,
+    {
+        index.get(key).is_some_and(|holder| *holder != id)
+    }
+
+    // Replaces any user with the same id
+    fn insert(&mut self, user: User) {
+        if let Some(previous) = self.by_id.remove(&user.id) {
+            self.unindex(&previous);
+        }
+
+        self.by_email.insert(user.email.clone(), user.id);
+        self.by_username.insert(canonical_username(&user.username), user.id);
+        if let (Some(provider), Some(subject)) = (&user.oauth_provider, &user.oauth_subject) {
+            self.by_oauth.insert((provider.clone(), subject.clone()), user.id);
+        }
+        self.by_id.insert(user.id, user);
+    }
+
+    // Removes the index entries still pointing to `user`
+    fn unindex(&mut self, user: &User) {
+        if !Self::held_by_other(&self.by_email, &user.email, user.id) {
+            self.by_email.remove(&user.email);
+        }
+        let username = canonical_username(&user.username);
+        if !Self::held_by_other(&self.by_username, &username, user.id) {
+            self.by_username.remove(&username);
+        }
+        if let (Some(provider), Some(subject)) = (&user.oauth_provider, &user.oauth_subject) {
+            let account = (provider.clone(), subject.clone());
+            if !Self::held_by_other(&self.by_oauth, &account, user.id) {
+                self.by_oauth.remove(&account);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: Uuid) -> Option<User> {
+        let user = self.by_id.remove(&id)?;
+        self.unindex(&user);
+        Some(user)
+    }
+
+    // Re-indexes a user after a change of its indexed fields (`change` must keep the id)
+    fn modify(&mut self, id: Uuid, change: impl FnOnce(&mut User)) -> Option<&User> {
+        let mut user = self.by_id.get(&id)?.clone();
+        change(&mut user);
+        self.insert(user);
+        self.by_id.get(&id)
+    }
+
+    fn email_taken(&self, email: &str, id: Uuid) -> bool {
+        Self::held_by_other(&self.by_email, email, id)
+    }
+
+    fn username_taken(&self, username: &str, id: Uuid) -> bool {
+        Self::held_by_other(&self.by_username, canonical_username(username).as_str(), id)
+    }
+
+    fn oauth_taken(&self, provider: &str, subject: &str, id: Uuid) -> bool {
+        Self::held_by_other(&self.by_oauth, &(provider.to_string(), subject.to_string()), id)
+    }
+
+    fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    fn values(&self) -> impl Iterator<Item = &User> {
+        self.by_id.values()
+    }
+}
+
+// The canonical form is computed on each comparison instead of being stored
+fn same_username(a: &str, b: &str) -> bool {
+    canonical_username(a) == canonical_username(b)
 }
 
 
@@ -44,7 +249,13 @@ impl Default for InMemoryUserRepository {
 #[async_trait]
 impl UserRepository for InMemoryUserRepository {
     async fn create(&self, user: CreateUser, password_hash: String) -> Result<User, AuthError> {
-        let mut users = self.users.lock().unwrap();
+        let mut users = self.users();
+
+        // Uniqueness is checked while holding the lock,
+        // so two concurrent registrations can't both succeed
+        if users.find_by_email_or_username(&user.email, &user.username).is_some() {
+            return Err(AuthError::UserAlreadyExists);
+        }
 
         // Generates a new UUID
         let id: Uuid = Uuid::new_v4();
@@ -58,32 +269,580 @@ impl UserRepository for InMemoryUserRepository {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: None,
+            pending_email: None,
+            tenant_id: user.tenant_id,
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
         };
 
         // Insert HashMap
-        users.insert(id.to_string(), new_user.clone());
+        users.insert(new_user.clone());
 
         Ok(new_user)
     }
 
+    async fn create_many(&self, batch: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+        let mut users = self.users();
+        let now = Utc::now();
+
+        // Everything is checked before the first insert, so a collision leaves the map untouched
+        let mut created: Vec<User> = Vec::with_capacity(batch.len());
+        for (user, password_hash) in batch {
+            let taken = users.find_by_email_or_username(&user.email, &user.username).is_some()
+                || created.iter().any(|u| u.email == user.email || same_username(&u.username, &user.username));
+            if taken {
+                return Err(AuthError::UserAlreadyExists);
+            }
+
+            created.push(User {
+                id: Uuid::new_v4(),
+                username: user.username,
+                email: user.email,
+                password_hash,
+                created_at: now,
+                updated_at: now,
+                is_active: true,
+                roles: Vec::new(),
+                email_verified: false,
+                last_login_at: None,
+                pending_email: None,
+                tenant_id: user.tenant_id,
+                token_version: 0,
+                oauth_provider: None,
+                oauth_subject: None,
+            });
+        }
+
+        for user in &created {
+            users.insert(user.clone());
+        }
+
+        Ok(created)
+    }
+
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
-        let users = self.users.lock().unwrap();
-        
-        // Linear search for email(Not eficient, but ok for testing)
-        Ok(users.values().find(|u| u.email == email).cloned())
+        Ok(self.users().find_by_email(email).cloned())
     }
 
     async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
-        let users = self.users.lock().unwrap();
-        
-        // Linear search for username
-        Ok(users.values().find(|u| u.username == username).cloned())
+        // Ignores the case like the databases' `username_canonical`
+        Ok(self.users().find_by_username(username).cloned())
+    }
+
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        // Both lookups under the same lock
+        Ok(self.users().find_by_email_or_username(email, username).cloned())
     }
 
     async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
-        let users = self.users.lock().unwrap();
-        
         // Direct search for ID (O(1))
-        Ok(users.get(&id.to_string()).cloned())
+        Ok(self.users().get(id).cloned())
+    }
+
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.users().find_by_oauth(provider, subject).cloned())
+    }
+
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+        let mut users = self.users();
+
+        if users.get(id).is_none() {
+            return Err(AuthError::UserNotFound);
+        }
+        // Like the unique indexes of the databases, the indexes hold one user per value
+        let email_taken = changes.email.as_deref().is_some_and(|email| users.email_taken(email, id));
+        let username_taken = changes.username.as_deref().is_some_and(|username| users.username_taken(username, id));
+        if email_taken || username_taken {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        let user = users.modify(id, |user| {
+            if let Some(username) = changes.username {
+                user.username = username;
+            }
+            if let Some(email) = changes.email {
+                user.email = email;
+            }
+            if let Some(password_hash) = password_hash {
+                user.password_hash = password_hash;
+            }
+            if let Some(roles) = changes.roles {
+                user.roles = roles;
+            }
+            user.updated_at = Utc::now();
+        });
+
+        user.cloned().ok_or(AuthError::UserNotFound)
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        let user = users.get_mut(id).ok_or(AuthError::UserNotFound)?;
+        user.email_verified = true;
+        user.updated_at = Utc::now();
+
+        Ok(())
+    }
+
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let mut users = self.users();
+        let user = users.get_mut(id).ok_or(AuthError::UserNotFound)?;
+
+        user.pending_email = Some(email.to_string());
+        user.updated_at = Utc::now();
+        Ok(())
+    }
+
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        // The address may have been taken since the change was requested
+        if users.email_taken(email, id) {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        if users.get(id).is_none_or(|u| u.pending_email.as_deref() != Some(email)) {
+            return Err(AuthError::UserNotFound);
+        }
+
+        users.modify(id, |user| {
+            user.email = email.to_string();
+            user.pending_email = None;
+            user.email_verified = true;
+            user.updated_at = Utc::now();
+        });
+        Ok(())
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        let user = users.get_mut(id).ok_or(AuthError::UserNotFound)?;
+        user.last_login_at = Some(Utc::now());
+
+        Ok(())
+    }
+
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        let user = users.get_mut(id).ok_or(AuthError::UserNotFound)?;
+        user.token_version += 1;
+
+        Ok(())
+    }
+
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        if users.get(id).is_none() {
+            return Err(AuthError::UserNotFound);
+        }
+        if users.oauth_taken(provider, subject, id) {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        users.modify(id, |user| {
+            user.oauth_provider = Some(provider.to_string());
+            user.oauth_subject = Some(subject.to_string());
+        });
+
+        Ok(())
+    }
+
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+        let mut users = self.users();
+
+        let user = users.get_mut(id).ok_or(AuthError::UserNotFound)?;
+        user.is_active = is_active;
+        user.updated_at = Utc::now();
+
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+        self.users().remove(id).ok_or(AuthError::UserNotFound)?;
+        self.history().remove(&id);
+
+        Ok(())
+    }
+
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let users = self.users();
+
+        // HashMap has no order: sort by creation date (id breaks ties, so pages are stable)
+        let mut sorted: Vec<&User> = users.values().collect();
+        sorted.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+        Ok(sorted
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn count(&self) -> Result<u64, AuthError> {
+        Ok(self.users().len() as u64)
+    }
+
+    // Always reachable, there is no connection
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+        Ok(self.history().get(&id).cloned().unwrap_or_default())
+    }
+
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+        let mut history = self.history();
+        let hashes = history.entry(id).or_default();
+
+        hashes.insert(0, password_hash);
+        hashes.truncate(keep);
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), AuthError> {
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<UserStats, AuthError> {
+        let users = self.users();
+
+        let total = users.len() as u64;
+        let active = users.values().filter(|u| u.is_active).count() as u64;
+
+        Ok(UserStats { total, active, inactive: total - active })
+    }
+
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        let users = self.users();
+
+        let mut found: Vec<User> = users.values().filter(|u| filter.matches(u)).cloned().collect();
+        found.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+        Ok(found)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_user(username: &str, email: &str) -> CreateUser {
+        CreateUser {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping_is_ok() {
+        assert!(InMemoryUserRepository::new().ping().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects_duplicate_username() {
+        let repo = InMemoryUserRepository::new();
+        repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let result = repo.create(create_user("john_doe", "other@example.com"), "hash".into()).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects_duplicate_email() {
+        let repo = InMemoryUserRepository::new();
+        repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let result = repo.create(create_user("jane_doe", "john@example.com"), "hash".into()).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn test_find_by_email_or_username_matches_either_field() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("JohnDoe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let by_email = repo.find_by_email_or_username("john@example.com", "jane_doe").await.unwrap();
+        assert_eq!(by_email.map(|u| u.id), Some(user.id));
+
+        let by_username = repo.find_by_email_or_username("jane@example.com", "johndoe").await.unwrap();
+        assert_eq!(by_username.map(|u| u.id), Some(user.id));
+
+        assert!(repo.find_by_email_or_username("jane@example.com", "jane_doe").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_moves_the_index_entries() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let changes = UpdateUser { username: Some("John_Smith".into()), email: Some("smith@example.com".into()), ..UpdateUser::default() };
+        repo.update(user.id, changes, None).await.unwrap();
+
+        // The old values are free, the new ones find the user
+        assert!(repo.find_by_username("john_doe").await.unwrap().is_none());
+        assert!(repo.find_by_email("john@example.com").await.unwrap().is_none());
+        assert_eq!(repo.find_by_username("john_smith").await.unwrap().map(|u| u.id), Some(user.id));
+        assert_eq!(repo.find_by_email("smith@example.com").await.unwrap().map(|u| u.id), Some(user.id));
+
+        let other = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        assert_eq!(repo.find_by_username("john_doe").await.unwrap().map(|u| u.id), Some(other.id));
+
+        // One user per value, like the unique indexes of the databases
+        let changes = UpdateUser { email: Some("smith@example.com".into()), ..UpdateUser::default() };
+        let result = repo.update(other.id, changes, None).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+        assert_eq!(repo.find_by_email("smith@example.com").await.unwrap().map(|u| u.id), Some(user.id));
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_email_and_oauth_link_are_indexed() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        repo.set_pending_email(user.id, "new@example.com").await.unwrap();
+        repo.confirm_pending_email(user.id, "new@example.com").await.unwrap();
+        assert!(repo.find_by_email("john@example.com").await.unwrap().is_none());
+        assert_eq!(repo.find_by_email("new@example.com").await.unwrap().map(|u| u.id), Some(user.id));
+
+        repo.link_oauth(user.id, "google", "google-1").await.unwrap();
+        assert_eq!(repo.find_by_oauth("google", "google-1").await.unwrap().map(|u| u.id), Some(user.id));
+        assert!(repo.find_by_oauth("google", "google-2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preloaded_users_are_indexed() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("JohnDoe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let preloaded = InMemoryUserRepository::with_users(vec![user.clone()]);
+        assert_eq!(preloaded.find_by_username("johndoe").await.unwrap().map(|u| u.id), Some(user.id));
+        assert_eq!(preloaded.find_by_email("john@example.com").await.unwrap().map(|u| u.id), Some(user.id));
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_in_creation_order() {
+        let repo = InMemoryUserRepository::new();
+        for i in 0..5 {
+            repo.create(create_user(&format!("user_{i}"), &format!("user_{i}@example.com")), "hash".into()).await.unwrap();
+        }
+
+        let usernames = |users: Vec<User>| users.into_iter().map(|u| u.username).collect::<Vec<_>>();
+
+        assert_eq!(usernames(repo.list(2, 0).await.unwrap()), ["user_0", "user_1"]);
+        assert_eq!(usernames(repo.list(2, 4).await.unwrap()), ["user_4"]);
+        assert!(repo.list(2, 5).await.unwrap().is_empty());
+        assert!(repo.list(0, 0).await.unwrap().is_empty());
+        assert_eq!(repo.count().await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_create_many_imports_all_users() {
+        let repo = InMemoryUserRepository::new();
+        let batch = (0..100)
+            .map(|i| (create_user(&format!("user_{i}"), &format!("user_{i}@example.com")), "hash".to_string()))
+            .collect();
+
+        let created = repo.create_many(batch).await.unwrap();
+        assert_eq!(created.len(), 100);
+        assert_eq!(repo.count().await.unwrap(), 100);
+        assert!(repo.find_by_username("user_42").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_create_many_duplicate_aborts_whole_import() {
+        let repo = InMemoryUserRepository::new();
+        repo.create(create_user("existing", "existing@example.com"), "hash".into()).await.unwrap();
+
+        let batch = vec![
+            (create_user("user_0", "user_0@example.com"), "hash".to_string()),
+            (create_user("existing", "other@example.com"), "hash".to_string()),
+            (create_user("user_2", "user_2@example.com"), "hash".to_string()),
+        ];
+        let result = repo.create_many(batch).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+        assert_eq!(repo.count().await.unwrap(), 1);
+
+        // A collision inside the batch itself is caught as well
+        let batch = vec![
+            (create_user("user_0", "same@example.com"), "hash".to_string()),
+            (create_user("user_1", "same@example.com"), "hash".to_string()),
+        ];
+        assert!(matches!(repo.create_many(batch).await, Err(AuthError::UserAlreadyExists)));
+        assert_eq!(repo.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_users_seeds_given_users() {
+        let existing = InMemoryUserRepository::new();
+        let mut admin = existing.create(create_user("admin", "admin@example.com"), "hash".into()).await.unwrap();
+        admin.roles = vec!["admin".to_string()];
+
+        let repo = InMemoryUserRepository::with_users(vec![admin.clone()]);
+        let found = repo.find_by_id(admin.id).await.unwrap().unwrap();
+        assert_eq!(found.roles, vec!["admin".to_string()]);
+
+        let user = repo.insert_with_password("john_doe", "john@example.com", "Password123!").unwrap();
+        assert!(crypto::verify_password(&user.password_hash, "Password123!").unwrap());
+        assert!(matches!(
+            repo.insert_with_password("admin", "other@example.com", "Password123!"),
+            Err(AuthError::UserAlreadyExists)
+        ));
+        assert_eq!(repo.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_stats_counts_by_activity() {
+        let repo = InMemoryUserRepository::new();
+        assert_eq!(repo.stats().await.unwrap(), UserStats::default());
+
+        for i in 0..5 {
+            let user = repo.create(create_user(&format!("user_{i}"), &format!("user_{i}@example.com")), "hash".into()).await.unwrap();
+            if i < 2 {
+                repo.set_active(user.id, false).await.unwrap();
+            }
+        }
+
+        assert_eq!(repo.stats().await.unwrap(), UserStats { total: 5, active: 3, inactive: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_search_combines_filters() {
+        let repo = InMemoryUserRepository::new();
+        repo.create(create_user("old_user", "old@example.com"), "hash".into()).await.unwrap();
+        let cutoff = Utc::now();
+        repo.create(create_user("new_active", "active@example.com"), "hash".into()).await.unwrap();
+        let inactive = repo.create(create_user("new_inactive", "inactive@example.com"), "hash".into()).await.unwrap();
+        repo.set_active(inactive.id, false).await.unwrap();
+
+        let found = repo.search(UserFilter {
+            is_active: Some(true),
+            created_after: Some(cutoff),
+            ..UserFilter::default()
+        }).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].username, "new_active");
+
+        let found = repo.search(UserFilter { username: Some("NEW".into()), ..UserFilter::default() }).await.unwrap();
+        assert_eq!(found.len(), 2);
+
+        let found = repo.search(UserFilter { email: Some("old@".into()), ..UserFilter::default() }).await.unwrap();
+        assert_eq!(found[0].username, "old_user");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_active_finders_skip_deactivated_users() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        assert!(repo.find_active_by_username("john_doe").await.unwrap().is_some());
+
+        repo.set_active(user.id, false).await.unwrap();
+
+        assert!(repo.find_active_by_username("john_doe").await.unwrap().is_none());
+        assert!(repo.find_active_by_email("john@example.com").await.unwrap().is_none());
+        assert!(repo.find_active_by_id(user.id).await.unwrap().is_none());
+        // The admin finders still return it
+        assert!(repo.find_by_username("john_doe").await.unwrap().is_some());
+        assert!(repo.find_by_id(user.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_finders_dont_cross_tenants() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(CreateUser { tenant_id: Some("acme".into()), ..create_user("john_doe", "john@example.com") }, "hash".into()).await.unwrap();
+
+        assert!(repo.find_by_username_in_tenant(Some("acme"), "john_doe").await.unwrap().is_some());
+        assert!(repo.find_by_username_in_tenant(Some("globex"), "john_doe").await.unwrap().is_none());
+        assert!(repo.find_by_email_in_tenant(Some("globex"), "john@example.com").await.unwrap().is_none());
+        assert!(repo.find_by_id_in_tenant(Some("globex"), user.id).await.unwrap().is_none());
+        // Nor from the context without a tenant
+        assert!(repo.find_by_id_in_tenant(None, user.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oauth_link_is_found_and_unique() {
+        let repo = InMemoryUserRepository::new();
+        let john = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        let jane = repo.create(create_user("jane_doe", "jane@example.com"), "hash".into()).await.unwrap();
+
+        repo.link_oauth(john.id, "google", "1234").await.unwrap();
+        assert_eq!(repo.find_by_oauth("google", "1234").await.unwrap().map(|u| u.id), Some(john.id));
+        assert!(repo.find_by_oauth("github", "1234").await.unwrap().is_none());
+
+        // The external account belongs to john
+        let result = repo.link_oauth(jane.id, "google", "1234").await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+        let result = repo.link_oauth(Uuid::new_v4(), "google", "5678").await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_password_history_keeps_newest_hashes() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash0".into()).await.unwrap();
+
+        for hash in ["hash1", "hash2", "hash3"] {
+            repo.add_password_history(user.id, hash.to_string(), 2).await.unwrap();
+        }
+
+        assert_eq!(repo.password_history(user.id).await.unwrap(), vec!["hash3", "hash2"]);
+        assert!(repo.password_history(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_frees_email_and_username() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash0".into()).await.unwrap();
+        repo.add_password_history(user.id, "hash1".into(), 5).await.unwrap();
+
+        repo.delete(user.id).await.unwrap();
+
+        assert!(repo.find_by_id(user.id).await.unwrap().is_none());
+        assert!(repo.find_by_email("john@example.com").await.unwrap().is_none());
+        assert!(repo.password_history(user.id).await.unwrap().is_empty());
+        assert!(matches!(repo.delete(user.id).await, Err(AuthError::UserNotFound)));
+        // Both can be registered again
+        repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_search_empty_filter_returns_all() {
+        let repo = InMemoryUserRepository::new();
+        repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        repo.create(create_user("jane_doe", "jane@example.com"), "hash".into()).await.unwrap();
+
+        assert_eq!(repo.search(UserFilter::default()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_poisoned_lock_is_recovered() {
+        let repo = InMemoryUserRepository::new();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        // A thread panics while holding the locks
+        let poisoner = repo.clone();
+        let panicked = std::thread::spawn(move || {
+            let _users = poisoner.users.lock().unwrap();
+            let _history = poisoner.password_history.lock().unwrap();
+            panic!("panic while holding the locks");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(repo.users.is_poisoned());
+
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().username, "john_doe");
+        repo.create(create_user("jane_doe", "jane@example.com"), "hash".into()).await.unwrap();
+        repo.set_active(user.id, false).await.unwrap();
+        repo.add_password_history(user.id, "hash0".to_string(), 5).await.unwrap();
+        assert_eq!(repo.count().await.unwrap(), 2);
+        assert_eq!(repo.password_history(user.id).await.unwrap(), vec!["hash0"]);
+    }
+}
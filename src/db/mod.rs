@@ -6,6 +6,31 @@ pub mod user_repository;
 /// In-memory implementation (for development and testing)
 pub mod memory_connection;
 
+/// Revoked tokens store (trait + in-memory implementation)
+pub mod token_blacklist;
+
+/// API keys store (trait + in-memory implementation)
+pub mod api_key_store;
+
+/// Sessions (logins) store (trait + in-memory implementation)
+pub mod session_store;
+
+/// Decorator caching the finders of another repository for a TTL
+pub mod caching_repository;
+
+/// Refresh token families, for the rotation of refresh tokens (trait + in-memory implementation)
+pub mod refresh_token_store;
+
+/// Connection pool settings and retried connection of the sqlx backends
+pub mod pool;
+
+/// Invite codes store, for invite-only registration (trait + in-memory implementation)
+pub mod invite_store;
+
+/// Passkeys of the users and started WebAuthn ceremonies (traits + in-memory implementations, feature "webauthn")
+#[cfg(feature = "webauthn")]
+pub mod passkey_store;
+
 /// PostgreSQL implementation (optional - feature "postgres")
 #[cfg(feature = "postgres")]
 pub mod postgres_connection;
@@ -20,4 +45,73 @@ pub mod sqlite_connection;
 
 /// MongoDB implementation (optional - feature "mongodb")
 #[cfg(feature = "mongodb")]
-pub mod mongodb_connection;
\ No newline at end of file
+pub mod mongodb_connection;
+
+
+// LIKE pattern matching values that contain `text`
+// The LIKE wildcards in `text` are escaped with '!' (queries use ESCAPE '!')
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite"))]
+pub(crate) fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_");
+    format!("%{}%", escaped)
+}
+
+// Maps an INSERT (or UPDATE) error: unique constraint violations are UserAlreadyExists
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite"))]
+pub(crate) fn insert_error(error: sqlx::Error) -> crate::errors::AuthError {
+    match error.as_database_error() {
+        Some(e) if e.is_unique_violation() => crate::errors::AuthError::UserAlreadyExists,
+        _ => crate::errors::AuthError::DatabaseError,
+    }
+}
+
+// Columns of the users table read or written by the sqlx repositories
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite"))]
+pub(crate) const USER_COLUMNS: &[&str] = &[
+    "id", "username", "username_canonical", "email", "password_hash", "created_at", "updated_at",
+    "is_active", "roles", "email_verified", "last_login_at", "pending_email", "tenant_id", "token_version",
+    "oauth_provider", "oauth_subject",
+];
+
+// Compares the columns found in the users table with USER_COLUMNS
+// No column at all means the table doesn't exist
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite"))]
+pub(crate) fn check_user_columns(found: &[String]) -> Result<(), crate::errors::AuthError> {
+    if found.is_empty() {
+        return Err(crate::errors::AuthError::SchemaMismatch(
+            "table `users` not found, run the migrations".to_string(),
+        ));
+    }
+
+    let missing: Vec<&str> = USER_COLUMNS
+        .iter()
+        .copied()
+        .filter(|column| !found.iter().any(|name| name.eq_ignore_ascii_case(column)))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(crate::errors::AuthError::SchemaMismatch(format!(
+            "table `users` is missing the columns: {}",
+            missing.join(", "),
+        )))
+    }
+}
+
+// MySQL and SQLite store roles as a comma-separated column ("admin,editor")
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn roles_to_column(roles: &[String]) -> String {
+    roles.join(",")
+}
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn roles_from_column(column: &str) -> Vec<String> {
+    column
+        .split(',')
+        .filter(|role| !role.is_empty())
+        .map(str::to_string)
+        .collect()
+}
\ No newline at end of file
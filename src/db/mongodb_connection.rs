@@ -16,9 +16,9 @@
 #[cfg(feature = "mongodb")]
 use async_trait::async_trait;
 #[cfg(feature = "mongodb")]
-use mongodb::{Client, Collection};
+use mongodb::{Client, Collection, error::{Error, ErrorKind, WriteFailure}};
 #[cfg(feature = "mongodb")]
-use mongodb::bson::doc;
+use mongodb::bson::{doc, to_bson, Document};
 #[cfg(feature = "mongodb")]
 use uuid::Uuid;
 #[cfg(feature = "mongodb")]
@@ -28,7 +28,7 @@ use serde::{Serialize, Deserialize};
 #[cfg(feature = "mongodb")]
 use crate::{
     db::user_repository::UserRepository,
-    models::user::{User, CreateUser},
+    models::{user::{User, CreateUser, UpdateUser, UserFilter, UserStats}, validation::canonical_username},
     errors::AuthError,
 };
 
@@ -38,11 +38,74 @@ struct UserDocument {
     #[serde(rename = "_id")]
     id: String,
     username: String,
+    /// `canonical_username(username)`, what `find_by_username` looks up
+    #[serde(default)]
+    username_canonical: String,
     email: String,
     password_hash: String,
     created_at: chrono::DateTime<Utc>,
     updated_at: chrono::DateTime<Utc>,
     is_active: bool,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    email_verified: bool,
+    #[serde(default)]
+    last_login_at: Option<chrono::DateTime<Utc>>,
+    #[serde(default)]
+    pending_email: Option<String>,
+    #[serde(default)]
+    tenant_id: Option<String>,
+    #[serde(default)]
+    token_version: i64,
+    // Left out of the document when unset, so the partial unique index only covers linked users
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    oauth_provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    oauth_subject: Option<String>,
+    /// Previous password hashes, newest first
+    #[serde(default)]
+    password_history: Vec<String>,
+}
+
+// Maps a document to a User
+// A malformed document (bad id) is a DatabaseError instead of a panic
+#[cfg(feature = "mongodb")]
+fn user_from_document(d: UserDocument) -> Result<User, AuthError> {
+    Ok(User {
+        id: Uuid::parse_str(&d.id).map_err(|_| AuthError::DatabaseError)?,
+        username: d.username,
+        email: d.email,
+        password_hash: d.password_hash,
+        created_at: d.created_at,
+        updated_at: d.updated_at,
+        is_active: d.is_active,
+        roles: d.roles,
+        email_verified: d.email_verified,
+        last_login_at: d.last_login_at,
+        pending_email: d.pending_email,
+        tenant_id: d.tenant_id,
+        token_version: d.token_version,
+        oauth_provider: d.oauth_provider,
+        oauth_subject: d.oauth_subject,
+    })
+}
+
+// Duplicate key on the unique indexes (see examples/mongodb_setup.rs)
+#[cfg(feature = "mongodb")]
+const DUPLICATE_KEY: i32 = 11000;
+
+// Maps an insert (or update) error: duplicate keys are UserAlreadyExists,
+// like the unique constraint violations of the SQL databases
+#[cfg(feature = "mongodb")]
+fn write_error(error: Error) -> AuthError {
+    let duplicate = match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::InsertMany(e) => e.write_errors.as_ref().is_some_and(|errors| errors.iter().any(|we| we.code == DUPLICATE_KEY)),
+        _ => false,
+    };
+
+    if duplicate { AuthError::UserAlreadyExists } else { AuthError::DatabaseError }
 }
 
 #[cfg(feature = "mongodb")]
@@ -56,6 +119,50 @@ impl MongoDBUserRepository {
         let collection = client.database(database_name).collection("users");
         Self { collection }
     }
+
+    // The user matching `query`
+    async fn find_user(&self, query: Document) -> Result<Option<User>, AuthError> {
+        let doc = self.collection
+            .find_one(query)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        doc.map(user_from_document).transpose()
+    }
+
+    // Users matching `query` ordered by creation date, the `(limit, offset)` page of them if given
+    async fn find_users(&self, query: Document, page: Option<(u32, u32)>) -> Result<Vec<User>, AuthError> {
+        let find = self.collection.find(query).sort(doc! { "created_at": 1, "_id": 1 });
+        let find = match page {
+            Some((limit, offset)) => find.skip(offset as u64).limit(limit as i64),
+            None => find,
+        };
+        let mut cursor = find.await.map_err(|_| AuthError::DatabaseError)?;
+
+        let mut users = Vec::new();
+        while cursor.advance().await.map_err(|_| AuthError::DatabaseError)? {
+            let d = cursor.deserialize_current().map_err(|_| AuthError::DatabaseError)?;
+            users.push(user_from_document(d)?);
+        }
+
+        Ok(users)
+    }
+
+    async fn count_users(&self, query: Document) -> Result<u64, AuthError> {
+        self.collection
+            .count_documents(query)
+            .await
+            .map_err(|_| AuthError::DatabaseError)
+    }
+
+    // Number of users matching `query`, in total and by activity status
+    async fn user_stats(&self, mut query: Document) -> Result<UserStats, AuthError> {
+        let total = self.count_users(query.clone()).await?;
+        query.insert("is_active", true);
+        let active = self.count_users(query).await?;
+
+        Ok(UserStats { total, active, inactive: total.saturating_sub(active) })
+    }
 }
 
 #[cfg(feature = "mongodb")]
@@ -68,17 +175,27 @@ impl UserRepository for MongoDBUserRepository {
         let doc = UserDocument {
             id: id.to_string(),
             username: user.username.clone(),
+            username_canonical: canonical_username(&user.username),
             email: user.email.clone(),
             password_hash: password_hash.clone(),
             created_at: now,
             updated_at: now,
             is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: None,
+            pending_email: None,
+            tenant_id: user.tenant_id.clone(),
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
+            password_history: Vec::new(),
         };
 
         self.collection
             .insert_one(doc)
             .await
-            .map_err(|_| AuthError::DatabaseError)?;
+            .map_err(write_error)?;
 
         Ok(User {
             id,
@@ -88,41 +205,79 @@ impl UserRepository for MongoDBUserRepository {
             created_at: now,
             updated_at: now,
             is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: None,
+            pending_email: None,
+            tenant_id: user.tenant_id,
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
         })
     }
 
+    async fn create_many(&self, batch: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+        let now = Utc::now();
+
+        let docs: Vec<UserDocument> = batch
+            .into_iter()
+            .map(|(user, password_hash)| UserDocument {
+                id: Uuid::new_v4().to_string(),
+                username_canonical: canonical_username(&user.username),
+                username: user.username,
+                email: user.email,
+                password_hash,
+                created_at: now,
+                updated_at: now,
+                is_active: true,
+                roles: Vec::new(),
+                email_verified: false,
+                last_login_at: None,
+                pending_email: None,
+                tenant_id: user.tenant_id,
+                token_version: 0,
+                oauth_provider: None,
+                oauth_subject: None,
+                password_history: Vec::new(),
+            })
+            .collect();
+        let ids: Vec<String> = docs.iter().map(|d| d.id.clone()).collect();
+
+        if let Err(e) = self.collection.insert_many(&docs).await {
+            // Transactions need a replica set, so the inserted documents are deleted instead
+            let _ = self.collection.delete_many(doc! { "_id": { "$in": &ids } }).await;
+
+            return Err(write_error(e));
+        }
+
+        docs.into_iter().map(user_from_document).collect()
+    }
+
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
         let doc = self.collection
             .find_one(doc! { "email": email })
             .await
             .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(doc.map(|d| User {
-            id: Uuid::parse_str(&d.id).unwrap(),
-            username: d.username,
-            email: d.email,
-            password_hash: d.password_hash,
-            created_at: d.created_at,
-            updated_at: d.updated_at,
-            is_active: d.is_active,
-        }))
+        doc.map(user_from_document).transpose()
     }
 
     async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
         let doc = self.collection
-            .find_one(doc! { "username": username })
+            .find_one(doc! { "username_canonical": canonical_username(username) })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        doc.map(user_from_document).transpose()
+    }
+
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        let doc = self.collection
+            .find_one(doc! { "$or": [{ "email": email }, { "username_canonical": canonical_username(username) }] })
             .await
             .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(doc.map(|d| User {
-            id: Uuid::parse_str(&d.id).unwrap(),
-            username: d.username,
-            email: d.email,
-            password_hash: d.password_hash,
-            created_at: d.created_at,
-            updated_at: d.updated_at,
-            is_active: d.is_active,
-        }))
+        doc.map(user_from_document).transpose()
     }
 
     async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
@@ -131,14 +286,349 @@ impl UserRepository for MongoDBUserRepository {
             .await
             .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(doc.map(|d| User {
-            id: Uuid::parse_str(&d.id).unwrap(),
-            username: d.username,
-            email: d.email,
-            password_hash: d.password_hash,
-            created_at: d.created_at,
-            updated_at: d.updated_at,
-            is_active: d.is_active,
-        }))
+        doc.map(user_from_document).transpose()
+    }
+
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+        let doc = self.collection
+            .find_one(doc! { "oauth_provider": provider, "oauth_subject": subject })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        doc.map(user_from_document).transpose()
+    }
+
+    async fn find_active_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "email": email, "is_active": true }).await
+    }
+
+    async fn find_active_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "username_canonical": canonical_username(username), "is_active": true }).await
+    }
+
+    async fn find_active_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "_id": id.to_string(), "is_active": true }).await
+    }
+
+    // `"tenant_id": null` also matches the documents without the field
+    async fn find_by_email_in_tenant(&self, tenant_id: Option<&str>, email: &str) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "email": email, "tenant_id": tenant_id }).await
+    }
+
+    async fn find_by_username_in_tenant(&self, tenant_id: Option<&str>, username: &str) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "username_canonical": canonical_username(username), "tenant_id": tenant_id }).await
+    }
+
+    async fn find_by_id_in_tenant(&self, tenant_id: Option<&str>, id: Uuid) -> Result<Option<User>, AuthError> {
+        self.find_user(doc! { "_id": id.to_string(), "tenant_id": tenant_id }).await
+    }
+
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+        // Only the fields that were given are set
+        // (dates are stored the same way serde writes them in `create`)
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+        let mut set = doc! { "updated_at": now };
+        if let Some(username) = changes.username {
+            set.insert("username_canonical", canonical_username(&username));
+            set.insert("username", username);
+        }
+        if let Some(email) = changes.email {
+            set.insert("email", email);
+        }
+        if let Some(password_hash) = password_hash {
+            set.insert("password_hash", password_hash);
+        }
+        if let Some(roles) = changes.roles {
+            set.insert("roles", roles);
+        }
+
+        let result = self.collection
+            .update_one(doc! { "_id": id.to_string() }, doc! { "$set": set })
+            .await
+            .map_err(write_error)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        self.find_by_id(id).await?.ok_or(AuthError::UserNotFound)
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "email_verified": true, "updated_at": now } },
+            )
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "pending_email": email, "updated_at": now } },
+            )
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string(), "pending_email": email },
+                doc! {
+                    "$set": { "email": email, "email_verified": true, "updated_at": now },
+                    "$unset": { "pending_email": "" },
+                },
+            )
+            .await
+            .map_err(write_error)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "last_login_at": now } },
+            )
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.collection
+            .update_one(doc! { "_id": id.to_string() }, doc! { "$inc": { "token_version": 1_i64 } })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+        // Checked here too, as the unique index of examples/mongodb_setup.rs is optional
+        if let Some(linked) = self.find_by_oauth(provider, subject).await?
+            && linked.id != id
+        {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "oauth_provider": provider, "oauth_subject": subject, "updated_at": now } },
+            )
+            .await
+            .map_err(write_error)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+        let now = to_bson(&Utc::now()).map_err(|_| AuthError::InternalError)?;
+
+        let result = self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$set": { "is_active": is_active, "updated_at": now } },
+            )
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.matched_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    // The password history is part of the document
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = self.collection
+            .delete_one(doc! { "_id": id.to_string() })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.deleted_count == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        self.find_users(doc! {}, Some((limit, offset))).await
+    }
+
+    async fn count(&self) -> Result<u64, AuthError> {
+        self.count_users(doc! {}).await
+    }
+
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+        let document = self.collection
+            .find_one(doc! { "_id": id.to_string() })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(document.map(|d| d.password_history).unwrap_or_default())
+    }
+
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+        // Pushed in front, then cut to the `keep` first ones
+        self.collection
+            .update_one(
+                doc! { "_id": id.to_string() },
+                doc! { "$push": { "password_history": {
+                    "$each": [password_hash],
+                    "$position": 0,
+                    "$slice": keep as i64,
+                } } },
+            )
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(())
+    }
+
+        async fn ping(&self) -> Result<(), AuthError> {
+        let database = self.collection.client().database(&self.collection.namespace().db);
+        database
+            .run_command(doc! { "ping": 1 })
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn stats(&self) -> Result<UserStats, AuthError> {
+        self.user_stats(doc! {}).await
+    }
+
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        self.find_users(search_query(filter)?, None).await
+    }
+
+    async fn list_in_tenant(&self, tenant_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        self.find_users(doc! { "tenant_id": tenant_id }, Some((limit, offset))).await
+    }
+
+    async fn count_in_tenant(&self, tenant_id: Option<&str>) -> Result<u64, AuthError> {
+        self.count_users(doc! { "tenant_id": tenant_id }).await
+    }
+
+    async fn stats_in_tenant(&self, tenant_id: Option<&str>) -> Result<UserStats, AuthError> {
+        self.user_stats(doc! { "tenant_id": tenant_id }).await
+    }
+
+    async fn search_in_tenant(&self, tenant_id: Option<&str>, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        let mut query = search_query(filter)?;
+        query.insert("tenant_id", tenant_id);
+        self.find_users(query, None).await
+    }
+}
+
+
+// Query of the users matching every criteria of `filter`
+// Substrings are matched with an escaped, case-insensitive regex
+#[cfg(feature = "mongodb")]
+fn search_query(filter: UserFilter) -> Result<Document, AuthError> {
+    let mut query = doc! {};
+    if let Some(username) = &filter.username {
+        query.insert("username", doc! { "$regex": regex::escape(username), "$options": "i" });
+    }
+    if let Some(email) = &filter.email {
+        query.insert("email", doc! { "$regex": regex::escape(email), "$options": "i" });
+    }
+    if let Some(is_active) = filter.is_active {
+        query.insert("is_active", is_active);
+    }
+    if let Some(created_after) = filter.created_after {
+        let created_after = to_bson(&created_after).map_err(|_| AuthError::InternalError)?;
+        query.insert("created_at", doc! { "$gt": created_after });
+    }
+
+    Ok(query)
+}
+
+
+#[cfg(all(test, feature = "mongodb"))]
+mod tests {
+    use super::*;
+    use mongodb::{IndexModel, options::IndexOptions};
+
+    fn create_user(username: &str, email: &str) -> CreateUser {
+        CreateUser {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }
+    }
+
+    // Needs a server: MONGODB_URI=mongodb://localhost:27017 cargo test --features mongodb -- --ignored
+    #[tokio::test]
+    #[ignore = "needs a MongoDB server (MONGODB_URI)"]
+    async fn test_duplicate_email_is_a_conflict() {
+        let uri = std::env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+        let client = Client::with_uri_str(&uri).await.unwrap();
+        let database = format!("auth_test_{}", Uuid::new_v4().simple());
+
+        // Same unique index as examples/mongodb_setup.rs
+        client.database(&database).collection::<mongodb::bson::Document>("users")
+            .create_index(IndexModel::builder()
+                .keys(doc! { "email": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build())
+            .await
+            .unwrap();
+
+        let repo = MongoDBUserRepository::new(client.clone(), &database);
+        repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        let result = repo.create(create_user("jane_doe", "john@example.com"), "hash".into()).await;
+
+        client.database(&database).drop().await.unwrap();
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
     }
 }
@@ -0,0 +1,171 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use webauthn_rs::prelude::{AuthenticationResult, Passkey, PasskeyAuthentication, PasskeyRegistration};
+use crate::errors::AuthError;
+
+/// Trait that defines the operations on the users' passkeys
+///
+/// Each user can have several passkeys (one per device), keyed by the user id.
+/// Only their public keys and signature counters are held, the private keys never leave the authenticators.
+#[async_trait]
+pub trait PasskeyStore: Send + Sync {
+    // Add a passkey registered by the user
+    async fn add(&self, user_id: Uuid, passkey: Passkey) -> Result<(), AuthError>;
+
+    // Passkeys of the user (empty when they have none)
+    async fn list(&self, user_id: Uuid) -> Result<Vec<Passkey>, AuthError>;
+
+    // Store the signature counter (and backup state) of the passkey used to log in
+    async fn update(&self, user_id: Uuid, result: &AuthenticationResult) -> Result<(), AuthError>;
+}
+
+
+/// State of a started ceremony, needed to verify the authenticator's answer
+#[derive(Clone)]
+pub enum PasskeyCeremony {
+    Registration(PasskeyRegistration),
+    Authentication(PasskeyAuthentication),
+}
+
+/// A ceremony waiting for its `/finish` call
+#[derive(Clone)]
+pub struct PendingCeremony {
+    /// User registering the passkey, or logging in with it
+    pub user_id: Uuid,
+    pub ceremony: PasskeyCeremony,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Trait that defines the operations on the started ceremonies
+///
+/// The state of each ceremony stays on the server between `/start` and `/finish`, keyed by
+/// the challenge id given to the client. `take` must remove it atomically, so an answer
+/// can't be replayed. Expired ceremonies can be kept, they are rejected by the handlers.
+/// `now` is given by the handlers (the clock of the tokens), the one they check `expires_at` with.
+#[async_trait]
+pub trait PasskeyChallengeStore: Send + Sync {
+    // Keep the ceremony until it is finished, ceremonies expired at `now` may be dropped
+    async fn insert(&self, challenge_id: Uuid, pending: PendingCeremony, now: DateTime<Utc>) -> Result<(), AuthError>;
+
+    // Remove and return the ceremony (None if unknown or already finished)
+    async fn take(&self, challenge_id: Uuid) -> Result<Option<PendingCeremony>, AuthError>;
+}
+
+
+/// In-memory implementation of PasskeyStore
+///
+/// WARNING: Passkeys are lost when the process ends, users must register them again!
+#[derive(Clone, Default)]
+pub struct InMemoryPasskeyStore {
+    /// Thread-safe map: user id -> passkeys
+    passkeys: Arc<Mutex<HashMap<Uuid, Vec<Passkey>>>>,
+}
+
+impl InMemoryPasskeyStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl PasskeyStore for InMemoryPasskeyStore {
+    async fn add(&self, user_id: Uuid, passkey: Passkey) -> Result<(), AuthError> {
+        self.passkeys.lock().unwrap().entry(user_id).or_default().push(passkey);
+        Ok(())
+    }
+
+    async fn list(&self, user_id: Uuid) -> Result<Vec<Passkey>, AuthError> {
+        Ok(self.passkeys.lock().unwrap().get(&user_id).cloned().unwrap_or_default())
+    }
+
+    async fn update(&self, user_id: Uuid, result: &AuthenticationResult) -> Result<(), AuthError> {
+        if let Some(passkeys) = self.passkeys.lock().unwrap().get_mut(&user_id) {
+            // Only the passkey with the credential id of the result is changed
+            for passkey in passkeys {
+                passkey.update_credential(result);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// In-memory implementation of PasskeyChallengeStore
+///
+/// WARNING: Started ceremonies are lost when the process ends, they must be started again!
+/// Each process has its own, so `/start` and `/finish` must reach the same instance.
+#[derive(Clone, Default)]
+pub struct InMemoryPasskeyChallengeStore {
+    /// Thread-safe map: challenge id -> ceremony
+    pending: Arc<Mutex<HashMap<Uuid, PendingCeremony>>>,
+}
+
+impl InMemoryPasskeyChallengeStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl PasskeyChallengeStore for InMemoryPasskeyChallengeStore {
+    async fn insert(&self, challenge_id: Uuid, pending: PendingCeremony, now: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut ceremonies = self.pending.lock().unwrap();
+        // The expired ones were never finished, they are dropped on the way
+        ceremonies.retain(|_, ceremony| ceremony.expires_at > now);
+        ceremonies.insert(challenge_id, pending);
+        Ok(())
+    }
+
+    async fn take(&self, challenge_id: Uuid) -> Result<Option<PendingCeremony>, AuthError> {
+        Ok(self.pending.lock().unwrap().remove(&challenge_id))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use crate::auth::webauthn::PasskeyProvider;
+
+    fn registration(user_id: Uuid) -> PasskeyCeremony {
+        let provider = PasskeyProvider::new("auth.example.com", "https://auth.example.com").unwrap();
+        PasskeyCeremony::Registration(provider.start_registration(user_id, "alice", &[]).unwrap().1)
+    }
+
+    #[tokio::test]
+    async fn test_ceremony_can_only_be_taken_once() {
+        let user_id = Uuid::new_v4();
+        let now = Utc::now();
+
+        let store = InMemoryPasskeyChallengeStore::new();
+        let challenge_id = Uuid::new_v4();
+        let pending = PendingCeremony { user_id, ceremony: registration(user_id), expires_at: now + Duration::minutes(5) };
+        store.insert(challenge_id, pending, now).await.unwrap();
+
+        assert!(store.take(Uuid::new_v4()).await.unwrap().is_none());
+        assert_eq!(store.take(challenge_id).await.unwrap().unwrap().user_id, user_id);
+        assert!(store.take(challenge_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_only_ceremonies_expired_at_the_given_time_are_dropped() {
+        // Long expired by the real time, not by the time given to the store
+        let now = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let user_id = Uuid::new_v4();
+
+        let store = InMemoryPasskeyChallengeStore::new();
+        let (live, expired) = (Uuid::new_v4(), Uuid::new_v4());
+        let pending = |expires_at| PendingCeremony { user_id, ceremony: registration(user_id), expires_at };
+        store.insert(expired, pending(now - Duration::seconds(1)), now - Duration::minutes(1)).await.unwrap();
+        store.insert(live, pending(now + Duration::minutes(5)), now).await.unwrap();
+
+        assert!(store.take(expired).await.unwrap().is_none());
+        assert!(store.take(live).await.unwrap().is_some());
+    }
+}
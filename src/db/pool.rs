@@ -0,0 +1,126 @@
+// This file is responsible for creating the connection pools of the sqlx backends,
+// retrying while the database is not reachable yet (e.g. starting with it in docker compose)
+
+use std::time::Duration;
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite", test))]
+use crate::backoff::Backoff;
+
+/// Settings of the connection pool of the sqlx repositories
+///
+/// Used by `PostgresUserRepository::connect`, `MySQLUserRepository::connect`
+/// and `SQLiteUserRepository::connect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    /// Connections kept open even when idle
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing
+    pub acquire_timeout: Duration,
+    /// Connection attempts after the first one fails (0 = fail right away)
+    pub connect_retries: u32,
+    /// Wait before the first retry, doubled after each failed attempt
+    pub retry_delay: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            connect_retries: 5,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Opens a pool for `database_url`, retrying with the delays of `config`
+///
+/// Returns the error of the last attempt when every attempt failed
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite"))]
+pub async fn connect_pool<DB: sqlx::Database>(database_url: &str, config: &PoolConfig) -> Result<sqlx::Pool<DB>, sqlx::Error> {
+    retry(config, || {
+        sqlx::pool::PoolOptions::<DB>::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .connect(database_url)
+    })
+    .await
+}
+
+// Runs `attempt` until it succeeds, at most `1 + connect_retries` times
+#[cfg(any(feature = "postgres", feature = "mysql", feature = "sqlite", test))]
+pub(crate) async fn retry<T, E, F, Fut>(config: &PoolConfig, mut attempt: F) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    // No jitter, a single server is connecting
+    let backoff = Backoff { base: config.retry_delay, max: Duration::MAX, jitter: false };
+    let mut retries_left = config.connect_retries;
+
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) if retries_left > 0 => {
+                let delay = backoff.delay(config.connect_retries - retries_left);
+                tracing::warn!(error = %error, retry_in = ?delay, retries_left, "database connection failed");
+                tokio::time::sleep(delay).await;
+                retries_left -= 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_config(connect_retries: u32) -> PoolConfig {
+        PoolConfig { connect_retries, retry_delay: Duration::from_millis(1), ..PoolConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_the_configured_retries() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), String> = retry(&fast_config(3), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err("connection refused".to_string())
+        })
+        .await;
+
+        assert_eq!(result.unwrap_err(), "connection refused");
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_stops_retrying_once_connected() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<u32, String> = retry(&fast_config(5), || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err("not ready".to_string()),
+                attempt => Ok(attempt),
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_bad_url_fails_after_retries() {
+        let started = std::time::Instant::now();
+        let config = PoolConfig { connect_retries: 2, retry_delay: Duration::from_millis(20), ..PoolConfig::default() };
+
+        let result = connect_pool::<sqlx::Sqlite>("sqlite:///nonexistent-dir/auth.db", &config).await;
+        assert!(result.is_err());
+        // Waited 20 ms then 40 ms between the three attempts
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+}
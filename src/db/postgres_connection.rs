@@ -7,8 +7,8 @@ use sqlx::PgPool;
 use uuid::Uuid;
 #[cfg(feature = "postgres")]
 use crate::{
-    db::user_repository::UserRepository,
-    models::user::{User, CreateUser},
+    db::{check_user_columns, pool::{connect_pool, PoolConfig}, contains_pattern, insert_error, user_repository::UserRepository},
+    models::{user::{User, CreateUser, UpdateUser, UserFilter, UserStats}, validation::canonical_username},
     errors::AuthError,
 };
 
@@ -17,7 +17,7 @@ use crate::{
 /// Uses SQLx to access the PostgreSQL database.
 /// Requires:
 /// - PostgreSQL installed and running
-/// - 'users' table created (see migrations)
+/// - 'users' table created (`run_migrations`, see migrations/postgres)
 /// - "postgres" feature enabled in Cargo.toml
 #[cfg(feature = "postgres")]
 pub struct PostgresUserRepository {
@@ -30,6 +30,27 @@ impl PostgresUserRepository {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
+
+    /// Connects to PostgreSQL with the pool settings of `config`,
+    /// retrying while the database is unreachable (see `PoolConfig`)
+    pub async fn connect(database_url: &str, config: &PoolConfig) -> Result<Self, sqlx::Error> {
+        Ok(Self::new(connect_pool::<sqlx::Postgres>(database_url, config).await?))
+    }
+
+    /// Creates or updates the tables, see `run_migrations`
+    pub async fn run_migrations(&self) -> Result<(), sqlx::migrate::MigrateError> {
+        run_migrations(&self.pool).await
+    }
+}
+
+/// Applies the migrations of `migrations/postgres` (embedded in the binary) that `pool`
+/// doesn't have yet, creating the tables and indexes on an empty database
+///
+/// The applied migrations are recorded in the `_sqlx_migrations` table.
+/// A database created by hand from the same SQL is accepted, the statements are idempotent.
+#[cfg(feature = "postgres")]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!("migrations/postgres").run(pool).await
 }
 
 #[cfg(feature = "postgres")]
@@ -42,26 +63,60 @@ impl UserRepository for PostgresUserRepository {
         let user = sqlx::query_as!(
             User,
             r#"
-            INSERT INTO users (id, username, email, password_hash, created_at, updated_at, is_active)
-            VALUES ($1, $2, $3, $4, NOW(), NOW(), true)
-            RETURNING id, username, email, password_hash, created_at, updated_at, is_active
+            INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at, is_active, tenant_id)
+            VALUES ($1, $2, $3, $4, $5, NOW(), NOW(), true, $6)
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
             "#,
             id,
             user.username,
+            canonical_username(&user.username),
             user.email,
-            password_hash
+            password_hash,
+            user.tenant_id
         )
         .fetch_one(&self.pool)
         .await
-        .map_err(|_| AuthError::DatabaseError)?;
+        .map_err(insert_error)?;
 
         Ok(user)
     }
 
+    async fn create_many(&self, batch: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+        // Dropping the transaction on error rolls back the users already inserted
+        let mut tx = self.pool.begin().await.map_err(|_| AuthError::DatabaseError)?;
+
+        let mut created = Vec::with_capacity(batch.len());
+        for (user, password_hash) in batch {
+            let user = sqlx::query_as!(
+                User,
+                r#"
+                INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at, is_active, tenant_id)
+                VALUES ($1, $2, $3, $4, $5, NOW(), NOW(), true, $6)
+                RETURNING id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+                "#,
+                Uuid::new_v4(),
+                user.username,
+                canonical_username(&user.username),
+                user.email,
+                password_hash,
+                user.tenant_id
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(insert_error)?;
+
+            created.push(user);
+        }
+
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(created)
+    }
+
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
         let user = sqlx::query_as!(
             User,
-            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active 
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
                FROM users WHERE email = $1"#,
             email
         )
@@ -75,9 +130,24 @@ impl UserRepository for PostgresUserRepository {
     async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
         let user = sqlx::query_as!(
             User,
-            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active 
-               FROM users WHERE username = $1"#,
-            username
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE username_canonical = $1"#,
+            canonical_username(username)
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE email = $1 OR username_canonical = $2 LIMIT 1"#,
+            email,
+            canonical_username(username)
         )
         .fetch_optional(&self.pool)
         .await
@@ -89,7 +159,7 @@ impl UserRepository for PostgresUserRepository {
     async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
         let user = sqlx::query_as!(
             User,
-            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active 
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
                FROM users WHERE id = $1"#,
             id
         )
@@ -99,4 +169,445 @@ impl UserRepository for PostgresUserRepository {
 
         Ok(user)
     }
+
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE oauth_provider = $1 AND oauth_subject = $2"#,
+            provider,
+            subject
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_active_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE email = $1 AND is_active"#,
+            email
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_active_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE username_canonical = $1 AND is_active"#,
+            canonical_username(username)
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_active_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE id = $1 AND is_active"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_by_email_in_tenant(&self, tenant_id: Option<&str>, email: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE email = $1 AND tenant_id IS NOT DISTINCT FROM $2::text"#,
+            email,
+            tenant_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_by_username_in_tenant(&self, tenant_id: Option<&str>, username: &str) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE username_canonical = $1 AND tenant_id IS NOT DISTINCT FROM $2::text"#,
+            canonical_username(username),
+            tenant_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn find_by_id_in_tenant(&self, tenant_id: Option<&str>, id: Uuid) -> Result<Option<User>, AuthError> {
+        let user = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE id = $1 AND tenant_id IS NOT DISTINCT FROM $2::text"#,
+            id,
+            tenant_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(user)
+    }
+
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+        // Fields left as NULL keep their current value
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET username = COALESCE($2, username),
+                username_canonical = COALESCE($3, username_canonical),
+                email = COALESCE($4, email),
+                password_hash = COALESCE($5, password_hash),
+                roles = COALESCE($6, roles),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+            "#,
+            id,
+            changes.username.as_deref(),
+            changes.username.as_deref().map(canonical_username),
+            changes.email,
+            password_hash,
+            changes.roles.as_deref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        // A changed username or email may be taken
+        .map_err(insert_error)?;
+
+        user.ok_or(AuthError::UserNotFound)
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query!(
+            "UPDATE users SET email_verified = TRUE, updated_at = NOW() WHERE id = $1",
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let result = sqlx::query!(
+            "UPDATE users SET pending_email = $1, updated_at = NOW() WHERE id = $2",
+            email,
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        // The UNIQUE constraint on email rejects an address taken since the change was requested
+        let result = sqlx::query!(
+            r#"UPDATE users SET email = pending_email, pending_email = NULL, email_verified = TRUE, updated_at = NOW()
+            WHERE id = $1 AND pending_email = $2"#,
+            id,
+            email
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(insert_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query!(
+            "UPDATE users SET last_login_at = NOW() WHERE id = $1",
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query!(
+            "UPDATE users SET token_version = token_version + 1 WHERE id = $1",
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+        // The unique index on (oauth_provider, oauth_subject) rejects an account linked to another user
+        let result = sqlx::query!(
+            "UPDATE users SET oauth_provider = $2, oauth_subject = $3, updated_at = NOW() WHERE id = $1",
+            id,
+            provider,
+            subject
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(insert_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+        let result = sqlx::query!(
+            "UPDATE users SET is_active = $2, updated_at = NOW() WHERE id = $1",
+            id,
+            is_active
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    // The password history goes with it (ON DELETE CASCADE)
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query!("DELETE FROM users WHERE id = $1", id)
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let users = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users ORDER BY created_at, id LIMIT $1 OFFSET $2"#,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(users)
+    }
+
+    async fn count(&self) -> Result<u64, AuthError> {
+        let count = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM users"#)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(count as u64)
+    }
+
+    async fn stats(&self) -> Result<UserStats, AuthError> {
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) as "total!", COUNT(*) FILTER (WHERE is_active) as "active!" FROM users"#
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(UserStats { total: row.total as u64, active: row.active as u64, inactive: (row.total - row.active) as u64 })
+    }
+
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+        sqlx::query_scalar("SELECT password_hash FROM password_history WHERE user_id = $1 ORDER BY id DESC")
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)
+    }
+
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+        let mut tx = self.pool.begin().await.map_err(|_| AuthError::DatabaseError)?;
+
+        sqlx::query("INSERT INTO password_history (user_id, password_hash) VALUES ($1, $2)")
+            .bind(id)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        sqlx::query(
+            "DELETE FROM password_history WHERE user_id = $1 AND id NOT IN
+             (SELECT id FROM password_history WHERE user_id = $1 ORDER BY id DESC LIMIT $2)"
+        )
+        .bind(id)
+        .bind(keep as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)
+    }
+
+    async fn ping(&self) -> Result<(), AuthError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn verify_schema(&self) -> Result<(), AuthError> {
+        let columns = sqlx::query_scalar!(
+            r#"SELECT column_name::text as "column_name!" FROM information_schema.columns
+               WHERE table_schema = current_schema() AND table_name = 'users'"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        check_user_columns(&columns)
+    }
+
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        // A criteria left as NULL matches every row
+        let users = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users
+               WHERE ($1::text IS NULL OR username ILIKE $1 ESCAPE '!')
+                 AND ($2::text IS NULL OR email ILIKE $2 ESCAPE '!')
+                 AND ($3::bool IS NULL OR is_active = $3)
+                 AND ($4::timestamptz IS NULL OR created_at > $4)
+               ORDER BY created_at, id"#,
+            filter.username.as_deref().map(contains_pattern),
+            filter.email.as_deref().map(contains_pattern),
+            filter.is_active,
+            filter.created_after
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(users)
+    }
+
+    // `IS NOT DISTINCT FROM`, so None matches the users without a tenant (NULL)
+    async fn list_in_tenant(&self, tenant_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let users = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users WHERE tenant_id IS NOT DISTINCT FROM $1::text ORDER BY created_at, id LIMIT $2 OFFSET $3"#,
+            tenant_id,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(users)
+    }
+
+    async fn count_in_tenant(&self, tenant_id: Option<&str>) -> Result<u64, AuthError> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!" FROM users WHERE tenant_id IS NOT DISTINCT FROM $1::text"#,
+            tenant_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(count as u64)
+    }
+
+    async fn stats_in_tenant(&self, tenant_id: Option<&str>) -> Result<UserStats, AuthError> {
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) as "total!", COUNT(*) FILTER (WHERE is_active) as "active!" FROM users WHERE tenant_id IS NOT DISTINCT FROM $1::text"#,
+            tenant_id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(UserStats { total: row.total as u64, active: row.active as u64, inactive: (row.total - row.active) as u64 })
+    }
+
+    async fn search_in_tenant(&self, tenant_id: Option<&str>, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        let users = sqlx::query_as!(
+            User,
+            r#"SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject
+               FROM users
+               WHERE tenant_id IS NOT DISTINCT FROM $1::text
+                 AND ($2::text IS NULL OR username ILIKE $2 ESCAPE '!')
+                 AND ($3::text IS NULL OR email ILIKE $3 ESCAPE '!')
+                 AND ($4::bool IS NULL OR is_active = $4)
+                 AND ($5::timestamptz IS NULL OR created_at > $5)
+               ORDER BY created_at, id"#,
+            tenant_id,
+            filter.username.as_deref().map(contains_pattern),
+            filter.email.as_deref().map(contains_pattern),
+            filter.is_active,
+            filter.created_after
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(users)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,137 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use crate::errors::AuthError;
+
+/// Trait that defines the operations on the refresh token families
+///
+/// A family is the chain of refresh tokens of one session (keyed by the session id):
+/// each rotation at `POST /token/refresh-rotate` replaces its current refresh token by a new one.
+/// A refresh token of the family that is not the current one was already used,
+/// presenting it again means it was stolen and the whole family is revoked.
+///
+/// Only the current jti of each family is held, until its `expires_at`; `rotate` must
+/// compare and replace it atomically, so a refresh token can't be rotated twice.
+/// `now` is given by the callers (the clock of the tokens), so both agree on what expired.
+#[async_trait]
+pub trait RefreshTokenStore: Send + Sync {
+    // Start the family `family` with its first refresh token `jti`, valid until `expires_at`
+    async fn issue(&self, family: &str, jti: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), AuthError>;
+
+    // Jti of the refresh token of the family that may still be used at `now`
+    // (None for an unknown, expired or revoked family)
+    async fn current(&self, family: &str, now: DateTime<Utc>) -> Result<Option<String>, AuthError>;
+
+    // Make `new_jti` the current refresh token of the family, only if `jti` still is (and didn't expire at `now`)
+    // Returns false otherwise (already rotated, or unknown family), nothing is changed then
+    async fn rotate(&self, family: &str, jti: &str, new_jti: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, AuthError>;
+
+    // Forget the family, its refresh tokens can't be rotated anymore
+    async fn revoke(&self, family: &str) -> Result<(), AuthError>;
+}
+
+
+// Current refresh token of a family
+#[derive(Clone)]
+struct Family {
+    jti: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// In-memory implementation of RefreshTokenStore
+///
+/// WARNING: Families are lost when the process ends, their refresh tokens can't be rotated anymore!
+#[derive(Clone, Default)]
+pub struct InMemoryRefreshTokenStore {
+    /// Thread-safe map: family -> current refresh token
+    families: Arc<Mutex<HashMap<String, Family>>>,
+}
+
+impl InMemoryRefreshTokenStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenStore for InMemoryRefreshTokenStore {
+    async fn issue(&self, family: &str, jti: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut families = self.families.lock().unwrap();
+
+        // Expired families are dropped here, so the map doesn't grow forever
+        families.retain(|_, entry| entry.expires_at > now);
+
+        families.insert(family.to_string(), Family { jti: jti.to_string(), expires_at });
+        Ok(())
+    }
+
+    async fn current(&self, family: &str, now: DateTime<Utc>) -> Result<Option<String>, AuthError> {
+        Ok(self.families.lock().unwrap()
+            .get(family)
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.jti.clone()))
+    }
+
+    async fn rotate(&self, family: &str, jti: &str, new_jti: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<bool, AuthError> {
+        let mut families = self.families.lock().unwrap();
+
+        match families.get_mut(family) {
+            Some(current) if current.jti == jti && current.expires_at > now => {
+                *current = Family { jti: new_jti.to_string(), expires_at };
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke(&self, family: &str) -> Result<(), AuthError> {
+        self.families.lock().unwrap().remove(family);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    // Far from the real time, the store must only go by the given `now`
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_the_current_token_rotates() {
+        let store = InMemoryRefreshTokenStore::new();
+        let expires_at = now() + Duration::hours(1);
+        store.issue("session-1", "r1", expires_at, now()).await.unwrap();
+
+        assert!(store.rotate("session-1", "r1", "r2", expires_at, now()).await.unwrap());
+        assert_eq!(store.current("session-1", now()).await.unwrap().as_deref(), Some("r2"));
+
+        // r1 was rotated out, and the family of another session is unknown
+        assert!(!store.rotate("session-1", "r1", "r3", expires_at, now()).await.unwrap());
+        assert!(!store.rotate("session-2", "r2", "r3", expires_at, now()).await.unwrap());
+        assert_eq!(store.current("session-1", now()).await.unwrap().as_deref(), Some("r2"));
+
+        store.revoke("session-1").await.unwrap();
+        assert_eq!(store.current("session-1", now()).await.unwrap(), None);
+        assert!(!store.rotate("session-1", "r2", "r3", expires_at, now()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_expired_family_is_not_rotated() {
+        let store = InMemoryRefreshTokenStore::new();
+        store.issue("session-1", "r1", now() - Duration::seconds(1), now()).await.unwrap();
+
+        assert_eq!(store.current("session-1", now()).await.unwrap(), None);
+        assert!(!store.rotate("session-1", "r1", "r2", now() + Duration::hours(1), now()).await.unwrap());
+
+        // Not expired yet an hour earlier
+        store.issue("session-2", "r1", now() - Duration::seconds(1), now() - Duration::hours(1)).await.unwrap();
+        assert_eq!(store.current("session-2", now() - Duration::hours(1)).await.unwrap().as_deref(), Some("r1"));
+    }
+}
@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::{errors::AuthError, models::session::Session};
+
+/// Trait that defines the operations on the sessions (logins) of the users
+///
+/// Sessions are only a listing of the issued tokens: removing one doesn't
+/// revoke its tokens, the handlers also add it to the `TokenBlacklist`.
+///
+/// A session is kept until its token expires (`expires_at`): expired ones are left out of
+/// `list`, and implementations may drop them whenever convenient.
+/// `now` is given by the callers (the clock of the tokens), so both agree on what expired.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    // Store a new session
+    async fn create(&self, session: Session, now: DateTime<Utc>) -> Result<(), AuthError>;
+
+    // Sessions of the user that didn't expire at `now`, oldest first
+    async fn list(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<Vec<Session>, AuthError>;
+
+    // Remove the session `jti` of the user, returns false if the user has no such session
+    async fn remove(&self, user_id: Uuid, jti: &str) -> Result<bool, AuthError>;
+
+    // Remove every session of the user, returns the removed sessions
+    async fn remove_all(&self, user_id: Uuid) -> Result<Vec<Session>, AuthError>;
+}
+
+
+/// In-memory implementation of SessionStore
+///
+/// WARNING: Sessions are lost when the process ends!
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    /// Thread-safe map: jti -> Session
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl InMemorySessionStore {
+    // Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn create(&self, session: Session, now: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        // Expired sessions are dropped here, so the map doesn't grow forever
+        sessions.retain(|_, session| session.expires_at > now);
+
+        sessions.insert(session.jti.clone(), session);
+        Ok(())
+    }
+
+    async fn list(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<Vec<Session>, AuthError> {
+        let mut sessions: Vec<Session> = self.sessions.lock().unwrap()
+            .values()
+            .filter(|session| session.user_id == user_id && session.expires_at > now)
+            .cloned()
+            .collect();
+
+        sessions.sort_by_key(|session| session.issued_at);
+        Ok(sessions)
+    }
+
+    async fn remove(&self, user_id: Uuid, jti: &str) -> Result<bool, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        match sessions.get(jti) {
+            Some(session) if session.user_id == user_id => {
+                sessions.remove(jti);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn remove_all(&self, user_id: Uuid) -> Result<Vec<Session>, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let jtis: Vec<String> = sessions.values()
+            .filter(|session| session.user_id == user_id)
+            .map(|session| session.jti.clone())
+            .collect();
+
+        Ok(jtis.iter().filter_map(|jti| sessions.remove(jti)).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    // Far from the real time, the store must only go by the given `now`
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    fn session(jti: &str, user_id: Uuid, expires_in: Duration) -> Session {
+        Session {
+            jti: jti.to_string(),
+            user_id,
+            issued_at: now(),
+            expires_at: now() + expires_in,
+            user_agent: None,
+            ip: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_only_returns_live_sessions_of_the_user() {
+        let store = InMemorySessionStore::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        store.create(session("a1", alice, Duration::hours(1)), now()).await.unwrap();
+        store.create(session("a2", alice, Duration::seconds(-1)), now()).await.unwrap();
+        store.create(session("b1", bob, Duration::hours(1)), now()).await.unwrap();
+
+        let sessions = store.list(alice, now()).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].jti, "a1");
+        assert!(store.list(alice, now() + Duration::hours(2)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_only_own_sessions() {
+        let store = InMemorySessionStore::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        store.create(session("a1", alice, Duration::hours(1)), now()).await.unwrap();
+        store.create(session("a2", alice, Duration::hours(1)), now()).await.unwrap();
+
+        assert!(!store.remove(bob, "a1").await.unwrap());
+        assert!(store.remove(alice, "a1").await.unwrap());
+        assert_eq!(store.list(alice, now()).await.unwrap().len(), 1);
+
+        assert_eq!(store.remove_all(alice).await.unwrap().len(), 1);
+        assert!(store.list(alice, now()).await.unwrap().is_empty());
+    }
+}
@@ -9,15 +9,31 @@
 /// 2. Configure DATABASE_URL in .env:
 ///    DATABASE_URL=sqlite://auth.db
 /// 
-/// 3. Create the table:
+/// 3. Create the tables with `run_migrations` (migrations/sqlite), or by hand:
 ///    CREATE TABLE users (
-///        id TEXT PRIMARY KEY,
+///        id TEXT PRIMARY KEY NOT NULL,
 ///        username TEXT UNIQUE NOT NULL,
+///        username_canonical TEXT UNIQUE NOT NULL,
 ///        email TEXT UNIQUE NOT NULL,
 ///        password_hash TEXT NOT NULL,
 ///        created_at TEXT NOT NULL,
 ///        updated_at TEXT NOT NULL,
-///        is_active INTEGER DEFAULT 1
+///        is_active INTEGER NOT NULL DEFAULT 1,
+///        roles TEXT NOT NULL DEFAULT '',
+///        email_verified INTEGER NOT NULL DEFAULT 0,
+///        last_login_at TEXT,
+///        pending_email TEXT,
+///        tenant_id TEXT,
+///        token_version INTEGER NOT NULL DEFAULT 0,
+///        oauth_provider TEXT,
+///        oauth_subject TEXT
+///    );
+///    CREATE UNIQUE INDEX idx_users_oauth ON users(oauth_provider, oauth_subject);
+///    CREATE TABLE password_history (
+///        id INTEGER PRIMARY KEY AUTOINCREMENT,
+///        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+///        password_hash TEXT NOT NULL,
+///        created_at TEXT NOT NULL
 ///    );
 
 #[cfg(feature = "sqlite")]
@@ -30,8 +46,8 @@ use uuid::Uuid;
 use chrono::Utc;
 #[cfg(feature = "sqlite")]
 use crate::{
-    db::user_repository::UserRepository,
-    models::user::{User, CreateUser},
+    db::{check_user_columns, pool::{connect_pool, PoolConfig}, contains_pattern, insert_error, roles_from_column, roles_to_column, user_repository::UserRepository},
+    models::{user::{User, CreateUser, UpdateUser, UserFilter, UserStats}, validation::canonical_username},
     errors::AuthError,
 };
 
@@ -45,6 +61,98 @@ impl SQLiteUserRepository {
     pub fn new(pool: SqlitePool) -> Self {
         Self { pool }
     }
+
+    /// Connects to SQLite with the pool settings of `config`,
+    /// retrying while the database is unreachable (see `PoolConfig`)
+    pub async fn connect(database_url: &str, config: &PoolConfig) -> Result<Self, sqlx::Error> {
+        Ok(Self::new(connect_pool::<sqlx::Sqlite>(database_url, config).await?))
+    }
+
+    /// Creates or updates the tables, see `run_migrations`
+    pub async fn run_migrations(&self) -> Result<(), sqlx::migrate::MigrateError> {
+        run_migrations(&self.pool).await
+    }
+}
+
+/// Applies the migrations of `migrations/sqlite` (embedded in the binary) that `pool`
+/// doesn't have yet, creating the tables and indexes on an empty database
+///
+/// The applied migrations are recorded in the `_sqlx_migrations` table.
+/// A database created by hand with `0001_create_users.sql` is accepted (its statements are idempotent),
+/// the later migrations upgrade it.
+#[cfg(feature = "sqlite")]
+pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!("migrations/sqlite").run(pool).await
+}
+
+// A users row as stored by SQLite: ids and dates are text, booleans are integers,
+// roles are comma-separated (types as inferred by `query_as!` from the migration)
+#[cfg(feature = "sqlite")]
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: String,
+    username: String,
+    email: String,
+    password_hash: String,
+    created_at: String,
+    updated_at: String,
+    is_active: i64,
+    roles: String,
+    email_verified: i64,
+    last_login_at: Option<String>,
+    pending_email: Option<String>,
+    tenant_id: Option<String>,
+    token_version: i64,
+    oauth_provider: Option<String>,
+    oauth_subject: Option<String>,
+}
+
+// Maps a row to a User
+// A malformed row (bad id or date) is a DatabaseError instead of a panic
+#[cfg(feature = "sqlite")]
+fn user_from_row(row: UserRow) -> Result<User, AuthError> {
+    Ok(User {
+        id: Uuid::parse_str(&row.id).map_err(|_| AuthError::DatabaseError)?,
+        username: row.username,
+        email: row.email,
+        password_hash: row.password_hash,
+        created_at: parse_timestamp(&row.created_at)?,
+        updated_at: parse_timestamp(&row.updated_at)?,
+        is_active: row.is_active != 0,
+        roles: roles_from_column(&row.roles),
+        email_verified: row.email_verified != 0,
+        last_login_at: row.last_login_at.as_deref().map(parse_timestamp).transpose()?,
+        pending_email: row.pending_email,
+        tenant_id: row.tenant_id,
+        token_version: row.token_version,
+        oauth_provider: row.oauth_provider,
+        oauth_subject: row.oauth_subject,
+    })
+}
+
+// SELECT of users into UserRow, with the query arguments
+// With the "checked-queries" feature it is `query_as!`, checked at compile time against
+// DATABASE_URL (or the .sqlx cache when SQLX_OFFLINE=true), otherwise a runtime query
+#[cfg(all(feature = "sqlite", feature = "checked-queries"))]
+macro_rules! query_users {
+    ($sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as!(UserRow, $sql $(, $arg)*)
+    };
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "checked-queries")))]
+macro_rules! query_users {
+    ($sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as::<_, UserRow>($sql)$(.bind($arg))*
+    };
+}
+
+// Timestamps are stored as RFC 3339 text
+#[cfg(feature = "sqlite")]
+fn parse_timestamp(value: &str) -> Result<chrono::DateTime<Utc>, AuthError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| AuthError::DatabaseError)
 }
 
 #[cfg(feature = "sqlite")]
@@ -56,20 +164,24 @@ impl UserRepository for SQLiteUserRepository {
         
         sqlx::query(
             r#"
-            INSERT INTO users (id, username, email, password_hash, created_at, updated_at, is_active)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at, is_active, roles, email_verified, tenant_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#
         )
         .bind(id.to_string())
         .bind(&user.username)
+        .bind(canonical_username(&user.username))
         .bind(&user.email)
         .bind(&password_hash)
         .bind(now.to_rfc3339())
         .bind(now.to_rfc3339())
         .bind(1)
+        .bind("")
+        .bind(false)
+        .bind(&user.tenant_id)
         .execute(&self.pool)
         .await
-        .map_err(|_| AuthError::DatabaseError)?;
+        .map_err(insert_error)?;
 
         Ok(User {
             id,
@@ -79,66 +191,899 @@ impl UserRepository for SQLiteUserRepository {
             created_at: now,
             updated_at: now,
             is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: None,
+            pending_email: None,
+            tenant_id: user.tenant_id,
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
         })
     }
 
+    async fn create_many(&self, batch: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError> {
+        let now = Utc::now();
+        // Dropping the transaction on error rolls back the users already inserted
+        let mut tx = self.pool.begin().await.map_err(|_| AuthError::DatabaseError)?;
+
+        let mut created = Vec::with_capacity(batch.len());
+        for (user, password_hash) in batch {
+            let id = Uuid::new_v4();
+
+            sqlx::query(
+                r#"
+                INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at, is_active, roles, email_verified, tenant_id)
+                VALUES (?, ?, ?, ?, ?, ?, ?, 1, '', 0, ?)
+                "#
+            )
+            .bind(id.to_string())
+            .bind(&user.username)
+            .bind(canonical_username(&user.username))
+            .bind(&user.email)
+            .bind(&password_hash)
+            .bind(now.to_rfc3339())
+            .bind(now.to_rfc3339())
+            .bind(&user.tenant_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(insert_error)?;
+
+            created.push(User {
+                id,
+                username: user.username,
+                email: user.email,
+                password_hash,
+                created_at: now,
+                updated_at: now,
+                is_active: true,
+                roles: Vec::new(),
+                email_verified: false,
+                last_login_at: None,
+                pending_email: None,
+                tenant_id: user.tenant_id,
+                token_version: 0,
+                oauth_provider: None,
+                oauth_subject: None,
+            });
+        }
+
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(created)
+    }
+
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
-        let result = sqlx::query_as::<_, (String, String, String, String, String, String, i32)>(
-            "SELECT id, username, email, password_hash, created_at, updated_at, is_active FROM users WHERE email = ?"
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE email = ?",
+            email,
         )
-        .bind(email)
         .fetch_optional(&self.pool)
         .await
         .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(result.map(|(id, username, email, password_hash, created_at, updated_at, is_active)| User {
-            id: Uuid::parse_str(&id).unwrap(),
-            username,
-            email,
-            password_hash,
-            created_at: chrono::DateTime::parse_from_rfc3339(&created_at).unwrap().with_timezone(&Utc),
-            updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at).unwrap().with_timezone(&Utc),
-            is_active: is_active != 0,
-        }))
+        result.map(user_from_row).transpose()
     }
 
     async fn find_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
-        let result = sqlx::query_as::<_, (String, String, String, String, String, String, i32)>(
-            "SELECT id, username, email, password_hash, created_at, updated_at, is_active FROM users WHERE username = ?"
+        let canonical = canonical_username(username);
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE username_canonical = ?",
+            canonical,
         )
-        .bind(username)
         .fetch_optional(&self.pool)
         .await
         .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(result.map(|(id, username, email, password_hash, created_at, updated_at, is_active)| User {
-            id: Uuid::parse_str(&id).unwrap(),
-            username,
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        let canonical = canonical_username(username);
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE email = ? OR username_canonical = ? LIMIT 1",
             email,
-            password_hash,
-            created_at: chrono::DateTime::parse_from_rfc3339(&created_at).unwrap().with_timezone(&Utc),
-            updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at).unwrap().with_timezone(&Utc),
-            is_active: is_active != 0,
-        }))
+            canonical,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
     }
 
     async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
-        let result = sqlx::query_as::<_, (String, String, String, String, String, String, i32)>(
-            "SELECT id, username, email, password_hash, created_at, updated_at, is_active FROM users WHERE id = ?"
+        let id = id.to_string();
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE id = ?",
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError> {
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE oauth_provider = ? AND oauth_subject = ?",
+            provider,
+            subject,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_active_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE email = ? AND is_active",
+            email,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_active_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        let canonical = canonical_username(username);
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE username_canonical = ? AND is_active",
+            canonical,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_active_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        let id = id.to_string();
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE id = ? AND is_active",
+            id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_by_email_in_tenant(&self, tenant_id: Option<&str>, email: &str) -> Result<Option<User>, AuthError> {
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE email = ? AND tenant_id IS ?",
+            email,
+            tenant_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_by_username_in_tenant(&self, tenant_id: Option<&str>, username: &str) -> Result<Option<User>, AuthError> {
+        let canonical = canonical_username(username);
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE username_canonical = ? AND tenant_id IS ?",
+            canonical,
+            tenant_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        result.map(user_from_row).transpose()
+    }
+
+    async fn find_by_id_in_tenant(&self, tenant_id: Option<&str>, id: Uuid) -> Result<Option<User>, AuthError> {
+        let id = id.to_string();
+        let result = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE id = ? AND tenant_id IS ?",
+            id,
+            tenant_id,
         )
-        .bind(id.to_string())
         .fetch_optional(&self.pool)
         .await
         .map_err(|_| AuthError::DatabaseError)?;
 
-        Ok(result.map(|(id, username, email, password_hash, created_at, updated_at, is_active)| User {
-            id: Uuid::parse_str(&id).unwrap(),
+        result.map(user_from_row).transpose()
+    }
+
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError> {
+        // Fields left as NULL keep their current value
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET username = COALESCE(?, username),
+                username_canonical = COALESCE(?, username_canonical),
+                email = COALESCE(?, email),
+                password_hash = COALESCE(?, password_hash),
+                roles = COALESCE(?, roles),
+                updated_at = ?
+            WHERE id = ?
+            "#
+        )
+        .bind(changes.username.as_deref())
+        .bind(changes.username.as_deref().map(canonical_username))
+        .bind(changes.email)
+        .bind(password_hash)
+        .bind(changes.roles.as_deref().map(roles_to_column))
+        .bind(Utc::now().to_rfc3339())
+        .bind(id.to_string())
+        .execute(&self.pool)
+        .await
+        // A changed username or email may be taken
+        .map_err(insert_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        self.find_by_id(id).await?.ok_or(AuthError::UserNotFound)
+    }
+
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET email_verified = 1, updated_at = ? WHERE id = ?")
+            .bind(Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET pending_email = ?, updated_at = ? WHERE id = ?")
+            .bind(email)
+            .bind(Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError> {
+        // The UNIQUE constraint on email rejects an address taken since the change was requested
+        let result = sqlx::query(
+            "UPDATE users SET email = pending_email, pending_email = NULL, email_verified = 1, updated_at = ? WHERE id = ? AND pending_email = ?"
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(id.to_string())
+        .bind(email)
+        .execute(&self.pool)
+        .await
+        .map_err(insert_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET last_login_at = ? WHERE id = ?")
+            .bind(Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = ?")
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError> {
+        // The unique index on (oauth_provider, oauth_subject) rejects an account linked to another user
+        let result = sqlx::query("UPDATE users SET oauth_provider = ?, oauth_subject = ?, updated_at = ? WHERE id = ?")
+            .bind(provider)
+            .bind(subject)
+            .bind(Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(insert_error)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError> {
+        let result = sqlx::query("UPDATE users SET is_active = ?, updated_at = ? WHERE id = ?")
+            .bind(is_active)
+            .bind(Utc::now().to_rfc3339())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    // The password history goes with it (ON DELETE CASCADE)
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let rows = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users ORDER BY created_at, id LIMIT ? OFFSET ?",
+            limit,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        rows.into_iter().map(user_from_row).collect()
+    }
+
+    async fn count(&self) -> Result<u64, AuthError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(count as u64)
+    }
+
+    async fn stats(&self) -> Result<UserStats, AuthError> {
+        // Single scan: COUNT ignores the NULLs of the CASE for inactive users
+        let (total, active): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(CASE WHEN is_active THEN 1 END) FROM users"
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(UserStats { total: total as u64, active: active as u64, inactive: (total - active) as u64 })
+    }
+
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError> {
+        sqlx::query_scalar("SELECT password_hash FROM password_history WHERE user_id = ? ORDER BY id DESC")
+            .bind(id.to_string())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)
+    }
+
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError> {
+        let mut tx = self.pool.begin().await.map_err(|_| AuthError::DatabaseError)?;
+
+        sqlx::query("INSERT INTO password_history (user_id, password_hash, created_at) VALUES (?, ?, ?)")
+            .bind(id.to_string())
+            .bind(password_hash)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        sqlx::query(
+            "DELETE FROM password_history WHERE user_id = ?1 AND id NOT IN
+             (SELECT id FROM password_history WHERE user_id = ?1 ORDER BY id DESC LIMIT ?2)"
+        )
+        .bind(id.to_string())
+        .bind(keep as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)
+    }
+
+    async fn ping(&self) -> Result<(), AuthError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn verify_schema(&self) -> Result<(), AuthError> {
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('users')")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        check_user_columns(&columns)
+    }
+
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        // A criteria left as NULL matches every row
+        let username = filter.username.as_deref().map(contains_pattern);
+        let email = filter.email.as_deref().map(contains_pattern);
+        let created_after = filter.created_after.map(|date| date.to_rfc3339());
+
+        let rows = query_users!(
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users
+            WHERE (?1 IS NULL OR username LIKE ?1 ESCAPE '!')
+              AND (?2 IS NULL OR email LIKE ?2 ESCAPE '!')
+              AND (?3 IS NULL OR is_active = ?3)
+              AND (?4 IS NULL OR created_at > ?4)
+            ORDER BY created_at, id
+            "#,
             username,
             email,
-            password_hash,
-            created_at: chrono::DateTime::parse_from_rfc3339(&created_at).unwrap().with_timezone(&Utc),
-            updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at).unwrap().with_timezone(&Utc),
-            is_active: is_active != 0,
-        }))
+            filter.is_active,
+            created_after,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        rows.into_iter().map(user_from_row).collect()
+    }
+
+    // The tenant is compared with `IS`, so None matches the users without a tenant (NULL)
+    async fn list_in_tenant(&self, tenant_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let rows = query_users!(
+            "SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users WHERE tenant_id IS ? ORDER BY created_at, id LIMIT ? OFFSET ?",
+            tenant_id,
+            limit,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        rows.into_iter().map(user_from_row).collect()
+    }
+
+    async fn count_in_tenant(&self, tenant_id: Option<&str>) -> Result<u64, AuthError> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE tenant_id IS ?")
+            .bind(tenant_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(count as u64)
+    }
+
+    async fn stats_in_tenant(&self, tenant_id: Option<&str>) -> Result<UserStats, AuthError> {
+        let (total, active): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(CASE WHEN is_active THEN 1 END) FROM users WHERE tenant_id IS ?"
+        )
+        .bind(tenant_id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        Ok(UserStats { total: total as u64, active: active as u64, inactive: (total - active) as u64 })
+    }
+
+    async fn search_in_tenant(&self, tenant_id: Option<&str>, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        let username = filter.username.as_deref().map(contains_pattern);
+        let email = filter.email.as_deref().map(contains_pattern);
+        let created_after = filter.created_after.map(|date| date.to_rfc3339());
+
+        let rows = query_users!(
+            r#"
+            SELECT id, username, email, password_hash, created_at, updated_at, is_active, roles, email_verified, last_login_at, pending_email, tenant_id, token_version, oauth_provider, oauth_subject FROM users
+            WHERE tenant_id IS ?1
+              AND (?2 IS NULL OR username LIKE ?2 ESCAPE '!')
+              AND (?3 IS NULL OR email LIKE ?3 ESCAPE '!')
+              AND (?4 IS NULL OR is_active = ?4)
+              AND (?5 IS NULL OR created_at > ?5)
+            ORDER BY created_at, id
+            "#,
+            tenant_id,
+            username,
+            email,
+            filter.is_active,
+            created_after,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+
+        rows.into_iter().map(user_from_row).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // A single connection, so every query sees the same in-memory database
+    async fn repo() -> SQLiteUserRepository {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        run_migrations(&pool).await.unwrap();
+        SQLiteUserRepository::new(pool)
+    }
+
+    async fn empty_pool() -> SqlitePool {
+        SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_create_the_schema() {
+        let pool = empty_pool().await;
+        run_migrations(&pool).await.unwrap();
+        // Already applied: nothing to do
+        run_migrations(&pool).await.unwrap();
+
+        let repo = SQLiteUserRepository::new(pool);
+        repo.verify_schema().await.unwrap();
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        assert_eq!(repo.find_by_username("john_doe").await.unwrap().unwrap().id, user.id);
+        repo.add_password_history(user.id, "old".to_string(), 5).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrations_accept_a_schema_created_by_hand() {
+        let pool = empty_pool().await;
+        sqlx::raw_sql(include_str!("../../migrations/sqlite/0001_create_users.sql")).execute(&pool).await.unwrap();
+
+        run_migrations(&pool).await.unwrap();
+        assert!(SQLiteUserRepository::new(pool).verify_schema().await.is_ok());
+    }
+
+    fn create_user(username: &str, email: &str) -> CreateUser {
+        CreateUser {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_and_find_roundtrip() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let found = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(found.username, "john_doe");
+        assert_eq!(found.created_at.timestamp(), user.created_at.timestamp());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_is_stored_and_scopes_lookups() {
+        let repo = repo().await;
+        let user = repo.create(CreateUser { tenant_id: Some("acme".into()), ..create_user("john_doe", "john@example.com") }, "hash".into()).await.unwrap();
+
+        let found = repo.find_by_id_in_tenant(Some("acme"), user.id).await.unwrap().unwrap();
+        assert_eq!(found.tenant_id.as_deref(), Some("acme"));
+        assert!(repo.find_by_username_in_tenant(Some("globex"), "john_doe").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_page_only_holds_the_tenant_users() {
+        let repo = repo().await;
+        for (i, tenant) in ["acme", "globex", "acme", "acme"].into_iter().enumerate() {
+            let create = CreateUser { tenant_id: Some(tenant.into()), ..create_user(&format!("user_{i}"), &format!("user_{i}@example.com")) };
+            let user = repo.create(create, "hash".into()).await.unwrap();
+            if i == 3 {
+                repo.set_active(user.id, false).await.unwrap();
+            }
+        }
+        repo.create(create_user("no_tenant", "no_tenant@example.com"), "hash".into()).await.unwrap();
+
+        let page = repo.list_in_tenant(Some("acme"), 2, 1).await.unwrap();
+        let usernames: Vec<_> = page.iter().map(|user| user.username.as_str()).collect();
+        assert_eq!(usernames, ["user_2", "user_3"]);
+        assert_eq!(repo.count_in_tenant(Some("acme")).await.unwrap(), 3);
+        assert_eq!(repo.stats_in_tenant(Some("acme")).await.unwrap(), UserStats { total: 3, active: 2, inactive: 1 });
+        assert_eq!(repo.count_in_tenant(None).await.unwrap(), 1);
+
+        let filter = UserFilter { is_active: Some(true), ..UserFilter::default() };
+        assert_eq!(repo.search_in_tenant(Some("globex"), filter).await.unwrap()[0].username, "user_1");
+        assert!(repo.find_active_by_username("user_3").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_username_is_unique_and_found_ignoring_case() {
+        let repo = repo().await;
+        repo.create(create_user("JohnDoe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let found = repo.find_by_username("johndoe").await.unwrap().unwrap();
+        assert_eq!(found.username, "JohnDoe");
+
+        let result = repo.create(create_user("johndoe", "jane@example.com"), "hash".into()).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn test_find_by_email_or_username_matches_either_field() {
+        let repo = repo().await;
+        let user = repo.create(create_user("JohnDoe", "john@example.com"), "hash".into()).await.unwrap();
+
+        let by_email = repo.find_by_email_or_username("john@example.com", "jane_doe").await.unwrap();
+        assert_eq!(by_email.map(|u| u.id), Some(user.id));
+
+        let by_username = repo.find_by_email_or_username("jane@example.com", "johndoe").await.unwrap();
+        assert_eq!(by_username.map(|u| u.id), Some(user.id));
+
+        assert!(repo.find_by_email_or_username("jane@example.com", "jane_doe").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_many_imports_all_users() {
+        let repo = repo().await;
+        let batch = (0..100)
+            .map(|i| (create_user(&format!("user_{i}"), &format!("user_{i}@example.com")), "hash".to_string()))
+            .collect();
+
+        assert_eq!(repo.create_many(batch).await.unwrap().len(), 100);
+        assert_eq!(repo.count().await.unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_create_many_duplicate_rolls_back() {
+        let repo = repo().await;
+        let batch = vec![
+            (create_user("user_0", "user_0@example.com"), "hash".to_string()),
+            (create_user("user_1", "user_1@example.com"), "hash".to_string()),
+            (create_user("user_0", "other@example.com"), "hash".to_string()),
+        ];
+
+        let result = repo.create_many(batch).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+        assert_eq!(repo.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stats_counts_by_activity() {
+        let repo = repo().await;
+        assert_eq!(repo.stats().await.unwrap(), UserStats::default());
+
+        for i in 0..4 {
+            let user = repo.create(create_user(&format!("user_{i}"), &format!("user_{i}@example.com")), "hash".into()).await.unwrap();
+            if i == 0 {
+                repo.set_active(user.id, false).await.unwrap();
+            }
+        }
+
+        assert_eq!(repo.stats().await.unwrap(), UserStats { total: 4, active: 3, inactive: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_pending_email_is_confirmed() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+
+        repo.set_pending_email(user.id, "new@example.com").await.unwrap();
+        let pending = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(pending.email, "john@example.com");
+        assert_eq!(pending.pending_email.as_deref(), Some("new@example.com"));
+
+        // Only the pending address can be confirmed
+        assert!(matches!(repo.confirm_pending_email(user.id, "other@example.com").await, Err(AuthError::UserNotFound)));
+
+        repo.confirm_pending_email(user.id, "new@example.com").await.unwrap();
+        let confirmed = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(confirmed.email, "new@example.com");
+        assert!(confirmed.pending_email.is_none());
+        assert!(confirmed.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_oauth_link_is_found_and_unique() {
+        let repo = repo().await;
+        let john = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        let jane = repo.create(create_user("jane_doe", "jane@example.com"), "hash".into()).await.unwrap();
+
+        repo.link_oauth(john.id, "google", "1234").await.unwrap();
+        let linked = repo.find_by_oauth("google", "1234").await.unwrap().unwrap();
+        assert_eq!(linked.id, john.id);
+        assert_eq!(linked.oauth_provider.as_deref(), Some("google"));
+        assert!(repo.find_by_oauth("github", "1234").await.unwrap().is_none());
+
+        // The unique index keeps the external account to john
+        assert!(matches!(repo.link_oauth(jane.id, "google", "1234").await, Err(AuthError::UserAlreadyExists)));
+        assert!(matches!(repo.link_oauth(Uuid::new_v4(), "google", "5678").await, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_touch_last_login() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        assert!(repo.find_by_id(user.id).await.unwrap().unwrap().last_login_at.is_none());
+
+        repo.touch_last_login(user.id).await.unwrap();
+        assert!(repo.find_by_id(user.id).await.unwrap().unwrap().last_login_at.is_some());
+
+        let result = repo.touch_last_login(Uuid::new_v4()).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_increment_token_version() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash".into()).await.unwrap();
+        assert_eq!(user.token_version, 0);
+
+        repo.increment_token_version(user.id).await.unwrap();
+        repo.increment_token_version(user.id).await.unwrap();
+        assert_eq!(repo.find_by_id(user.id).await.unwrap().unwrap().token_version, 2);
+
+        let result = repo.increment_token_version(Uuid::new_v4()).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_bad_uuid_row_is_database_error() {
+        let repo = repo().await;
+        let now = Utc::now().to_rfc3339();
+        sqlx::query("INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)")
+            .bind("not-a-uuid")
+            .bind("broken")
+            .bind("broken")
+            .bind("broken@example.com")
+            .bind("hash")
+            .bind(&now)
+            .bind(&now)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let result = repo.find_by_username("broken").await;
+        assert!(matches!(result, Err(AuthError::DatabaseError)));
+
+        let result = repo.list(10, 0).await;
+        assert!(matches!(result, Err(AuthError::DatabaseError)));
+    }
+
+    #[tokio::test]
+    async fn test_bad_timestamp_row_is_database_error() {
+        let repo = repo().await;
+        sqlx::query("INSERT INTO users (id, username, username_canonical, email, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?)")
+            .bind(Uuid::new_v4().to_string())
+            .bind("broken")
+            .bind("broken")
+            .bind("broken@example.com")
+            .bind("hash")
+            .bind("yesterday")
+            .bind("yesterday")
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let result = repo.find_by_email("broken@example.com").await;
+        assert!(matches!(result, Err(AuthError::DatabaseError)));
+    }
+
+    #[tokio::test]
+    async fn test_search_filters_in_sql() {
+        let repo = repo().await;
+        repo.create(create_user("old_user", "old@example.com"), "hash".into()).await.unwrap();
+        let cutoff = Utc::now();
+        repo.create(create_user("new_active", "active@example.com"), "hash".into()).await.unwrap();
+        let inactive = repo.create(create_user("new_inactive", "inactive@example.com"), "hash".into()).await.unwrap();
+        repo.set_active(inactive.id, false).await.unwrap();
+
+        let found = repo.search(UserFilter {
+            is_active: Some(true),
+            created_after: Some(cutoff),
+            ..UserFilter::default()
+        }).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].username, "new_active");
+
+        // "_" is a literal underscore, not the LIKE wildcard
+        let found = repo.search(UserFilter { username: Some("w_a".into()), ..UserFilter::default() }).await.unwrap();
+        assert_eq!(found[0].username, "new_active");
+        let found = repo.search(UserFilter { username: Some("_".into()), ..UserFilter::default() }).await.unwrap();
+        assert_eq!(found.len(), 3);
+        let found = repo.search(UserFilter { username: Some("d_u".into()), ..UserFilter::default() }).await.unwrap();
+        assert_eq!(found.len(), 1);
+
+        assert_eq!(repo.search(UserFilter::default()).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_password_history_keeps_newest_hashes() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash0".into()).await.unwrap();
+
+        for hash in ["hash1", "hash2", "hash3"] {
+            repo.add_password_history(user.id, hash.to_string(), 2).await.unwrap();
+        }
+
+        assert_eq!(repo.password_history(user.id).await.unwrap(), vec!["hash3", "hash2"]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_removes_user_and_history() {
+        let repo = repo().await;
+        let user = repo.create(create_user("john_doe", "john@example.com"), "hash0".into()).await.unwrap();
+        repo.add_password_history(user.id, "hash1".into(), 5).await.unwrap();
+
+        repo.delete(user.id).await.unwrap();
+
+        assert!(repo.find_by_id(user.id).await.unwrap().is_none());
+        assert!(repo.password_history(user.id).await.unwrap().is_empty());
+        assert!(matches!(repo.delete(user.id).await, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_ping_reaches_the_pool() {
+        assert!(repo().await.ping().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema_accepts_migrated_table() {
+        assert!(repo().await.verify_schema().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema_names_missing_column() {
+        let repo = repo().await;
+        sqlx::query("ALTER TABLE users DROP COLUMN pending_email")
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+
+        let error = repo.verify_schema().await.unwrap_err();
+        assert!(matches!(&error, AuthError::SchemaMismatch(_)));
+        assert_eq!(error.to_string(), "Database schema mismatch: table `users` is missing the columns: pending_email");
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema_without_table() {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        let error = SQLiteUserRepository::new(pool).verify_schema().await.unwrap_err();
+        assert!(error.to_string().contains("table `users` not found"));
     }
 }
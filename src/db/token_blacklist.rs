@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use crate::errors::AuthError;
+
+/// Trait that defines token revocation operations
+///
+/// JWTs are stateless, so a token stays valid until it expires.
+/// The blacklist stores the `jti` (unique token id) of revoked tokens,
+/// and the `AuthUser` extractor rejects any token found here.
+///
+/// Like `UserRepository`, it can be implemented for any storage
+/// (Redis, SQL table, In-Memory, etc).
+#[async_trait]
+pub trait TokenBlacklist: Send + Sync {
+    // Revoke the token with the given jti
+    // Returns false if it already was: checked and set atomically, so single-use tokens
+    // can be claimed by revoking them (only one of concurrent calls gets true)
+    async fn revoke(&self, jti: &str) -> Result<bool, AuthError>;
+
+    // Check if the token with the given jti was revoked
+    async fn is_revoked(&self, jti: &str) -> Result<bool, AuthError>;
+}
+
+
+/// In-memory implementation of TokenBlacklist
+///
+/// WARNING: Revocations are lost when the process ends!
+#[derive(Clone, Default)]
+pub struct InMemoryTokenBlacklist {
+    /// Thread-safe set of revoked jti
+    revoked: Arc<Mutex<HashSet<String>>>,
+}
+
+impl InMemoryTokenBlacklist {
+    // Create a new empty blacklist
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TokenBlacklist for InMemoryTokenBlacklist {
+    async fn revoke(&self, jti: &str) -> Result<bool, AuthError> {
+        Ok(self.revoked.lock().unwrap().insert(jti.to_string()))
+    }
+
+    async fn is_revoked(&self, jti: &str) -> Result<bool, AuthError> {
+        Ok(self.revoked.lock().unwrap().contains(jti))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_revoke_marks_token_as_revoked() {
+        let blacklist = InMemoryTokenBlacklist::new();
+        assert!(!blacklist.is_revoked("abc").await.unwrap());
+
+        assert!(blacklist.revoke("abc").await.unwrap());
+        assert!(blacklist.is_revoked("abc").await.unwrap());
+        assert!(!blacklist.revoke("abc").await.unwrap());
+        assert!(!blacklist.is_revoked("other").await.unwrap());
+    }
+}
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use crate::models::user::{User, CreateUser};
+use crate::models::user::{User, CreateUser, UpdateUser, UserFilter, UserStats};
 use crate::errors::AuthError;
 use uuid::Uuid;
 
@@ -21,6 +21,11 @@ pub trait UserRepository: Send + Sync {
     //Create a new user
     async fn create(&self, user: CreateUser, password_hash: String) -> Result<User, AuthError>;
 
+    // Create several users at once (seeding, migrations), each with its password hash
+    // All or nothing: if one collides (with a stored user or another of the batch),
+    // none is created and UserAlreadyExists is returned
+    async fn create_many(&self, users: Vec<(CreateUser, String)>) -> Result<Vec<User>, AuthError>;
+
     // Search user by email
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, AuthError>;
 
@@ -29,4 +34,140 @@ pub trait UserRepository: Send + Sync {
 
     // Search user by Id
     async fn find_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError>;
+
+    // Search a user having this email or this username (either one, when they are two users)
+    // One round trip instead of find_by_email + find_by_username, for the uniqueness check of /register
+    async fn find_by_email_or_username(&self, email: &str, username: &str) -> Result<Option<User>, AuthError> {
+        match self.find_by_email(email).await? {
+            Some(user) => Ok(Some(user)),
+            None => self.find_by_username(username).await,
+        }
+    }
+
+    // Search the user linked to the account `subject` of the OAuth provider `provider`
+    async fn find_by_oauth(&self, provider: &str, subject: &str) -> Result<Option<User>, AuthError>;
+
+    // Same as the finders above, without the deactivated users (None for them)
+    // For the self-service flows (password reset, ...); admin routes use the unfiltered ones.
+    // Login keeps find_by_username(_in_tenant), to answer AccountDisabled once the password is checked
+    async fn find_active_by_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_email(email).await?.filter(|user| user.is_active))
+    }
+
+    async fn find_active_by_username(&self, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_username(username).await?.filter(|user| user.is_active))
+    }
+
+    async fn find_active_by_id(&self, id: Uuid) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_id(id).await?.filter(|user| user.is_active))
+    }
+
+    // Same as the finders above, only finding the users of `tenant_id` (None = users without a tenant)
+    // so a user of one tenant is not found from the context of another.
+    // Emails and usernames stay unique across tenants
+    async fn find_by_email_in_tenant(&self, tenant_id: Option<&str>, email: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_email(email).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    async fn find_by_username_in_tenant(&self, tenant_id: Option<&str>, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_username(username).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    async fn find_by_id_in_tenant(&self, tenant_id: Option<&str>, id: Uuid) -> Result<Option<User>, AuthError> {
+        Ok(self.find_by_id(id).await?.filter(|user| user.tenant_id.as_deref() == tenant_id))
+    }
+
+    // Update the fields set in `changes` (and the password hash, if given)
+    // `changes.password` is ignored, like in `create`: pass the new hash instead
+    // Returns UserNotFound if no user has this id
+    async fn update(&self, id: Uuid, changes: UpdateUser, password_hash: Option<String>) -> Result<User, AuthError>;
+
+    // Set `email_verified` to true
+    // Returns UserNotFound if no user has this id
+    async fn mark_email_verified(&self, id: Uuid) -> Result<(), AuthError>;
+
+    // Store `email` as the pending new email of the user (replacing a previous one)
+    // The email itself only changes in confirm_pending_email
+    async fn set_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError>;
+
+    // Make the pending email the (verified) email of the user, if it is still `email`
+    // Returns UserNotFound when the user has no such pending email,
+    // UserAlreadyExists when another account took the address meanwhile
+    async fn confirm_pending_email(&self, id: Uuid, email: &str) -> Result<(), AuthError>;
+
+    // Set `last_login_at` to now, called on every successful login
+    // Returns UserNotFound if no user has this id
+    async fn touch_last_login(&self, id: Uuid) -> Result<(), AuthError>;
+
+    // Add one to `token_version`, so every token issued to the user before is rejected
+    // Returns UserNotFound if no user has this id
+    async fn increment_token_version(&self, id: Uuid) -> Result<(), AuthError>;
+
+    // Link the user to the account `subject` of the OAuth provider `provider` (replacing a previous link)
+    // Returns UserNotFound if no user has this id,
+    // UserAlreadyExists when the account is already linked to another user
+    async fn link_oauth(&self, id: Uuid, provider: &str, subject: &str) -> Result<(), AuthError>;
+
+    // Activate or deactivate the user (deactivated users can't log in)
+    // Returns UserNotFound if no user has this id
+    async fn set_active(&self, id: Uuid, is_active: bool) -> Result<(), AuthError>;
+
+    // Delete the user for good, with its password history
+    // Returns UserNotFound if no user has this id
+    async fn delete(&self, id: Uuid) -> Result<(), AuthError>;
+
+    // List users ordered by creation date (oldest first)
+    // Skips `offset` users and returns at most `limit`
+    async fn list(&self, limit: u32, offset: u32) -> Result<Vec<User>, AuthError>;
+
+    // Total number of users
+    async fn count(&self) -> Result<u64, AuthError>;
+
+    // Number of users, in total and by activity status
+    async fn stats(&self) -> Result<UserStats, AuthError>;
+
+    // Users matching every criteria of `filter`, ordered by creation date
+    async fn search(&self, filter: UserFilter) -> Result<Vec<User>, AuthError>;
+
+    // Same as list / count / stats / search, only over the users of `tenant_id` (None = users without a tenant)
+    // so the admin of one tenant doesn't see the users of another
+    async fn list_in_tenant(&self, tenant_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<User>, AuthError> {
+        let users = self.search_in_tenant(tenant_id, UserFilter::default()).await?;
+        Ok(users.into_iter().skip(offset as usize).take(limit as usize).collect())
+    }
+
+    async fn count_in_tenant(&self, tenant_id: Option<&str>) -> Result<u64, AuthError> {
+        Ok(self.search_in_tenant(tenant_id, UserFilter::default()).await?.len() as u64)
+    }
+
+    async fn stats_in_tenant(&self, tenant_id: Option<&str>) -> Result<UserStats, AuthError> {
+        let users = self.search_in_tenant(tenant_id, UserFilter::default()).await?;
+        let total = users.len() as u64;
+        let active = users.iter().filter(|user| user.is_active).count() as u64;
+        Ok(UserStats { total, active, inactive: total - active })
+    }
+
+    async fn search_in_tenant(&self, tenant_id: Option<&str>, filter: UserFilter) -> Result<Vec<User>, AuthError> {
+        let users = self.search(filter).await?;
+        Ok(users.into_iter().filter(|user| user.tenant_id.as_deref() == tenant_id).collect())
+    }
+
+    // Hashes of the previous passwords of the user, newest first (empty for an unknown user)
+    // The current `password_hash` is not part of it
+    async fn password_history(&self, id: Uuid) -> Result<Vec<String>, AuthError>;
+
+    // Add `password_hash` (a password being replaced) to the history of the user,
+    // keeping only the `keep` newest hashes
+    async fn add_password_history(&self, id: Uuid, password_hash: String, keep: usize) -> Result<(), AuthError>;
+
+    // Cheap connectivity check for health checks, independent of any stored user
+    // (`SELECT 1` for the SQL databases, a `ping` command for MongoDB)
+    async fn ping(&self) -> Result<(), AuthError>;
+
+    // Checks at startup that the storage matches the migrations (SchemaMismatch naming
+    // the missing table or columns), instead of failing on the first request
+    // Storages without a fixed schema (in-memory, MongoDB) have nothing to check
+    async fn verify_schema(&self) -> Result<(), AuthError> {
+        Ok(())
+    }
 }
\ No newline at end of file
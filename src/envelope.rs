@@ -0,0 +1,73 @@
+// This file is responsible for the optional response envelope, wrapping every JSON body
+// as `{"data": ..., "error": null}` or `{"data": null, "error": ...}` for frontends expecting it
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{json, Value};
+
+/// Wraps a JSON body in the envelope, as `data` for a success status and as `error` otherwise
+///
+/// ```
+/// use axum::http::StatusCode;
+/// use auth_system::envelope::wrap;
+/// use serde_json::json;
+///
+/// let body = wrap(StatusCode::OK, json!({ "token": "..." }));
+/// assert_eq!(body, json!({ "data": { "token": "..." }, "error": null }));
+/// ```
+pub fn wrap(status: StatusCode, body: Value) -> Value {
+    match status.is_success() {
+        true => json!({ "data": body, "error": null }),
+        false => json!({ "data": null, "error": body }),
+    }
+}
+
+/// Middleware wrapping the JSON responses (handler results and `AuthError` bodies) with `wrap`
+///
+/// Installed by `build_router` when `AppState::response_envelope` is on.
+/// Responses without a JSON body (`204 No Content`, the plain text routes) are left as they are.
+pub async fn envelope(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+
+    let is_json = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // The bodies are built by the handlers, the limit is only a safeguard
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => serde_json::from_slice::<Value>(&bytes).map_err(|_| bytes),
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+
+    let bytes = match body {
+        Ok(value) => wrap(parts.status, value).to_string().into(),
+        // Not actually JSON: sent unchanged
+        Err(bytes) => bytes,
+    };
+    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_puts_errors_under_error() {
+        let error = json!({ "error": "Invalid credentials", "code": "invalid_credentials" });
+
+        assert_eq!(wrap(StatusCode::UNAUTHORIZED, error.clone()), json!({ "data": null, "error": error }));
+        assert_eq!(wrap(StatusCode::CREATED, json!([1, 2])), json!({ "data": [1, 2], "error": null }));
+    }
+}
@@ -1,10 +1,12 @@
 use axum::{
-    http::StatusCode, 
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
 use thiserror::Error;
+use utoipa::ToSchema;
+use crate::models::validation::{describe, ValidationReason};
 
 
 #[derive(Debug, Error)]
@@ -15,6 +17,12 @@ pub enum AuthError {
     #[error("UserAlreadyExists")]
     UserAlreadyExists,
 
+    #[error("Email already in use")]
+    EmailTaken,
+
+    #[error("Username already in use")]
+    UsernameTaken,
+
     #[error("User not found")]
     UserNotFound,
 
@@ -24,34 +32,270 @@ pub enum AuthError {
     #[error("Token expired")]
     TokenExpired,
 
+    #[error("Email not verified")]
+    EmailNotVerified,
+
+    #[error("Account disabled")]
+    AccountDisabled,
+
+    /// The token is valid but lacks a role required by the route
+    #[error("Missing required role: {0}")]
+    MissingRole(String),
+
+    /// Request authenticated by the auth cookie without the matching CSRF token
+    #[error("Invalid CSRF token")]
+    CsrfTokenMismatch,
+
+    #[error("API key not found")]
+    ApiKeyNotFound,
+
+    #[error("Session not found")]
+    SessionNotFound,
+
+    /// Registration is closed on this deployment
+    #[error("Registration disabled")]
+    RegistrationDisabled,
+
+    /// Invite-only registration without a valid (unused) invite code
+    #[error("Invalid invite code")]
+    InvalidInviteCode,
+
+    /// Too many requests, the client should retry after this many seconds
+    #[error("Too many requests")]
+    RateLimited(u64),
+
+    /// The request body is over the limit of the route (see `app::AUTH_BODY_LIMIT_BYTES`)
+    #[error("Request body too large")]
+    PayloadTooLarge,
+
+    /// The OAuth provider refused the code or couldn't be reached (see `auth::oauth`)
+    #[error("OAuth provider error")]
+    OAuthProviderError,
+
+    /// The authenticator's answer to a passkey registration didn't verify (see `auth::webauthn`)
+    #[error("Passkey rejected")]
+    PasskeyRejected,
+
+    /// The email sender couldn't hand the email over (see `email::EmailSender`)
+    #[error("Email delivery failed")]
+    EmailDeliveryFailed,
+
     #[error("Database error")]
     DatabaseError,
+
+    /// The database tables don't match the migrations (found at startup by `verify_schema`)
+    #[error("Database schema mismatch: {0}")]
+    SchemaMismatch(String),
     
     #[error("Internal server error")]
     InternalError,
 
-    #[error("Validation error: {0}")]
-    ValidationError(String)
+    /// Every reason the input was rejected (at least one)
+    #[error("Validation error: {}", describe(.0))]
+    ValidationError(Vec<ValidationReason>)
+}
+
+// Only an expired token is reported as such, so clients know they can refresh it
+// (bad signature, malformed, wrong audience, ... are all `InvalidToken`)
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        match error.kind() {
+            jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::TokenExpired,
+            _ => AuthError::InvalidToken,
+        }
+    }
+}
+
+impl From<ValidationReason> for AuthError {
+    fn from(reason: ValidationReason) -> Self {
+        AuthError::ValidationError(vec![reason])
+    }
+}
+
+
+impl AuthError {
+    /// Stable machine-readable code, sent as `code` in the error body
+    ///
+    /// Clients should branch on it instead of the (human-readable) `error` message
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::InvalidCredentials => "invalid_credentials",
+            AuthError::UserAlreadyExists => "user_already_exists",
+            AuthError::EmailTaken => "email_taken",
+            AuthError::UsernameTaken => "username_taken",
+            AuthError::UserNotFound => "user_not_found",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::TokenExpired => "token_expired",
+            AuthError::EmailNotVerified => "email_not_verified",
+            AuthError::AccountDisabled => "account_disabled",
+            AuthError::MissingRole(_) => "missing_role",
+            AuthError::CsrfTokenMismatch => "csrf_token_mismatch",
+            AuthError::ApiKeyNotFound => "api_key_not_found",
+            AuthError::SessionNotFound => "session_not_found",
+            AuthError::RegistrationDisabled => "registration_disabled",
+            AuthError::InvalidInviteCode => "invalid_invite_code",
+            AuthError::RateLimited(_) => "rate_limited",
+            AuthError::PayloadTooLarge => "payload_too_large",
+            AuthError::OAuthProviderError => "oauth_provider_error",
+            AuthError::PasskeyRejected => "passkey_rejected",
+            AuthError::EmailDeliveryFailed => "email_delivery_failed",
+            AuthError::DatabaseError => "database_error",
+            AuthError::SchemaMismatch(_) => "schema_mismatch",
+            AuthError::InternalError => "internal_error",
+            AuthError::ValidationError(_) => "validation_error",
+        }
+    }
+
+    /// Slug for client-side translation, sent as `kind` in the error body
+    ///
+    /// Same as `code()`, except for validation errors where it is the kind
+    /// of the (first) reason, e.g. `"password_too_short"`
+    pub fn error_kind(&self) -> &'static str {
+        match self {
+            AuthError::ValidationError(reasons) => reasons.first().map_or("validation_error", ValidationReason::kind),
+            other => other.code(),
+        }
+    }
+}
+
+
+/// Body of every error response, as built by `AuthError::into_response` (for the OpenAPI document)
+#[derive(ToSchema)]
+pub struct ErrorResponse {
+    /// Message for developers, in English
+    pub error: String,
+    /// `AuthError::code`, e.g. `"invalid_credentials"`
+    pub code: String,
+    /// `AuthError::error_kind`, for client-side translation
+    pub kind: String,
+    /// Kinds of every reason of a `validation_error`
+    pub reasons: Option<Vec<String>>,
 }
 
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
+        let code = self.code();
+        let kind = self.error_kind();
+        let reasons: Option<Vec<&str>> = match &self {
+            AuthError::ValidationError(reasons) => Some(reasons.iter().map(ValidationReason::kind).collect()),
+            _ => None,
+        };
+        let retry_after = match self {
+            AuthError::RateLimited(seconds) => Some(seconds),
+            _ => None,
+        };
         let (status, message) = match self {
             AuthError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid credentials".to_string()),
             AuthError::UserAlreadyExists => (StatusCode::CONFLICT, "User already exists".to_string()),
+            AuthError::EmailTaken => (StatusCode::CONFLICT, "Email already in use".to_string()),
+            AuthError::UsernameTaken => (StatusCode::CONFLICT, "Username already in use".to_string()),
             AuthError::UserNotFound => (StatusCode::NOT_FOUND, "User not found".to_string()),
             AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token".to_string()),
             AuthError::TokenExpired => (StatusCode::UNAUTHORIZED, "Token expired".to_string()),
+            AuthError::EmailNotVerified => (StatusCode::FORBIDDEN, "Email not verified".to_string()),
+            AuthError::AccountDisabled => (StatusCode::FORBIDDEN, "Account disabled".to_string()),
+            AuthError::MissingRole(_) => (StatusCode::FORBIDDEN, self.to_string()),
+            AuthError::CsrfTokenMismatch => (StatusCode::FORBIDDEN, self.to_string()),
+            AuthError::ApiKeyNotFound => (StatusCode::NOT_FOUND, "API key not found".to_string()),
+            AuthError::SessionNotFound => (StatusCode::NOT_FOUND, "Session not found".to_string()),
+            AuthError::RegistrationDisabled => (StatusCode::FORBIDDEN, "Registration disabled".to_string()),
+            AuthError::InvalidInviteCode => (StatusCode::FORBIDDEN, "Invalid invite code".to_string()),
+            AuthError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "Too many requests".to_string()),
+            AuthError::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large".to_string()),
+            AuthError::OAuthProviderError => (StatusCode::BAD_GATEWAY, "OAuth provider error".to_string()),
+            AuthError::PasskeyRejected => (StatusCode::BAD_REQUEST, "Passkey rejected".to_string()),
+            AuthError::EmailDeliveryFailed => (StatusCode::BAD_GATEWAY, "Email delivery failed".to_string()),
             AuthError::DatabaseError => (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()),
+            // The details are for the operator (logs), not for clients
+            AuthError::SchemaMismatch(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string()),
             AuthError::InternalError => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string()),
-            AuthError::ValidationError(msg) => (StatusCode::BAD_REQUEST, msg),
+            AuthError::ValidationError(reasons) => (StatusCode::BAD_REQUEST, describe(&reasons)),
         };
 
-        let body = Json(json!({
-            "error": message
-        }));
+        let mut body = json!({
+            "error": message,
+            "code": code,
+            "kind": kind
+        });
+        // Validation errors list the kind of every reason
+        if let Some(reasons) = reasons {
+            body["reasons"] = json!(reasons);
+        }
+        let body = Json(body);
+
+        let mut response = (status, body).into_response();
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_each_variant_maps_to_code_and_status() {
+        let cases = [
+            (AuthError::InvalidCredentials, "invalid_credentials", StatusCode::UNAUTHORIZED),
+            (AuthError::UserAlreadyExists, "user_already_exists", StatusCode::CONFLICT),
+            (AuthError::EmailTaken, "email_taken", StatusCode::CONFLICT),
+            (AuthError::UsernameTaken, "username_taken", StatusCode::CONFLICT),
+            (AuthError::UserNotFound, "user_not_found", StatusCode::NOT_FOUND),
+            (AuthError::InvalidToken, "invalid_token", StatusCode::UNAUTHORIZED),
+            (AuthError::TokenExpired, "token_expired", StatusCode::UNAUTHORIZED),
+            (AuthError::EmailNotVerified, "email_not_verified", StatusCode::FORBIDDEN),
+            (AuthError::AccountDisabled, "account_disabled", StatusCode::FORBIDDEN),
+            (AuthError::MissingRole("admin".into()), "missing_role", StatusCode::FORBIDDEN),
+            (AuthError::CsrfTokenMismatch, "csrf_token_mismatch", StatusCode::FORBIDDEN),
+            (AuthError::ApiKeyNotFound, "api_key_not_found", StatusCode::NOT_FOUND),
+            (AuthError::SessionNotFound, "session_not_found", StatusCode::NOT_FOUND),
+            (AuthError::RegistrationDisabled, "registration_disabled", StatusCode::FORBIDDEN),
+            (AuthError::InvalidInviteCode, "invalid_invite_code", StatusCode::FORBIDDEN),
+            (AuthError::RateLimited(30), "rate_limited", StatusCode::TOO_MANY_REQUESTS),
+            (AuthError::PayloadTooLarge, "payload_too_large", StatusCode::PAYLOAD_TOO_LARGE),
+            (AuthError::OAuthProviderError, "oauth_provider_error", StatusCode::BAD_GATEWAY),
+            (AuthError::PasskeyRejected, "passkey_rejected", StatusCode::BAD_REQUEST),
+            (AuthError::EmailDeliveryFailed, "email_delivery_failed", StatusCode::BAD_GATEWAY),
+            (AuthError::DatabaseError, "database_error", StatusCode::INTERNAL_SERVER_ERROR),
+            (AuthError::SchemaMismatch("missing column".into()), "schema_mismatch", StatusCode::INTERNAL_SERVER_ERROR),
+            (AuthError::InternalError, "internal_error", StatusCode::INTERNAL_SERVER_ERROR),
+            (ValidationReason::InvalidEmail.into(), "validation_error", StatusCode::BAD_REQUEST),
+        ];
+
+        for (error, code, status) in cases {
+            assert_eq!(error.code(), code);
+            assert_eq!(error.into_response().status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_keeps_error_message_and_adds_code() {
+        let response = AuthError::from(ValidationReason::InvalidEmail).into_response();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(body["error"], "Invalid email format!");
+        assert_eq!(body["code"], "validation_error");
+    }
+
+    #[tokio::test]
+    async fn test_body_has_kind_for_translation() {
+        let error = AuthError::ValidationError(vec![
+            ValidationReason::PasswordTooShort { min: 8 },
+            ValidationReason::PasswordMissingDigit,
+        ]);
+        assert_eq!(error.error_kind(), "password_too_short");
+
+        let response = error.into_response();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["kind"], "password_too_short");
+        assert_eq!(body["reasons"], json!(["password_too_short", "password_missing_digit"]));
 
-        (status, body).into_response()
+        assert_eq!(AuthError::TokenExpired.error_kind(), "token_expired");
     }
 }
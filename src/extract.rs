@@ -0,0 +1,57 @@
+// This file is responsible for the JSON extractor of the handlers, rejecting malformed
+// bodies with the usual `{"error": ..., "code": ...}` body instead of axum's plain text
+
+use std::ops::{Deref, DerefMut};
+use axum::{
+    extract::{FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::{errors::AuthError, models::validation::ValidationReason};
+
+/// Drop-in replacement of `axum::Json`, used by every handler
+///
+/// A body that isn't valid JSON, misses a required field or has a field of the wrong type
+/// (or a request without `Content-Type: application/json`) is rejected with
+/// `AuthError::ValidationError`, the reason `invalid_body` carrying serde's message
+/// (e.g. "missing field `password`"). A body over the limit of the route (`DefaultBodyLimit`)
+/// is rejected with `AuthError::PayloadTooLarge`. As a response it is the same as `axum::Json`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::Json::<T>::from_request(req, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => Err(AuthError::PayloadTooLarge),
+            Err(rejection) => Err(ValidationReason::InvalidBody(rejection.body_text()).into()),
+        }
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
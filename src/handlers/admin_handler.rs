@@ -0,0 +1,336 @@
+use axum::extract::{Path, Query, State};
+use tracing::info;
+use uuid::Uuid;
+use crate::{
+    extract::Json,
+    models::{page::Page, user::{ListUsersQuery, SetActiveRequest, User, UserFilter, UserStats}},
+    auth::extractor::{AdminRole, RequireRole},
+    errors::AuthError,
+    AppState,
+};
+
+/// Page size used when `limit` is not given
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+
+/// Largest page a client can ask for
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// Handler listing the registered users (admin only)
+///
+/// Endpoint: GET /users?limit=20&offset=0
+/// Headers: Authorization: Bearer <token>
+///
+/// Flow:
+/// 1. Checks that the user has the "admin" role (403 otherwise)
+/// 2. Clamps `limit` to `MAX_PAGE_SIZE`
+/// 3. Returns the page of users (oldest first) and the total count, both limited to the tenant of the admin
+///
+/// The admin routes only see the users of the admin's tenant (`tenant_id` claim), those
+/// without a tenant for an admin without one. The password hashes are never serialized
+pub async fn list_users_handler(
+    State(state): State<AppState>,
+    RequireRole { user: admin, .. }: RequireRole<AdminRole>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<Page<User>>, AuthError> {
+
+    let (limit, offset) = page_bounds(&query);
+    let tenant_id = admin.tenant_id.as_deref();
+
+    let items = state.user_repo.list_in_tenant(tenant_id, limit, offset).await?;
+    let total = state.user_repo.count_in_tenant(tenant_id).await?;
+
+    Ok(Json(Page { items, total, limit, offset }))
+}
+
+
+/// Handler searching the users (admin only)
+///
+/// Endpoint: GET /users/search?username=doe&is_active=true&created_after=2025-01-01T00:00:00Z&limit=20&offset=0
+/// Headers: Authorization: Bearer <token>
+///
+/// Every criteria is optional, the given ones must all match.
+/// Returns a page of the matching users of the admin's tenant (oldest first, paged like `GET /users`)
+/// and the number of matches
+pub async fn search_users_handler(
+    State(state): State<AppState>,
+    RequireRole { user: admin, .. }: RequireRole<AdminRole>,
+    Query(filter): Query<UserFilter>,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<Page<User>>, AuthError> {
+
+    let (limit, offset) = page_bounds(&query);
+
+    // The repositories return every match, the page is cut from them
+    let users = state.user_repo.search_in_tenant(admin.tenant_id.as_deref(), filter).await?;
+
+    Ok(Json(Page::slice(users, limit, offset)))
+}
+
+
+// Page size (clamped to `MAX_PAGE_SIZE`) and offset asked for
+fn page_bounds(query: &ListUsersQuery) -> (u32, u32) {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    (limit, query.offset.unwrap_or(0))
+}
+
+
+/// Handler returning the user counts by activity status (admin only)
+///
+/// Endpoint: GET /stats
+/// Headers: Authorization: Bearer <token>
+///
+/// Only the users of the admin's tenant are counted
+pub async fn stats_handler(
+    State(state): State<AppState>,
+    RequireRole { user: admin, .. }: RequireRole<AdminRole>,
+) -> Result<Json<UserStats>, AuthError> {
+
+    let stats = state.user_repo.stats_in_tenant(admin.tenant_id.as_deref()).await?;
+
+    Ok(Json(stats))
+}
+
+
+/// Handler activating or deactivating a user (admin only)
+///
+/// Endpoint: PUT /users/{id}/active
+/// Headers: Authorization: Bearer <token>
+/// Body: {"is_active": false}
+///
+/// A deactivated user can't log in nor refresh tokens.
+/// Already issued access tokens are only rejected when `check_active_on_request` is enabled.
+/// A user of another tenant is not found (404)
+pub async fn set_user_active_handler(
+    State(state): State<AppState>,
+    RequireRole { user: admin, .. }: RequireRole<AdminRole>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SetActiveRequest>,
+) -> Result<Json<User>, AuthError> {
+
+    state.user_repo
+        .find_by_id_in_tenant(admin.tenant_id.as_deref(), id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+    state.user_repo.set_active(id, payload.is_active).await?;
+
+    info!(admin_id = %admin.user_id, user_id = %id, is_active = payload.is_active, "user activation changed");
+
+    let user = state.user_repo
+        .find_by_id(id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+    Ok(Json(user))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::extract::FromRequestParts;
+    use axum::http::Request;
+    use crate::auth::jwt::{create_tenant_token, create_token_with_config};
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::models::user::{CreateUser, UserId};
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+
+    async fn state_with_users(count: usize) -> AppState {
+        let state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        for i in 0..count {
+            state.user_repo.create(CreateUser {
+                username: format!("user_{i}"),
+                email: format!("user_{i}@example.com"),
+                password: "Password123!".to_string().into(),
+                tenant_id: None,
+            }, "secret-hash".to_string()).await.unwrap();
+        }
+        state
+    }
+
+    async fn admin(state: &AppState, roles: &[String]) -> Result<RequireRole<AdminRole>, AuthError> {
+        let token = create_token_with_config(UserId(Uuid::new_v4()), roles, &state.jwt_keys, &state.token_config).unwrap();
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        RequireRole::<AdminRole>::from_request_parts(&mut parts, state).await
+    }
+
+    // Stored admin of `tenant_id`, extracted from a login token of the tenant
+    async fn tenant_admin(state: &AppState, tenant_id: &str) -> RequireRole<AdminRole> {
+        let user = state.user_repo.create(CreateUser {
+            username: format!("admin_{tenant_id}"),
+            email: format!("admin@{tenant_id}.example.com"),
+            password: "Password123!".to_string().into(),
+            tenant_id: Some(tenant_id.to_string()),
+        }, "secret-hash".to_string()).await.unwrap();
+        let token = create_tenant_token(UserId(user.id), &["admin".to_string()], Some(tenant_id), user.token_version, &state.jwt_keys, &state.token_config).unwrap();
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        RequireRole::<AdminRole>::from_request_parts(&mut parts, state).await.unwrap()
+    }
+
+    async fn list(state: &AppState, limit: Option<u32>, offset: Option<u32>) -> Page<User> {
+        let admin = admin(state, &["admin".to_string()]).await.unwrap();
+        let Json(response) = list_users_handler(State(state.clone()), admin, Query(ListUsersQuery { limit, offset }))
+            .await
+            .unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_list_users_paging_boundaries() {
+        let state = state_with_users(5).await;
+
+        let first = list(&state, Some(2), None).await;
+        assert_eq!(first.items.len(), 2);
+        assert_eq!(first.total, 5);
+        assert_eq!(first.items[0].username, "user_0");
+
+        let last = list(&state, Some(2), Some(4)).await;
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.items[0].username, "user_4");
+
+        let past_end = list(&state, Some(2), Some(5)).await;
+        assert!(past_end.items.is_empty());
+        assert_eq!(past_end.total, 5);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_limit_is_clamped() {
+        let state = state_with_users(0).await;
+        assert_eq!(list(&state, Some(10_000), None).await.limit, MAX_PAGE_SIZE);
+        assert_eq!(list(&state, None, None).await.limit, DEFAULT_PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_does_not_serialize_password_hash() {
+        let state = state_with_users(1).await;
+        let json = serde_json::to_string(&list(&state, None, None).await).unwrap();
+        assert!(json.contains("user_0"));
+        assert!(!json.contains("password_hash"));
+        assert!(!json.contains("secret-hash"));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_requires_admin_role() {
+        let state = state_with_users(1).await;
+        let Err(rejection) = admin(&state, &[]).await else { panic!("non-admin was accepted") };
+        assert!(matches!(rejection, AuthError::MissingRole(_)));
+    }
+
+    #[tokio::test]
+    async fn test_search_users_filters_by_username() {
+        let state = state_with_users(3).await;
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+
+        let filter = UserFilter { username: Some("USER_1".to_string()), ..UserFilter::default() };
+        let Json(page) = search_users_handler(State(state), admin_user, Query(filter), Query(ListUsersQuery::default())).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].username, "user_1");
+        assert_eq!(page.total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_users_total_counts_every_match() {
+        let state = state_with_users(5).await;
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+
+        let paging = ListUsersQuery { limit: Some(2), offset: Some(1) };
+        let Json(page) = search_users_handler(State(state), admin_user, Query(UserFilter::default()), Query(paging)).await.unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].username, "user_1");
+        assert_eq!((page.limit, page.offset), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_stats_counts_active_and_inactive_users() {
+        let state = state_with_users(3).await;
+        let user = state.user_repo.find_by_username("user_1").await.unwrap().unwrap();
+        state.user_repo.set_active(user.id, false).await.unwrap();
+
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+        let Json(stats) = stats_handler(State(state), admin_user).await.unwrap();
+        assert_eq!(stats, UserStats { total: 3, active: 2, inactive: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_set_user_active_toggles_flag() {
+        let state = state_with_users(1).await;
+        let user = state.user_repo.find_by_username("user_0").await.unwrap().unwrap();
+
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+        let Json(updated) = set_user_active_handler(
+            State(state.clone()),
+            admin_user,
+            Path(user.id),
+            Json(SetActiveRequest { is_active: false }),
+        ).await.unwrap();
+        assert!(!updated.is_active);
+
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+        let Json(updated) = set_user_active_handler(
+            State(state),
+            admin_user,
+            Path(user.id),
+            Json(SetActiveRequest { is_active: true }),
+        ).await.unwrap();
+        assert!(updated.is_active);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_only_see_the_admin_tenant() {
+        let state = state_with_users(2).await;
+        let outsider = state.user_repo.find_by_username("user_0").await.unwrap().unwrap();
+
+        let Json(page) = list_users_handler(State(state.clone()), tenant_admin(&state, "acme").await, Query(ListUsersQuery::default())).await.unwrap();
+        let usernames: Vec<&str> = page.items.iter().map(|user| user.username.as_str()).collect();
+        assert_eq!(usernames, vec!["admin_acme"]);
+        assert_eq!(page.total, 1);
+
+        let admin_user = tenant_admin(&state, "globex").await;
+        let Json(page) = search_users_handler(State(state.clone()), admin_user, Query(UserFilter::default()), Query(ListUsersQuery::default())).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].username, "admin_globex");
+
+        let admin_user = tenant_admin(&state, "initech").await;
+        let Json(stats) = stats_handler(State(state.clone()), admin_user).await.unwrap();
+        assert_eq!(stats, UserStats { total: 1, active: 1, inactive: 0 });
+
+        // The users of other tenants (here, without a tenant) can't be deactivated either
+        let admin_user = tenant_admin(&state, "umbrella").await;
+        let result = set_user_active_handler(
+            State(state.clone()),
+            admin_user,
+            Path(outsider.id),
+            Json(SetActiveRequest { is_active: false }),
+        ).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+        assert!(state.user_repo.find_by_id(outsider.id).await.unwrap().unwrap().is_active);
+
+        // An admin without a tenant only sees the users without one
+        assert_eq!(list(&state, None, None).await.total, 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_user_active_unknown_user() {
+        let state = state_with_users(0).await;
+        let admin_user = admin(&state, &["admin".to_string()]).await.unwrap();
+
+        let result = set_user_active_handler(
+            State(state),
+            admin_user,
+            Path(Uuid::new_v4()),
+            Json(SetActiveRequest { is_active: false }),
+        ).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+}
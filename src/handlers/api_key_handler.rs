@@ -0,0 +1,187 @@
+use axum::{extract::{Path, State}, http::StatusCode};
+use tracing::info;
+use uuid::Uuid;
+use crate::{
+    extract::Json,
+    models::{api_key::{CreateApiKeyRequest, CreateApiKeyResponse}, validation::ValidationReason},
+    auth::{api_key::{generate_api_key, hash_api_key}, extractor::AuthUser},
+    errors::AuthError,
+    AppState,
+};
+
+/// Handler minting an API key for the authenticated user
+///
+/// Endpoint: POST /api-keys
+/// Headers: Authorization: Bearer <token>
+/// Body: {"name": "billing-service"}
+///
+/// Flow:
+/// 1. Generates a random key
+/// 2. Stores only its SHA-256 hash
+/// 3. Returns the plaintext key (the only time it is available)
+///
+/// The key is then sent as `X-API-Key: <key>` and authenticates as the user (`ApiKeyUser`)
+pub async fn create_api_key_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), AuthError> {
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ValidationReason::ApiKeyNameRequired.into());
+    }
+
+    let user_id = user.user_id.as_uuid();
+
+    let key = generate_api_key();
+    let api_key = state.api_keys.create(user_id, name, hash_api_key(&key)).await?;
+
+    info!(user_id = %user_id, key_id = %api_key.id, "api key created");
+
+    Ok((StatusCode::CREATED, Json(CreateApiKeyResponse {
+        id: api_key.id,
+        name: api_key.name,
+        key,
+        created_at: api_key.created_at,
+    })))
+}
+
+
+/// Handler revoking one of the authenticated user's API keys
+///
+/// Endpoint: DELETE /api-keys/{id}
+/// Headers: Authorization: Bearer <token>
+///
+/// Requests using the key are rejected right away.
+/// Returns 404 when the user has no key with this id
+pub async fn revoke_api_key_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    if !state.api_keys.revoke(id, user_id).await? {
+        return Err(AuthError::ApiKeyNotFound);
+    }
+
+    info!(user_id = %user_id, key_id = %id, "api key revoked");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::extract::FromRequestParts;
+    use axum::http::{Request, request::Parts};
+    use crate::auth::extractor::ApiKeyUser;
+    use crate::auth::jwt::create_token;
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::models::user::{CreateUser, UserId};
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+
+    async fn state_with_user() -> (AppState, Uuid) {
+        let state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        let user = state.user_repo.create(CreateUser {
+            username: "service".to_string(),
+            email: "service@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }, "hash".to_string()).await.unwrap();
+        (state, user.id)
+    }
+
+    async fn auth_user(state: &AppState, user_id: Uuid) -> AuthUser {
+        let token = create_token(UserId(user_id), SECRET);
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AuthUser::from_request_parts(&mut parts, state).await.unwrap()
+    }
+
+    async fn mint(state: &AppState, user_id: Uuid) -> CreateApiKeyResponse {
+        let user = auth_user(state, user_id).await;
+        let payload = CreateApiKeyRequest { name: "billing-service".to_string() };
+        let (status, Json(response)) = create_api_key_handler(State(state.clone()), user, Json(payload))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        response
+    }
+
+    fn parts_with_key(key: &str) -> Parts {
+        let (parts, _) = Request::builder()
+            .header("X-API-Key", key)
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts
+    }
+
+    #[tokio::test]
+    async fn test_minted_key_is_stored_hashed() {
+        let (state, user_id) = state_with_user().await;
+        let response = mint(&state, user_id).await;
+
+        assert!(state.api_keys.find_by_hash(&response.key).await.unwrap().is_none());
+        let stored = state.api_keys.find_by_hash(&hash_api_key(&response.key)).await.unwrap().unwrap();
+        assert_eq!(stored.user_id, user_id);
+        assert_eq!(stored.name, "billing-service");
+    }
+
+    #[tokio::test]
+    async fn test_api_key_authenticates_owner() {
+        let (state, user_id) = state_with_user().await;
+        let response = mint(&state, user_id).await;
+
+        let user = ApiKeyUser::from_request_parts(&mut parts_with_key(&response.key), &state).await.unwrap();
+        assert_eq!(user.user_id, UserId(user_id));
+        assert_eq!(user.key_id, response.id);
+
+        let result = ApiKeyUser::from_request_parts(&mut parts_with_key("ak_unknown"), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_revoked_key_is_rejected() {
+        let (state, user_id) = state_with_user().await;
+        let response = mint(&state, user_id).await;
+
+        let user = auth_user(&state, user_id).await;
+        let status = revoke_api_key_handler(State(state.clone()), user, Path(response.id)).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let result = ApiKeyUser::from_request_parts(&mut parts_with_key(&response.key), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_revoke_other_users_key() {
+        let (state, user_id) = state_with_user().await;
+        let response = mint(&state, user_id).await;
+
+        let other = auth_user(&state, Uuid::new_v4()).await;
+        let result = revoke_api_key_handler(State(state.clone()), other, Path(response.id)).await;
+        assert!(matches!(result, Err(AuthError::ApiKeyNotFound)));
+
+        assert!(ApiKeyUser::from_request_parts(&mut parts_with_key(&response.key), &state).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_key_of_disabled_account_is_rejected() {
+        let (state, user_id) = state_with_user().await;
+        let response = mint(&state, user_id).await;
+        state.user_repo.set_active(user_id, false).await.unwrap();
+
+        let result = ApiKeyUser::from_request_parts(&mut parts_with_key(&response.key), &state).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+    }
+}
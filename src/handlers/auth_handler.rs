@@ -1,66 +1,168 @@
-use axum::{Json, extract::State};
+use axum::{extract::State, http::{HeaderMap, HeaderValue, StatusCode}};
+use uuid::Uuid;
+use tracing::{info, warn};
 use crate::{
-    models::auth::{LoginRequest, LoginResponse, RegisterRequest},
-    models::user::CreateUser,
-    models::validation::{validate_email, validate_username, validate_password},
-    auth::{crypto, jwt::create_token},
-    errors::AuthError,
+    extract::Json,
+    models::auth::{
+        AccountDeletionMode, ChangeEmailRequest, DeleteAccountRequest, ChangePasswordRequest, ForgotPasswordRequest, IntrospectBatchRequest, IntrospectBatchResponse, IntrospectRequest,
+        IntrospectResponse, LoginIdentifierMode, LoginRequest, MAX_INTROSPECT_BATCH,
+        LoginResponse, MessageResponse, PrivateResponse, RefreshRequest, RefreshResponse, RegisterRequest, RegisterResponse, ResetPasswordRequest, VerifyEmailRequest,
+    },
+    models::session::Session,
+    models::user::{CreateUser, UpdateUser, User, UserId},
+    models::validation::{
+        check_password_size, normalize_email, normalize_username, validate_email, validate_password_with, validate_username_with, validate_all, ValidationReason,
+    },
+    auth::{crypto, extractor::{check_stored_user, AuthUser, ClientInfo, CurrentUser, RequestTenant}, jwt::{
+        create_tenant_token, create_session_refresh_token, create_session_token, create_reset_token, create_verification_token,
+        create_email_change_token, validate_token_type, Claims, TokenType, NEW_EMAIL_CLAIM,
+    }},
+    audit::{AuditAction, AuditEvent},
+    errors::{AuthError, ErrorResponse},
     AppState,
 };
 
 /// Handler for registering new users
 /// 
 /// Endpoint: POST /register
-/// Body: {"username": "...", "email": "...", "password": "..."}
+/// Body: {"username": "...", "email": "...", "password": "...", "invite_code": "..."}
 /// 
 /// Flow:
-/// 1. Checks if email already exists
-/// 2. Checks if username already exists
-/// 3. Hash the password with Argon2
-/// 4. Creates the user in the database
-/// 5. Generates JWT token
-/// 6. Returns the token
+/// 1. Rejects the request when registration is disabled, or invite-only without a code (403)
+/// 2. Normalizes the email (trim + lowercase) and checks if it already exists
+/// 3. Checks if username already exists
+///    (409 `UserAlreadyExists`, or `EmailTaken` / `UsernameTaken` when `reveal_conflicting_field` is enabled)
+/// 4. Redeems the invite code (invite-only mode)
+/// 5. Hash the password with Argon2
+/// 6. Creates the user in the database (email not verified yet), in the tenant of the request
+/// 7. Emails the email verification token (`email_sender`), a failed delivery is only logged
+/// 8. Generates JWT token
+/// 9. Returns 201 Created with the token (also in a cookie when `auth_cookie` is set,
+///    and in the `token_response_header`) and the user
 /// 
 /// This handler is GENERIC - it doesn't know which bank is being used!
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "auth",
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "User created, already logged in", body = RegisterResponse),
+        (status = 400, description = "Invalid username, email or password", body = ErrorResponse),
+        (status = 403, description = "Registration disabled or invalid invite code", body = ErrorResponse),
+        (status = 409, description = "Email or username already in use", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
 pub async fn register_handler(
     State(state): State<AppState>,
+    client: ClientInfo,
+    RequestTenant(tenant_id): RequestTenant,
     Json(payload): Json<RegisterRequest>,
-) -> Result<Json<LoginResponse>, AuthError> {
+) -> Result<(StatusCode, HeaderMap, Json<RegisterResponse>), AuthError> {
+
+    if !state.registration_enabled {
+        return Err(AuthError::RegistrationDisabled);
+    }
+
+    // The code is only redeemed once the input is known to be valid
+    let invite_code = match state.invite_only {
+        true => Some(payload.invite_code.as_deref().filter(|code| !code.is_empty()).ok_or(AuthError::InvalidInviteCode)?),
+        false => None,
+    };
 
-    // Validation
-    validate_email(&payload.email)?;
-    validate_username(&payload.username)?;
-    validate_password(&payload.password)?;
+    // Emails and usernames are stored and looked up in their normalized form
+    let email = normalize_email(&payload.email);
+    let username = normalize_username(&payload.username);
 
-    // Check if the email is already in use
-    if state.user_repo.find_by_email(&payload.email).await?.is_some() {
-        return Err(AuthError::UserAlreadyExists);
+    // Validation, every invalid field is reported at once
+    validate_all([
+        validate_email(&email),
+        validate_username_with(state.username_policy, &username),
+        validate_password_with(&state.password_policy, &payload.password),
+    ])?;
+
+    // Fast path: the repository `create` is the source of truth for uniqueness
+    // Check if the email or the username is already in use, in one lookup
+    if let Some(existing) = state.user_repo.find_by_email_or_username(&email, &username).await? {
+        let error = match existing.email == email {
+            true => AuthError::EmailTaken,
+            false => AuthError::UsernameTaken,
+        };
+        warn!(username = %username, error = %error, "registration rejected: already in use");
+        return Err(conflict(&state, error));
     }
 
-    // Check if the username is already in use
-    if state.user_repo.find_by_username(&payload.username).await?.is_some() {
-        return Err(AuthError::UserAlreadyExists);
+    if let Some(code) = invite_code
+        && !state.invites.redeem(code).await?
+    {
+        warn!(username = %username, "registration rejected: invalid invite code");
+        return Err(AuthError::InvalidInviteCode);
     }
 
-    // Generates a safe hash for the password using Argon2
-    let password_hash = crypto::hash_password(&payload.password)
+    // Generates a safe hash for the password using Argon2 and the configured cost
+    let password_hash = crypto::hash_password_with(&state.argon2_config, &payload.password)
         .map_err(|_| AuthError::InternalError)?;
 
     // Creater user in db via trait UserRepository
-    let user = state.user_repo.create(
+    let created = state.user_repo.create(
         CreateUser{
-            username: payload.username.clone(),
-            email: payload.email,
+            username,
+            email,
             password: payload.password,
+            tenant_id,
         }, 
         password_hash,
-    ).await?;
+    ).await;
+
+    // The code wasn't used if the account couldn't be created (e.g. a concurrent registration)
+    let user = match created {
+        Ok(user) => user,
+        Err(error) => {
+            if let Some(code) = invite_code {
+                state.invites.add(code.to_string()).await?;
+            }
+            return Err(error);
+        }
+    };
+    let user = grant_default_roles(&state, user).await?;
+
+    let verification_token = create_verification_token(UserId(user.id), &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
+    // The account exists either way: a failed delivery doesn't fail the registration
+    if let Err(error) = send_verification_token(&state, &user.email, &verification_token).await {
+        warn!(user_id = %user.id, error = %error, "verification email not sent");
+    }
+
+    info!(user_id = %user.id, username = %user.username, "user registered");
+    state.audit.record(AuditEvent::success(AuditAction::Register, Some(user.id), &client)).await;
+
+    // Return the tokens and the new user for the client
+    let tokens = issue_tokens(&state, &user, client).await?;
+    let headers = token_headers(&state, &tokens);
+    let LoginResponse { token, refresh_token } = tokens;
+    Ok((StatusCode::CREATED, headers, Json(RegisterResponse { token, refresh_token, user })))
+}
+
+
+// Gives a new user the configured `default_roles`, before its first token is issued
+pub(crate) async fn grant_default_roles(state: &AppState, user: User) -> Result<User, AuthError> {
+    if state.default_roles.is_empty() {
+        return Ok(user);
+    }
+
+    let changes = UpdateUser { roles: Some(state.default_roles.clone()), ..UpdateUser::default() };
+    state.user_repo.update(user.id, changes, None).await
+}
 
-    // Generate valid jwt token for 24 hours
-    let token = create_token(&user.id.to_string(), &state.jwt_secret);
 
-    // Return a token for the client
-    Ok(Json(LoginResponse { token }))    
+// Error for a taken email or username, only precise when the state allows revealing it
+fn conflict(state: &AppState, error: AuthError) -> AuthError {
+    if state.reveal_conflicting_field {
+        error
+    } else {
+        AuthError::UserAlreadyExists
+    }
 }
 
 
@@ -70,34 +172,2456 @@ pub async fn register_handler(
 /// Body: {"username": "...", "password": "..."}
 /// 
 /// Flow:
-/// 1. User search for username
+/// 1. User search for username or email (`login_identifier`), among the users of the tenant of the request
 /// 2. Checks if the password is correct
-/// 3. Generates JWT token
-/// 4. Returns the token
+/// 3. Rejects deactivated accounts, and unverified emails when `require_verified_email` is enabled
+/// 4. Re-hashes the password if the stored hash used weaker Argon2 parameters
+/// 5. Records the login time (`last_login_at`)
+/// 6. Generates JWT token
+/// 7. Returns the token (also in a cookie when `auth_cookie` is set, and in the `token_response_header`)
 /// 
 /// This handler is GENERIC - it doesn't know which bank is being used!
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in", body = LoginResponse),
+        (status = 401, description = "Invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Email not verified or account disabled", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
 pub async fn login_handler(
     State(state): State<AppState>,
+    client: ClientInfo,
+    RequestTenant(tenant_id): RequestTenant,
     Json(payload): Json<LoginRequest>,
+) -> Result<(HeaderMap, Json<LoginResponse>), AuthError> {
+
+    // Oversized passwords are rejected before any Argon2 work
+    check_password_size(&payload.password)?;
+
+    // A user of another tenant is not found, like an unknown username
+    let user = find_login_user(&state, tenant_id.as_deref(), &payload.username).await?;
+
+    // Unknown usernames are verified against a dummy hash,
+    // so the response time doesn't reveal which usernames exist
+    let hash = user.as_ref().map(|u| u.password_hash.as_str());
+    let is_valid = crypto::verify_or_dummy_with(&state.argon2_config, hash, &payload.password)
+        .map_err(|_| AuthError::InternalError)?;
+
+    let user = match user {
+        Some(user) if is_valid => user,
+        user => {
+            warn!(username = %payload.username, "login failed: invalid credentials");
+            let error = AuthError::InvalidCredentials;
+            state.audit.record(AuditEvent::failure(AuditAction::Login, user.map(|u| u.id), &client, &error)).await;
+            return Err(error);
+        }
+    };
+
+    // Checked after the password, so it doesn't reveal which accounts exist
+    let rejection = if !user.is_active {
+        warn!(user_id = %user.id, "login failed: account disabled");
+        Some(AuthError::AccountDisabled)
+    } else if state.require_verified_email && !user.email_verified {
+        warn!(user_id = %user.id, "login failed: email not verified");
+        Some(AuthError::EmailNotVerified)
+    } else {
+        None
+    };
+    if let Some(error) = rejection {
+        state.audit.record(AuditEvent::failure(AuditAction::Login, Some(user.id), &client, &error)).await;
+        return Err(error);
+    }
+
+    // The plaintext is only available now, so this is the moment to upgrade old hashes
+    // Best effort: a failed rehash must not prevent the login
+    if crypto::needs_rehash(&user.password_hash, &state.argon2_config)
+        && let Ok(password_hash) = crypto::hash_password_with(&state.argon2_config, &payload.password)
+    {
+        let _ = state.user_repo.update(user.id, UpdateUser::default(), Some(password_hash)).await;
+        info!(user_id = %user.id, "password hash upgraded");
+    }
+
+    state.user_repo.touch_last_login(user.id).await?;
+
+    info!(user_id = %user.id, "login succeeded");
+    state.audit.record(AuditEvent::success(AuditAction::Login, Some(user.id), &client)).await;
+
+    let tokens = issue_tokens(&state, &user, client).await?;
+    Ok((token_headers(&state, &tokens), Json(tokens)))
+}
+
+
+/// Handler for exchanging a refresh token for a new access token
+///
+/// Endpoint: POST /refresh
+/// Body: {"refresh_token": "..."}
+///
+/// Flow:
+/// 1. Validates the refresh token (access tokens are rejected)
+/// 2. Rejects a refresh token rotated out by `/token/refresh-rotate`, revoking its session
+/// 3. Checks that the user still exists and is active
+/// 4. Returns a fresh access token
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New access token", body = RefreshResponse),
+        (status = 401, description = "Invalid or expired refresh token", body = ErrorResponse),
+        (status = 403, description = "Account disabled", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn refresh_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<RefreshResponse>, AuthError> {
+
+    let (claims, user) = refreshing_user(&state, &client, &payload.refresh_token).await?;
+
+    // Roles are read from the user, so role changes apply on the next refresh
+    // The new token belongs to the session of the refresh token
+    let token = create_session_token(claims.sub, &user.roles, user.tenant_id.as_deref(), claims.session_id(), user.token_version, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
+
+    info!(user_id = %user.id, "access token refreshed");
+
+    Ok(Json(RefreshResponse { token }))
+}
+
+
+/// Handler for exchanging a refresh token for a new access token and a new refresh token
+///
+/// Endpoint: POST /token/refresh-rotate
+/// Body: {"refresh_token": "..."}
+///
+/// Flow:
+/// 1. Same checks as `/refresh`
+/// 2. Replaces the refresh token by a new one of the same session: the old one is used up
+/// 3. Returns the new access and refresh tokens
+///
+/// Presenting a used refresh token again means it was stolen (either the thief or the user
+/// already rotated it): the session is revoked, with every token issued for it (401).
+#[utoipa::path(
+    post,
+    path = "/token/refresh-rotate",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New access and refresh tokens, the old refresh token can't be used anymore", body = LoginResponse),
+        (status = 401, description = "Invalid, expired or already used refresh token (revoking the session)", body = ErrorResponse),
+        (status = 403, description = "Account disabled", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn refresh_rotate_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshRequest>,
 ) -> Result<Json<LoginResponse>, AuthError> {
 
+    let (claims, user) = refreshing_user(&state, &client, &payload.refresh_token).await?;
+    let session_id = claims.session_id();
+
+    let token = create_session_token(claims.sub, &user.roles, user.tenant_id.as_deref(), session_id, user.token_version, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
+    let refresh_token = create_session_refresh_token(claims.sub, session_id, user.token_version, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?
+        .ok_or(AuthError::InternalError)?;
+    let new_jti = validate_token_type(&refresh_token, &state.jwt_keys, &state.token_config, TokenType::Refresh)
+        .map_err(|_| AuthError::InternalError)?
+        .jti;
+
+    // Only one of two concurrent rotations of the same token wins, the other one is a reuse
+    let now = state.token_config.clock.now();
+    if !state.refresh_tokens.rotate(session_id, &claims.jti, &new_jti, refresh_expiry(&state), now).await? {
+        // Unknown session (issued before rotation was tracked, or storage lost): nothing to revoke
+        if state.refresh_tokens.current(session_id, now).await?.is_some() {
+            revoke_refresh_family(&state, &client, user.id, session_id).await?;
+        }
+        return Err(AuthError::InvalidToken);
+    }
+
+    info!(user_id = %user.id, "refresh token rotated");
+
+    Ok(Json(LoginResponse { token, refresh_token: Some(refresh_token) }))
+}
+
+
+// Validates a refresh token and finds its user, who must still exist and be active
+// A token that is no longer the current one of its session was already rotated: reuse
+async fn refreshing_user(state: &AppState, client: &ClientInfo, refresh_token: &str) -> Result<(Claims, User), AuthError> {
+    let claims = validate_token_type(refresh_token, &state.jwt_keys, &state.token_config, TokenType::Refresh)
+        .map_err(|_| AuthError::InvalidToken)?;
+
+    // Revoked by itself, or with its session (remote logout)
+    if state.token_blacklist.is_revoked(&claims.jti).await?
+        || state.token_blacklist.is_revoked(claims.session_id()).await?
+    {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let user_id = claims.sub.as_uuid();
+
+    if let Some(current) = state.refresh_tokens.current(claims.session_id(), state.token_config.clock.now()).await?
+        && current != claims.jti
+    {
+        revoke_refresh_family(state, client, user_id, claims.session_id()).await?;
+        return Err(AuthError::InvalidToken);
+    }
+
+    // The user may have been deleted after the refresh token was issued
+    let user = state.user_repo
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+
+    // Issued before a password change or a logout everywhere
+    if claims.token_version.is_some_and(|version| version != user.token_version) {
+        return Err(AuthError::InvalidToken);
+    }
+
+    // A deactivated user can't extend the session
+    if !user.is_active {
+        return Err(AuthError::AccountDisabled);
+    }
+
+    Ok((claims, user))
+}
+
+// Revokes the session of a reused refresh token, so every token issued for it:
+// the access tokens and the refresh tokens of the chain, including the thief's
+async fn revoke_refresh_family(state: &AppState, client: &ClientInfo, user_id: Uuid, session_id: &str) -> Result<(), AuthError> {
+    state.token_blacklist.revoke(session_id).await?;
+    state.refresh_tokens.revoke(session_id).await?;
+    state.sessions.remove(user_id, session_id).await?;
+
+    warn!(user_id = %user_id, session = %session_id, "refresh token reused, session revoked");
+    state.audit.record(AuditEvent::failure(AuditAction::RevokedTokenUse, Some(user_id), client, &AuthError::InvalidToken)).await;
+
+    Ok(())
+}
+
+// Expiry of a session and of its refresh tokens, from now on the clock of the token settings
+fn refresh_expiry(state: &AppState) -> chrono::DateTime<chrono::Utc> {
+    state.token_config.clock.now() + state.token_config.expiries.refresh.unwrap_or(state.token_config.expiries.access)
+}
+
+
+/// Handler for requesting a password reset
+///
+/// Endpoint: POST /forgot-password
+/// Body: {"email": "..."}
+///
+/// Flow:
+/// 1. Searches the active user with this (normalized) email
+/// 2. If found, generates a reset token valid for `token_config.expiries.reset` (15 minutes)
+/// 3. Emails the token to the user (`email_sender`), a failed delivery is only logged
+///
+/// Always returns 200, so the response doesn't reveal which emails are registered
+#[utoipa::path(
+    post,
+    path = "/forgot-password",
+    tag = "auth",
+    request_body = ForgotPasswordRequest,
+    responses(
+        (status = 200, description = "Always returned, whether the email exists or not", body = MessageResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+
+    let email = normalize_email(&payload.email);
+
+    // Deactivated accounts get no reset token
+    if let Some(user) = state.user_repo.find_active_by_email(&email).await? {
+        let token = create_reset_token(UserId(user.id), &state.jwt_keys, &state.token_config)
+            .map_err(|_| AuthError::InternalError)?;
+
+        // Still 200: an error would tell that the email is registered
+        match send_reset_token(&state, &user.email, &token).await {
+            Ok(()) => info!(user_id = %user.id, "password reset requested"),
+            Err(error) => warn!(user_id = %user.id, error = %error, "password reset email not sent"),
+        }
+    }
+
+    Ok(Json(MessageResponse {
+        message: "If the email is registered, a password reset link was sent".to_string(),
+    }))
+}
+
+
+/// Handler for resetting the password with a reset token
+///
+/// Endpoint: POST /reset-password
+/// Body: {"token": "...", "new_password": "..."}
+///
+/// Flow:
+/// 1. Validates the reset token (other token types are rejected)
+/// 2. Validates the new password
+/// 3. Rejects a password among the recent ones (`PasswordPolicy::history`)
+/// 4. Revokes the reset token, rejected if another request already did: it can't be used twice
+/// 5. Hashes and persists the new password
+/// 6. Invalidates every token of the user (`User::token_version`)
+#[utoipa::path(
+    post,
+    path = "/reset-password",
+    tag = "auth",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = MessageResponse),
+        (status = 400, description = "Weak password", body = ErrorResponse),
+        (status = 401, description = "Invalid or expired token", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn reset_password_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+
+    let claims = validate_token_type(&payload.token, &state.jwt_keys, &state.token_config, TokenType::Reset)?;
+
+    // Reset tokens are single-use (a used one is rejected early here, see the claim below)
+    if state.token_blacklist.is_revoked(&claims.jti).await? {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let user_id = claims.sub.as_uuid();
+
+    validate_password_with(&state.password_policy, &payload.new_password)?;
+
+    // A token issued before the account was deactivated can't be used anymore
+    let user = state.user_repo
+        .find_active_by_id(user_id)
+        .await?
+        .ok_or(AuthError::InvalidToken)?;
+
+    check_password_reuse(&state, &user, &payload.new_password).await?;
+
+    let password_hash = crypto::hash_password_with(&state.argon2_config, &payload.new_password)
+        .map_err(|_| AuthError::InternalError)?;
+
+    // Claimed before anything changes: of concurrent requests with the same token, only
+    // the one revoking it goes on. A failure from here on burns the token, a new one must be requested
+    if !state.token_blacklist.revoke(&claims.jti).await? {
+        return Err(AuthError::InvalidToken);
+    }
+
+    remember_current_password(&state, &user).await?;
+
+    state.user_repo
+        .update(user_id, UpdateUser::default(), Some(password_hash))
+        .await
+        .map_err(|e| match e {
+            AuthError::UserNotFound => AuthError::InvalidToken,
+            other => other,
+        })?;
+    // Whoever knew the old password is logged out
+    state.user_repo.increment_token_version(user_id).await?;
+
+    info!(user_id = %user_id, "password reset");
+    state.audit.record(AuditEvent::success(AuditAction::PasswordReset, Some(user_id), &client)).await;
+
+    Ok(Json(MessageResponse {
+        message: "Password has been reset".to_string(),
+    }))
+}
+
+
+/// Handler for changing the password of the authenticated user
+///
+/// Endpoint: POST /change-password
+/// Headers: Authorization: Bearer <token>
+/// Body: {"current_password": "...", "new_password": "..."}
+///
+/// Flow:
+/// 1. Verifies the current password
+/// 2. Rejects a new password equal to the current one
+/// 3. Validates the new password
+/// 4. Rejects a password among the recent ones (`PasswordPolicy::history`)
+/// 5. Hashes and persists the new password
+/// 6. Invalidates every token of the user (`User::token_version`), the current one included
+#[utoipa::path(
+    post,
+    path = "/change-password",
+    tag = "auth",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = MessageResponse),
+        (status = 400, description = "Weak password", body = ErrorResponse),
+        (status = 401, description = "Invalid token or current password", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    check_password_size(&payload.current_password)?;
+
+    let user = state.user_repo
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+    let is_valid = crypto::verify_password_with(&state.argon2_config, &user.password_hash, &payload.current_password)
+        .map_err(|_| AuthError::InternalError)?;
+
+    if !is_valid {
+        warn!(user_id = %user.id, "password change failed: invalid current password");
+        let error = AuthError::InvalidCredentials;
+        state.audit.record(AuditEvent::failure(AuditAction::PasswordChange, Some(user.id), &client, &error)).await;
+        return Err(error);
+    }
+
+    if payload.new_password == payload.current_password {
+        return Err(ValidationReason::PasswordUnchanged.into());
+    }
+
+    validate_password_with(&state.password_policy, &payload.new_password)?;
+
+    check_password_reuse(&state, &user, &payload.new_password).await?;
+
+    let password_hash = crypto::hash_password_with(&state.argon2_config, &payload.new_password)
+        .map_err(|_| AuthError::InternalError)?;
+
+    remember_current_password(&state, &user).await?;
+
+    state.user_repo
+        .update(user.id, UpdateUser::default(), Some(password_hash))
+        .await?;
+    // Logs out every device, the current one included
+    state.user_repo.increment_token_version(user.id).await?;
+
+    info!(user_id = %user.id, "password changed");
+    state.audit.record(AuditEvent::success(AuditAction::PasswordChange, Some(user.id), &client)).await;
+
+    Ok(Json(MessageResponse {
+        message: "Password has been changed".to_string(),
+    }))
+}
+
+
+/// Handler requesting a change of the user's email
+///
+/// Endpoint: POST /change-email
+/// Headers: Authorization: Bearer <token>
+/// Body: {"new_email": "..."}
+///
+/// Flow:
+/// 1. Normalizes and validates the new email
+/// 2. Checks that it is not the current email nor used by another account
+/// 3. Stores it as the pending email, the current email stays in use
+/// 4. Emails a verification token to the new address (502 `EmailDeliveryFailed` when it fails)
+///
+/// The email only changes once the token is used at `POST /verify-email`
+#[utoipa::path(
+    post,
+    path = "/change-email",
+    tag = "auth",
+    request_body = ChangeEmailRequest,
+    responses(
+        (status = 200, description = "Verification token sent to the new email", body = MessageResponse),
+        (status = 400, description = "Invalid or unchanged email", body = ErrorResponse),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+        (status = 409, description = "Email already in use", body = ErrorResponse),
+        (status = 502, description = "The email couldn't be sent", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn change_email_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    let new_email = normalize_email(&payload.new_email);
+    validate_email(&new_email)?;
+
+    let user = state.user_repo
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+    if new_email == user.email {
+        return Err(ValidationReason::EmailUnchanged.into());
+    }
+
+    if state.user_repo.find_by_email(&new_email).await?.is_some() {
+        warn!(user_id = %user.id, "email change rejected: email already in use");
+        return Err(conflict(&state, AuthError::EmailTaken));
+    }
+
+    state.user_repo.set_pending_email(user.id, &new_email).await?;
+
+    let token = create_email_change_token(UserId(user.id), &new_email, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
+    send_verification_token(&state, &new_email, &token).await?;
+
+    info!(user_id = %user.id, "email change requested");
+
+    Ok(Json(MessageResponse {
+        message: "A verification token was sent to the new email".to_string(),
+    }))
+}
+
+
+/// Handler for confirming the email address of a user
+///
+/// Endpoint: POST /verify-email
+/// Body: {"token": "..."}
+///
+/// Flow:
+/// 1. Validates the verification token (other token types are rejected)
+/// 2. Marks the email of the user as verified, or for a token sent by
+///    `POST /change-email`, replaces the email with the pending one
+///
+/// Verifying twice is harmless, so the token is not revoked
+#[utoipa::path(
+    post,
+    path = "/verify-email",
+    tag = "auth",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "Email verified", body = MessageResponse),
+        (status = 401, description = "Invalid or expired token", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyEmailRequest>,
+) -> Result<Json<MessageResponse>, AuthError> {
+
+    let claims = validate_token_type(&payload.token, &state.jwt_keys, &state.token_config, TokenType::Verify)?;
+
+    let user_id = claims.sub.as_uuid();
+
+    // A token of an email change can only confirm that change (not an older or newer one)
+    let new_email = claims.extra.get(NEW_EMAIL_CLAIM).and_then(|email| email.as_str());
+    let result = match new_email {
+        Some(new_email) => state.user_repo.confirm_pending_email(user_id, new_email).await,
+        None => state.user_repo.mark_email_verified(user_id).await,
+    };
+    result.map_err(|e| match e {
+        AuthError::UserNotFound => AuthError::InvalidToken,
+        AuthError::UserAlreadyExists => conflict(&state, AuthError::EmailTaken),
+        other => other,
+    })?;
+
+    info!(user_id = %user_id, email_changed = new_email.is_some(), "email verified");
+
+    Ok(Json(MessageResponse {
+        message: "Email has been verified".to_string(),
+    }))
+}
+
+
+// Emails the email verification token (`POST /verify-email`) to `email`, through `state.email_sender`
+async fn send_verification_token(state: &AppState, email: &str, token: &str) -> Result<(), AuthError> {
+    let body = format!("Confirm your email address with this token:\n\n{}\n", token);
+    state.email_sender.send(email, "Verify your email address", &body).await
+}
+
+
+// Emails the password reset token (`POST /reset-password`) to `email`, through `state.email_sender`
+async fn send_reset_token(state: &AppState, email: &str, token: &str) -> Result<(), AuthError> {
+    let body = format!(
+        "Reset your password with this token, valid for {} minutes:\n\n{}\n\nIf you didn't ask for it, ignore this email: your password is unchanged.\n",
+        state.token_config.expiries.reset.num_minutes(),
+        token,
+    );
+    state.email_sender.send(email, "Reset your password", &body).await
+}
+
+
+// Rejects `new_password` when it is one of the last `history` passwords of the user:
+// the current one, then the stored previous hashes
+// A stored hash that can't be parsed (e.g. of a removed algorithm) counts as no match
+async fn check_password_reuse(state: &AppState, user: &User, new_password: &str) -> Result<(), AuthError> {
+    let history = state.password_policy.history;
+    if history == 0 {
+        return Ok(());
+    }
+
+    let previous = state.user_repo.password_history(user.id).await?;
+    let recent = std::iter::once(&user.password_hash).chain(previous.iter().take(history - 1));
+
+    for hash in recent {
+        if matches!(crypto::verify_password_with(&state.argon2_config, hash, new_password), Ok(true)) {
+            return Err(ValidationReason::PasswordReused { history }.into());
+        }
+    }
+
+    Ok(())
+}
+
+
+// Stores the hash being replaced in the password history, before the new one is saved
+// (the current password is checked separately, so `history - 1` previous ones are kept)
+async fn remember_current_password(state: &AppState, user: &User) -> Result<(), AuthError> {
+    let keep = state.password_policy.history.saturating_sub(1);
+    if keep == 0 {
+        return Ok(());
+    }
+
+    state.user_repo.add_password_history(user.id, user.password_hash.clone(), keep).await
+}
+
+
+/// Handler for logging out
+///
+/// Endpoint: POST /logout
+/// Headers: Authorization: Bearer <token>
+///
+/// Revokes the token used for this request and its session (so the refresh token too),
+/// any later use of them returns 401 (and clears the auth cookie, when enabled)
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Logged out, the token and its session are revoked"),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn logout_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    client: ClientInfo,
+) -> Result<(HeaderMap, StatusCode), AuthError> {
+
+    state.token_blacklist.revoke(&user.jti).await?;
+    if user.session_id() != user.jti {
+        state.token_blacklist.revoke(user.session_id()).await?;
+    }
+    state.sessions.remove(user.user_id.as_uuid(), user.session_id()).await?;
+
+    info!(user_id = %user.user_id, "logged out");
+    state.audit.record(AuditEvent::success(AuditAction::TokenRevocation, Some(user.user_id.as_uuid()), &client)).await;
+
+    Ok((clear_cookie_headers(&state), StatusCode::NO_CONTENT))
+}
+
+
+// Headers removing the auth cookie and its CSRF cookie (empty when cookies are disabled)
+pub(crate) fn clear_cookie_headers(state: &AppState) -> HeaderMap {
+    match &state.auth_cookie {
+        Some(cookie) => cookie.clear_headers(),
+        None => HeaderMap::new(),
+    }
+}
+
+
+/// Handler validating a token for other services (token introspection)
+///
+/// Endpoint: POST /introspect
+/// Body: {"token": "..."}
+///
+/// Returns `{"active": true, "sub", "exp", "roles"}` for a valid access token,
+/// and `{"active": false}` (200, not an error) when the token is invalid, expired or revoked,
+/// or rejected for its user like `AuthUser` does (deleted, deactivated when checked), so gateways don't have to implement the JWT checks themselves
+#[utoipa::path(
+    post,
+    path = "/introspect",
+    tag = "auth",
+    request_body = IntrospectRequest,
+    responses(
+        (status = 200, description = "`active` with the claims, or only `active: false` for an invalid, expired or revoked token", body = IntrospectResponse),
+        (status = 413, description = "Body over 200 KiB", body = ErrorResponse),
+    ),
+)]
+pub async fn introspect_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<IntrospectRequest>,
+) -> Result<Json<IntrospectResponse>, AuthError> {
+
+    Ok(Json(introspect_token(&state, &payload.token).await?))
+}
+
+
+/// Handler validating several tokens at once, for gateways checking many per request
+///
+/// Endpoint: POST /introspect/batch
+/// Body: {"tokens": ["...", "..."]}
+///
+/// Returns `{"results": [...]}`, the `POST /introspect` answer of each token in the same order.
+/// A bad token only makes its own result inactive, and so does a store failing while one token
+/// is checked (logged). More than `MAX_INTROSPECT_BATCH` tokens
+/// is a validation error, so one request can't make the server check an unbounded number
+#[utoipa::path(
+    post,
+    path = "/introspect/batch",
+    tag = "auth",
+    request_body = IntrospectBatchRequest,
+    responses(
+        (status = 200, description = "The `/introspect` answer of each token, in the same order", body = IntrospectBatchResponse),
+        (status = 400, description = "More than 100 tokens", body = ErrorResponse),
+        (status = 413, description = "Body over 200 KiB", body = ErrorResponse),
+    ),
+)]
+pub async fn introspect_batch_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<IntrospectBatchRequest>,
+) -> Result<Json<IntrospectBatchResponse>, AuthError> {
+
+    if payload.tokens.len() > MAX_INTROSPECT_BATCH {
+        return Err(ValidationReason::TooManyTokens { max: MAX_INTROSPECT_BATCH }.into());
+    }
+
+    let mut results = Vec::with_capacity(payload.tokens.len());
+    for token in &payload.tokens {
+        // The other tokens still get their answer, an unchecked token is never reported active
+        let result = introspect_token(&state, token).await.unwrap_or_else(|error| {
+            warn!(error = %error, "token introspection failed, reported inactive");
+            IntrospectResponse::default()
+        });
+        results.push(result);
+    }
+
+    Ok(Json(IntrospectBatchResponse { results }))
+}
+
+
+// Introspection answer for one token: active with its claims, or inactive
+async fn introspect_token(state: &AppState, token: &str) -> Result<IntrospectResponse, AuthError> {
+    let claims = validate_token_type(token, &state.jwt_keys, &state.token_config, TokenType::Access).ok();
+
+    // Both revocation lookups are always made, so the response time doesn't tell
+    // an invalid token from a revoked one
+    let (jti, session_id) = claims.as_ref().map_or(("", ""), |c| (c.jti.as_str(), c.session_id()));
+    let token_revoked = state.token_blacklist.is_revoked(jti).await?;
+    let session_revoked = state.token_blacklist.is_revoked(session_id).await?;
+
+    // Same checks of the stored user as `AuthUser`: issued before a password change or a logout
+    // everywhere, user deleted, or account deactivated when `check_active_on_request` is set
+    let user_rejected = match &claims {
+        Some(claims) => match check_stored_user(state, claims.sub.as_uuid(), claims.token_version).await {
+            Ok(_) => false,
+            Err(AuthError::InvalidToken | AuthError::UserNotFound | AuthError::AccountDisabled) => true,
+            Err(error) => return Err(error),
+        },
+        None => false,
+    };
+
+    let response = match claims {
+        Some(claims) if !token_revoked && !session_revoked && !user_rejected => IntrospectResponse {
+            active: true,
+            sub: Some(claims.sub),
+            exp: Some(claims.exp),
+            roles: Some(claims.roles),
+        },
+        _ => IntrospectResponse::default(),
+    };
+
+    Ok(response)
+}
+
+
+/// Handler of the example protected route, usable as is to check a token from a client
+///
+/// Endpoint: GET /private
+/// Headers: Authorization: Bearer <token>
+///
+/// Answers with the id of the user and when the token was issued, without any lookup
+#[utoipa::path(
+    get,
+    path = "/private",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The id of the user and when the token was issued", body = PrivateResponse),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn private_handler(user: AuthUser) -> Json<PrivateResponse> {
+    Json(PrivateResponse { user_id: user.user_id, authenticated_at: user.issued_at })
+}
+
+
+/// Handler returning the profile of the authenticated user
+///
+/// Endpoint: GET /me
+/// Headers: Authorization: Bearer <token>
+///
+/// The password hash is never serialized. The user is loaded by `CurrentUser`,
+/// so a deleted user is 404 and a deactivated account 403.
+#[utoipa::path(
+    get,
+    path = "/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The user, without the password hash", body = User),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+        (status = 403, description = "Account disabled", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn me_handler(CurrentUser { user, .. }: CurrentUser) -> Json<User> {
+    Json(user)
+}
+
+
+/// Handler updating the profile of the authenticated user
+///
+/// Endpoint: PATCH /me
+/// Headers: Authorization: Bearer <token>
+/// Body: {"username": "..."}
+///
+/// Flow:
+/// 1. Rejects `password` (see `POST /change-password`), `email` (see `POST /change-email`) and `roles`
+/// 2. Normalizes and validates the username, every invalid field is reported at once
+/// 3. Checks that a changed username is not used by another account
+/// 4. Saves the change and returns the updated user
+#[utoipa::path(
+    patch,
+    path = "/me",
+    tag = "auth",
+    request_body = UpdateUser,
+    responses(
+        (status = 200, description = "The updated user", body = User),
+        (status = 400, description = "Invalid username, or password, email or roles given", body = ErrorResponse),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+        (status = 409, description = "Username already in use", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn update_me_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<UpdateUser>,
+) -> Result<Json<User>, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    let username = payload.username.as_deref().map(normalize_username);
+
+    validate_all([
+        match payload.password {
+            Some(_) => Err(ValidationReason::PasswordNotEditable.into()),
+            None => Ok(()),
+        },
+        // The new address has to be verified first, or an unverified email would be marked as verified
+        match payload.email {
+            Some(_) => Err(ValidationReason::EmailNotEditable.into()),
+            None => Ok(()),
+        },
+        match payload.roles {
+            Some(_) => Err(ValidationReason::RolesNotEditable.into()),
+            None => Ok(()),
+        },
+        username.as_deref().map_or(Ok(()), |username| validate_username_with(state.username_policy, username)),
+    ])?;
+
+    let user = state.user_repo
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AuthError::UserNotFound)?;
+
+    // Only a username that actually changes is checked and saved
+    let username = username.filter(|username| *username != user.username);
+
+    // Usernames are compared case-insensitively, so changing only the case of one's own is allowed
+    if let Some(username) = &username
+        && state.user_repo.find_by_username(username).await?.is_some_and(|other| other.id != user.id)
+    {
+        warn!(user_id = %user.id, "profile update rejected: username already in use");
+        return Err(conflict(&state, AuthError::UsernameTaken));
+    }
+
+    if username.is_none() {
+        return Ok(Json(user));
+    }
+
     let user = state.user_repo
-        .find_by_username(&payload.username)
+        .update(user.id, UpdateUser { username, ..UpdateUser::default() }, None)
+        .await?;
+
+    info!(user_id = %user.id, "profile updated");
+
+    Ok(Json(user))
+}
+
+
+/// Handler deleting the account of the authenticated user
+///
+/// Endpoint: DELETE /me
+/// Headers: Authorization: Bearer <token>
+/// Body: {"password": "..."}
+///
+/// Flow:
+/// 1. Checks the current password (401 `InvalidCredentials` otherwise)
+/// 2. Revokes every session of the user, and the current token even if its session wasn't recorded
+/// 3. Deletes the user as `account_deletion` says: `Soft` deactivates it and invalidates every
+///    token issued before (`User::token_version`), `Hard` removes it and its password history
+/// 4. Returns 204 No Content (and clears the auth cookie, when enabled)
+#[utoipa::path(
+    delete,
+    path = "/me",
+    tag = "auth",
+    request_body = DeleteAccountRequest,
+    responses(
+        (status = 204, description = "Account deleted, its tokens revoked"),
+        (status = 401, description = "Wrong password, or invalid or missing token", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn delete_me_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<(HeaderMap, StatusCode), AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    check_password_size(&payload.password)?;
+
+    let stored = state.user_repo
+        .find_by_id(user_id)
         .await?
-        .ok_or(AuthError::InvalidCredentials)?;
+        .ok_or(AuthError::UserNotFound)?;
 
-    let is_valid = crypto::verify_password(&user.password_hash, &payload.password)
+    let is_valid = crypto::verify_password_with(&state.argon2_config, &stored.password_hash, &payload.password)
         .map_err(|_| AuthError::InternalError)?;
-    
+
     if !is_valid {
-        return Err(AuthError::InvalidCredentials);
+        warn!(user_id = %user_id, "account deletion failed: invalid password");
+        let error = AuthError::InvalidCredentials;
+        state.audit.record(AuditEvent::failure(AuditAction::AccountDeletion, Some(user_id), &client, &error)).await;
+        return Err(error);
+    }
+
+    for session in state.sessions.remove_all(user_id).await? {
+        state.token_blacklist.revoke(&session.jti).await?;
+    }
+    state.token_blacklist.revoke(&user.jti).await?;
+    state.token_blacklist.revoke(user.session_id()).await?;
+
+    match state.account_deletion {
+        AccountDeletionMode::Soft => {
+            state.user_repo.set_active(user_id, false).await?;
+            state.user_repo.increment_token_version(user_id).await?;
+        }
+        AccountDeletionMode::Hard => state.user_repo.delete(user_id).await?,
     }
-    
-    // Generate valid JWT token for 24 hours
-    let token = create_token(&user.id.to_string(), &state.jwt_secret);
 
-    Ok(Json(LoginResponse { token }))
+    info!(user_id = %user_id, mode = ?state.account_deletion, "account deleted");
+    state.audit.record(AuditEvent::success(AuditAction::AccountDeletion, Some(user_id), &client)).await;
+
+    Ok((clear_cookie_headers(&state), StatusCode::NO_CONTENT))
+}
+
+
+// User of the login identifier, looked up by username or email as `login_identifier` says
+// An identifier of the wrong form is not looked up, the login fails like an unknown user
+pub(crate) async fn find_login_user(state: &AppState, tenant_id: Option<&str>, identifier: &str) -> Result<Option<User>, AuthError> {
+    let by_email = match state.login_identifier {
+        LoginIdentifierMode::UsernameOnly => false,
+        LoginIdentifierMode::EmailOnly if !identifier.contains('@') => return Ok(None),
+        LoginIdentifierMode::EmailOnly => true,
+        LoginIdentifierMode::Either => identifier.contains('@'),
+    };
+
+    match by_email {
+        true => state.user_repo.find_by_email_in_tenant(tenant_id, &normalize_email(identifier)).await,
+        false => state.user_repo.find_by_username_in_tenant(tenant_id, &normalize_username(identifier)).await,
+    }
 }
 
 
+// Generates the access token (and the refresh token, when enabled) for a user,
+// and records them as a new session of the client
+pub(crate) async fn issue_tokens(state: &AppState, user: &User, client: ClientInfo) -> Result<LoginResponse, AuthError> {
+    let user_id = UserId(user.id);
+    let token = create_tenant_token(user_id, &user.roles, user.tenant_id.as_deref(), user.token_version, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
 
+    // The session is identified by the jti of this access token
+    let claims = validate_token_type(&token, &state.jwt_keys, &state.token_config, TokenType::Access)
+        .map_err(|_| AuthError::InternalError)?;
+    let refresh_token = create_session_refresh_token(user_id, &claims.jti, user.token_version, &state.jwt_keys, &state.token_config)
+        .map_err(|_| AuthError::InternalError)?;
+
+    let now = state.token_config.clock.now();
+    let expires_at = refresh_expiry(state);
+    // Starts the chain of refresh tokens of the session, see refresh_rotate_handler
+    if let Some(refresh_token) = &refresh_token {
+        let refresh_claims = validate_token_type(refresh_token, &state.jwt_keys, &state.token_config, TokenType::Refresh)
+            .map_err(|_| AuthError::InternalError)?;
+        state.refresh_tokens.issue(&claims.jti, &refresh_claims.jti, expires_at, now).await?;
+    }
+
+    state.sessions.create(Session {
+        jti: claims.jti,
+        user_id: user.id,
+        issued_at: now,
+        expires_at,
+        user_agent: client.user_agent,
+        ip: client.ip,
+    }, now).await?;
+
+    info!(user_id = %user_id, refresh_token = refresh_token.is_some(), "tokens issued");
+
+    Ok(LoginResponse { token, refresh_token })
+}
+
+
+// Headers delivering the access token besides the body: the auth cookie and the
+// `token_response_header` (`Bearer <token>`), each only when enabled
+pub(crate) fn token_headers(state: &AppState, tokens: &LoginResponse) -> HeaderMap {
+    let mut headers = match &state.auth_cookie {
+        Some(cookie) => cookie.headers(&tokens.token, state.token_config.expiries.access),
+        None => HeaderMap::new(),
+    };
+    if let Some(name) = &state.token_response_header
+        && let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", tokens.token))
+    {
+        headers.insert(name.clone(), value);
+    }
+    headers
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::http::header;
+    use crate::auth::{crypto::Argon2Config, jwt::{create_token_with_config, JwtKeys, TokenConfig, TokenExpiries}};
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::email::{EmailSender, InMemoryEmailSender};
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+    const USER_ID: UserId = UserId(Uuid::from_u128(1));
+
+    fn state() -> AppState {
+        let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        // Low cost keeps the tests fast
+        state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+        state
+    }
+
+    // Token settings of `state` issuing tokens that expired a minute ago
+    fn expired_token_config(state: &AppState) -> TokenConfig {
+        let expired = chrono::Duration::minutes(-1);
+        TokenConfig {
+            expiries: TokenExpiries { access: expired, refresh: Some(expired), reset: expired, verify: expired },
+            ..state.token_config.clone()
+        }
+    }
+
+    fn register_request(username: &str, email: &str) -> RegisterRequest {
+        RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: "Password123!".to_string().into(),
+            invite_code: None,
+        }
+    }
+
+    async fn register(state: &AppState, username: &str, email: &str) -> RegisterResponse {
+        let (status, _, Json(response)) = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request(username, email)))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        response
+    }
+
+    #[tokio::test]
+    async fn test_register_returns_the_created_user() {
+        let state = state();
+        let (_, _, Json(response)) = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", " John@Example.com ")))
+            .await
+            .unwrap();
+
+        let stored = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        let body = serde_json::to_value(&response).unwrap();
+        assert_eq!(body["user"]["id"], stored.id.to_string());
+        assert_eq!(body["user"]["username"], "john_doe");
+        assert_eq!(body["user"]["email"], "john@example.com");
+        assert_eq!(body["user"]["email_verified"], false);
+        assert!(body["user"].get("password_hash").is_none());
+        assert!(body["token"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_login_returns_refresh_token() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let (_, Json(response)) = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: "Password123!".to_string().into(),
+        })).await.unwrap();
+
+        assert!(response.refresh_token.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_users_are_not_found_from_another_tenant() {
+        let state = state();
+        let acme = RequestTenant(Some("acme".to_string()));
+        register_handler(State(state.clone()), ClientInfo::default(), acme.clone(), Json(register_request("john_doe", "john@example.com")))
+            .await
+            .unwrap();
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(user.tenant_id.as_deref(), Some("acme"));
+
+        let login = || Json(login_request("john_doe"));
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant(Some("globex".to_string())), login()).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), login()).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        let (_, Json(response)) = login_handler(State(state.clone()), ClientInfo::default(), acme, login()).await.unwrap();
+        let claims = validate_token_type(&response.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.tenant_id.as_deref(), Some("acme"));
+    }
+
+    async fn introspect(state: &AppState, token: &str) -> IntrospectResponse {
+        let Json(response) = introspect_handler(State(state.clone()), Json(IntrospectRequest { token: token.to_string() }))
+            .await
+            .unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_introspect_batch_keeps_the_order_of_the_tokens() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+        let (_, tokens) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+        let expired = create_token_with_config(USER_ID, &[], &state.jwt_keys, &expired_token_config(&state)).unwrap();
+
+        let request = IntrospectBatchRequest {
+            tokens: vec![tokens.token.clone(), "not-a-jwt".to_string(), expired, tokens.token.clone()],
+        };
+        let Json(response) = introspect_batch_handler(State(state), Json(request)).await.unwrap();
+
+        let active: Vec<bool> = response.results.iter().map(|result| result.active).collect();
+        assert_eq!(active, vec![true, false, false, true]);
+        assert!(response.results[0].sub.is_some());
+        assert!(response.results[1].sub.is_none());
+    }
+
+    // Blacklist whose store fails when asked about one jti
+    struct FailingBlacklist {
+        jti: String,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::db::token_blacklist::TokenBlacklist for FailingBlacklist {
+        async fn revoke(&self, _: &str) -> Result<bool, AuthError> {
+            Ok(true)
+        }
+
+        async fn is_revoked(&self, jti: &str) -> Result<bool, AuthError> {
+            match jti == self.jti {
+                true => Err(AuthError::InternalError),
+                false => Ok(false),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_introspect_batch_store_error_only_fails_its_token() {
+        let mut state = state();
+        let failing = create_token_with_config(USER_ID, &[], &state.jwt_keys, &state.token_config).unwrap();
+        let working = create_token_with_config(USER_ID, &[], &state.jwt_keys, &state.token_config).unwrap();
+        let jti = validate_token_type(&failing, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap().jti;
+        state.token_blacklist = Arc::new(FailingBlacklist { jti });
+
+        let request = IntrospectBatchRequest { tokens: vec![failing.clone(), working] };
+        let Json(response) = introspect_batch_handler(State(state.clone()), Json(request)).await.unwrap();
+        let active: Vec<bool> = response.results.iter().map(|result| result.active).collect();
+        assert_eq!(active, vec![false, true]);
+
+        // Alone, the failure is still an error
+        let result = introspect_handler(State(state), Json(IntrospectRequest { token: failing })).await;
+        assert!(matches!(result, Err(AuthError::InternalError)));
+    }
+
+    #[tokio::test]
+    async fn test_introspect_batch_rejects_too_many_tokens() {
+        let request = IntrospectBatchRequest { tokens: vec!["token".to_string(); MAX_INTROSPECT_BATCH + 1] };
+
+        let result = introspect_batch_handler(State(state()), Json(request)).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_introspect_valid_token_returns_claims() {
+        let state = state();
+        let roles = vec!["admin".to_string()];
+        let token = create_token_with_config(USER_ID, &roles, &state.jwt_keys, &state.token_config).unwrap();
+
+        let response = introspect(&state, &token).await;
+        assert!(response.active);
+        assert_eq!(response.sub, Some(USER_ID));
+        assert_eq!(response.roles, Some(roles));
+        assert!(response.exp.unwrap() > chrono::Utc::now().timestamp() as usize);
+    }
+
+    #[tokio::test]
+    async fn test_introspect_expired_revoked_or_garbage_token_is_inactive() {
+        let state = state();
+        let expired = create_token_with_config(USER_ID, &[], &state.jwt_keys, &expired_token_config(&state)).unwrap();
+
+        let response = introspect(&state, &expired).await;
+        assert!(!response.active);
+        assert_eq!(serde_json::to_value(&response).unwrap(), serde_json::json!({ "active": false }));
+
+        let token = create_token_with_config(USER_ID, &[], &state.jwt_keys, &state.token_config).unwrap();
+        let claims = validate_token_type(&token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        state.token_blacklist.revoke(&claims.jti).await.unwrap();
+        assert!(!introspect(&state, &token).await.active);
+
+        assert!(!introspect(&state, "not-a-jwt").await.active);
+    }
+
+    #[tokio::test]
+    async fn test_introspect_deactivated_account_is_inactive_when_checked() {
+        let mut state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        state.user_repo.set_active(user.id, false).await.unwrap();
+        assert!(introspect(&state, &tokens.token).await.active);
+
+        state.check_active_on_request = true;
+        assert!(!introspect(&state, &tokens.token).await.active);
+    }
+
+    #[tokio::test]
+    async fn test_failed_login_is_audited_without_password() {
+        use crate::audit::{AuditOutcome, InMemoryAuditSink};
+
+        let mut state = state();
+        let audit = InMemoryAuditSink::new();
+        state.audit = Arc::new(audit.clone());
+        register(&state, "john_doe", "john@example.com").await;
+
+        let client = ClientInfo { user_agent: None, ip: Some("203.0.113.7".to_string()) };
+        let result = login_handler(State(state.clone()), client, RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: "WrongPassword1!".to_string().into(),
+        })).await;
+        assert!(result.is_err());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        let events = audit.events();
+        let event = events.last().unwrap();
+        assert_eq!(event.action, AuditAction::Login);
+        assert_eq!(event.outcome, AuditOutcome::Failure);
+        assert_eq!(event.user_id, Some(user.id));
+        assert_eq!(event.ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(event.reason, Some("invalid_credentials"));
+
+        let json = serde_json::to_value(event).unwrap();
+        assert!(json.get("password").is_none());
+        assert!(!json.to_string().contains("WrongPassword1!"));
+    }
+
+    #[tokio::test]
+    async fn test_login_records_last_login_at() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+        let before = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(before.last_login_at.is_none());
+
+        assert!(login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+
+        let after = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(after.last_login_at.is_some_and(|date| date >= before.created_at));
+        // A login doesn't count as a profile change
+        assert_eq!(after.updated_at, before.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_issues_access_token() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+
+        let Json(response) = refresh_handler(State(state), ClientInfo::default(), Json(RefreshRequest {
+            refresh_token: tokens.refresh_token.unwrap(),
+        })).await.unwrap();
+
+        assert!(validate_token_type(&response.token, &JwtKeys::hmac(SECRET), &TokenConfig::default(), TokenType::Access).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rejects_access_token() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+
+        let result = refresh_handler(State(state), ClientInfo::default(), Json(RefreshRequest {
+            refresh_token: tokens.token,
+        })).await;
+
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    // Rotates `refresh_token` at /token/refresh-rotate
+    async fn rotate(state: &AppState, refresh_token: &str) -> Result<LoginResponse, AuthError> {
+        let request = RefreshRequest { refresh_token: refresh_token.to_string() };
+        let Json(response) = refresh_rotate_handler(State(state.clone()), ClientInfo::default(), Json(request)).await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn test_session_times_follow_the_token_clock() {
+        use chrono::TimeZone;
+        use crate::clock::MockClock;
+
+        let mut state = state();
+        let now = chrono::Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+        state.token_config.clock = Arc::new(MockClock::new(now));
+        register(&state, "john_doe", "john@example.com").await;
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        let sessions = state.sessions.list(user.id, state.token_config.clock.now()).await.unwrap();
+        assert_eq!(sessions[0].issued_at, now);
+        assert_eq!(sessions[0].expires_at, now + state.token_config.expiries.refresh.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_and_rotation_follow_a_clock_in_the_past() {
+        use chrono::TimeZone;
+        use crate::clock::MockClock;
+
+        // Every token of this clock expired long ago by the real time
+        let mut state = state();
+        state.token_config.clock = Arc::new(MockClock::new(chrono::Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()));
+        let first = register(&state, "john_doe", "john@example.com").await.refresh_token.unwrap();
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(state.sessions.list(user.id, state.token_config.clock.now()).await.unwrap().len(), 1);
+
+        let second = rotate(&state, &first).await.unwrap().refresh_token.unwrap();
+        assert!(rotate(&state, &second).await.is_ok());
+        // The family is still live by this clock, so the reuse is caught
+        assert!(matches!(rotate(&state, &first).await, Err(AuthError::InvalidToken)));
+        assert!(state.sessions.list(user.id, state.token_config.clock.now()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotate_replaces_the_refresh_token() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let first = tokens.refresh_token.unwrap();
+
+        let rotated = rotate(&state, &first).await.unwrap();
+        let second = rotated.refresh_token.unwrap();
+        assert_ne!(second, first);
+        let keys = JwtKeys::hmac(SECRET);
+        let access = validate_token_type(&rotated.token, &keys, &TokenConfig::default(), TokenType::Access).unwrap();
+        let refresh = validate_token_type(&second, &keys, &TokenConfig::default(), TokenType::Refresh).unwrap();
+        // Both stay in the session of the login
+        assert_eq!(access.session_id(), refresh.session_id());
+        assert_eq!(state.sessions.list(state.user_repo.find_by_username("john_doe").await.unwrap().unwrap().id, state.token_config.clock.now()).await.unwrap().len(), 1);
+
+        // The new refresh token rotates in turn
+        let third = rotate(&state, &second).await.unwrap().refresh_token.unwrap();
+        assert!(refresh_handler(State(state.clone()), ClientInfo::default(), Json(RefreshRequest { refresh_token: third })).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reused_refresh_token_revokes_the_session() {
+        use crate::audit::InMemoryAuditSink;
+
+        let audit = InMemoryAuditSink::new();
+        let state = AppState { audit: Arc::new(audit.clone()), ..state() };
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let stolen = tokens.refresh_token.unwrap();
+
+        // The thief rotates the stolen token, then the user presents it again
+        let thief = rotate(&state, &stolen).await.unwrap();
+        assert!(matches!(rotate(&state, &stolen).await, Err(AuthError::InvalidToken)));
+
+        // Every token of the chain is revoked, the thief's ones included
+        assert!(matches!(rotate(&state, &thief.refresh_token.unwrap()).await, Err(AuthError::InvalidToken)));
+        let session_id = validate_token_type(&thief.token, &JwtKeys::hmac(SECRET), &TokenConfig::default(), TokenType::Access)
+            .unwrap()
+            .session_id()
+            .to_string();
+        assert!(state.token_blacklist.is_revoked(&session_id).await.unwrap());
+        assert!(state.sessions.list(state.user_repo.find_by_username("john_doe").await.unwrap().unwrap().id, state.token_config.clock.now()).await.unwrap().is_empty());
+        assert!(audit.events().iter().any(|event| event.action == AuditAction::RevokedTokenUse));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rejects_rotated_out_token() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let first = tokens.refresh_token.unwrap();
+        let second = rotate(&state, &first).await.unwrap().refresh_token.unwrap();
+
+        let result = refresh_handler(State(state.clone()), ClientInfo::default(), Json(RefreshRequest { refresh_token: first })).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+        assert!(matches!(rotate(&state, &second).await, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_token_rejected_after_logout() {
+        use axum::extract::FromRequestParts;
+        use axum::http::Request;
+
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let parts = || Request::builder()
+            .header("Authorization", format!("Bearer {}", tokens.token))
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+
+        let user = AuthUser::from_request_parts(&mut parts(), &state).await.unwrap();
+        let (_, status) = logout_handler(State(state.clone()), user, ClientInfo::default()).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let result = AuthUser::from_request_parts(&mut parts(), &state).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    fn auth_user(user_id: UserId) -> AuthUser {
+        AuthUser {
+            user_id, jti: Uuid::new_v4().to_string(), roles: Vec::new(), issued_at: chrono::Utc::now(), tenant_id: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_me_returns_current_user() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+        let stored = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+
+        let current = CurrentUser { token: auth_user(UserId(stored.id)), user: stored.clone() };
+        let Json(user) = me_handler(current).await;
+        assert_eq!(user.id, stored.id);
+
+        let body = serde_json::to_value(&user).unwrap();
+        assert_eq!(body["username"], "john_doe");
+        assert!(body.get("password_hash").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_me_deleted_user_is_not_found() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        state.user_repo.delete(user.id).await.unwrap();
+
+        // The login token carries a version, so `AuthUser` already loads the user
+        let result = authenticate(&state, &tokens.token).await;
+        assert!(matches!(result, Err(AuthError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_update_me_changes_username() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let changes = UpdateUser { username: Some("john_smith".to_string()), ..UpdateUser::default() };
+
+        let Json(user) = update_me_handler(State(state.clone()), auth_user(user_id), Json(changes)).await.unwrap();
+        assert_eq!(user.username, "john_smith");
+        assert_eq!(user.email, "john@example.com");
+
+        assert!(state.user_repo.find_by_username("john_doe").await.unwrap().is_none());
+        assert!(state.user_repo.find_by_username("john_smith").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_update_me_rejects_email() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        state.user_repo.mark_email_verified(user_id.as_uuid()).await.unwrap();
+        let changes = UpdateUser { email: Some("john.smith@example.com".to_string()), ..UpdateUser::default() };
+
+        let result = update_me_handler(State(state.clone()), auth_user(user_id), Json(changes)).await;
+        let Err(AuthError::ValidationError(reasons)) = result else { panic!("expected a validation error") };
+        assert_eq!(reasons, vec![ValidationReason::EmailNotEditable]);
+
+        let user = state.user_repo.find_by_id(user_id.as_uuid()).await.unwrap().unwrap();
+        assert_eq!(user.email, "john@example.com");
+        assert!(user.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_update_me_rejects_password_and_roles() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let changes = UpdateUser {
+            password: Some("NewPassword456!".to_string().into()),
+            roles: Some(vec!["admin".to_string()]),
+            ..UpdateUser::default()
+        };
+
+        let result = update_me_handler(State(state.clone()), auth_user(user_id), Json(changes)).await;
+        let Err(AuthError::ValidationError(reasons)) = result else { panic!("expected a validation error") };
+        assert_eq!(reasons, vec![ValidationReason::PasswordNotEditable, ValidationReason::RolesNotEditable]);
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(user.roles.is_empty());
+    }
+
+    fn login_request(username: &str) -> LoginRequest {
+        LoginRequest { username: username.to_string(), password: "Password123!".to_string().into() }
+    }
+
+    // Whether logging in with `identifier` succeeds in `mode`
+    async fn logs_in(state: &AppState, mode: LoginIdentifierMode, identifier: &str) -> bool {
+        let state = AppState { login_identifier: mode, ..state.clone() };
+        match login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(login_request(identifier))).await {
+            Ok(_) => true,
+            Err(AuthError::InvalidCredentials) => false,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_identifier_username_only() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        assert!(logs_in(&state, LoginIdentifierMode::UsernameOnly, "john_doe").await);
+        assert!(!logs_in(&state, LoginIdentifierMode::UsernameOnly, "john@example.com").await);
+    }
+
+    #[tokio::test]
+    async fn test_login_identifier_email_only() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        assert!(logs_in(&state, LoginIdentifierMode::EmailOnly, " John@Example.com ").await);
+        // A username-form identifier is rejected like wrong credentials
+        assert!(!logs_in(&state, LoginIdentifierMode::EmailOnly, "john_doe").await);
+    }
+
+    #[tokio::test]
+    async fn test_login_identifier_either() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        assert!(logs_in(&state, LoginIdentifierMode::Either, "john_doe").await);
+        assert!(logs_in(&state, LoginIdentifierMode::Either, "john@example.com").await);
+        assert!(!logs_in(&state, LoginIdentifierMode::Either, "jane@example.com").await);
+    }
+
+    #[tokio::test]
+    async fn test_login_rehashes_outdated_hash() {
+        let mut state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        // Cost is raised after the user registered
+        state.argon2_config = Argon2Config { memory_kib: 128, iterations: 2, parallelism: 1, ..Argon2Config::default() };
+        assert!(login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$v=19$m=128,t=2,p=1$"));
+        assert!(crypto::verify_password(&user.password_hash, "Password123!").unwrap());
+    }
+
+    #[cfg(feature = "bcrypt")]
+    #[tokio::test]
+    async fn test_login_upgrades_legacy_bcrypt_hash() {
+        let state = state();
+        // Imported from the legacy system: bcrypt (cost 4) of "Password123!"
+        let legacy = CreateUser {
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        };
+        let bcrypt_hash = "$2b$04$LPzSKi9aRSn9jM7ZkZy8FOW0vI2nEeDs35fwvwkiN2qRLEUM3jonm";
+        state.user_repo.create(legacy, bcrypt_hash.to_string()).await.unwrap();
+
+        assert!(login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$"));
+        assert!(login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_login_keeps_hash_when_parameters_match() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+        let before = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+
+        assert!(login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+
+        let after = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(before.password_hash, after.password_hash);
+    }
+
+    #[tokio::test]
+    async fn test_login_unknown_user_is_invalid_credentials() {
+        let result = login_handler(State(state()), ClientInfo::default(), RequestTenant::default(), Json(login_request("nobody"))).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+    }
+
+    #[tokio::test]
+    async fn test_login_wrong_password_is_invalid_credentials() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let result = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: "WrongPassword1!".to_string().into(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+    }
+
+    #[tokio::test]
+    async fn test_huge_password_is_rejected_before_hashing() {
+        use axum::response::IntoResponse;
+        use std::time::{Duration, Instant};
+
+        // Default Argon2 cost, so hashing the input would be noticeable
+        let state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        let huge = format!("Aa1!{}", "a".repeat(10 * 1024 * 1024));
+
+        let started = Instant::now();
+        let mut request = register_request("john_doe", "john@example.com");
+        request.password = huge.clone().into();
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(request)).await;
+        let Err(error) = result else { panic!("huge password was accepted") };
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: huge.into(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(state.user_repo.find_by_username("john_doe").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_register_reports_every_invalid_field() {
+        let state = state();
+        let request = RegisterRequest {
+            username: "ab".to_string(),
+            email: "not-an-email".to_string(),
+            password: "weakpassword".to_string().into(),
+            invite_code: None,
+        };
+
+        let Err(AuthError::ValidationError(reasons)) = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(request)).await else {
+            panic!("invalid registration was accepted");
+        };
+        let kinds: Vec<&str> = reasons.iter().map(ValidationReason::kind).collect();
+        assert!(kinds.contains(&"invalid_email"));
+        assert!(kinds.contains(&"username_too_short"));
+        assert!(kinds.contains(&"password_missing_uppercase"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_registrations_only_one_succeeds() {
+        let state = state();
+
+        let first = tokio::spawn(register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "john@example.com"))));
+        let second = tokio::spawn(register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "john@example.com"))));
+
+        let results = [first.await.unwrap(), second.await.unwrap()];
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().any(|r| matches!(r, Err(AuthError::UserAlreadyExists))));
+    }
+
+    #[tokio::test]
+    async fn test_register_normalizes_email() {
+        let state = state();
+        register(&state, "john_doe", "  John.Doe@Example.COM ").await;
+
+        let user = state.user_repo.find_by_email("john.doe@example.com").await.unwrap().unwrap();
+        assert_eq!(user.username, "john_doe");
+        assert_eq!(user.email, "john.doe@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_register_email_differing_only_in_case_is_taken() {
+        let state = state();
+        register(&state, "john_doe", "John@Example.com").await;
+
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("jane_doe", "john@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn test_unicode_username_is_normalized() {
+        use crate::models::validation::UsernamePolicy;
+
+        let mut state = state();
+        let decomposed = "jose\u{0301}";
+        assert!(register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request(decomposed, "jose@example.com"))).await.is_err());
+
+        state.username_policy = UsernamePolicy::Unicode;
+        register(&state, decomposed, "jose@example.com").await;
+        assert!(state.user_repo.find_by_username("jos\u{00E9}").await.unwrap().is_some());
+
+        // Logging in with the composed form finds the same account
+        let result = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "jos\u{00E9}".to_string(),
+            password: "Password123!".to_string().into(),
+        })).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_usernames_are_case_insensitive() {
+        let mut state = state();
+        register(&state, "JohnDoe", "john@example.com").await;
+
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request(" johndoe "))).await;
+        assert!(result.is_ok());
+        let user = state.user_repo.find_by_username("JOHNDOE").await.unwrap().unwrap();
+        assert_eq!(user.username, "JohnDoe");
+
+        state.reveal_conflicting_field = true;
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("johndoe", "jane@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UsernameTaken)));
+    }
+
+    #[tokio::test]
+    async fn test_update_me_can_change_the_case_of_own_username() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let changes = UpdateUser { username: Some("John_Doe".to_string()), ..UpdateUser::default() };
+
+        let Json(user) = update_me_handler(State(state), auth_user(user_id), Json(changes)).await.unwrap();
+        assert_eq!(user.username, "John_Doe");
+    }
+
+    // The user of the token, as the extractor of a protected route sees it
+    async fn authenticate(state: &AppState, token: &str) -> Result<AuthUser, AuthError> {
+        use axum::extract::FromRequestParts;
+        use axum::http::Request;
+
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AuthUser::from_request_parts(&mut parts, state).await
+    }
+
+    fn delete_request(password: &str) -> DeleteAccountRequest {
+        DeleteAccountRequest { password: password.to_string().into() }
+    }
+
+    #[tokio::test]
+    async fn test_delete_me_deactivates_by_default() {
+        let state = state();
+        let registered = register(&state, "john_doe", "john@example.com").await;
+        let user = authenticate(&state, &registered.token).await.unwrap();
+
+        let (_, status) = delete_me_handler(State(state.clone()), user, ClientInfo::default(), Json(delete_request("Password123!"))).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let stored = state.user_repo.find_by_id(registered.user.id).await.unwrap().unwrap();
+        assert!(!stored.is_active);
+        assert!(matches!(authenticate(&state, &registered.token).await, Err(AuthError::InvalidToken)));
+        let refreshed = rotate(&state, registered.refresh_token.as_deref().unwrap()).await;
+        assert!(matches!(refreshed, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_delete_me_hard_removes_the_user() {
+        let mut state = state();
+        state.account_deletion = AccountDeletionMode::Hard;
+        let registered = register(&state, "john_doe", "john@example.com").await;
+        let user = authenticate(&state, &registered.token).await.unwrap();
+
+        delete_me_handler(State(state.clone()), user, ClientInfo::default(), Json(delete_request("Password123!"))).await.unwrap();
+
+        assert!(state.user_repo.find_by_id(registered.user.id).await.unwrap().is_none());
+        assert!(matches!(authenticate(&state, &registered.token).await, Err(AuthError::InvalidToken)));
+        // The email and username are free again
+        register(&state, "john_doe", "john@example.com").await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_me_wrong_password_keeps_the_account() {
+        let state = state();
+        let registered = register(&state, "john_doe", "john@example.com").await;
+        let user = authenticate(&state, &registered.token).await.unwrap();
+
+        let result = delete_me_handler(State(state.clone()), user, ClientInfo::default(), Json(delete_request("WrongPassword1!"))).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        assert!(state.user_repo.find_by_id(registered.user.id).await.unwrap().unwrap().is_active);
+        assert!(authenticate(&state, &registered.token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_grants_default_roles() {
+        let mut state = state();
+        state.default_roles = vec!["user".to_string(), "reader".to_string()];
+
+        let response = register(&state, "john_doe", "john@example.com").await;
+        assert_eq!(response.user.roles, ["user", "reader"]);
+
+        let stored = state.user_repo.find_by_id(response.user.id).await.unwrap().unwrap();
+        assert_eq!(stored.roles, ["user", "reader"]);
+        let claims = validate_token_type(&response.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.roles, ["user", "reader"]);
+    }
+
+    #[tokio::test]
+    async fn test_register_without_default_roles_has_none() {
+        let state = state();
+        let response = register(&state, "john_doe", "john@example.com").await;
+
+        let claims = validate_token_type(&response.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert!(claims.roles.is_empty());
+        assert!(response.user.roles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_register_conflicts_are_generic_by_default() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request("jane_doe", "john@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "jane@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn test_register_reveals_taken_email() {
+        let mut state = state();
+        state.reveal_conflicting_field = true;
+        register(&state, "john_doe", "john@example.com").await;
+
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("jane_doe", "John@Example.com"))).await;
+        assert!(matches!(result, Err(AuthError::EmailTaken)));
+    }
+
+    #[tokio::test]
+    async fn test_register_reveals_taken_username() {
+        let mut state = state();
+        state.reveal_conflicting_field = true;
+        register(&state, "john_doe", "john@example.com").await;
+
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "jane@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UsernameTaken)));
+    }
+
+    #[tokio::test]
+    async fn test_register_succeeds_when_enabled() {
+        let state = state();
+        assert!(state.registration_enabled);
+
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "john@example.com"))).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_register_is_forbidden_when_disabled() {
+        let mut state = state();
+        state.registration_enabled = false;
+
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(register_request("john_doe", "john@example.com"))).await;
+        let error = result.err().unwrap();
+        assert!(matches!(error, AuthError::RegistrationDisabled));
+        assert_eq!(axum::response::IntoResponse::into_response(error).status(), StatusCode::FORBIDDEN);
+        assert!(state.user_repo.find_by_username("john_doe").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invite_only_registration_requires_a_valid_code() {
+        use crate::db::invite_store::InMemoryInviteStore;
+
+        let mut state = state();
+        state.invite_only = true;
+        state.invites = Arc::new(InMemoryInviteStore::with_codes(["welcome-42".to_string()]));
+
+        let with_code = |username: &str, email: &str, code: Option<&str>| RegisterRequest {
+            invite_code: code.map(str::to_string),
+            ..register_request(username, email)
+        };
+
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(with_code("john_doe", "john@example.com", None))).await;
+        assert!(matches!(result, Err(AuthError::InvalidInviteCode)));
+
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(with_code("john_doe", "john@example.com", Some("wrong")))).await;
+        assert!(matches!(result, Err(AuthError::InvalidInviteCode)));
+
+        // A rejected registration doesn't use up the code
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(with_code("ab", "john@example.com", Some("welcome-42")))).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+
+        let result = register_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(with_code("john_doe", "john@example.com", Some("welcome-42")))).await;
+        assert!(result.is_ok());
+
+        // Each code registers a single account
+        let result = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(with_code("jane_doe", "jane@example.com", Some("welcome-42")))).await;
+        assert!(matches!(result, Err(AuthError::InvalidInviteCode)));
+    }
+
+    async fn registered_user_id(state: &AppState) -> UserId {
+        register(state, "john_doe", "john@example.com").await;
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        UserId(user.id)
+    }
+
+    fn reset_request(token: &str) -> ResetPasswordRequest {
+        ResetPasswordRequest { token: token.to_string(), new_password: "NewPassword456!".to_string().into() }
+    }
+
+    #[tokio::test]
+    async fn test_forgot_password_always_succeeds() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        for email in ["john@example.com", "nobody@example.com"] {
+            let result = forgot_password_handler(State(state.clone()), Json(ForgotPasswordRequest {
+                email: email.to_string(),
+            })).await;
+            assert!(result.is_ok());
+        }
+    }
+
+    // State keeping the sent emails, and the token of the last one with `subject`
+    fn state_with_emails() -> (AppState, InMemoryEmailSender) {
+        let mut state = state();
+        let emails = InMemoryEmailSender::new();
+        state.email_sender = Arc::new(emails.clone());
+        (state, emails)
+    }
+
+    fn emailed_token(emails: &InMemoryEmailSender, subject: &str) -> String {
+        let email = emails.sent().into_iter().rev().find(|email| email.subject == subject).unwrap();
+        // The token is the line that looks like a JWT
+        email.body.lines().find(|line| line.split('.').count() == 3).unwrap().to_string()
+    }
+
+    struct FailingEmailSender;
+
+    #[async_trait::async_trait]
+    impl EmailSender for FailingEmailSender {
+        async fn send(&self, _: &str, _: &str, _: &str) -> Result<(), AuthError> {
+            Err(AuthError::EmailDeliveryFailed)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forgot_password_emails_the_reset_token() {
+        let (state, emails) = state_with_emails();
+        register(&state, "john_doe", "john@example.com").await;
+
+        forgot_password_handler(State(state.clone()), Json(ForgotPasswordRequest { email: "john@example.com".to_string() })).await.unwrap();
+
+        let email = emails.sent().pop().unwrap();
+        assert_eq!(email.to, "john@example.com");
+        assert!(email.body.contains("valid for 15 minutes"));
+        let token = emailed_token(&emails, "Reset your password");
+        assert!(reset_password_handler(State(state), ClientInfo::default(), Json(reset_request(&token))).await.is_ok());
+
+        // Nothing is sent for an unknown email
+        let (state, emails) = state_with_emails();
+        forgot_password_handler(State(state), Json(ForgotPasswordRequest { email: "nobody@example.com".to_string() })).await.unwrap();
+        assert!(emails.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_register_emails_the_verification_token() {
+        let (state, emails) = state_with_emails();
+        register(&state, "john_doe", "john@example.com").await;
+
+        assert_eq!(emails.sent()[0].to, "john@example.com");
+        let token = emailed_token(&emails, "Verify your email address");
+        verify_email_handler(State(state.clone()), Json(VerifyEmailRequest { token })).await.unwrap();
+        assert!(state.user_repo.find_by_username("john_doe").await.unwrap().unwrap().email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_only_fails_the_email_change() {
+        let mut state = state();
+        state.email_sender = Arc::new(FailingEmailSender);
+        let user_id = registered_user_id(&state).await;
+
+        let result = forgot_password_handler(State(state.clone()), Json(ForgotPasswordRequest { email: "john@example.com".to_string() })).await;
+        assert!(result.is_ok());
+
+        let result = change_email_handler(State(state), auth_user(user_id), Json(change_email_request("new@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::EmailDeliveryFailed)));
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_happy_path() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token = create_reset_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+
+        assert!(reset_password_handler(State(state.clone()), ClientInfo::default(), Json(reset_request(&token))).await.is_ok());
+
+        // The new password works, the old one doesn't
+        let new_login = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: "NewPassword456!".to_string().into(),
+        })).await;
+        assert!(new_login.is_ok());
+        let old_login = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await;
+        assert!(matches!(old_login, Err(AuthError::InvalidCredentials)));
+
+        // The token is single-use
+        let reused = reset_password_handler(State(state), ClientInfo::default(), Json(reset_request(&token))).await;
+        assert!(matches!(reused, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_resets_with_one_token_only_one_succeeds() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token_version = state.user_repo.find_by_id(user_id.as_uuid()).await.unwrap().unwrap().token_version;
+        let token = create_reset_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+
+        let first = tokio::spawn(reset_password_handler(State(state.clone()), ClientInfo::default(), Json(reset_request(&token))));
+        let second = tokio::spawn(reset_password_handler(State(state.clone()), ClientInfo::default(), Json(reset_request(&token))));
+
+        let results = [first.await.unwrap(), second.await.unwrap()];
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().any(|r| matches!(r, Err(AuthError::InvalidToken))));
+        // The password was only replaced once
+        let user = state.user_repo.find_by_id(user_id.as_uuid()).await.unwrap().unwrap();
+        assert_eq!(user.token_version, token_version + 1);
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_expired_token() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token = create_reset_token(user_id, &state.jwt_keys, &expired_token_config(&state)).unwrap();
+
+        let result = reset_password_handler(State(state), ClientInfo::default(), Json(reset_request(&token))).await;
+        assert!(matches!(result, Err(AuthError::TokenExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_rejects_access_token() {
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+        let (_, tokens) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+
+        let result = reset_password_handler(State(state), ClientInfo::default(), Json(reset_request(&tokens.token))).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_rejects_weak_password() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token = create_reset_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+
+        let result = reset_password_handler(State(state), ClientInfo::default(), Json(ResetPasswordRequest {
+            token,
+            new_password: "weak".to_string().into(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+    }
+
+    fn change_request(current: &str, new: &str) -> ChangePasswordRequest {
+        ChangePasswordRequest { current_password: current.to_string().into(), new_password: new.to_string().into() }
+    }
+
+    #[tokio::test]
+    async fn test_change_password_success() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let result = change_password_handler(
+            State(state.clone()),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("Password123!", "NewPassword456!")),
+        ).await;
+        assert!(result.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(crypto::verify_password(&user.password_hash, "NewPassword456!").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_change_password_invalidates_previous_tokens() {
+        let state = state();
+        let registered = register(&state, "john_doe", "john@example.com").await;
+        let user_id = registered.user.id;
+        assert!(introspect(&state, &registered.token).await.active);
+
+        let changed = change_password_handler(
+            State(state.clone()),
+            auth_user(UserId(user_id)),
+            ClientInfo::default(),
+            Json(change_request("Password123!", "NewPassword456!")),
+        ).await;
+        assert!(changed.is_ok());
+        assert_eq!(state.user_repo.find_by_id(user_id).await.unwrap().unwrap().token_version, 1);
+
+        // The access and refresh tokens issued before are dead
+        assert!(!introspect(&state, &registered.token).await.active);
+        let refreshed = rotate(&state, registered.refresh_token.as_deref().unwrap()).await;
+        assert!(matches!(refreshed, Err(AuthError::InvalidToken)));
+
+        // The ones of a new login work
+        let login = LoginRequest { username: "john_doe".to_string(), password: "NewPassword456!".to_string().into() };
+        let (_, Json(tokens)) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login)).await.unwrap();
+        assert!(introspect(&state, &tokens.token).await.active);
+        assert!(rotate(&state, tokens.refresh_token.as_deref().unwrap()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_change_password_wrong_current_password() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let result = change_password_handler(
+            State(state),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("WrongPassword1!", "NewPassword456!")),
+        ).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+    }
+
+    #[tokio::test]
+    async fn test_change_password_weak_new_password() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let result = change_password_handler(
+            State(state),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("Password123!", "weak")),
+        ).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_change_password_same_as_current() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let result = change_password_handler(
+            State(state),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("Password123!", "Password123!")),
+        ).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_change_password_rejects_previous_password() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let changed = change_password_handler(
+            State(state.clone()),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("Password123!", "NewPassword456!")),
+        ).await;
+        assert!(changed.is_ok());
+
+        let result = change_password_handler(
+            State(state),
+            auth_user(user_id),
+            ClientInfo::default(),
+            Json(change_request("NewPassword456!", "Password123!")),
+        ).await;
+        match result {
+            Err(AuthError::ValidationError(reasons)) => assert_eq!(reasons, vec![ValidationReason::PasswordReused { history: 5 }]),
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_password_accepts_fresh_password_after_history() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        for (current, new) in [("Password123!", "NewPassword456!"), ("NewPassword456!", "Another789!")] {
+            let result = change_password_handler(
+                State(state.clone()),
+                auth_user(user_id),
+                ClientInfo::default(),
+                Json(change_request(current, new)),
+            ).await;
+            assert!(result.is_ok());
+        }
+
+        assert_eq!(state.user_repo.password_history(user_id.as_uuid()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_rejects_deactivated_user() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token = create_reset_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+        state.user_repo.set_active(user_id.as_uuid(), false).await.unwrap();
+
+        let result = reset_password_handler(State(state), ClientInfo::default(), Json(reset_request(&token))).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_rejects_current_password() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let token = create_reset_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+
+        let result = reset_password_handler(State(state), ClientInfo::default(), Json(ResetPasswordRequest {
+            token,
+            new_password: "Password123!".to_string().into(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_marks_user_as_verified() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(!user.email_verified);
+
+        let token = create_verification_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+        let result = verify_email_handler(State(state.clone()), Json(VerifyEmailRequest { token })).await;
+        assert!(result.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(user.email_verified);
+    }
+
+    fn change_email_request(new_email: &str) -> ChangeEmailRequest {
+        ChangeEmailRequest { new_email: new_email.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_change_email_is_pending_until_verified() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let result = change_email_handler(State(state.clone()), auth_user(user_id), Json(change_email_request(" New@Example.com "))).await;
+        assert!(result.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(user.email, "john@example.com");
+        assert_eq!(user.pending_email.as_deref(), Some("new@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_change_email_is_committed_by_verification() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        assert!(change_email_handler(State(state.clone()), auth_user(user_id), Json(change_email_request("new@example.com"))).await.is_ok());
+
+        // A registration token doesn't confirm the change
+        let token = create_verification_token(user_id, &state.jwt_keys, &state.token_config).unwrap();
+        assert!(verify_email_handler(State(state.clone()), Json(VerifyEmailRequest { token })).await.is_ok());
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(user.email, "john@example.com");
+
+        // Nor does a token for another address
+        let token = create_email_change_token(user_id, "other@example.com", &state.jwt_keys, &state.token_config).unwrap();
+        let result = verify_email_handler(State(state.clone()), Json(VerifyEmailRequest { token })).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+
+        let token = create_email_change_token(user_id, "new@example.com", &state.jwt_keys, &state.token_config).unwrap();
+        assert!(verify_email_handler(State(state.clone()), Json(VerifyEmailRequest { token })).await.is_ok());
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert_eq!(user.email, "new@example.com");
+        assert!(user.pending_email.is_none());
+        assert!(user.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_change_email_rejects_used_email() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+        register(&state, "jane_doe", "jane@example.com").await;
+
+        let result = change_email_handler(State(state.clone()), auth_user(user_id), Json(change_email_request("jane@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::UserAlreadyExists)));
+
+        let result = change_email_handler(State(state.clone()), auth_user(user_id), Json(change_email_request("john@example.com"))).await;
+        assert!(matches!(result, Err(AuthError::ValidationError(_))));
+
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        assert!(user.pending_email.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_rejects_other_token_types() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+
+        let result = verify_email_handler(State(state), Json(VerifyEmailRequest { token: tokens.token })).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_expired_token() {
+        let state = state();
+        let user_id = registered_user_id(&state).await;
+
+        let token = create_verification_token(user_id, &state.jwt_keys, &expired_token_config(&state)).unwrap();
+        let result = verify_email_handler(State(state), Json(VerifyEmailRequest { token })).await;
+        assert!(matches!(result, Err(AuthError::TokenExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_login_requires_verified_email_when_enabled() {
+        let mut state = state();
+        state.require_verified_email = true;
+        let user_id = registered_user_id(&state).await;
+
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await;
+        assert!(matches!(result, Err(AuthError::EmailNotVerified)));
+
+        state.user_repo.mark_email_verified(user_id.as_uuid()).await.unwrap();
+        assert!(login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+    }
+
+    // Collects what the tracing subscriber writes
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_login_is_logged_without_password() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        // The current-thread runtime polls the handler on this thread
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let result = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(LoginRequest {
+            username: "john_doe".to_string(),
+            password: "WrongSecret99!".to_string().into(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("login failed"));
+        assert!(output.contains("john_doe"));
+        assert!(!output.contains("WrongSecret99!"));
+        assert!(!output.contains("Password123!"));
+    }
+
+    #[tokio::test]
+    async fn test_login_sets_cookie_when_enabled() {
+        let mut state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let (headers, _) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+        assert!(headers.get(header::SET_COOKIE).is_none());
+
+        state.auth_cookie = Some(crate::auth::cookie::CookieConfig::default());
+        let (headers, Json(tokens)) = login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+
+        let cookie = headers.get(header::SET_COOKIE).unwrap().to_str().unwrap();
+        assert!(cookie.starts_with(&format!("auth_token={};", tokens.token)));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("Secure"));
+    }
+
+    #[tokio::test]
+    async fn test_login_and_register_echo_token_header_when_enabled() {
+        let mut state = state();
+        register(&state, "john_doe", "john@example.com").await;
+
+        let (headers, _) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+        assert!(headers.get(header::AUTHORIZATION).is_none());
+
+        state.token_response_header = Some(header::AUTHORIZATION);
+        let (headers, Json(tokens)) = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.unwrap();
+        assert_eq!(headers[header::AUTHORIZATION], format!("Bearer {}", tokens.token));
+
+        let (_, headers, Json(registered)) = register_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(register_request("jane_doe", "jane@example.com"))).await.unwrap();
+        assert_eq!(headers[header::AUTHORIZATION], format!("Bearer {}", registered.token));
+    }
+
+    #[tokio::test]
+    async fn test_logout_clears_cookie() {
+        let mut state = state();
+        state.auth_cookie = Some(crate::auth::cookie::CookieConfig::default());
+        let user_id = registered_user_id(&state).await;
+
+        let (headers, _) = logout_handler(State(state), auth_user(user_id), ClientInfo::default()).await.unwrap();
+        let cookie = headers.get(header::SET_COOKIE).unwrap().to_str().unwrap();
+        assert!(cookie.starts_with("auth_token=;"));
+        assert!(cookie.contains("Max-Age=0"));
+        let csrf_cookie = headers.get_all(header::SET_COOKIE).iter().nth(1).unwrap().to_str().unwrap();
+        assert!(csrf_cookie.starts_with("csrf_token=;"));
+    }
+
+    #[tokio::test]
+    async fn test_deactivated_user_cannot_login_until_reactivated() {
+        let state = state();
+        let user_id = registered_user_id(&state).await.as_uuid();
+
+        state.user_repo.set_active(user_id, false).await.unwrap();
+        let result = login_handler(State(state.clone()), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+
+        state.user_repo.set_active(user_id, true).await.unwrap();
+        assert!(login_handler(State(state), ClientInfo::default(), RequestTenant::default(), Json(login_request("john_doe"))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deactivated_user_cannot_refresh() {
+        let state = state();
+        let tokens = register(&state, "john_doe", "john@example.com").await;
+        let user = state.user_repo.find_by_username("john_doe").await.unwrap().unwrap();
+        state.user_repo.set_active(user.id, false).await.unwrap();
+
+        let result = refresh_handler(State(state), ClientInfo::default(), Json(RefreshRequest {
+            refresh_token: tokens.refresh_token.unwrap(),
+        })).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+    }
+}
@@ -1 +1,8 @@
-pub mod auth_handler;
\ No newline at end of file
+pub mod auth_handler;
+pub mod admin_handler;
+pub mod api_key_handler;
+pub mod session_handler;
+#[cfg(feature = "oauth")]
+pub mod oauth_handler;
+#[cfg(feature = "webauthn")]
+pub mod webauthn_handler;
@@ -0,0 +1,425 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{extract::{Query, State}, http::{HeaderMap, header}, response::Redirect};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+use crate::{
+    extract::Json,
+    models::auth::{LoginResponse, OAuthCallbackQuery},
+    models::user::{CreateUser, User},
+    models::validation::{normalize_email, validate_username_with},
+    auth::{crypto, extractor::ClientInfo, oauth::{ExternalIdentity, OAuthProvider}},
+    audit::{AuditAction, AuditEvent},
+    handlers::auth_handler::{grant_default_roles, issue_tokens, token_headers},
+    errors::{AuthError, ErrorResponse},
+    AppState,
+};
+
+/// Handler starting the login with Google
+///
+/// Endpoint: GET /auth/google
+///
+/// Redirects (303) to Google's consent page, with a new CSRF state and PKCE challenge.
+/// The state and the PKCE verifier are kept in the `oauth_state` cookie until the callback.
+#[utoipa::path(
+    get,
+    path = "/auth/google",
+    tag = "auth",
+    responses(
+        (status = 303, description = "Redirect to Google's consent page, the state is kept in the oauth_state cookie"),
+    ),
+)]
+pub async fn google_login_handler(State(state): State<AppState>) -> Result<(HeaderMap, Redirect), AuthError> {
+    let provider = google(&state)?;
+    let request = provider.authorize()?;
+
+    // Both are base64url, so the dot can't appear in either
+    let cookie = format!("{}.{}", request.state, request.pkce_verifier);
+    let headers = provider.state_cookie().headers(&cookie, provider.state_expiry());
+
+    Ok((headers, Redirect::to(&request.url)))
+}
+
+
+/// Handler of the redirect back from Google
+///
+/// Endpoint: GET /auth/google/callback?code=...&state=...
+///
+/// Flow:
+/// 1. Checks the `state` against the `oauth_state` cookie (401 `InvalidToken` otherwise)
+/// 2. Exchanges the code for the user's identity at Google (502 `OAuthProviderError` when refused)
+/// 3. Finds the user linked to the Google account, or links the user with the same (verified) email,
+///    or creates a new user (email verified, random password: use forgot-password to set one)
+/// 4. Rejects deactivated accounts
+/// 5. Returns our tokens, like `/login` (also in a cookie when `auth_cookie` is set)
+///
+/// New users are created without tenant, and only when registration is open (not invite-only).
+#[utoipa::path(
+    get,
+    path = "/auth/google/callback",
+    tag = "auth",
+    params(OAuthCallbackQuery),
+    responses(
+        (status = 200, description = "Logged in", body = LoginResponse),
+        (status = 401, description = "Missing or mismatched state, or consent refused", body = ErrorResponse),
+        (status = 403, description = "Email not verified, account disabled or registration closed", body = ErrorResponse),
+        (status = 409, description = "Email linked to another Google account", body = ErrorResponse),
+        (status = 502, description = "Google refused the code or couldn't be reached", body = ErrorResponse),
+    ),
+)]
+pub async fn google_callback_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> Result<(HeaderMap, Json<LoginResponse>), AuthError> {
+
+    let provider = google(&state)?;
+
+    // The user refused the consent, or the provider failed before redirecting
+    if let Some(error) = &query.error {
+        warn!(error = %error, "oauth login refused by the provider");
+        return Err(AuthError::InvalidCredentials);
+    }
+
+    let cookie = provider.state_cookie();
+    let (expected_state, pkce_verifier) = cookie
+        .token_from_headers(&headers)
+        .and_then(|value| value.split_once('.'))
+        .ok_or(AuthError::InvalidToken)?;
+    let (Some(code), Some(returned_state)) = (&query.code, &query.state) else {
+        return Err(AuthError::InvalidToken);
+    };
+    if returned_state != expected_state {
+        warn!("oauth login rejected: state mismatch");
+        return Err(AuthError::InvalidToken);
+    }
+
+    let identity = provider.exchange(code, pkce_verifier).await?;
+    let user = oauth_user(&state, &identity).await?;
+
+    if !user.is_active {
+        warn!(user_id = %user.id, "oauth login failed: account disabled");
+        let error = AuthError::AccountDisabled;
+        state.audit.record(AuditEvent::failure(AuditAction::Login, Some(user.id), &client, &error)).await;
+        return Err(error);
+    }
+
+    state.user_repo.touch_last_login(user.id).await?;
+
+    info!(user_id = %user.id, provider = %identity.provider, "oauth login succeeded");
+    state.audit.record(AuditEvent::success(AuditAction::Login, Some(user.id), &client)).await;
+
+    let tokens = issue_tokens(&state, &user, client).await?;
+    let mut headers = token_headers(&state, &tokens);
+    // The state was used, the cookie is dropped
+    if let Ok(value) = cookie.clear_cookie().parse() {
+        headers.append(header::SET_COOKIE, value);
+    }
+    Ok((headers, Json(tokens)))
+}
+
+
+// Provider of the Google routes (they are only mounted when it is set)
+fn google(state: &AppState) -> Result<&OAuthProvider, AuthError> {
+    state.google_oauth.as_ref().ok_or(AuthError::InternalError)
+}
+
+
+// Local user of the external identity: the linked one, else the one with the same verified
+// email (linked now), else a new one
+async fn oauth_user(state: &AppState, identity: &ExternalIdentity) -> Result<User, AuthError> {
+    if let Some(user) = state.user_repo.find_by_oauth(&identity.provider, &identity.subject).await? {
+        return Ok(user);
+    }
+
+    // Linking or creating by email is only safe when the provider checked the address
+    if !identity.email_verified {
+        warn!(provider = %identity.provider, "oauth login rejected: email not verified by the provider");
+        return Err(AuthError::EmailNotVerified);
+    }
+
+    let email = normalize_email(&identity.email);
+    let user = match state.user_repo.find_by_email(&email).await? {
+        // Already linked to another account of a provider
+        Some(user) if user.oauth_subject.is_some() => {
+            warn!(user_id = %user.id, "oauth login rejected: email linked to another external account");
+            return Err(AuthError::UserAlreadyExists);
+        }
+        // Whoever registered it never proved they own the address: linking would let them keep
+        // logging in with the password they set, next to the real owner (account pre-hijacking)
+        Some(user) if !user.email_verified => {
+            warn!(user_id = %user.id, "oauth login rejected: local account with an unverified email");
+            return Err(AuthError::UserAlreadyExists);
+        }
+        Some(user) => user,
+        None => create_oauth_user(state, &email).await?,
+    };
+
+    state.user_repo.link_oauth(user.id, &identity.provider, &identity.subject).await?;
+    state.user_repo.mark_email_verified(user.id).await?;
+    info!(user_id = %user.id, provider = %identity.provider, "external account linked");
+
+    state.user_repo.find_by_id(user.id).await?.ok_or(AuthError::UserNotFound)
+}
+
+
+// New user for an email verified by the provider, with a username derived from it
+// and a random password nobody knows
+async fn create_oauth_user(state: &AppState, email: &str) -> Result<User, AuthError> {
+    if !state.registration_enabled || state.invite_only {
+        warn!("oauth registration rejected: registration closed");
+        return Err(AuthError::RegistrationDisabled);
+    }
+
+    let username = available_username(state, email).await?;
+    let password = Zeroizing::new(random_hex(32));
+    let password_hash = crypto::hash_password_with(&state.argon2_config, &password)
+        .map_err(|_| AuthError::InternalError)?;
+
+    let user = state.user_repo.create(
+        CreateUser {
+            username,
+            email: email.to_string(),
+            password,
+            tenant_id: None,
+        },
+        password_hash,
+    ).await?;
+    let user = grant_default_roles(state, user).await?;
+
+    info!(user_id = %user.id, username = %user.username, "user registered through oauth");
+    Ok(user)
+}
+
+
+// Local part of the email made into a valid username (`john.doe@...` -> `john_doe`),
+// with a random suffix when it is taken
+async fn available_username(state: &AppState, email: &str) -> Result<String, AuthError> {
+    let local_part = email.split('@').next().unwrap_or_default();
+    let base: String = local_part
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .take(40)
+        .collect();
+    let base = base.trim_matches(['_', '-']);
+    let base = match validate_username_with(state.username_policy, base) {
+        Ok(()) => base.to_string(),
+        Err(_) => "user".to_string(),
+    };
+
+    if base != "user" && state.user_repo.find_by_username(&base).await?.is_none() {
+        return Ok(base);
+    }
+    for _ in 0..5 {
+        let candidate = format!("{}_{}", base, random_hex(3));
+        if state.user_repo.find_by_username(&candidate).await?.is_none() {
+            return Ok(candidate);
+        }
+    }
+    Err(AuthError::UserAlreadyExists)
+}
+
+
+fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    hex::encode(buffer)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::{Form, Router, http::HeaderValue, response::IntoResponse, routing::{get, post}};
+    use serde_json::json;
+    use crate::auth::{crypto::Argon2Config, jwt::{validate_token_type, TokenType}};
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::models::user::UserId;
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+
+    // Token and userinfo endpoints of a fake Google, answering for the code "good-code"
+    // with the identity `sub` / `email` / `email_verified`
+    async fn mock_provider(sub: &'static str, email: &'static str, email_verified: bool) -> OAuthProvider {
+        let token = post(|Form(form): Form<std::collections::HashMap<String, String>>| async move {
+            match (form.get("code").map(String::as_str), form.get("code_verifier")) {
+                (Some("good-code"), Some(_)) => Json(json!({ "access_token": "access-123", "token_type": "Bearer", "expires_in": 3600 })).into_response(),
+                _ => (axum::http::StatusCode::BAD_REQUEST, Json(json!({ "error": "invalid_grant" }))).into_response(),
+            }
+        });
+        let userinfo = get(move |headers: HeaderMap| async move {
+            match headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) {
+                Some("Bearer access-123") => Json(json!({ "sub": sub, "email": email, "email_verified": email_verified })).into_response(),
+                _ => axum::http::StatusCode::UNAUTHORIZED.into_response(),
+            }
+        });
+        let app = Router::new().route("/token", token).route("/userinfo", userinfo);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        OAuthProvider {
+            token_url: format!("{base}/token"),
+            userinfo_url: format!("{base}/userinfo"),
+            ..OAuthProvider::google("client-id", "client-secret", "http://localhost:3000/auth/google/callback")
+        }
+    }
+
+    async fn state(provider: OAuthProvider) -> AppState {
+        let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        // Low cost keeps the tests fast
+        state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+        state.google_oauth = Some(provider);
+        state
+    }
+
+    // Goes through GET /auth/google, then calls back with `code` and the state that was issued
+    async fn login(state: &AppState, code: &str) -> Result<LoginResponse, AuthError> {
+        let (headers, _) = google_login_handler(State(state.clone())).await.unwrap();
+        let cookie = headers[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+        let returned_state = cookie.split_once('=').unwrap().1.split_once('.').unwrap().0.to_string();
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        let query = OAuthCallbackQuery { code: Some(code.to_string()), state: Some(returned_state), error: None };
+
+        let (_, Json(tokens)) = google_callback_handler(State(state.clone()), ClientInfo::default(), request_headers, Query(query)).await?;
+        Ok(tokens)
+    }
+
+    #[tokio::test]
+    async fn test_login_sets_state_cookie_and_redirects_to_google() {
+        let state = state(OAuthProvider::google("client-id", "client-secret", "https://auth.example.com/auth/google/callback")).await;
+
+        let (headers, redirect) = google_login_handler(State(state)).await.unwrap();
+        let cookie = headers[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("oauth_state="));
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Lax") && cookie.contains("Secure"));
+
+        let location = redirect.into_response().headers()[header::LOCATION].to_str().unwrap().to_string();
+        assert!(location.starts_with("https://accounts.google.com/o/oauth2/v2/auth?"));
+    }
+
+    #[tokio::test]
+    async fn test_callback_creates_user_and_returns_jwt() {
+        let state = state(mock_provider("google-1", "John.Doe@Gmail.com", true).await).await;
+
+        let tokens = login(&state, "good-code").await.unwrap();
+
+        let user = state.user_repo.find_by_oauth("google", "google-1").await.unwrap().unwrap();
+        assert_eq!(user.email, "john.doe@gmail.com");
+        assert_eq!(user.username, "john_doe");
+        assert!(user.email_verified);
+        let claims = validate_token_type(&tokens.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.sub, UserId(user.id));
+        assert!(tokens.refresh_token.is_some());
+
+        // The next login finds the same user
+        login(&state, "good-code").await.unwrap();
+        assert_eq!(state.user_repo.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_callback_grants_default_roles_to_new_users() {
+        let mut state = state(mock_provider("google-1", "john.doe@gmail.com", true).await).await;
+        state.default_roles = vec!["user".to_string()];
+
+        let tokens = login(&state, "good-code").await.unwrap();
+
+        let user = state.user_repo.find_by_oauth("google", "google-1").await.unwrap().unwrap();
+        assert_eq!(user.roles, ["user"]);
+        let claims = validate_token_type(&tokens.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.roles, ["user"]);
+    }
+
+    #[tokio::test]
+    async fn test_callback_links_existing_user_by_email() {
+        let state = state(mock_provider("google-1", "john@example.com", true).await).await;
+        let password_hash = crypto::hash_password_with(&state.argon2_config, "Password123!").unwrap();
+        let existing = state.user_repo.create(
+            CreateUser { username: "john".into(), email: "john@example.com".into(), password: "Password123!".to_string().into(), tenant_id: None },
+            password_hash,
+        ).await.unwrap();
+        state.user_repo.mark_email_verified(existing.id).await.unwrap();
+
+        let tokens = login(&state, "good-code").await.unwrap();
+
+        let claims = validate_token_type(&tokens.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.sub, UserId(existing.id));
+        let linked = state.user_repo.find_by_id(existing.id).await.unwrap().unwrap();
+        assert_eq!(linked.oauth_provider.as_deref(), Some("google"));
+        assert_eq!(linked.oauth_subject.as_deref(), Some("google-1"));
+    }
+
+    #[tokio::test]
+    async fn test_unverified_local_account_is_not_linked() {
+        let state = state(mock_provider("google-1", "john@example.com", true).await).await;
+        let password_hash = crypto::hash_password_with(&state.argon2_config, "Password123!").unwrap();
+        let existing = state.user_repo.create(
+            CreateUser { username: "john".into(), email: "john@example.com".into(), password: "Password123!".to_string().into(), tenant_id: None },
+            password_hash,
+        ).await.unwrap();
+
+        assert!(matches!(login(&state, "good-code").await, Err(AuthError::UserAlreadyExists)));
+        let untouched = state.user_repo.find_by_id(existing.id).await.unwrap().unwrap();
+        assert!(untouched.oauth_subject.is_none());
+        assert!(!untouched.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_unverified_email_is_not_linked() {
+        let state = state(mock_provider("google-1", "john@example.com", false).await).await;
+
+        assert!(matches!(login(&state, "good-code").await, Err(AuthError::EmailNotVerified)));
+        assert_eq!(state.user_repo.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_refused_code_is_a_provider_error() {
+        let state = state(mock_provider("google-1", "john@example.com", true).await).await;
+
+        assert!(matches!(login(&state, "bad-code").await, Err(AuthError::OAuthProviderError)));
+    }
+
+    #[tokio::test]
+    async fn test_state_mismatch_is_rejected() {
+        let state = state(mock_provider("google-1", "john@example.com", true).await).await;
+        let (headers, _) = google_login_handler(State(state.clone())).await.unwrap();
+        let cookie = headers[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        let query = OAuthCallbackQuery { code: Some("good-code".into()), state: Some("forged".into()), error: None };
+
+        let result = google_callback_handler(State(state.clone()), ClientInfo::default(), request_headers, Query(query)).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+        assert_eq!(state.user_repo.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_closed_registration_creates_no_user() {
+        let mut state = state(mock_provider("google-1", "john@example.com", true).await).await;
+        state.registration_enabled = false;
+
+        assert!(matches!(login(&state, "good-code").await, Err(AuthError::RegistrationDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_taken_username_gets_a_suffix() {
+        let state = state(mock_provider("google-1", "john@gmail.com", true).await).await;
+        let password_hash = crypto::hash_password_with(&state.argon2_config, "Password123!").unwrap();
+        state.user_repo.create(
+            CreateUser { username: "john".into(), email: "john@example.com".into(), password: "Password123!".to_string().into(), tenant_id: None },
+            password_hash,
+        ).await.unwrap();
+
+        login(&state, "good-code").await.unwrap();
+
+        let user = state.user_repo.find_by_oauth("google", "google-1").await.unwrap().unwrap();
+        assert!(user.username.starts_with("john_"));
+        // A real hash, of a password nobody knows
+        assert!(user.password_hash.starts_with("$argon2"));
+    }
+}
@@ -0,0 +1,228 @@
+use axum::{extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use tracing::info;
+use crate::{
+    extract::Json,
+    models::session::SessionResponse,
+    auth::extractor::{AuthUser, ClientInfo},
+    audit::{AuditAction, AuditEvent},
+    handlers::auth_handler::clear_cookie_headers,
+    errors::{AuthError, ErrorResponse},
+    AppState,
+};
+
+/// Handler listing the sessions (logins) of the authenticated user
+///
+/// Endpoint: GET /sessions
+/// Headers: Authorization: Bearer <token>
+///
+/// Each session is a device or browser that logged in, `current` marks
+/// the one of the token used for this request
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The unexpired sessions, oldest first", body = Vec<SessionResponse>),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn list_sessions_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<SessionResponse>>, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    let sessions = state.sessions
+        .list(user_id, state.token_config.clock.now())
+        .await?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: session.jti == user.session_id(),
+            session,
+        })
+        .collect();
+
+    Ok(Json(sessions))
+}
+
+
+/// Handler revoking one session of the authenticated user (remote logout)
+///
+/// Endpoint: DELETE /sessions/{jti}
+/// Headers: Authorization: Bearer <token>
+///
+/// Every token of the session (access and refresh) is rejected right away.
+/// Returns 404 when the user has no session with this id
+#[utoipa::path(
+    delete,
+    path = "/sessions/{jti}",
+    tag = "auth",
+    params(("jti" = String, Path, description = "Id of the session")),
+    responses(
+        (status = 204, description = "Session revoked"),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn revoke_session_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    client: ClientInfo,
+    Path(jti): Path<String>,
+) -> Result<StatusCode, AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    if !state.sessions.remove(user_id, &jti).await? {
+        return Err(AuthError::SessionNotFound);
+    }
+    state.token_blacklist.revoke(&jti).await?;
+
+    info!(user_id = %user_id, session = %jti, "session revoked");
+    state.audit.record(AuditEvent::success(AuditAction::TokenRevocation, Some(user_id), &client)).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+/// Handler logging out every device of the authenticated user
+///
+/// Endpoint: POST /logout-all
+/// Headers: Authorization: Bearer <token>
+///
+/// Revokes all the sessions, including the current one, and every token issued
+/// to the user before (`User::token_version`), recorded as a session or not
+/// (and clears the auth cookie, when enabled)
+#[utoipa::path(
+    post,
+    path = "/logout-all",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Logged out of all devices"),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn logout_all_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    client: ClientInfo,
+) -> Result<(HeaderMap, StatusCode), AuthError> {
+
+    let user_id = user.user_id.as_uuid();
+
+    let sessions = state.sessions.remove_all(user_id).await?;
+    for session in &sessions {
+        state.token_blacklist.revoke(&session.jti).await?;
+    }
+    // The current token, even if its session wasn't recorded
+    state.token_blacklist.revoke(&user.jti).await?;
+    state.token_blacklist.revoke(user.session_id()).await?;
+    state.user_repo.increment_token_version(user_id).await?;
+
+    info!(user_id = %user_id, sessions = sessions.len(), "logged out of all sessions");
+    state.audit.record(AuditEvent::success(AuditAction::TokenRevocation, Some(user_id), &client)).await;
+
+    Ok((clear_cookie_headers(&state), StatusCode::NO_CONTENT))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use axum::extract::FromRequestParts;
+    use axum::http::Request;
+    use crate::auth::{crypto::Argon2Config, extractor::{ClientInfo, RequestTenant}};
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::handlers::auth_handler::{login_handler, register_handler};
+    use crate::models::auth::{LoginRequest, RegisterRequest};
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+
+    fn state() -> AppState {
+        let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        // Low cost keeps the tests fast
+        state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+        state
+    }
+
+    fn client(user_agent: &str) -> ClientInfo {
+        ClientInfo { user_agent: Some(user_agent.to_string()), ip: Some("203.0.113.7".to_string()) }
+    }
+
+    // Registers john_doe from a phone, then logs in from a laptop, returns both access tokens
+    async fn two_sessions(state: &AppState) -> (String, String) {
+        let register = RegisterRequest {
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password: "Password123!".to_string().into(),
+            invite_code: None,
+        };
+        let (_, _, Json(phone)) = register_handler(State(state.clone()), client("Phone"), RequestTenant::default(), Json(register)).await.unwrap();
+
+        let login = LoginRequest { username: "john_doe".to_string(), password: "Password123!".to_string().into() };
+        let (_, Json(laptop)) = login_handler(State(state.clone()), client("Laptop"), RequestTenant::default(), Json(login)).await.unwrap();
+
+        (phone.token, laptop.token)
+    }
+
+    async fn authenticate(state: &AppState, token: &str) -> Result<AuthUser, AuthError> {
+        let (mut parts, _) = Request::builder()
+            .header("Authorization", format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AuthUser::from_request_parts(&mut parts, state).await
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_listed_with_their_client() {
+        let state = state();
+        let (_, laptop) = two_sessions(&state).await;
+        let user = authenticate(&state, &laptop).await.unwrap();
+
+        let Json(sessions) = list_sessions_handler(State(state.clone()), user).await.unwrap();
+        let agents: Vec<_> = sessions.iter().map(|s| s.session.user_agent.as_deref().unwrap()).collect();
+        assert_eq!(agents, vec!["Phone", "Laptop"]);
+        assert_eq!(sessions[0].session.ip.as_deref(), Some("203.0.113.7"));
+        assert!(!sessions[0].current);
+        assert!(sessions[1].current);
+    }
+
+    #[tokio::test]
+    async fn test_revoked_session_token_is_rejected() {
+        let state = state();
+        let (phone, laptop) = two_sessions(&state).await;
+        let phone_session = authenticate(&state, &phone).await.unwrap().jti;
+
+        let user = authenticate(&state, &laptop).await.unwrap();
+        let status = revoke_session_handler(State(state.clone()), user, ClientInfo::default(), Path(phone_session.clone())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        assert!(matches!(authenticate(&state, &phone).await, Err(AuthError::InvalidToken)));
+        assert!(authenticate(&state, &laptop).await.is_ok());
+
+        // Already revoked
+        let user = authenticate(&state, &laptop).await.unwrap();
+        let result = revoke_session_handler(State(state.clone()), user, ClientInfo::default(), Path(phone_session)).await;
+        assert!(matches!(result, Err(AuthError::SessionNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_logout_all_revokes_every_session() {
+        let state = state();
+        let (phone, laptop) = two_sessions(&state).await;
+
+        let user = authenticate(&state, &laptop).await.unwrap();
+        let user_id = user.user_id.as_uuid();
+        logout_all_handler(State(state.clone()), user, ClientInfo::default()).await.unwrap();
+
+        assert!(authenticate(&state, &phone).await.is_err());
+        assert!(authenticate(&state, &laptop).await.is_err());
+        assert!(state.sessions.list(user_id, state.token_config.clock.now()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+use axum::{extract::State, http::{HeaderMap, StatusCode}};
+use tracing::{info, warn};
+use uuid::Uuid;
+use crate::{
+    extract::Json,
+    models::auth::LoginResponse,
+    models::passkey::{PasskeyLoginFinish, PasskeyLoginRequest, PasskeyLoginStart, PasskeyRegistrationFinish, PasskeyRegistrationStart},
+    auth::{extractor::{AuthUser, ClientInfo, CurrentUser, RequestTenant}, webauthn::PasskeyProvider},
+    audit::{AuditAction, AuditEvent},
+    db::passkey_store::{PasskeyCeremony, PendingCeremony},
+    handlers::auth_handler::{find_login_user, issue_tokens, token_headers},
+    errors::{AuthError, ErrorResponse},
+    AppState,
+};
+
+/// Handler starting the registration of a passkey for the authenticated user
+///
+/// Endpoint: POST /webauthn/register/start
+/// Headers: Authorization: Bearer <token>
+///
+/// Returns the options for `navigator.credentials.create()`, the passkeys the user
+/// already has are excluded. The challenge is kept on the server until `/finish`.
+#[utoipa::path(
+    post,
+    path = "/webauthn/register/start",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Challenge for the authenticator", body = PasskeyRegistrationStart),
+        (status = 401, description = "Invalid or missing token", body = ErrorResponse),
+        (status = 403, description = "Account disabled", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn register_start_handler(
+    State(state): State<AppState>,
+    CurrentUser { user, .. }: CurrentUser,
+) -> Result<Json<PasskeyRegistrationStart>, AuthError> {
+
+    let provider = provider(&state)?;
+    let existing = state.passkeys.list(user.id).await?;
+    let (options, registration) = provider.start_registration(user.id, &user.username, &existing)?;
+
+    let challenge_id = start_ceremony(&state, user.id, PasskeyCeremony::Registration(registration)).await?;
+    Ok(Json(PasskeyRegistrationStart { challenge_id, options }))
+}
+
+
+/// Handler finishing the registration of a passkey
+///
+/// Endpoint: POST /webauthn/register/finish
+/// Headers: Authorization: Bearer <token>
+/// Body: {"challenge_id": "...", "credential": <answer of navigator.credentials.create()>}
+///
+/// Stores the public key of the new passkey, the user can then log in with it.
+/// Returns 401 (`InvalidToken`) when the challenge is unknown, expired or another user's,
+/// and 400 (`PasskeyRejected`) when the attestation doesn't verify.
+#[utoipa::path(
+    post,
+    path = "/webauthn/register/finish",
+    tag = "auth",
+    request_body = PasskeyRegistrationFinish,
+    responses(
+        (status = 201, description = "Passkey registered"),
+        (status = 400, description = "The attestation didn't verify", body = ErrorResponse),
+        (status = 401, description = "Invalid or missing token, or unknown or expired challenge", body = ErrorResponse),
+    ),
+    security(("bearerAuth" = [])),
+)]
+pub async fn register_finish_handler(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(payload): Json<PasskeyRegistrationFinish>,
+) -> Result<StatusCode, AuthError> {
+
+    let provider = provider(&state)?;
+    let user_id = user.user_id.as_uuid();
+
+    let registration = match finish_ceremony(&state, payload.challenge_id).await? {
+        PendingCeremony { user_id: owner, ceremony: PasskeyCeremony::Registration(registration), .. } if owner == user_id => registration,
+        _ => {
+            warn!(user_id = %user_id, "passkey registration rejected: challenge of another ceremony");
+            return Err(AuthError::InvalidToken);
+        }
+    };
+
+    let passkey = provider.finish_registration(&payload.credential, &registration)?;
+    state.passkeys.add(user_id, passkey).await?;
+
+    info!(user_id = %user_id, "passkey registered");
+    Ok(StatusCode::CREATED)
+}
+
+
+/// Handler starting a login with a passkey
+///
+/// Endpoint: POST /webauthn/login/start
+/// Body: {"username": "alice"}
+///
+/// Returns the options for `navigator.credentials.get()`, allowing the user's passkeys.
+/// Returns 401 (`InvalidCredentials`) for an unknown user or a user without passkeys.
+#[utoipa::path(
+    post,
+    path = "/webauthn/login/start",
+    tag = "auth",
+    request_body = PasskeyLoginRequest,
+    responses(
+        (status = 200, description = "Challenge for the authenticator", body = PasskeyLoginStart),
+        (status = 401, description = "Unknown user, or no passkey registered", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn login_start_handler(
+    State(state): State<AppState>,
+    RequestTenant(tenant_id): RequestTenant,
+    Json(payload): Json<PasskeyLoginRequest>,
+) -> Result<Json<PasskeyLoginStart>, AuthError> {
+
+    let provider = provider(&state)?;
+
+    let Some(user) = find_login_user(&state, tenant_id.as_deref(), &payload.username).await? else {
+        warn!(username = %payload.username, "passkey login failed: unknown user");
+        return Err(AuthError::InvalidCredentials);
+    };
+    let passkeys = state.passkeys.list(user.id).await?;
+    if passkeys.is_empty() {
+        warn!(user_id = %user.id, "passkey login failed: no passkey registered");
+        return Err(AuthError::InvalidCredentials);
+    }
+
+    let (options, authentication) = provider.start_authentication(&passkeys)?;
+
+    let challenge_id = start_ceremony(&state, user.id, PasskeyCeremony::Authentication(authentication)).await?;
+    Ok(Json(PasskeyLoginStart { challenge_id, options }))
+}
+
+
+/// Handler finishing a login with a passkey
+///
+/// Endpoint: POST /webauthn/login/finish
+/// Body: {"challenge_id": "...", "credential": <answer of navigator.credentials.get()>}
+///
+/// Flow:
+/// 1. Takes the challenge of `/start` (401 `InvalidToken` when unknown or expired)
+/// 2. Verifies the signature with the stored public key (401 `InvalidCredentials` otherwise)
+/// 3. Rejects deactivated accounts, and unverified emails when `require_verified_email` is on
+/// 4. Returns the same tokens as `/login` (also in a cookie when `auth_cookie` is set)
+#[utoipa::path(
+    post,
+    path = "/webauthn/login/finish",
+    tag = "auth",
+    request_body = PasskeyLoginFinish,
+    responses(
+        (status = 200, description = "Logged in", body = LoginResponse),
+        (status = 401, description = "Unknown or expired challenge, or the signature didn't verify", body = ErrorResponse),
+        (status = 403, description = "Email not verified or account disabled", body = ErrorResponse),
+        (status = 429, description = "Too many requests", body = ErrorResponse),
+    ),
+)]
+pub async fn login_finish_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<PasskeyLoginFinish>,
+) -> Result<(HeaderMap, Json<LoginResponse>), AuthError> {
+
+    let provider = provider(&state)?;
+
+    let (user_id, authentication) = match finish_ceremony(&state, payload.challenge_id).await? {
+        PendingCeremony { user_id, ceremony: PasskeyCeremony::Authentication(authentication), .. } => (user_id, authentication),
+        _ => {
+            warn!("passkey login rejected: challenge of another ceremony");
+            return Err(AuthError::InvalidToken);
+        }
+    };
+
+    let result = match provider.finish_authentication(&payload.credential, &authentication) {
+        Ok(result) => result,
+        Err(error) => {
+            state.audit.record(AuditEvent::failure(AuditAction::Login, Some(user_id), &client, &error)).await;
+            return Err(error);
+        }
+    };
+
+    // Deleted between start and finish
+    let user = state.user_repo.find_by_id(user_id).await?.ok_or(AuthError::InvalidCredentials)?;
+
+    let rejection = if !user.is_active {
+        warn!(user_id = %user.id, "passkey login failed: account disabled");
+        Some(AuthError::AccountDisabled)
+    } else if state.require_verified_email && !user.email_verified {
+        warn!(user_id = %user.id, "passkey login failed: email not verified");
+        Some(AuthError::EmailNotVerified)
+    } else {
+        None
+    };
+    if let Some(error) = rejection {
+        state.audit.record(AuditEvent::failure(AuditAction::Login, Some(user.id), &client, &error)).await;
+        return Err(error);
+    }
+
+    // The signature counter lets a cloned authenticator be noticed on its next use
+    state.passkeys.update(user.id, &result).await?;
+    state.user_repo.touch_last_login(user.id).await?;
+
+    info!(user_id = %user.id, "passkey login succeeded");
+    state.audit.record(AuditEvent::success(AuditAction::Login, Some(user.id), &client)).await;
+
+    let tokens = issue_tokens(&state, &user, client).await?;
+    Ok((token_headers(&state, &tokens), Json(tokens)))
+}
+
+
+// Relying party of the passkey routes (they are only mounted when it is set)
+fn provider(state: &AppState) -> Result<&PasskeyProvider, AuthError> {
+    state.webauthn.as_ref().ok_or(AuthError::InternalError)
+}
+
+// Keeps the ceremony on the server, under the new challenge id returned to the client
+async fn start_ceremony(state: &AppState, user_id: Uuid, ceremony: PasskeyCeremony) -> Result<Uuid, AuthError> {
+    let challenge_id = Uuid::new_v4();
+    let now = state.token_config.clock.now();
+    let expires_at = now + PasskeyProvider::challenge_expiry();
+    state.passkey_challenges.insert(challenge_id, PendingCeremony { user_id, ceremony, expires_at }, now).await?;
+    Ok(challenge_id)
+}
+
+// The ceremony of the challenge id, which can't be finished a second time
+async fn finish_ceremony(state: &AppState, challenge_id: Uuid) -> Result<PendingCeremony, AuthError> {
+    match state.passkey_challenges.take(challenge_id).await? {
+        Some(pending) if pending.expires_at > state.token_config.clock.now() => Ok(pending),
+        _ => {
+            warn!(challenge_id = %challenge_id, "passkey ceremony rejected: unknown or expired challenge");
+            Err(AuthError::InvalidToken)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+    use webauthn_rs::prelude::Url;
+    use crate::auth::jwt::{validate_token_type, TokenType};
+    use crate::db::memory_connection::InMemoryUserRepository;
+    use crate::models::user::{CreateUser, User, UserId};
+
+    const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+    const ORIGIN: &str = "https://auth.example.com";
+
+    fn state() -> AppState {
+        let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+        state.webauthn = Some(PasskeyProvider::new("auth.example.com", ORIGIN).unwrap());
+        state
+    }
+
+    // Software authenticator, verifying the user like a fingerprint reader would
+    fn authenticator() -> WebauthnAuthenticator<SoftPasskey> {
+        WebauthnAuthenticator::new(SoftPasskey::new(true))
+    }
+
+    async fn create_user(state: &AppState, username: &str) -> User {
+        state.user_repo.create(CreateUser {
+            username: username.to_string(),
+            email: format!("{username}@example.com"),
+            password: "Password123!".to_string().into(),
+            tenant_id: None,
+        }, "secret-hash".to_string()).await.unwrap()
+    }
+
+    fn auth_user(user_id: Uuid) -> AuthUser {
+        AuthUser {
+            user_id: UserId(user_id), jti: Uuid::new_v4().to_string(), roles: Vec::new(), issued_at: chrono::Utc::now(), tenant_id: None,
+            extra: Default::default(),
+        }
+    }
+
+    // Goes through /webauthn/register/start and /finish with the authenticator
+    async fn register_passkey(state: &AppState, authenticator: &mut WebauthnAuthenticator<SoftPasskey>, user: &User) {
+        let current = CurrentUser { token: auth_user(user.id), user: user.clone() };
+        let Json(start) = register_start_handler(State(state.clone()), current).await.unwrap();
+
+        let credential = authenticator.do_registration(Url::parse(ORIGIN).unwrap(), start.options).unwrap();
+        let payload = PasskeyRegistrationFinish { challenge_id: start.challenge_id, credential };
+        let status = register_finish_handler(State(state.clone()), auth_user(user.id), Json(payload)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    // Goes through /webauthn/login/start with the authenticator, the answer is for /finish
+    async fn sign_login(state: &AppState, authenticator: &mut WebauthnAuthenticator<SoftPasskey>, username: &str) -> PasskeyLoginFinish {
+        let request = PasskeyLoginRequest { username: username.to_string() };
+        let Json(start) = login_start_handler(State(state.clone()), RequestTenant::default(), Json(request)).await.unwrap();
+
+        let credential = authenticator.do_authentication(Url::parse(ORIGIN).unwrap(), start.options).unwrap();
+        PasskeyLoginFinish { challenge_id: start.challenge_id, credential }
+    }
+
+    #[tokio::test]
+    async fn test_register_then_log_in_with_the_passkey() {
+        let state = state();
+        let mut authenticator = authenticator();
+        let user = create_user(&state, "alice").await;
+
+        register_passkey(&state, &mut authenticator, &user).await;
+        assert_eq!(state.passkeys.list(user.id).await.unwrap().len(), 1);
+
+        let payload = sign_login(&state, &mut authenticator, "alice").await;
+        let (_, Json(tokens)) = login_finish_handler(State(state.clone()), ClientInfo::default(), Json(payload)).await.unwrap();
+
+        // The same token as the password login
+        let claims = validate_token_type(&tokens.token, &state.jwt_keys, &state.token_config, TokenType::Access).unwrap();
+        assert_eq!(claims.sub, UserId(user.id));
+        let stored = state.user_repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert!(stored.last_login_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_login_answer_cannot_be_replayed() {
+        let state = state();
+        let mut authenticator = authenticator();
+        let user = create_user(&state, "alice").await;
+        register_passkey(&state, &mut authenticator, &user).await;
+
+        let payload = sign_login(&state, &mut authenticator, "alice").await;
+        let replayed = PasskeyLoginFinish { challenge_id: payload.challenge_id, credential: payload.credential.clone() };
+        login_finish_handler(State(state.clone()), ClientInfo::default(), Json(payload)).await.unwrap();
+
+        let result = login_finish_handler(State(state.clone()), ClientInfo::default(), Json(replayed)).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_login_start_rejects_users_without_passkey() {
+        let state = state();
+        create_user(&state, "alice").await;
+
+        for username in ["alice", "nobody"] {
+            let request = PasskeyLoginRequest { username: username.to_string() };
+            let result = login_start_handler(State(state.clone()), RequestTenant::default(), Json(request)).await;
+            assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registration_challenge_of_another_user_is_rejected() {
+        let state = state();
+        let mut authenticator = authenticator();
+        let alice = create_user(&state, "alice").await;
+        let mallory = create_user(&state, "mallory").await;
+
+        let current = CurrentUser { token: auth_user(alice.id), user: alice.clone() };
+        let Json(start) = register_start_handler(State(state.clone()), current).await.unwrap();
+        let credential = authenticator.do_registration(Url::parse(ORIGIN).unwrap(), start.options).unwrap();
+
+        let payload = PasskeyRegistrationFinish { challenge_id: start.challenge_id, credential };
+        let result = register_finish_handler(State(state.clone()), auth_user(mallory.id), Json(payload)).await;
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+        assert!(state.passkeys.list(mallory.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_rejects_disabled_accounts() {
+        let state = state();
+        let mut authenticator = authenticator();
+        let user = create_user(&state, "alice").await;
+        register_passkey(&state, &mut authenticator, &user).await;
+        state.user_repo.set_active(user.id, false).await.unwrap();
+
+        let payload = sign_login(&state, &mut authenticator, "alice").await;
+        let result = login_finish_handler(State(state.clone()), ClientInfo::default(), Json(payload)).await;
+        assert!(matches!(result, Err(AuthError::AccountDisabled)));
+    }
+}
@@ -1,20 +1,318 @@
+pub mod app;
+pub mod audit;
 pub mod auth;
+pub mod backoff;
+pub mod clock;
+pub mod email;
 pub mod handlers;
 pub mod models;
 pub mod errors;
+pub mod extract;
 pub mod db;
+pub mod config;
+pub mod cors;
+pub mod csrf;
+pub mod envelope;
+pub mod rate_limit;
 
 
 use std::sync::Arc;
+use axum::http::HeaderName;
+use crate::auth::cookie::CookieConfig;
+#[cfg(feature = "oauth")]
+use crate::auth::oauth::OAuthProvider;
+#[cfg(feature = "webauthn")]
+use crate::auth::webauthn::PasskeyProvider;
+#[cfg(feature = "webauthn")]
+use crate::db::passkey_store::{InMemoryPasskeyChallengeStore, InMemoryPasskeyStore, PasskeyChallengeStore, PasskeyStore};
+use crate::auth::crypto::Argon2Config;
+use crate::auth::jwt::{JwtKeys, TokenConfig};
 use crate::db::user_repository::UserRepository;
+use crate::models::auth::{AccountDeletionMode, LoginIdentifierMode};
+use crate::models::validation::{PasswordPolicy, UsernamePolicy};
+use crate::rate_limit::RateLimitConfig;
+use crate::cors::CorsConfig;
+use crate::db::token_blacklist::{InMemoryTokenBlacklist, TokenBlacklist};
+use crate::db::api_key_store::{ApiKeyStore, InMemoryApiKeyStore};
+use crate::db::session_store::{InMemorySessionStore, SessionStore};
+use crate::db::refresh_token_store::{InMemoryRefreshTokenStore, RefreshTokenStore};
+use crate::db::invite_store::{InMemoryInviteStore, InviteStore};
+use crate::audit::{AuditSink, LogAuditSink};
+use crate::email::{EmailSender, LoggingEmailSender};
+use crate::handlers::{auth_handler, session_handler};
+use utoipa::{openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme}, Modify, OpenApi};
 
 #[derive(Clone)]
 pub struct AppState {
-    /// Secret used to sign and verify JWT tokens
-    pub jwt_secret: String,
+    /// Keys used to sign and verify JWT tokens (HS256 secret or RS256 key pair)
+    pub jwt_keys: JwtKeys,
     
     /// User repository (trait object)
     /// Allows using any UserRepository implementation
     /// (PostgreSQL, MongoDB, In-Memory, etc)
     pub user_repo: Arc<dyn UserRepository>,
-}
\ No newline at end of file
+
+    /// Revoked tokens store (trait object)
+    pub token_blacklist: Arc<dyn TokenBlacklist>,
+
+    /// API keys store (trait object)
+    pub api_keys: Arc<dyn ApiKeyStore>,
+
+    /// Sessions (logins) of the users, listed at `GET /sessions` (trait object)
+    pub sessions: Arc<dyn SessionStore>,
+
+    /// Current refresh token of each session, for `POST /token/refresh-rotate` (trait object)
+    pub refresh_tokens: Arc<dyn RefreshTokenStore>,
+
+    /// Invite codes accepted by `/register` when `invite_only` is on (trait object)
+    pub invites: Arc<dyn InviteStore>,
+
+    /// Where the audit events (logins, password changes, ...) are recorded (trait object)
+    pub audit: Arc<dyn AuditSink>,
+
+    /// Delivers the verification and password reset emails (trait object),
+    /// only logged by default (`LoggingEmailSender`)
+    pub email_sender: Arc<dyn EmailSender>,
+
+    /// Settings used when issuing tokens (expiry, ...)
+    pub token_config: TokenConfig,
+
+    /// Argon2 cost parameters used when hashing passwords
+    pub argon2_config: Argon2Config,
+
+    /// Rules new passwords must follow
+    pub password_policy: PasswordPolicy,
+
+    /// Letters allowed in usernames (ASCII only by default)
+    pub username_policy: UsernamePolicy,
+
+    /// Whether `/login` looks users up by username, email or either
+    pub login_identifier: LoginIdentifierMode,
+
+    /// Refuse to log in users that didn't verify their email yet
+    pub require_verified_email: bool,
+
+    /// Tell registering clients whether the email or the username is taken
+    /// (`EmailTaken` / `UsernameTaken` instead of `UserAlreadyExists`).
+    /// Off by default: it lets anyone check if an email has an account
+    pub reveal_conflicting_field: bool,
+
+    /// Accept new accounts on `/register` (`RegistrationDisabled` otherwise)
+    pub registration_enabled: bool,
+
+    /// Only register users with an unused invite code from `invites`
+    pub invite_only: bool,
+
+    /// Roles given to every new account (`/register` and social login), admins can grant more later
+    pub default_roles: Vec<String>,
+
+    /// Whether `DELETE /me` deactivates the account (`Soft`, by default) or removes it (`Hard`)
+    pub account_deletion: AccountDeletionMode,
+
+    /// Also deliver the access token in a cookie (`None` = Bearer header only)
+    pub auth_cookie: Option<CookieConfig>,
+
+    /// Also deliver the access token in this response header, as `Bearer <token>`
+    /// (e.g. `Authorization`, `None` = JSON body only)
+    pub token_response_header: Option<HeaderName>,
+
+    /// Login with Google at `GET /auth/google` (`None` = routes not mounted)
+    #[cfg(feature = "oauth")]
+    pub google_oauth: Option<OAuthProvider>,
+
+    /// Passkey registration and login at `/webauthn/*` (`None` = routes not mounted)
+    #[cfg(feature = "webauthn")]
+    pub webauthn: Option<PasskeyProvider>,
+
+    /// Passkeys of the users, keyed by user id (trait object)
+    #[cfg(feature = "webauthn")]
+    pub passkeys: Arc<dyn PasskeyStore>,
+
+    /// Passkey ceremonies between their `/start` and `/finish` calls (trait object)
+    #[cfg(feature = "webauthn")]
+    pub passkey_challenges: Arc<dyn PasskeyChallengeStore>,
+
+    /// Look the user up on every authenticated request and reject deactivated accounts
+    /// (otherwise a deactivated user keeps access until the token expires)
+    pub check_active_on_request: bool,
+
+    /// Domain whose subdomains name the tenant of anonymous requests
+    /// (`acme.example.com` with `example.com`), see `RequestTenant`
+    pub tenant_domain: Option<String>,
+
+    /// Limit of the anonymous auth routes per client IP (`None` = no limit)
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Number of reverse proxies in front of the server appending to `X-Forwarded-For`
+    /// (0 = the client IP is the socket address, the header is ignored)
+    pub trusted_proxies: usize,
+
+    /// Origins allowed to call the API from a browser (`None` = no CORS headers)
+    pub cors: Option<CorsConfig>,
+
+    /// Wrap the JSON bodies as `{"data": ..., "error": ...}`, see `envelope`
+    pub response_envelope: bool,
+}
+
+impl AppState {
+    /// Creates a state signing tokens with HS256 and the default settings
+    pub fn new(jwt_secret: String, user_repo: Arc<dyn UserRepository>) -> Self {
+        Self::with_keys(JwtKeys::hmac(&jwt_secret), user_repo)
+    }
+
+    /// Creates a state with the given signing keys (e.g. RS256) and the default settings
+    pub fn with_keys(jwt_keys: JwtKeys, user_repo: Arc<dyn UserRepository>) -> Self {
+        Self {
+            jwt_keys,
+            user_repo,
+            token_blacklist: Arc::new(InMemoryTokenBlacklist::new()),
+            api_keys: Arc::new(InMemoryApiKeyStore::new()),
+            sessions: Arc::new(InMemorySessionStore::new()),
+            refresh_tokens: Arc::new(InMemoryRefreshTokenStore::new()),
+            invites: Arc::new(InMemoryInviteStore::new()),
+            audit: Arc::new(LogAuditSink),
+            email_sender: Arc::new(LoggingEmailSender::default()),
+            token_config: TokenConfig::default(),
+            argon2_config: Argon2Config::default(),
+            password_policy: PasswordPolicy::default(),
+            username_policy: UsernamePolicy::default(),
+            login_identifier: LoginIdentifierMode::default(),
+            require_verified_email: false,
+            reveal_conflicting_field: false,
+            registration_enabled: true,
+            invite_only: false,
+            default_roles: Vec::new(),
+            account_deletion: AccountDeletionMode::default(),
+            auth_cookie: None,
+            token_response_header: None,
+            #[cfg(feature = "oauth")]
+            google_oauth: None,
+            #[cfg(feature = "webauthn")]
+            webauthn: None,
+            #[cfg(feature = "webauthn")]
+            passkeys: Arc::new(InMemoryPasskeyStore::new()),
+            #[cfg(feature = "webauthn")]
+            passkey_challenges: Arc::new(InMemoryPasskeyChallengeStore::new()),
+            check_active_on_request: false,
+            tenant_domain: None,
+            rate_limit: None,
+            trusted_proxies: 0,
+            cors: None,
+            response_envelope: false,
+        }
+    }
+}
+
+
+/// OpenAPI document of the auth endpoints, served on `GET /openapi.json`
+///
+/// Generated from the `#[utoipa::path]` of the handlers and the `ToSchema` of their bodies,
+/// so a new route only has to be listed in `paths`. The Google login routes are added
+/// with the "oauth" feature, the passkey routes with the "webauthn" feature.
+///
+/// Usage: `let document = ApiDoc::openapi();`
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        auth_handler::register_handler,
+        auth_handler::login_handler,
+        auth_handler::refresh_handler,
+        auth_handler::refresh_rotate_handler,
+        auth_handler::forgot_password_handler,
+        auth_handler::reset_password_handler,
+        auth_handler::verify_email_handler,
+        auth_handler::change_password_handler,
+        auth_handler::change_email_handler,
+        auth_handler::introspect_handler,
+        auth_handler::introspect_batch_handler,
+        auth_handler::logout_handler,
+        session_handler::logout_all_handler,
+        session_handler::list_sessions_handler,
+        session_handler::revoke_session_handler,
+        auth_handler::me_handler,
+        auth_handler::update_me_handler,
+        auth_handler::delete_me_handler,
+        auth_handler::private_handler,
+    ),
+    modifiers(&BearerAuth, &OAuthPaths, &PasskeyPaths),
+)]
+pub struct ApiDoc;
+
+// The `bearerAuth` scheme of the secured operations (`Authorization: Bearer <JWT>`)
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let scheme = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build();
+        openapi.components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme("bearerAuth", SecurityScheme::Http(scheme));
+    }
+}
+
+// Routes of the login with Google, only built with the "oauth" feature
+struct OAuthPaths;
+
+impl Modify for OAuthPaths {
+    #[cfg(feature = "oauth")]
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        #[derive(OpenApi)]
+        #[openapi(paths(handlers::oauth_handler::google_login_handler, handlers::oauth_handler::google_callback_handler))]
+        struct OAuthApiDoc;
+
+        openapi.merge(OAuthApiDoc::openapi());
+    }
+
+    #[cfg(not(feature = "oauth"))]
+    fn modify(&self, _: &mut utoipa::openapi::OpenApi) {}
+}
+
+// Routes of the passkey registration and login, only built with the "webauthn" feature
+struct PasskeyPaths;
+
+impl Modify for PasskeyPaths {
+    #[cfg(feature = "webauthn")]
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        use crate::handlers::webauthn_handler;
+
+        #[derive(OpenApi)]
+        #[openapi(paths(
+            webauthn_handler::register_start_handler,
+            webauthn_handler::register_finish_handler,
+            webauthn_handler::login_start_handler,
+            webauthn_handler::login_finish_handler,
+        ))]
+        struct PasskeyApiDoc;
+
+        openapi.merge(PasskeyApiDoc::openapi());
+    }
+
+    #[cfg(not(feature = "webauthn"))]
+    fn modify(&self, _: &mut utoipa::openapi::OpenApi) {}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_schema_reference_is_defined() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let text = document.to_string();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+
+        for part in text.split("#/components/schemas/").skip(1) {
+            let name = part.split('"').next().unwrap();
+            assert!(schemas.contains_key(name), "missing schema {name}");
+        }
+    }
+
+    #[test]
+    fn test_secured_operations_use_the_bearer_scheme() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert_eq!(document["components"]["securitySchemes"]["bearerAuth"]["scheme"], "bearer");
+        assert_eq!(document["paths"]["/me"]["get"]["security"][0]["bearerAuth"], serde_json::json!([]));
+        assert!(document["paths"]["/login"]["post"].get("security").is_none());
+    }
+}
@@ -1,41 +1,61 @@
 use std::sync::Arc;
-use auth_system::{auth::extractor::AuthUser, db::memory_connection::InMemoryUserRepository};
-use auth_system::handlers::auth_handler;
-use auth_system::AppState;
+use auth_system::{app::build_router, db::{memory_connection::InMemoryUserRepository, user_repository::UserRepository}};
+use auth_system::{auth::jwt::is_low_entropy_secret, config::Config};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
-use axum::{Router, routing::{get, post}};
 use dotenv::dotenv;
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    let jwt_secret =  std::env::var("JWT_SECRET").expect("JWT_SECRET must be set in .env file");
-    let user_repo = Arc::new(InMemoryUserRepository::new());
-    
-    let state = AppState { 
-        jwt_secret, 
-        user_repo,
-    };
 
-    let app = Router::new()
-        .route("/register", post(auth_handler::register_handler))
-        .route("/login", post(auth_handler::login_handler))
-        .route("/private", get(protect_handler))
-        .with_state(state);
+    // Log level is read from RUST_LOG (e.g. RUST_LOG=auth_system=debug,tower_http=debug)
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("auth_system=info,tower_http=info"))
+        )
+        .init();
+
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if is_low_entropy_secret(&config.jwt_secret) {
+        tracing::warn!("JWT_SECRET looks easy to guess, generate one with: openssl rand -base64 32");
+    }
+    let user_repo = Arc::new(InMemoryUserRepository::new());
 
-    let listener = TcpListener::bind("0.0.0.0:3000")
-        .await
-        .expect("Failed to bind to port 3000");
+    // Fails fast when the database doesn't match the migrations
+    if let Err(e) = user_repo.verify_schema().await {
+        tracing::error!("Invalid database: {}", e);
+        std::process::exit(1);
+    }
+    
+    let state = config.app_state(user_repo);
 
+    let app = build_router(state);
 
-    println!("Auth System running on http://0.0.0.0:3000");
+    let address = config.address();
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind to {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
 
-    axum::serve(listener, app).await.expect("Failed to start server");
-}
 
+    tracing::info!("Auth System running on http://{}", address);
 
-async fn protect_handler(user: AuthUser) -> String {
-    format!("Access granted for user: {}", user.user_id)
+    // The socket address is the rate limit key, unless TRUSTED_PROXIES is set
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .expect("Failed to start server");
 }
 
 
@@ -70,25 +90,23 @@ async fn protect_handler(user: AuthUser) -> String {
 
 /*
 use auth_system::db::postgres_connection::PostgresUserRepository;
-use sqlx::postgres::PgPoolOptions;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let config = Config::from_env().expect("Invalid configuration");
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     
-    // Create PostgreSQL connection pool
-    let db_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+    // Connect with the DATABASE_* pool settings, retrying while the database starts
+    let user_repo = PostgresUserRepository::connect(&database_url, &config.pool)
         .await
         .expect("Failed to connect to PostgreSQL");
+    // Creates the tables on a new database, applies the new migrations otherwise
+    user_repo.run_migrations().await.expect("Failed to run the migrations");
+    let user_repo = Arc::new(user_repo);
     
-    let user_repo = Arc::new(PostgresUserRepository::new(db_pool));
-    
-    let state = AppState { jwt_secret, user_repo };
+    let state = config.app_state(user_repo);
     
     // ... rest of code is the same
 }
@@ -114,25 +132,23 @@ async fn main() {
 
 /*
 use auth_system::db::mysql_connection::MySQLUserRepository;
-use sqlx::mysql::MySqlPoolOptions;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let config = Config::from_env().expect("Invalid configuration");
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     
-    // Create MySQL connection pool
-    let db_pool = MySqlPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+    // Connect with the DATABASE_* pool settings, retrying while the database starts
+    let user_repo = MySQLUserRepository::connect(&database_url, &config.pool)
         .await
         .expect("Failed to connect to MySQL");
+    // Creates the tables on a new database, applies the new migrations otherwise
+    user_repo.run_migrations().await.expect("Failed to run the migrations");
+    let user_repo = Arc::new(user_repo);
     
-    let user_repo = Arc::new(MySQLUserRepository::new(db_pool));
-    
-    let state = AppState { jwt_secret, user_repo };
+    let state = config.app_state(user_repo);
     
     // ... rest of code is the same
 }
@@ -158,25 +174,23 @@ async fn main() {
 
 /*
 use auth_system::db::sqlite_connection::SQLiteUserRepository;
-use sqlx::sqlite::SqlitePoolOptions;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let config = Config::from_env().expect("Invalid configuration");
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     
-    // Create SQLite connection pool
-    let db_pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+    // Connect with the DATABASE_* pool settings, retrying while the database starts
+    let user_repo = SQLiteUserRepository::connect(&database_url, &config.pool)
         .await
         .expect("Failed to connect to SQLite");
+    // Creates the tables on a new database, applies the new migrations otherwise
+    user_repo.run_migrations().await.expect("Failed to run the migrations");
+    let user_repo = Arc::new(user_repo);
     
-    let user_repo = Arc::new(SQLiteUserRepository::new(db_pool));
-    
-    let state = AppState { jwt_secret, user_repo };
+    let state = config.app_state(user_repo);
     
     // ... rest of code is the same
 }
@@ -208,7 +222,7 @@ use mongodb::Client;
 async fn main() {
     dotenv().ok();
     
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let config = Config::from_env().expect("Invalid configuration");
     let mongodb_uri = std::env::var("MONGODB_URI").expect("MONGODB_URI must be set");
     let mongodb_database = std::env::var("MONGODB_DATABASE").expect("MONGODB_DATABASE must be set");
     
@@ -219,7 +233,7 @@ async fn main() {
     
     let user_repo = Arc::new(MongoDBUserRepository::new(client, &mongodb_database));
     
-    let state = AppState { jwt_secret, user_repo };
+    let state = config.app_state(user_repo);
     
     // ... rest of code is the same
 }
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// API key of a user, used by services instead of a JWT
+///
+/// Only the SHA-256 hash of the key is stored, the plaintext is returned once when minted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    pub created_at: DateTime<Utc>,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    /// Label to recognize the key (e.g. "billing-service")
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    /// Plaintext key, it can't be retrieved again
+    pub key: String,
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    pub created_at: DateTime<Utc>,
+}
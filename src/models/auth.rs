@@ -1,20 +1,262 @@
+use std::str::FromStr;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use zeroize::Zeroizing;
+use crate::models::user::{User, UserId};
+use crate::models::validation::{bounded_option, bounded_secret, bounded_string, MAX_EMAIL_INPUT_BYTES, MAX_FIELD_INPUT_BYTES, MAX_PASSWORD_INPUT_BYTES, MAX_USERNAME_INPUT_BYTES};
 
-#[derive(Deserialize)]
+/// Body of `POST /login`
+///
+/// `username` is the login identifier: a username or an email, see `LoginIdentifierMode`.
+/// Like in every request, the plaintext password is `Zeroizing`: its memory is
+/// overwritten when the request is dropped, right after hashing or verification.
+#[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
+    // The identifier may be an email
+    #[serde(deserialize_with = "bounded_string::<MAX_EMAIL_INPUT_BYTES, _>")]
     pub username: String,
-    pub password: String,
+    #[serde(deserialize_with = "bounded_secret::<MAX_PASSWORD_INPUT_BYTES, _>")]
+    #[schema(value_type = String)]
+    pub password: Zeroizing<String>,
 }
 
-#[derive(Serialize)]
+/// What users log in with (`LOGIN_IDENTIFIER`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoginIdentifierMode {
+    /// `username`
+    #[default]
+    UsernameOnly,
+    /// `email`
+    EmailOnly,
+    /// `either`: the email when the identifier contains `@`, the username otherwise
+    Either,
+}
+
+impl FromStr for LoginIdentifierMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "username" => Ok(Self::UsernameOnly),
+            "email" => Ok(Self::EmailOnly),
+            "either" => Ok(Self::Either),
+            _ => Err(format!("expected `username`, `email` or `either`, got `{value}`")),
+        }
+    }
+}
+
+/// What `DELETE /me` does with the account (`ACCOUNT_DELETION`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountDeletionMode {
+    /// `soft`: deactivates the user, the data is kept and an admin can reactivate it
+    /// (the email and username stay taken)
+    #[default]
+    Soft,
+    /// `hard`: removes the user and its password history for good
+    Hard,
+}
+
+impl FromStr for AccountDeletionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "soft" => Ok(Self::Soft),
+            "hard" => Ok(Self::Hard),
+            _ => Err(format!("expected `soft` or `hard`, got `{value}`")),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+
+/// Body of `POST /register`: the tokens of `LoginResponse` and the created user,
+/// so the client doesn't have to call `GET /me` (the password hash is never serialized)
+#[derive(Serialize, ToSchema)]
+pub struct RegisterResponse {
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub user: User,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RefreshResponse {
+    pub token: String,
+}
+
+
+#[derive(Deserialize, ToSchema)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    #[schema(value_type = String)]
+    pub new_password: Zeroizing<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    #[schema(value_type = String)]
+    pub current_password: Zeroizing<String>,
+    #[schema(value_type = String)]
+    pub new_password: Zeroizing<String>,
+}
+
+/// Body of `DELETE /me`, the current password confirms the deletion
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteAccountRequest {
+    #[schema(value_type = String)]
+    pub password: Zeroizing<String>,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct ChangeEmailRequest {
+    pub new_email: String,
+}
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize, ToSchema)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct IntrospectRequest {
+    pub token: String,
+}
+
+/// Most tokens `POST /introspect/batch` checks in one request
+pub const MAX_INTROSPECT_BATCH: usize = 100;
+
+/// Body of `POST /introspect/batch`, at most `MAX_INTROSPECT_BATCH` tokens
+#[derive(Deserialize, ToSchema)]
+pub struct IntrospectBatchRequest {
+    #[schema(max_items = 100)]
+    pub tokens: Vec<String>,
+}
+
+/// Answer of `POST /introspect/batch`: one result per token, in the order of the request
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IntrospectBatchResponse {
+    pub results: Vec<IntrospectResponse>,
+}
+
+/// Answer of `POST /introspect` (RFC 7662 style)
+///
+/// An invalid, expired or revoked token is `{"active": false}`, without claims
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct IntrospectResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<UserId>,
+    /// Expiration time (seconds since the epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<String>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+/// Query string of `GET /auth/google/callback`, as sent back by the provider
+///
+/// `error` is set instead of `code` when the user refused the consent.
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Response of `GET /private`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PrivateResponse {
+    pub user_id: UserId,
+    /// When the token used was issued
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = i64))]
+    pub authenticated_at: chrono::DateTime<chrono::Utc>,
+}
+
+
+/// Body of `POST /register`
+///
+/// Each field is bounded while deserializing (`bounded_string`), so an oversized value
+/// is rejected before being normalized, validated or hashed.
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterRequest {
+    #[serde(deserialize_with = "bounded_string::<MAX_USERNAME_INPUT_BYTES, _>")]
     pub username: String,
+    #[serde(deserialize_with = "bounded_string::<MAX_EMAIL_INPUT_BYTES, _>")]
     pub email: String,
-    pub password: String,
-}
\ No newline at end of file
+    #[serde(deserialize_with = "bounded_secret::<MAX_PASSWORD_INPUT_BYTES, _>")]
+    #[schema(value_type = String)]
+    pub password: Zeroizing<String>,
+    /// Required when registration is invite-only
+    #[serde(default, deserialize_with = "bounded_option::<MAX_FIELD_INPUT_BYTES, _>")]
+    pub invite_code: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zeroize::Zeroize;
+
+    #[test]
+    fn test_password_deserializes_and_is_zeroized() {
+        let request: LoginRequest = serde_json::from_str(r#"{"username": "john_doe", "password": "Password123!"}"#).unwrap();
+        let mut password = request.password;
+        assert_eq!(password.as_str(), "Password123!");
+
+        // What dropping the `Zeroizing` does, before the buffer is freed
+        let (buffer, capacity) = (password.as_ptr(), password.capacity());
+        password.zeroize();
+
+        // SAFETY: zeroizing keeps the allocation, `password` is still alive
+        let bytes = unsafe { std::slice::from_raw_parts(buffer, capacity) };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+        assert!(password.is_empty());
+    }
+
+    fn register_body(username: &str) -> String {
+        serde_json::json!({ "username": username, "email": "john@example.com", "password": "Password123!" }).to_string()
+    }
+
+    #[test]
+    fn test_register_rejects_huge_username_while_parsing() {
+        let body = register_body(&"a".repeat(5 * 1024 * 1024));
+
+        let error = serde_json::from_str::<RegisterRequest>(&body).unwrap_err();
+        assert!(error.to_string().contains("expected a string of at most 256 bytes"), "{error}");
+    }
+
+    #[test]
+    fn test_register_fields_up_to_the_caps_are_accepted() {
+        let request: RegisterRequest = serde_json::from_str(&register_body(&"a".repeat(MAX_USERNAME_INPUT_BYTES))).unwrap();
+        assert_eq!(request.username.len(), MAX_USERNAME_INPUT_BYTES);
+        assert_eq!(request.invite_code, None);
+
+        let body = r#"{"username": "john_doe", "email": "john@example.com", "password": "Password123!", "invite_code": null}"#;
+        assert!(serde_json::from_str::<RegisterRequest>(body).is_ok());
+        let body = format!(r#"{{"username": "john_doe", "email": "john@example.com", "password": "Password123!", "invite_code": "{}"}}"#, "c".repeat(MAX_FIELD_INPUT_BYTES + 1));
+        assert!(serde_json::from_str::<RegisterRequest>(&body).is_err());
+    }
+}
@@ -1,3 +1,8 @@
 pub mod user;
 pub mod auth;
-pub mod validation;
\ No newline at end of file
+pub mod validation;
+pub mod page;
+pub mod api_key;
+pub mod session;
+#[cfg(feature = "webauthn")]
+pub mod passkey;
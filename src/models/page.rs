@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+/// One page of a listing, plus the total so clients can page through all the items
+///
+/// Returned by the admin listings (`GET /users`, `GET /users/search`).
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of items of the whole listing, not only of this page
+    pub total: u64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl<T> Page<T> {
+    /// Page of `all`, every item of a listing the storage doesn't page itself
+    pub fn slice(all: Vec<T>, limit: u32, offset: u32) -> Self {
+        let total = all.len() as u64;
+        let items = all.into_iter().skip(offset as usize).take(limit as usize).collect();
+
+        Self { items, total, limit, offset }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_keeps_the_total_of_all_items() {
+        let page = Page::slice((0..5).collect(), 2, 3);
+        assert_eq!(page.items, vec![3, 4]);
+        assert_eq!(page.total, 5);
+
+        assert!(Page::slice((0..5).collect::<Vec<_>>(), 2, 10).items.is_empty());
+    }
+}
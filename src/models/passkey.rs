@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use webauthn_rs::prelude::{CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse};
+use crate::models::validation::{bounded_string, MAX_EMAIL_INPUT_BYTES};
+
+/// Response of `POST /webauthn/register/start`
+///
+/// `options` is passed to `navigator.credentials.create()`, its answer is sent
+/// to `/webauthn/register/finish` with the same `challenge_id`.
+#[derive(Serialize, ToSchema)]
+pub struct PasskeyRegistrationStart {
+    pub challenge_id: Uuid,
+    /// `PublicKeyCredentialCreationOptions` of the WebAuthn spec, under `publicKey`
+    #[schema(value_type = Object)]
+    pub options: CreationChallengeResponse,
+}
+
+/// Body of `POST /webauthn/register/finish`
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyRegistrationFinish {
+    pub challenge_id: Uuid,
+    /// The `PublicKeyCredential` returned by `navigator.credentials.create()`
+    #[schema(value_type = Object)]
+    pub credential: RegisterPublicKeyCredential,
+}
+
+/// Body of `POST /webauthn/login/start`
+///
+/// `username` is the login identifier, like in `LoginRequest`.
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyLoginRequest {
+    #[serde(deserialize_with = "bounded_string::<MAX_EMAIL_INPUT_BYTES, _>")]
+    pub username: String,
+}
+
+/// Response of `POST /webauthn/login/start`
+///
+/// `options` is passed to `navigator.credentials.get()`, its answer is sent
+/// to `/webauthn/login/finish` with the same `challenge_id`.
+#[derive(Serialize, ToSchema)]
+pub struct PasskeyLoginStart {
+    pub challenge_id: Uuid,
+    /// `PublicKeyCredentialRequestOptions` of the WebAuthn spec, under `publicKey`
+    #[schema(value_type = Object)]
+    pub options: RequestChallengeResponse,
+}
+
+/// Body of `POST /webauthn/login/finish`
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyLoginFinish {
+    pub challenge_id: Uuid,
+    /// The `PublicKeyCredential` returned by `navigator.credentials.get()`
+    #[schema(value_type = Object)]
+    pub credential: PublicKeyCredential,
+}
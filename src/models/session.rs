@@ -0,0 +1,34 @@
+use serde::Serialize;
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A login of a user (one device or browser)
+///
+/// Identified by the `jti` of the access token issued at login. The tokens
+/// refreshed from it carry this id (`sid` claim), so revoking the session
+/// revokes all of them.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Session {
+    pub jti: String,
+    #[serde(skip_serializing)]
+    pub user_id: Uuid,
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = i64))]
+    pub issued_at: DateTime<Utc>,
+    /// When the refresh token (or the access token, without refresh tokens) expires
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = i64))]
+    pub expires_at: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Item of `GET /sessions`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    pub session: Session,
+    /// The session of the token used for the request
+    pub current: bool,
+}
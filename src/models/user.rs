@@ -1,30 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
+use utoipa::ToSchema;
+use zeroize::Zeroizing;
+use crate::models::validation::{bounded_option, bounded_secret, bounded_string, MAX_EMAIL_INPUT_BYTES, MAX_FIELD_INPUT_BYTES, MAX_PASSWORD_INPUT_BYTES, MAX_USERNAME_INPUT_BYTES};
 
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Id of a user, as carried by the tokens (`Claims::sub`) and `AuthUser`
+///
+/// Serialized as the hyphenated UUID, like `User::id`: a token whose `sub` isn't a UUID
+/// fails to decode, so handlers get a valid id without parsing it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct UserId(pub Uuid);
+
+impl UserId {
+    pub const fn as_uuid(self) -> Uuid {
+        self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for UserId {
+    type Err = uuid::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(value).map(UserId)
+    }
+}
+
+impl From<Uuid> for UserId {
+    fn from(id: Uuid) -> Self {
+        UserId(id)
+    }
+}
+
+impl From<UserId> for Uuid {
+    fn from(id: UserId) -> Self {
+        id.0
+    }
+}
+
+/// A user account
+///
+/// With PostgreSQL the columns map one to one, so rows decode straight into a `User`
+/// (`FromRow`); SQLite and MySQL store some fields as text and go through their own row type.
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
 pub struct User {
     pub id: Uuid,
     pub username: String,
     pub email: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
+    /// RFC 3339 string, or epoch milliseconds with the `epoch-millis` feature
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = i64))]
     pub created_at: DateTime<Utc>,
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = i64))]
     pub updated_at: DateTime<Utc>,
     pub is_active: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Last successful login (`None` until the first one)
+    #[serde(default)]
+    #[cfg_attr(feature = "epoch-millis", serde(with = "chrono::serde::ts_milliseconds_option"))]
+    #[cfg_attr(feature = "epoch-millis", schema(value_type = Option<i64>))]
+    pub last_login_at: Option<DateTime<Utc>>,
+    /// New email waiting for confirmation (`POST /change-email`), `email` is unchanged until then
+    #[serde(default)]
+    pub pending_email: Option<String>,
+    /// Tenant the account belongs to (`None` without multi-tenancy), see `auth::extractor::Tenant`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// Version of the user's tokens, embedded in them at login
+    /// Bumped by `UserRepository::increment_token_version` (password change, logout everywhere),
+    /// which invalidates every token issued before
+    #[serde(default, skip_serializing)]
+    pub token_version: i64,
+    /// External provider the user logs in with (`"google"`), `None` for password-only accounts
+    /// Set with `UserRepository::link_oauth`, together with `oauth_subject`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth_provider: Option<String>,
+    /// Id of the user at `oauth_provider` (the `sub` of its userinfo)
+    #[serde(default, skip_serializing)]
+    pub oauth_subject: Option<String>,
 }
 
+/// New user, bounded while deserializing like `RegisterRequest`
 #[derive(Debug, Deserialize)]
 pub struct CreateUser {
+    #[serde(deserialize_with = "bounded_string::<MAX_USERNAME_INPUT_BYTES, _>")]
     pub username: String,
+    #[serde(deserialize_with = "bounded_string::<MAX_EMAIL_INPUT_BYTES, _>")]
     pub email: String,
-    pub password: String,
+    #[serde(deserialize_with = "bounded_secret::<MAX_PASSWORD_INPUT_BYTES, _>")]
+    pub password: Zeroizing<String>,
+    /// Tenant of the new account, set at registration from `RequestTenant`
+    #[serde(default, deserialize_with = "bounded_option::<MAX_FIELD_INPUT_BYTES, _>")]
+    pub tenant_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Changes of a user, also the body of `PATCH /me`
+///
+/// The document only shows `username`, the other fields are rejected by `PATCH /me`.
+#[derive(Debug, Default, Deserialize, ToSchema)]
 pub struct UpdateUser {
     pub username: Option<String>,
+    #[schema(ignore)]
+    pub email: Option<String>,
+    #[schema(ignore)]
+    pub password: Option<Zeroizing<String>>,
+    #[schema(ignore)]
+    pub roles: Option<Vec<String>>,
+}
+/// Paging of `GET /users` and `GET /users/search` (`?limit=20&offset=0`)
+#[derive(Debug, Default, Deserialize)]
+pub struct ListUsersQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// User counts by activity status, returned by `GET /stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UserStats {
+    pub total: u64,
+    pub active: u64,
+    pub inactive: u64,
+}
+
+/// Body of `PUT /users/{id}/active`
+#[derive(Debug, Deserialize)]
+pub struct SetActiveRequest {
+    pub is_active: bool,
+}
+
+/// Criteria of `UserRepository::search`, all given criteria must match
+///
+/// Also the query string of `GET /users/search`.
+/// An empty filter matches every user
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserFilter {
+    /// Username contains this text (case-insensitive)
+    pub username: Option<String>,
+    /// Email contains this text (case-insensitive)
     pub email: Option<String>,
-    pub password: Option<String>
-}
\ No newline at end of file
+    pub is_active: Option<bool>,
+    /// Created strictly after this date
+    pub created_after: Option<DateTime<Utc>>,
+}
+
+impl UserFilter {
+    /// Checks a user against the filter (used by the in-memory repository)
+    pub fn matches(&self, user: &User) -> bool {
+        let contains = |value: &str, part: &Option<String>| {
+            part.as_ref().is_none_or(|part| value.to_lowercase().contains(&part.to_lowercase()))
+        };
+
+        contains(&user.username, &self.username)
+            && contains(&user.email, &self.email)
+            && self.is_active.is_none_or(|is_active| user.is_active == is_active)
+            && self.created_after.is_none_or(|date| user.created_at > date)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        let created_at = DateTime::from_timestamp_millis(1_735_689_600_123).unwrap();
+        User {
+            id: Uuid::nil(),
+            username: "john_doe".to_string(),
+            email: "john@example.com".to_string(),
+            password_hash: "hash".to_string(),
+            created_at,
+            updated_at: created_at,
+            is_active: true,
+            roles: Vec::new(),
+            email_verified: false,
+            last_login_at: Some(created_at),
+            pending_email: None,
+            tenant_id: None,
+            token_version: 0,
+            oauth_provider: None,
+            oauth_subject: None,
+        }
+    }
+
+    #[cfg(not(feature = "epoch-millis"))]
+    #[test]
+    fn test_timestamps_serialize_as_rfc3339() {
+        let json = serde_json::to_value(user()).unwrap();
+        assert_eq!(json["created_at"], "2025-01-01T00:00:00.123Z");
+        assert_eq!(json["last_login_at"], "2025-01-01T00:00:00.123Z");
+    }
+
+    #[cfg(feature = "epoch-millis")]
+    #[test]
+    fn test_timestamps_serialize_as_epoch_millis() {
+        let json = serde_json::to_value(user()).unwrap();
+        assert_eq!(json["created_at"], 1_735_689_600_123_i64);
+        assert_eq!(json["updated_at"], 1_735_689_600_123_i64);
+        assert_eq!(json["last_login_at"], 1_735_689_600_123_i64);
+    }
+
+    #[test]
+    fn test_missing_last_login_deserializes_as_none() {
+        let mut json = serde_json::to_value(user()).unwrap();
+        json.as_object_mut().unwrap().remove("last_login_at");
+        json["password_hash"] = "hash".into();
+
+        let user: User = serde_json::from_value(json).unwrap();
+        assert!(user.last_login_at.is_none());
+    }
+}
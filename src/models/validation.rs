@@ -1,30 +1,272 @@
+use std::fmt;
 use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroizing;
 use crate::errors::AuthError;
 
+/// Reason an input was rejected, carried by `AuthError::ValidationError`
+///
+/// `kind()` is a stable slug clients can translate,
+/// the `Display` text is the English message sent as `error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationReason {
+    InvalidEmail,
+    EmailTooLong,
+    /// The new email is the current one
+    EmailUnchanged,
+    UsernameTooShort,
+    UsernameTooLong,
+    UsernameInvalidCharacters,
+    UsernameInvisibleCharacters,
+    UsernameMixedScripts,
+    /// Fewer characters than the policy's `min_length`
+    PasswordTooShort { min: usize },
+    /// More characters than the policy's `max_length`
+    PasswordTooLong { max: usize },
+    /// Over `MAX_PASSWORD_BYTES`, whatever the policy
+    PasswordTooLarge,
+    PasswordMissingUppercase,
+    PasswordMissingLowercase,
+    PasswordMissingDigit,
+    PasswordMissingSpecial,
+    /// The new password is the current one
+    PasswordUnchanged,
+    /// The new password is one of the last `history` passwords of the user
+    PasswordReused { history: usize },
+    /// The password can only be changed with the current one (`POST /change-password`)
+    PasswordNotEditable,
+    /// Users can't change their own roles
+    RolesNotEditable,
+    /// The email is only changed once the new address is verified (`POST /change-email`)
+    EmailNotEditable,
+    ApiKeyNameRequired,
+    /// More tokens than `MAX_INTROSPECT_BATCH` in one introspection request
+    TooManyTokens { max: usize },
+    /// The request body isn't the expected JSON (syntax, missing field, wrong type, ...),
+    /// with the message of the JSON extractor
+    InvalidBody(String),
+}
+
+impl ValidationReason {
+    /// Stable machine-readable slug, e.g. `"password_too_short"`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ValidationReason::InvalidEmail => "invalid_email",
+            ValidationReason::EmailTooLong => "email_too_long",
+            ValidationReason::EmailUnchanged => "email_unchanged",
+            ValidationReason::UsernameTooShort => "username_too_short",
+            ValidationReason::UsernameTooLong => "username_too_long",
+            ValidationReason::UsernameInvalidCharacters => "username_invalid_characters",
+            ValidationReason::UsernameInvisibleCharacters => "username_invisible_characters",
+            ValidationReason::UsernameMixedScripts => "username_mixed_scripts",
+            ValidationReason::PasswordTooShort { .. } => "password_too_short",
+            ValidationReason::PasswordTooLong { .. } => "password_too_long",
+            ValidationReason::PasswordTooLarge => "password_too_large",
+            ValidationReason::PasswordMissingUppercase => "password_missing_uppercase",
+            ValidationReason::PasswordMissingLowercase => "password_missing_lowercase",
+            ValidationReason::PasswordMissingDigit => "password_missing_digit",
+            ValidationReason::PasswordMissingSpecial => "password_missing_special",
+            ValidationReason::PasswordUnchanged => "password_unchanged",
+            ValidationReason::PasswordReused { .. } => "password_reused",
+            ValidationReason::PasswordNotEditable => "password_not_editable",
+            ValidationReason::RolesNotEditable => "roles_not_editable",
+            ValidationReason::EmailNotEditable => "email_not_editable",
+            ValidationReason::ApiKeyNameRequired => "api_key_name_required",
+            ValidationReason::TooManyTokens { .. } => "too_many_tokens",
+            ValidationReason::InvalidBody(_) => "invalid_body",
+        }
+    }
+}
+
+impl fmt::Display for ValidationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationReason::InvalidEmail => write!(f, "Invalid email format!"),
+            ValidationReason::EmailTooLong => write!(f, "Email is too long (max 254 characters, 64 before the @)"),
+            ValidationReason::EmailUnchanged => write!(f, "New email must be different from the current email"),
+            ValidationReason::UsernameTooShort => write!(f, "Username must be at least 3 characters long"),
+            ValidationReason::UsernameTooLong => write!(f, "Username is too long (max 50 characters)"),
+            ValidationReason::UsernameInvalidCharacters => write!(
+                f,
+                "Username can only contain letters, numbers, underscore and hyphen. Cannot start or end with special characters"
+            ),
+            ValidationReason::UsernameInvisibleCharacters => write!(f, "Username cannot contain invisible or control characters"),
+            ValidationReason::UsernameMixedScripts => write!(f, "Username cannot mix letters of different scripts"),
+            ValidationReason::PasswordTooShort { min } => write!(f, "Password must contain at least {} characters", min),
+            ValidationReason::PasswordTooLong { max } => write!(f, "Password is too long (max {} characters)", max),
+            ValidationReason::PasswordTooLarge => write!(f, "Password is too long (max {} bytes)", MAX_PASSWORD_BYTES),
+            ValidationReason::PasswordMissingUppercase => write!(f, "Password must contain at least one uppercase letter (A-Z)"),
+            ValidationReason::PasswordMissingLowercase => write!(f, "Password must contain at least one lowercase letter (a-z)"),
+            ValidationReason::PasswordMissingDigit => write!(f, "Password must contain at least one number (0-9)"),
+            ValidationReason::PasswordMissingSpecial => write!(f, "Password must contain at least one special character"),
+            ValidationReason::PasswordUnchanged => write!(f, "New password must be different from the current password"),
+            ValidationReason::PasswordReused { history } => {
+                write!(f, "New password must be different from the last {} passwords", history)
+            }
+            ValidationReason::PasswordNotEditable => write!(f, "Use /change-password to change the password"),
+            ValidationReason::RolesNotEditable => write!(f, "Roles can't be changed through this route"),
+            ValidationReason::EmailNotEditable => write!(f, "Use /change-email to change the email"),
+            ValidationReason::ApiKeyNameRequired => write!(f, "API key name is required"),
+            ValidationReason::TooManyTokens { max } => write!(f, "At most {} tokens can be introspected at once", max),
+            ValidationReason::InvalidBody(message) => write!(f, "Invalid request body: {}", message),
+        }
+    }
+}
+
+/// Runs several validations and reports every failure together
+///
+/// The reasons of all the failed validations are merged into one `ValidationError`,
+/// so a form with several bad fields is rejected once with all of them.
+/// Any other error is returned as is.
+pub fn validate_all(results: impl IntoIterator<Item = Result<(), AuthError>>) -> Result<(), AuthError> {
+    let mut reasons = Vec::new();
+
+    for result in results {
+        match result {
+            Ok(()) => {}
+            Err(AuthError::ValidationError(failed)) => reasons.extend(failed),
+            Err(error) => return Err(error),
+        }
+    }
+
+    if !reasons.is_empty() {
+        return Err(AuthError::ValidationError(reasons));
+    }
+
+    Ok(())
+}
+
+/// English message for several reasons, joined with "; "
+pub fn describe(reasons: &[ValidationReason]) -> String {
+    reasons.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+/// Normalizes an email before storage and lookup
+///
+/// Trims surrounding whitespace and lowercases it, so
+/// `" User@Example.com "` and `"user@example.com"` are the same account.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+
+// Longest address and local part accepted by SMTP (RFC 5321)
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LABEL_LENGTH: usize = 63;
+
 /// Checks if the email has the correct format
 ///
+/// The local part (before the last `@`) is either dot-separated atoms of letters, digits and
+/// ``!#$%&'*+-/=?^_`{|}~`` or a quoted string (RFC 5322). The domain is a host name with
+/// at least two labels and an alphabetic (or punycode) top-level domain.
+/// IP literals (`user@[192.0.2.1]`), comments and non-ASCII addresses are rejected.
+///
 /// Valid examples:
 /// - user@email.com
-/// - name.surname@company.com.br
-/// - test123@provider.co
+/// - name.surname+tag@company.com.br
+/// - "john doe"@provider.co
 ///
 /// Invalid examples:
 /// - email@
 /// - @email.com
 /// - email.com
+/// - user..name@email.com
+///
+/// Longer than 254 characters (64 for the local part) is `EmailTooLong`.
 pub fn validate_email(email: &str) -> Result<(), AuthError> {
-    let email_regex = Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap();
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err(ValidationReason::InvalidEmail.into());
+    };
 
-    if !email_regex.is_match(email){
-        return Err(AuthError::ValidationError("Invalid email format!".to_string()));
+    if !is_valid_local_part(local) || !is_valid_domain(domain) {
+        return Err(ValidationReason::InvalidEmail.into());
     }
 
-    if email.len() > 255 {
-        return Err(AuthError::ValidationError("Email is to long (max 255 characters)".to_string()));
+    if email.len() > MAX_EMAIL_LENGTH || local.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(ValidationReason::EmailTooLong.into());
     }
     Ok(())
 }
 
+// Characters of an unquoted local part, besides letters and digits (RFC 5322 `atext`)
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+fn is_valid_local_part(local: &str) -> bool {
+    if let Some(quoted) = local.strip_prefix('"') {
+        let Some(content) = quoted.strip_suffix('"') else {
+            return false;
+        };
+        // Printable ASCII, `"` and `\` only when escaped with `\`
+        let mut chars = content.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if !chars.next().is_some_and(|escaped| matches!(escaped, ' '..='~')) {
+                        return false;
+                    }
+                }
+                '"' => return false,
+                ' '..='~' => {}
+                _ => return false,
+            }
+        }
+        return true;
+    }
+
+    // Dot-atom: no leading, trailing or consecutive dots
+    !local.is_empty() && local.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+// The length of the whole domain is bounded by MAX_EMAIL_LENGTH
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= MAX_DOMAIN_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    let tld = labels[labels.len() - 1];
+    let valid_tld = tld.len() >= 2
+        && (tld.chars().all(|c| c.is_ascii_alphabetic()) || tld.to_ascii_lowercase().starts_with("xn--"));
+
+    labels.len() >= 2 && valid_labels && valid_tld
+}
+
+
+
+/// Which letters a username may contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UsernamePolicy {
+    /// Only ASCII letters (a-z, A-Z)
+    #[default]
+    Ascii,
+    /// Any Unicode letter, as long as all the letters are of one script
+    /// (no "pаypal" with a Cyrillic "а") and there are no invisible characters
+    Unicode,
+}
+
+/// Normalizes a username before storage and lookup
+///
+/// Trims the surrounding whitespace and applies Unicode NFC, so "é" typed as one
+/// code point or as "e" + accent is the same username. The casing is kept for display.
+pub fn normalize_username(username: &str) -> String {
+    username.trim().nfc().collect()
+}
+
+/// Canonical form of a username: normalized (`normalize_username`) and lowercased
+///
+/// The repositories store it next to the display `username` and compare it in
+/// `find_by_username` and the uniqueness checks, so "JohnDoe" and "johndoe" are one account
+pub fn canonical_username(username: &str) -> String {
+    normalize_username(username).to_lowercase()
+}
 
 
 /// Validates if the username is valid
@@ -35,63 +277,256 @@ pub fn validate_email(email: &str) -> Result<(), AuthError> {
 /// - Only letters, numbers, underscores, and hyphens
 /// - Cannot start or end with an underscore/hyphen
 pub fn validate_username(username: &str) -> Result<(), AuthError> {
+    validate_username_with(UsernamePolicy::Ascii, username)
+}
+
+/// Validates the username against the given policy
+///
+/// In `Unicode` mode the username must already be normalized (`normalize_username`)
+pub fn validate_username_with(policy: UsernamePolicy, username: &str) -> Result<(), AuthError> {
+    match policy {
+        UsernamePolicy::Ascii => validate_ascii_username(username),
+        UsernamePolicy::Unicode => validate_unicode_username(username),
+    }
+}
+
+fn validate_ascii_username(username: &str) -> Result<(), AuthError> {
     if username.len() < 3 {
-        return Err(AuthError::ValidationError("Username must be at least 3 characters long".to_string()));
+        return Err(ValidationReason::UsernameTooShort.into());
     }
 
     if username.len() > 50 {
-        return Err(AuthError::ValidationError("Username is to long (max 50 characters)".to_string()));
+        return Err(ValidationReason::UsernameTooLong.into());
     }
 
     let username_regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_-]*[a-zA-Z0-9]$").unwrap();
     if !username_regex.is_match(username) {
-        return Err(AuthError::ValidationError(
-            "Username can only contain letters, numbers, underscore and hyphen. Cannot start or end with special characters".to_string()
-        ));
+        return Err(ValidationReason::UsernameInvalidCharacters.into());
+    }
+    Ok(())
+}
+
+fn validate_unicode_username(username: &str) -> Result<(), AuthError> {
+    // Same bounds as the ASCII mode, counted in characters
+    let length = username.chars().count();
+    if length < 3 {
+        return Err(ValidationReason::UsernameTooShort.into());
+    }
+
+    if length > 50 {
+        return Err(ValidationReason::UsernameTooLong.into());
     }
+
+    if username.chars().any(is_invisible) {
+        return Err(ValidationReason::UsernameInvisibleCharacters.into());
+    }
+
+    let is_separator = |c: char| c == '_' || c == '-';
+    let valid_chars = username.chars().all(|c| c.is_alphabetic() || c.is_ascii_digit() || is_separator(c));
+    let starts_or_ends_with_separator = username.starts_with(is_separator) || username.ends_with(is_separator);
+    if !valid_chars || starts_or_ends_with_separator {
+        return Err(ValidationReason::UsernameInvalidCharacters.into());
+    }
+
+    // Letters that look alike across scripts (Latin "a" / Cyrillic "а") allow impersonation
+    let mut scripts = username.chars().filter(|c| c.is_alphabetic()).map(script_of);
+    if let Some(first) = scripts.next()
+        && scripts.any(|script| script != first)
+    {
+        return Err(ValidationReason::UsernameMixedScripts.into());
+    }
+
     Ok(())
 }
 
+// Zero-width, bidirectional and other characters that don't render
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(c, '\u{00AD}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}')
+}
+
+// Script of a letter, close enough to catch the confusable ones
+// (Latin, Greek and Cyrillic share most look-alikes); other letters are grouped
+// by their 256 code point block
+fn script_of(c: char) -> u32 {
+    const LATIN: u32 = 0;
+    const GREEK: u32 = 1;
+    const CYRILLIC: u32 = 2;
+
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => LATIN,
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => GREEK,
+        '\u{0400}'..='\u{052F}' => CYRILLIC,
+        _ => 3 + (c as u32 >> 8),
+    }
+}
+
+
+
+/// Hard limit on the password size in bytes, whatever the policy
+///
+/// Argon2 hashes inputs of any size, so without it a multi-megabyte
+/// password costs CPU on every register/login attempt.
+pub const MAX_PASSWORD_BYTES: usize = 1024;
+
+/// Rejects passwords larger than `MAX_PASSWORD_BYTES`
+///
+/// Must run before any Argon2 work (hashing or verifying)
+pub fn check_password_size(password: &str) -> Result<(), AuthError> {
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(ValidationReason::PasswordTooLarge.into());
+    }
+
+    Ok(())
+}
+
+
+/// Largest `username` accepted when deserializing a request
+///
+/// Looser than `validate_username` (50 characters, of up to 4 bytes each, plus the whitespace
+/// trimmed by `normalize_username`): it only stops huge inputs before they are copied and validated.
+pub const MAX_USERNAME_INPUT_BYTES: usize = 256;
+
+/// Largest `email` accepted when deserializing a request (`validate_email` allows 254 bytes, once trimmed)
+pub const MAX_EMAIL_INPUT_BYTES: usize = 320;
+
+/// Largest password accepted when deserializing a request
+///
+/// Above `MAX_PASSWORD_BYTES`, so that a password only somewhat too long is still
+/// reported as `password_too_large` by `check_password_size`
+pub const MAX_PASSWORD_INPUT_BYTES: usize = 4 * MAX_PASSWORD_BYTES;
+
+/// Largest value of the other string fields of a request (invite code, tenant)
+pub const MAX_FIELD_INPUT_BYTES: usize = 256;
+
+/// `#[serde(deserialize_with = "bounded_string::<MAX, _>")]`: a string of at most `MAX` bytes
+///
+/// A longer value fails the deserialization itself (`invalid_body`, naming the field),
+/// before anything is allocated for it. The validators still apply afterwards.
+pub fn bounded_string<'de, const MAX: usize, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    BoundedString::<MAX>::deserialize(deserializer).map(|bounded| bounded.0)
+}
+
+/// `bounded_string` for a password
+pub fn bounded_secret<'de, const MAX: usize, D: Deserializer<'de>>(deserializer: D) -> Result<Zeroizing<String>, D::Error> {
+    bounded_string::<MAX, D>(deserializer).map(Zeroizing::new)
+}
+
+/// `bounded_string` for an optional field (also needs `#[serde(default)]`)
+pub fn bounded_option<'de, const MAX: usize, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Option::<BoundedString<MAX>>::deserialize(deserializer).map(|value| value.map(|bounded| bounded.0))
+}
+
+struct BoundedString<const MAX: usize>(String);
+
+impl<'de, const MAX: usize> Deserialize<'de> for BoundedString<MAX> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(BoundedVisitor::<MAX>)
+    }
+}
+
+struct BoundedVisitor<const MAX: usize>;
+
+impl<const MAX: usize> de::Visitor<'_> for BoundedVisitor<MAX> {
+    type Value = BoundedString<MAX>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a string of at most {MAX} bytes")
+    }
+
+    // Only the length is read before refusing, the value is never copied
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.len() > MAX {
+            return Err(E::invalid_length(value.len(), &self));
+        }
+        Ok(BoundedString(value.to_string()))
+    }
+}
+
+
+/// Rules a password must follow
+///
+/// The default is the strong password policy below,
+/// deployments can relax or tighten it via `AppState::password_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters
+    pub min_length: usize,
+    /// Maximum number of characters (also bounds the Argon2 work)
+    pub max_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    /// Number of recent passwords (the current one included) a new password can't be,
+    /// 0 allows any of them
+    pub history: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_special: true,
+            history: 5,
+        }
+    }
+}
 
 
 /// Validates if the password is strong enough
 ///
 /// Strong password rules:
 /// - Minimum 8 characters
+/// - Maximum 128 characters
 /// - At least 1 uppercase letter (A-Z)
 /// - At least 1 lowercase letter (a-z)
 /// - At least 1 number (0-9)
 /// - At least 1 special character (!@#$%^&*()_+-=[]{}|;:,.<>?)
 pub fn validate_password(password: &str) -> Result<(), AuthError> {
+    validate_password_with(&PasswordPolicy::default(), password)
+}
+
+/// Validates the password against the given policy
+pub fn validate_password_with(policy: &PasswordPolicy, password: &str) -> Result<(), AuthError> {
+    check_password_size(password)?;
+
+    let length = password.chars().count();
+
+    if length > policy.max_length {
+        return Err(ValidationReason::PasswordTooLong { max: policy.max_length }.into());
+    }
+
     let mut errors = Vec::new();
 
-    if password.len() < 8 {
-        errors.push("at least 8 characters");
+    if length < policy.min_length {
+        errors.push(ValidationReason::PasswordTooShort { min: policy.min_length });
     }
 
-    if !password.chars().any(|c| c.is_uppercase()) {
-        errors.push("at least one uppercase letter (A-Z)");
+    if policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        errors.push(ValidationReason::PasswordMissingUppercase);
     }
 
-    if !password.chars().any(|c: char| c.is_lowercase()) {
-        errors.push("at least one lowercase letter (a-z)");
+    if policy.require_lowercase && !password.chars().any(|c: char| c.is_lowercase()) {
+        errors.push(ValidationReason::PasswordMissingLowercase);
     }
 
-    if !password.chars().any(|c: char| c.is_numeric()){
-        errors.push("at least one number (0-9)");
+    if policy.require_digit && !password.chars().any(|c: char| c.is_numeric()){
+        errors.push(ValidationReason::PasswordMissingDigit);
     }
 
     let special_chars = "!@#$%^&*()_+-=[]{}|;:,.<>?";
-    if !password.chars().any(|c| special_chars.contains(c)) {
-        errors.push("at least one special character");
+    if policy.require_special && !password.chars().any(|c| special_chars.contains(c)) {
+        errors.push(ValidationReason::PasswordMissingSpecial);
     }
 
+    // Every missing rule is reported at once
     if !errors.is_empty() {
-        let error_msg = format!(
-            "Password must contain: {}",
-            errors.join(", ")
-        );
-        return Err(AuthError::ValidationError(error_msg))
+        return Err(AuthError::ValidationError(errors))
     }
 
     Ok(())
@@ -120,6 +555,81 @@ mod tests {
         assert!(validate_email("user@com").is_err());
     }
 
+    #[test]
+    fn test_email_edge_cases() {
+        let long_local = format!("{}@example.com", "a".repeat(65));
+        let long_address = format!("{}@{}.com", "a".repeat(60), vec!["a".repeat(63); 3].join("."));
+        let long_label = format!("user@{}.com", "a".repeat(64));
+
+        let cases: &[(&str, Option<&str>)] = &[
+            // Valid
+            ("a@b.cc", None),
+            ("user+tag@example.com", None),
+            ("user+tag+more@example.com", None),
+            ("first.last@sub.example.co.uk", None),
+            ("x@example.com", None),
+            ("UPPER@EXAMPLE.COM", None),
+            ("o'brien@example.ie", None),
+            ("!#$%&'*+-/=?^_`{|}~@example.org", None),
+            ("user@my-domain.com", None),
+            ("user@123.example.com", None),
+            ("user@example.xn--p1ai", None),
+            ("\"john doe\"@example.com", None),
+            ("\"john..doe\"@example.com", None),
+            ("\"john@doe\"@example.com", None),
+            ("\"quote\\\"inside\"@example.com", None),
+            ("\"\"@example.com", None),
+            (&format!("{}@example.com", "a".repeat(64)), None),
+            // Invalid format
+            ("", Some("invalid_email")),
+            ("plainaddress", Some("invalid_email")),
+            ("@example.com", Some("invalid_email")),
+            ("user@", Some("invalid_email")),
+            ("user@@example.com", Some("invalid_email")),
+            ("user@com", Some("invalid_email")),
+            ("user@example.c", Some("invalid_email")),
+            ("user@example.123", Some("invalid_email")),
+            (".user@example.com", Some("invalid_email")),
+            ("user.@example.com", Some("invalid_email")),
+            ("user..name@example.com", Some("invalid_email")),
+            ("user name@example.com", Some("invalid_email")),
+            ("user@exam ple.com", Some("invalid_email")),
+            ("user@-example.com", Some("invalid_email")),
+            ("user@example-.com", Some("invalid_email")),
+            ("user@example..com", Some("invalid_email")),
+            ("user@.example.com", Some("invalid_email")),
+            ("user@example.com.", Some("invalid_email")),
+            ("user@exa_mple.com", Some("invalid_email")),
+            ("user@[192.0.2.1]", Some("invalid_email")),
+            ("user(comment)@example.com", Some("invalid_email")),
+            ("j\u{00F6}rg@example.com", Some("invalid_email")),
+            ("\"unterminated@example.com", Some("invalid_email")),
+            ("\"a\"b\"@example.com", Some("invalid_email")),
+            ("\"trailing\\\"@example.com", Some("invalid_email")),
+            ("a\"b@example.com", Some("invalid_email")),
+            ("user@example.com\n", Some("invalid_email")),
+            (&long_label, Some("invalid_email")),
+            // Too long
+            (&long_local, Some("email_too_long")),
+            (&long_address, Some("email_too_long")),
+        ];
+
+        for (email, expected) in cases {
+            let result = validate_email(email);
+            match expected {
+                None => assert!(result.is_ok(), "{email:?} should be valid"),
+                Some(code) => assert_eq!(kinds(result), [*code], "{email:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email("User@Example.com"), "user@example.com");
+        assert_eq!(normalize_email("  user@example.com \n"), "user@example.com");
+        assert_eq!(normalize_email("user@example.com"), "user@example.com");
+    }
+
     #[test]
     fn test_valid_username() {
         assert!(validate_username("john_doe").is_ok());
@@ -135,6 +645,55 @@ mod tests {
         assert!(validate_username("user name").is_err()); // has space
     }
 
+    #[test]
+    fn test_ascii_policy_keeps_current_rules() {
+        assert!(validate_username_with(UsernamePolicy::Ascii, "john_doe").is_ok());
+        assert!(validate_username_with(UsernamePolicy::Ascii, "josé").is_err());
+        assert!(validate_username_with(UsernamePolicy::Ascii, "_user").is_err());
+        assert_eq!(UsernamePolicy::default(), UsernamePolicy::Ascii);
+    }
+
+    #[test]
+    fn test_unicode_policy_accepts_accented_name() {
+        assert!(validate_username_with(UsernamePolicy::Unicode, &normalize_username("josé_müller")).is_ok());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "иван").is_ok());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "_josé").is_err());
+    }
+
+    #[test]
+    fn test_unicode_policy_rejects_invisible_characters() {
+        assert!(validate_username_with(UsernamePolicy::Unicode, "john\u{200B}doe").is_err());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "john\u{202E}doe").is_err());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "john\ndoe").is_err());
+    }
+
+    #[test]
+    fn test_unicode_policy_rejects_mixed_scripts() {
+        // "pаypal" with a Cyrillic "а"
+        assert!(validate_username_with(UsernamePolicy::Unicode, "p\u{0430}ypal").is_err());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "paypal").is_ok());
+    }
+
+    #[test]
+    fn test_unicode_policy_counts_characters() {
+        assert!(validate_username_with(UsernamePolicy::Unicode, &"é".repeat(50)).is_ok());
+        assert!(validate_username_with(UsernamePolicy::Unicode, &"é".repeat(51)).is_err());
+        assert!(validate_username_with(UsernamePolicy::Unicode, "éé").is_err());
+    }
+
+    #[test]
+    fn test_normalize_username_composes_accents() {
+        assert_eq!(normalize_username("jose\u{0301}"), "jos\u{00E9}");
+        assert_eq!(normalize_username("john_doe"), "john_doe");
+        assert_eq!(normalize_username("  JohnDoe "), "JohnDoe");
+    }
+
+    #[test]
+    fn test_canonical_username_ignores_case_and_spaces() {
+        assert_eq!(canonical_username(" JohnDoe"), "johndoe");
+        assert_eq!(canonical_username("JOSE\u{0301}"), "jos\u{00E9}");
+    }
+
     #[test]
     fn test_valid_password() {
         assert!(validate_password("Password123!").is_ok());
@@ -150,4 +709,81 @@ mod tests {
         assert!(validate_password("NoNumbers!").is_err()); // no number
         assert!(validate_password("NoSpecial123").is_err()); // no special
     }
+
+    #[test]
+    fn test_relaxed_policy_accepts_simple_password() {
+        let policy = PasswordPolicy {
+            min_length: 10,
+            require_uppercase: false,
+            require_digit: false,
+            require_special: false,
+            ..PasswordPolicy::default()
+        };
+
+        assert!(validate_password("correcthorsebattery").is_err());
+        assert!(validate_password_with(&policy, "correcthorsebattery").is_ok());
+        assert!(validate_password_with(&policy, "short").is_err());
+    }
+
+    #[test]
+    fn test_password_size_is_capped_in_bytes() {
+        assert!(check_password_size(&"a".repeat(MAX_PASSWORD_BYTES)).is_ok());
+        assert!(check_password_size(&"a".repeat(MAX_PASSWORD_BYTES + 1)).is_err());
+
+        // The cap holds even for a policy that allows more characters
+        let policy = PasswordPolicy { max_length: usize::MAX, ..PasswordPolicy::default() };
+        let multibyte = format!("Aa1!{}", "é".repeat(MAX_PASSWORD_BYTES));
+        assert!(validate_password_with(&policy, &multibyte).is_err());
+    }
+
+    // Kinds of the reasons of a failed validation
+    fn kinds(result: Result<(), AuthError>) -> Vec<&'static str> {
+        match result {
+            Err(AuthError::ValidationError(reasons)) => reasons.iter().map(ValidationReason::kind).collect(),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validation_failures_map_to_kinds() {
+        assert_eq!(kinds(validate_email("invalid")), ["invalid_email"]);
+        assert_eq!(kinds(validate_username("ab")), ["username_too_short"]);
+        assert_eq!(kinds(validate_username("_user")), ["username_invalid_characters"]);
+        assert_eq!(kinds(validate_username_with(UsernamePolicy::Unicode, "p\u{0430}ypal")), ["username_mixed_scripts"]);
+        assert_eq!(kinds(validate_password("NoSpecial123")), ["password_missing_special"]);
+        assert_eq!(
+            kinds(validate_password("weak")),
+            ["password_too_short", "password_missing_uppercase", "password_missing_digit", "password_missing_special"]
+        );
+        assert_eq!(kinds(check_password_size(&"a".repeat(MAX_PASSWORD_BYTES + 1))), ["password_too_large"]);
+    }
+
+    #[test]
+    fn test_validate_all_merges_every_failure() {
+        assert!(validate_all([validate_email("user@example.com"), validate_password("Password123!")]).is_ok());
+
+        let result = validate_all([
+            validate_email("invalid"),
+            validate_username("john_doe"),
+            validate_password("NoSpecial123"),
+        ]);
+        assert_eq!(kinds(result), ["invalid_email", "password_missing_special"]);
+    }
+
+    #[test]
+    fn test_reason_message_keeps_policy_values() {
+        let reason = ValidationReason::PasswordTooShort { min: 12 };
+        assert_eq!(reason.to_string(), "Password must contain at least 12 characters");
+        assert_eq!(reason.kind(), "password_too_short");
+    }
+
+    #[test]
+    fn test_policy_max_length() {
+        let policy = PasswordPolicy { max_length: 16, ..PasswordPolicy::default() };
+        assert!(validate_password_with(&policy, "Password123!").is_ok());
+        assert!(validate_password_with(&policy, "Password123!Password123!").is_err());
+
+        let very_long = format!("Aa1!{}", "a".repeat(10_000));
+        assert!(validate_password(&very_long).is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,284 @@
+// This file is responsible for throttling the auth routes (register, login, ...)
+// with a fixed-window limiter keyed on the client IP
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use crate::errors::AuthError;
+
+// Past this many tracked clients, the oldest windows are dropped to bound memory
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Settings of the rate limiter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Requests allowed per client in a window
+    pub max_requests: u32,
+    /// Length of a window
+    pub window: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_requests: 20,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+// Requests counted for one client since `started`
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+// Windows by client, and their starts oldest first, so expired ones are dropped
+// from the front instead of scanning every window
+#[derive(Default)]
+struct Windows {
+    by_ip: HashMap<IpAddr, Window>,
+    // A start may be stale (its window restarted since), it is skipped when popped
+    starts: VecDeque<(Instant, IpAddr)>,
+}
+
+impl Windows {
+    fn prune(&mut self, now: Instant, window_length: Duration) {
+        while let Some(&(started, ip)) = self.starts.front() {
+            let expired = now.duration_since(started) >= window_length;
+            if !expired && self.by_ip.len() <= PRUNE_THRESHOLD {
+                break;
+            }
+            self.starts.pop_front();
+            if self.by_ip.get(&ip).is_some_and(|window| window.started == started) {
+                self.by_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Fixed-window rate limiter, shared by all the requests of the layer
+///
+/// Usage:
+/// ```ignore
+/// let limiter = RateLimiter::new(RateLimitConfig::default());
+/// let routes = routes.layer(axum::middleware::from_fn_with_state(limiter, rate_limit));
+/// ```
+///
+/// The server must be started with `into_make_service_with_connect_info::<SocketAddr>()`
+/// so the socket address is known. Behind reverse proxies, see `with_trusted_proxies`.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    trusted_proxies: usize,
+    windows: Arc<Mutex<Windows>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self { config, trusted_proxies: 0, windows: Arc::default() }
+    }
+
+    /// Keys the clients on `X-Forwarded-For` as appended by this many proxies in front
+    /// of the server (see `AppState::trusted_proxies`), instead of the socket address
+    pub fn with_trusted_proxies(mut self, trusted_proxies: usize) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Counts a request of `ip`, returns the time to wait when the limit is exceeded
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    // Same as `check` with the current time given (so tests don't have to sleep)
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        let window_length = self.config.window;
+        windows.prune(now, window_length);
+
+        let Windows { by_ip, starts } = &mut *windows;
+        let window = by_ip.entry(ip).or_insert_with(|| {
+            starts.push_back((now, ip));
+            Window { started: now, count: 0 }
+        });
+
+        // Start a new window once the previous one is over
+        if now.duration_since(window.started) >= window_length {
+            window.started = now;
+            window.count = 0;
+            starts.push_back((now, ip));
+        }
+
+        if window.count >= self.config.max_requests {
+            return Err(window_length - now.duration_since(window.started));
+        }
+
+        window.count += 1;
+        Ok(())
+    }
+}
+
+/// Middleware rejecting clients over the limit with 429 and a `Retry-After` header
+pub async fn rate_limit(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    let socket_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+    let ip = client_ip(request.headers(), socket_ip, limiter.trusted_proxies);
+
+    match limiter.check(ip) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            tracing::warn!(ip = %ip, "rate limit exceeded");
+            // Round up, so the client doesn't retry a moment too early
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            AuthError::RateLimited(seconds).into_response()
+        }
+    }
+}
+
+// IP of the client, see known_client_ip
+fn client_ip(headers: &HeaderMap, socket_ip: Option<IpAddr>, trusted_proxies: usize) -> IpAddr {
+    known_client_ip(headers, socket_ip, trusted_proxies)
+        // Requests without any address share one bucket
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+// IP of the client: with `trusted_proxies` proxies in front of the server, the address the
+// outermost one appended, counting from the right end of `X-Forwarded-For` (each proxy appends
+// the address it received the request from, the entries left of these are sent by the client).
+// Otherwise, or when the header doesn't have that many valid entries, the socket address.
+// `None` when the request has no address at all
+pub(crate) fn known_client_ip(headers: &HeaderMap, socket_ip: Option<IpAddr>, trusted_proxies: usize) -> Option<IpAddr> {
+    let forwarded = trusted_proxies.checked_sub(1).and_then(|hops| {
+        headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .rev()
+            .nth(hops)
+            .and_then(|entry| entry.trim().parse().ok())
+    });
+    forwarded.or(socket_ip)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::{Request, StatusCode, header}, middleware, routing::post};
+    use tower::ServiceExt;
+
+    fn limiter(max_requests: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig { max_requests, window: Duration::from_secs(60) })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_limit_is_enforced_then_recovers_after_window() {
+        let limiter = limiter(3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(ip(1), start).is_ok());
+        }
+        let retry_after = limiter.check_at(ip(1), start + Duration::from_secs(20)).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(40));
+
+        // Other clients have their own window
+        assert!(limiter.check_at(ip(2), start).is_ok());
+
+        assert!(limiter.check_at(ip(1), start + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_read_behind_trusted_proxies() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers, Some(ip(1)), 1), ip(1));
+
+        // The client sent the first entry, the proxy appended the second one
+        headers.insert("X-Forwarded-For", "198.51.100.9, 203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(ip(1)), 0), ip(1));
+        assert_eq!(client_ip(&headers, Some(ip(1)), 1), "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&headers, Some(ip(1)), 2), "198.51.100.9".parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&headers, Some(ip(1)), 3), ip(1));
+
+        headers.insert("X-Forwarded-For", "not-an-ip".parse().unwrap());
+        assert_eq!(client_ip(&headers, Some(ip(1)), 1), ip(1));
+    }
+
+    #[test]
+    fn test_rotating_forwarded_for_does_not_bypass_the_limit() {
+        let limiter = limiter(2).with_trusted_proxies(1);
+        let app_ip = |spoofed: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Forwarded-For", format!("{}, 203.0.113.7", spoofed).parse().unwrap());
+            client_ip(&headers, Some(ip(1)), limiter.trusted_proxies)
+        };
+        let start = Instant::now();
+
+        assert!(limiter.check_at(app_ip("192.0.2.1"), start).is_ok());
+        assert!(limiter.check_at(app_ip("192.0.2.2"), start).is_ok());
+        assert!(limiter.check_at(app_ip("192.0.2.3"), start).is_err());
+    }
+
+    #[test]
+    fn test_oldest_windows_are_dropped_past_the_threshold() {
+        let limiter = limiter(1);
+        let start = Instant::now();
+        let many = |n: usize| IpAddr::V6(std::net::Ipv6Addr::from(n as u128));
+
+        for n in 0..=PRUNE_THRESHOLD + 1 {
+            assert!(limiter.check_at(many(n), start + Duration::from_millis(n as u64)).is_ok());
+        }
+        let windows = limiter.windows.lock().unwrap();
+        assert!(windows.by_ip.len() <= PRUNE_THRESHOLD + 1);
+        assert!(!windows.by_ip.contains_key(&many(0)));
+        assert!(windows.by_ip.contains_key(&many(PRUNE_THRESHOLD + 1)));
+    }
+
+    #[test]
+    fn test_expired_windows_are_dropped() {
+        let limiter = limiter(1);
+        let start = Instant::now();
+        assert!(limiter.check_at(ip(1), start).is_ok());
+        assert!(limiter.check_at(ip(2), start + Duration::from_secs(61)).is_ok());
+
+        let windows = limiter.windows.lock().unwrap();
+        assert_eq!(windows.by_ip.len(), 1);
+        assert_eq!(windows.starts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_returns_429_with_retry_after() {
+        let app = Router::new()
+            .route("/login", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(limiter(2), rate_limit));
+
+        let request = || Request::post("/login")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+    }
+}
@@ -0,0 +1,391 @@
+// End-to-end tests of the router, requests go through every layer without binding a socket
+
+use std::sync::Arc;
+use auth_system::{
+    app::{build_router, AUTH_BODY_LIMIT_BYTES, INTROSPECT_BODY_LIMIT_BYTES},
+    auth::{cookie::CookieConfig, crypto::Argon2Config},
+    cors::{AllowedOrigins, CorsConfig},
+    db::memory_connection::InMemoryUserRepository,
+    AppState,
+};
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{HeaderValue, Request, StatusCode, header},
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+const SECRET: &str = "test_secret_that_is_long_enough_for_hs256";
+
+fn app() -> Router {
+    let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+    // Cheap hashing keeps the tests fast
+    state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+    build_router(state)
+}
+
+async fn post_json(app: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    post_raw(app, uri, "application/json", body.to_string()).await
+}
+
+async fn post_raw(app: &Router, uri: &str, content_type: &str, body: String) -> (StatusCode, Value) {
+    let request = Request::post(uri)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+async fn get_with_token(app: &Router, uri: &str, token: &str) -> (StatusCode, String) {
+    let request = Request::get(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_malformed_json_is_a_json_validation_error() {
+    let app = app();
+
+    let (status, body) = post_raw(&app, "/login", "application/json", "{\"username\": ".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "validation_error");
+    assert_eq!(body["kind"], "invalid_body");
+    assert!(body["error"].as_str().unwrap().starts_with("Invalid request body"));
+}
+
+#[tokio::test]
+async fn test_missing_field_and_content_type_are_json_validation_errors() {
+    let app = app();
+
+    let (status, body) = post_json(&app, "/login", json!({ "username": "john_doe" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["reasons"], json!(["invalid_body"]));
+    assert!(body["error"].as_str().unwrap().contains("missing field `password`"));
+
+    let (status, body) = post_raw(&app, "/login", "text/plain", json!({ "username": "a", "password": "b" }).to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["kind"], "invalid_body");
+}
+
+#[tokio::test]
+async fn test_oversized_body_is_rejected_with_413() {
+    let app = app();
+    let username = "a".repeat(AUTH_BODY_LIMIT_BYTES);
+
+    let (status, body) = post_json(&app, "/register", json!({
+        "username": username,
+        "email": "john@example.com",
+        "password": "Password123!",
+    })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "payload_too_large");
+
+    // Not registered: the email is still free
+    let (status, _) = post_json(&app, "/register", json!({
+        "username": "john_doe",
+        "email": "john@example.com",
+        "password": "Password123!",
+    })).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_oversized_introspect_body_is_rejected_with_413() {
+    let app = app();
+
+    let (status, body) = post_json(&app, "/introspect/batch", json!({ "tokens": ["a".repeat(INTROSPECT_BODY_LIMIT_BYTES)] })).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["code"], "payload_too_large");
+
+    let (status, body) = post_json(&app, "/introspect/batch", json!({ "tokens": ["not-a-jwt"] })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["active"], false);
+}
+
+#[tokio::test]
+async fn test_overlong_field_is_rejected_while_parsing() {
+    let app = app();
+
+    // Under the body limit, over the cap of the field
+    let (status, body) = post_json(&app, "/register", json!({
+        "username": "a".repeat(1000),
+        "email": "john@example.com",
+        "password": "Password123!",
+    })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["kind"], "invalid_body");
+    assert!(body["error"].as_str().unwrap().contains("username: invalid length 1000"), "{body}");
+}
+
+#[tokio::test]
+async fn test_openapi_document_describes_login() {
+    let request = Request::get("/openapi.json").body(Body::empty()).unwrap();
+    let response = app().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let spec: Value = serde_json::from_slice(&bytes).unwrap();
+    let login = &spec["paths"]["/login"]["post"];
+    assert!(login["responses"]["200"].is_object());
+    assert!(login["responses"]["401"].is_object());
+    assert_eq!(login["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/LoginRequest");
+}
+
+#[cfg(feature = "utoipa-swagger-ui")]
+#[tokio::test]
+async fn test_swagger_ui_reads_the_openapi_document() {
+    let request = Request::get("/swagger-ui/swagger-initializer.js").body(Body::empty()).unwrap();
+    let response = app().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(String::from_utf8_lossy(&bytes).contains("/openapi.json"));
+}
+
+#[tokio::test]
+async fn test_register_login_and_access_protected_route() {
+    let app = app();
+
+    let (status, _) = post_json(&app, "/register", json!({
+        "username": "john_doe",
+        "email": "john@example.com",
+        "password": "Password123!"
+    })).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = post_json(&app, "/login", json!({
+        "username": "john_doe",
+        "password": "Password123!"
+    })).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["token"].as_str().unwrap();
+
+    let (status, body) = get_with_token(&app, "/private", token).await;
+    assert_eq!(status, StatusCode::OK);
+    let private: Value = serde_json::from_str(&body).unwrap();
+    assert!(private["user_id"].as_str().is_some_and(|id| uuid::Uuid::parse_str(id).is_ok()));
+    // An RFC 3339 string, or milliseconds with the epoch-millis feature
+    let authenticated_at = &private["authenticated_at"];
+    assert!(authenticated_at.as_str().is_some_and(|at| at.parse::<chrono::DateTime<chrono::Utc>>().is_ok()) || authenticated_at.is_i64());
+    assert_eq!(private.as_object().unwrap().len(), 2);
+
+    let (status, body) = get_with_token(&app, "/me", token).await;
+    assert_eq!(status, StatusCode::OK);
+    let me: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(me["username"], "john_doe");
+    assert!(me.get("password_hash").is_none());
+}
+
+// Registers john_doe then logs in with `password`
+async fn register_and_login(app: &Router, password: &str) -> (StatusCode, Value) {
+    post_json(app, "/register", json!({
+        "username": "john_doe",
+        "email": "john@example.com",
+        "password": "Password123!"
+    })).await;
+    post_json(app, "/login", json!({ "username": "john_doe", "password": password })).await
+}
+
+#[tokio::test]
+async fn test_envelope_wraps_successes_and_errors() {
+    let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+    state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+    state.response_envelope = true;
+    let app = build_router(state);
+
+    let (status, body) = register_and_login(&app, "Password123!").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["data"]["token"].is_string());
+    assert_eq!(body["error"], Value::Null);
+
+    let (status, body) = post_json(&app, "/login", json!({ "username": "john_doe", "password": "WrongPassword1!" })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["data"], Value::Null);
+    assert_eq!(body["error"]["code"], "invalid_credentials");
+}
+
+#[tokio::test]
+async fn test_no_envelope_by_default() {
+    let app = app();
+
+    let (_, body) = register_and_login(&app, "Password123!").await;
+    assert!(body["token"].is_string());
+    assert!(body.get("data").is_none());
+
+    let (_, body) = post_json(&app, "/login", json!({ "username": "john_doe", "password": "WrongPassword1!" })).await;
+    assert_eq!(body["code"], "invalid_credentials");
+    assert!(body.get("data").is_none());
+}
+
+async fn preflight(app: &Router, origin: &str) -> axum::response::Response {
+    let request = Request::options("/login")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_cors_preflight_only_allows_listed_origins() {
+    let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+    state.cors = Some(CorsConfig {
+        allowed_origins: AllowedOrigins::List(vec!["https://app.example.com".parse().unwrap()]),
+        allow_credentials: true,
+    });
+    let app = build_router(state);
+
+    let response = preflight(&app, "https://app.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+    let response = preflight(&app, "https://evil.example.com").await;
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
+
+#[tokio::test]
+async fn test_no_cors_headers_by_default() {
+    let response = preflight(&app(), "https://app.example.com").await;
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
+
+#[tokio::test]
+async fn test_wrong_password_and_missing_token_are_rejected() {
+    let app = app();
+
+    post_json(&app, "/register", json!({
+        "username": "john_doe",
+        "email": "john@example.com",
+        "password": "Password123!"
+    })).await;
+
+    let (status, body) = post_json(&app, "/login", json!({
+        "username": "john_doe",
+        "password": "WrongPassword1!"
+    })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "invalid_credentials");
+
+    let request = Request::get("/private").body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let (status, body) = get_with_token(&app, "/private", "not-a-jwt").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["code"], "invalid_token");
+}
+
+// App returning the token in the auth cookie, with its CSRF cookie
+fn cookie_app() -> Router {
+    let mut state = AppState::new(SECRET.to_string(), Arc::new(InMemoryUserRepository::new()));
+    state.argon2_config = Argon2Config { memory_kib: 64, iterations: 1, parallelism: 1, ..Argon2Config::default() };
+    state.auth_cookie = Some(CookieConfig::default());
+    build_router(state)
+}
+
+// Registers and logs john_doe in, returns the `Cookie` header a browser would send back,
+// the CSRF token and the access token
+async fn cookie_login(app: &Router) -> (String, String, String) {
+    post_json(app, "/register", json!({
+        "username": "john_doe",
+        "email": "john@example.com",
+        "password": "Password123!"
+    })).await;
+    let request = Request::post("/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": "john_doe", "password": "Password123!" }).to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cookies: Vec<String> = response.headers().get_all(header::SET_COOKIE).iter()
+        .map(|value| value.to_str().unwrap().split(';').next().unwrap().to_string())
+        .collect();
+    let csrf_token = cookies.iter().find_map(|cookie| cookie.strip_prefix("csrf_token=")).unwrap().to_string();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let token = serde_json::from_slice::<Value>(&bytes).unwrap()["token"].as_str().unwrap().to_string();
+    (cookies.join("; "), csrf_token, token)
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_cookie_request_needs_the_csrf_header() {
+    let app = cookie_app();
+    let (cookies, csrf_token, _) = cookie_login(&app).await;
+
+    let logout = |csrf_header: Option<&str>| {
+        let mut request = Request::post("/logout").header(header::COOKIE, &cookies);
+        if let Some(value) = csrf_header {
+            request = request.header("x-csrf-token", value);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let (status, body) = send(&app, logout(None)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "csrf_token_mismatch");
+    let (status, _) = send(&app, logout(Some("not-the-token"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Reads don't change anything, the cookie is enough
+    let me = Request::get("/me").header(header::COOKIE, &cookies).body(Body::empty()).unwrap();
+    assert_eq!(send(&app, me).await.0, StatusCode::OK);
+
+    let (status, _) = send(&app, logout(Some(&csrf_token))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_bearer_request_is_exempt_from_csrf() {
+    let app = cookie_app();
+    let (cookies, _, token) = cookie_login(&app).await;
+
+    let request = Request::post("/logout")
+        .header(header::COOKIE, &cookies)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::NO_CONTENT);
+
+    // A leftover cookie doesn't block the login either
+    let request = Request::post("/login")
+        .header(header::COOKIE, &cookies)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": "john_doe", "password": "Password123!" }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_unreadable_authorization_header_is_not_exempt_from_csrf() {
+    let app = cookie_app();
+    let (cookies, _, _) = cookie_login(&app).await;
+
+    let request = Request::post("/logout")
+        .header(header::COOKIE, &cookies)
+        .header(header::AUTHORIZATION, HeaderValue::from_bytes(b"Bearer \xff").unwrap())
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "csrf_token_mismatch");
+}